@@ -0,0 +1,335 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use argh::FromArgs;
+use bril_interp::{Bytecode, Interpreter, Machine};
+use bril_rs::Program;
+use serde::Deserialize;
+use snafu::{OptionExt, ResultExt, Whatever, whatever};
+
+enum Format {
+    Csv,
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = Whatever;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "csv" => Self::Csv,
+            "markdown" => Self::Markdown,
+            _ => whatever!("Unknown format '{}'", s),
+        })
+    }
+}
+
+/// Runs every Bril benchmark in a directory through each pipeline in a
+/// brench-style config and reports dynamic instruction counts, like `brench`
+#[derive(FromArgs)]
+struct Opts {
+    /// output format: `csv` (the default) or `markdown`
+    #[argh(option, default = "Format::Csv")]
+    format: Format,
+
+    /// interpret benchmarks as bytecode, which is faster but does not support
+    /// speculation
+    #[argh(switch)]
+    bytecode: bool,
+
+    /// TOML config with a `[runs.<name>]` table for each pipeline, one of
+    /// which must be named `baseline`
+    #[argh(positional)]
+    config: PathBuf,
+
+    /// directory searched recursively for `.bril` benchmarks
+    #[argh(positional)]
+    benchmarks: PathBuf,
+}
+
+/// Unlike in `brench`, pipelines end with a command printing Bril JSON, like
+/// `bril2json`, which is then interpreted in-process, so there is no `extract`
+/// pattern.
+#[derive(Deserialize)]
+struct Config {
+    /// Seconds each pipeline may run before the benchmark times out.
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    /// Directory passed to every pipeline as `$BRIL_ANALYSIS_CACHE`, so that
+    /// tools like `bril-opt` reuse the analyses of functions unchanged
+    /// between pipelines instead of recomputing them.
+    analysis_cache: Option<PathBuf>,
+    runs: BTreeMap<String, Run>,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+#[derive(Deserialize)]
+struct Run {
+    /// Shell commands piped into each other, with `{args}` replaced by the
+    /// arguments of the benchmark.
+    pipeline: Vec<String>,
+}
+
+/// The result of running a benchmark through one pipeline.
+enum Outcome {
+    DynamicInstructions(u64),
+    /// The output differed from that of the baseline.
+    Incorrect,
+    Timeout,
+    /// The pipeline or the interpreter failed.
+    Missing,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DynamicInstructions(count) => count.fmt(f),
+            Self::Incorrect => "incorrect".fmt(f),
+            Self::Timeout => "timeout".fmt(f),
+            Self::Missing => "missing".fmt(f),
+        }
+    }
+}
+
+/// The arguments from the `# ARGS:` comment in a benchmark, if any.
+fn benchmark_arguments(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .find_map(|line| line.strip_prefix("# ARGS:"))
+        .map(|arguments| {
+            arguments.split_whitespace().map(str::to_string).collect()
+        })
+        .unwrap_or_default()
+}
+
+fn find_benchmarks(
+    directory: &Path,
+    benchmarks: &mut Vec<PathBuf>,
+) -> Result<(), Whatever> {
+    let entries = fs::read_dir(directory).whatever_context(format!(
+        "Failed to read the directory {}",
+        directory.to_string_lossy()
+    ))?;
+    for entry in entries {
+        let path = entry
+            .whatever_context("Failed to read a directory entry")?
+            .path();
+        if path.is_dir() {
+            find_benchmarks(&path, benchmarks)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "bril")
+        {
+            benchmarks.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `pipeline` through the shell on the benchmark at `path`, returning
+/// its standard output, or the outcome if it fails or runs for longer than
+/// `timeout`.
+fn run_pipeline(
+    pipeline: &[String],
+    arguments: &[String],
+    path: &Path,
+    timeout: Duration,
+    analysis_cache: Option<&Path>,
+) -> Result<Result<Vec<u8>, Outcome>, Whatever> {
+    let arguments = arguments.join(" ");
+    let command = pipeline
+        .iter()
+        .map(|stage| stage.replace("{args}", &arguments))
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    let input = fs::File::open(path).whatever_context(format!(
+        "Failed to open {}",
+        path.to_string_lossy()
+    ))?;
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(&command);
+    if let Some(analysis_cache) = analysis_cache {
+        shell.env("BRIL_ANALYSIS_CACHE", analysis_cache);
+    }
+    let mut child = shell
+        .stdin(input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .whatever_context(format!("Failed to run `{command}`"))?;
+
+    // read concurrently so the pipeline cannot block on a full pipe
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = vec![];
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .whatever_context(format!("Failed to wait for `{command}`"))?
+        {
+            break Some(status);
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let output =
+        reader
+            .join()
+            .ok()
+            .and_then(Result::ok)
+            .whatever_context(format!(
+                "Failed to read the output of `{command}`"
+            ))?;
+    Ok(match status {
+        Some(status) if status.success() => Ok(output),
+        Some(_) => Err(Outcome::Missing),
+        None => Err(Outcome::Timeout),
+    })
+}
+
+/// Interprets the Bril JSON in `program`, returning its output and dynamic
+/// instruction count. With `bytecode`, the program is run on a [`Machine`].
+fn interpret(
+    program: &[u8],
+    arguments: &[String],
+    bytecode: bool,
+) -> Result<(Vec<u8>, u64), Whatever> {
+    let program: Program = serde_json::from_slice(program)
+        .whatever_context("Failed to parse a valid Bril program")?;
+    let mut output = vec![];
+    if bytecode {
+        let bytecode = Bytecode::compile(&program)?;
+        let mut machine = Machine::new(&bytecode, &mut output);
+        machine.run_main(arguments)?;
+        let dynamic_instructions = machine.dynamic_instructions();
+        return Ok((output, dynamic_instructions));
+    }
+    let mut interpreter = Interpreter::new(&program, &mut output);
+    interpreter.run_main(arguments)?;
+    let dynamic_instructions = interpreter.dynamic_instructions();
+    Ok((output, dynamic_instructions))
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+
+    let config = fs::read_to_string(&opts.config).whatever_context(format!(
+        "Failed to read the contents of {}",
+        opts.config.to_string_lossy()
+    ))?;
+    let config: Config = toml::from_str(&config)
+        .whatever_context("Failed to parse the config")?;
+    if !config.runs.contains_key("baseline") {
+        whatever!("The config has no `baseline` run");
+    }
+    let timeout = Duration::from_secs(config.timeout);
+
+    let mut benchmarks = vec![];
+    find_benchmarks(&opts.benchmarks, &mut benchmarks)?;
+    benchmarks.sort();
+
+    let mut results = vec![];
+    for path in &benchmarks {
+        let source = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        let arguments = benchmark_arguments(&source);
+
+        let mut runs = vec![];
+        for (name, run) in &config.runs {
+            let result = run_pipeline(
+                &run.pipeline,
+                &arguments,
+                path,
+                timeout,
+                config.analysis_cache.as_deref(),
+            )?
+            .and_then(|program| {
+                interpret(&program, &arguments, opts.bytecode)
+                    .map_err(|_| Outcome::Missing)
+            });
+            runs.push((name, result));
+        }
+
+        let expected_output = runs
+            .iter()
+            .find(|(name, _)| *name == "baseline")
+            .and_then(|(_, result)| result.as_ref().ok())
+            .map(|(output, _)| output.clone());
+        let outcomes = runs
+            .into_iter()
+            .map(|(name, result)| {
+                let outcome = match result {
+                    Ok((output, _))
+                        if Some(&output) != expected_output.as_ref() =>
+                    {
+                        Outcome::Incorrect
+                    }
+                    Ok((_, dynamic_instructions)) => {
+                        Outcome::DynamicInstructions(dynamic_instructions)
+                    }
+                    Err(outcome) => outcome,
+                };
+                (name, outcome)
+            })
+            .collect::<Vec<_>>();
+
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        results.push((name, outcomes));
+    }
+
+    match opts.format {
+        Format::Csv => {
+            println!("benchmark,run,result");
+            for (benchmark, outcomes) in &results {
+                for (run, outcome) in outcomes {
+                    println!("{benchmark},{run},{outcome}");
+                }
+            }
+        }
+        Format::Markdown => {
+            let runs = config.runs.keys().cloned().collect::<Vec<_>>();
+            println!("| benchmark | {} |", runs.join(" | "));
+            println!("|---|{}", "---|".repeat(runs.len()));
+            for (benchmark, outcomes) in &results {
+                println!(
+                    "| {} | {} |",
+                    benchmark,
+                    outcomes
+                        .iter()
+                        .map(|(_, outcome)| outcome.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
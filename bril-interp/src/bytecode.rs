@@ -0,0 +1,390 @@
+use std::{collections::HashMap, iter};
+
+use bril_rs::{EffectOps, Instruction, Program, Type, ValueOps};
+use build_cfg::{BasicBlockIdx, Exit, FunctionCfg};
+use snafu::{OptionExt, ResultExt, Whatever, whatever};
+
+use crate::value::Value;
+
+/// A variable of a function, numbered as in [`FunctionCfg::symbols`], so the
+/// parameters come first.
+pub type Register = u32;
+
+/// A run of registers in [`BytecodeFunction::operands`], for operations with
+/// any number of arguments.
+#[derive(Debug, Clone, Copy)]
+pub struct Operands {
+    start: u32,
+    len: u32,
+}
+
+impl Operands {
+    pub fn of(self, function: &BytecodeFunction) -> &[Register] {
+        let start = self.start as usize;
+        &function.operands[start..start + self.len as usize]
+    }
+}
+
+/// An instruction with its variables numbered and its labels resolved to
+/// indices into [`BytecodeFunction::code`]. Every operation counts as one
+/// dynamic instruction except [`Op::Goto`] and [`Op::End`], which stand for
+/// falling through to a block laid out elsewhere or off the end of the
+/// function.
+#[derive(Debug, Clone)]
+pub enum Op {
+    Const {
+        dest: Register,
+        value: Value,
+    },
+    /// `id`, `not`, `char2int`, or `int2char`.
+    Unary {
+        op: ValueOps,
+        dest: Register,
+        arg: Register,
+    },
+    /// Any other operation on values without side effects.
+    Binary {
+        op: ValueOps,
+        dest: Register,
+        lhs: Register,
+        rhs: Register,
+    },
+    Call {
+        dest: Option<Register>,
+        function: u32,
+        arguments: Operands,
+    },
+    Alloc {
+        dest: Register,
+        size: Register,
+        /// The index of the instruction in
+        /// [`BytecodeFunction::allocation_sites`].
+        site: u32,
+    },
+    Load {
+        dest: Register,
+        pointer: Register,
+    },
+    Store {
+        pointer: Register,
+        value: Register,
+    },
+    Free {
+        pointer: Register,
+    },
+    Print {
+        arguments: Operands,
+    },
+    Nop,
+    Jump {
+        target: u32,
+    },
+    Branch {
+        condition: Register,
+        if_true: u32,
+        if_false: u32,
+    },
+    Return {
+        value: Option<Register>,
+    },
+    Get {
+        dest: Register,
+    },
+    /// Writes the shadow variable `dest` read by a later `get`.
+    Set {
+        dest: Register,
+        value: Register,
+    },
+    Undef {
+        dest: Register,
+    },
+    Goto {
+        target: u32,
+    },
+    End,
+}
+
+pub struct BytecodeFunction {
+    pub name: String,
+    pub parameters: Vec<Type>,
+    /// The name of each register, for errors.
+    pub registers: Vec<String>,
+    pub code: Vec<Op>,
+    pub operands: Vec<Register>,
+    /// The `alloc` instructions, for reporting leaks.
+    pub allocation_sites: Vec<Instruction>,
+}
+
+/// A program encoded for [`crate::Machine`], which runs it without looking up
+/// variables or labels by name.
+pub struct Bytecode {
+    pub functions: Vec<BytecodeFunction>,
+}
+
+impl Bytecode {
+    /// Encodes every function in `program` from its CFG. Speculation is not
+    /// supported.
+    pub fn compile(program: &Program) -> Result<Self, Whatever> {
+        let indices = program
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, function)| (function.name.as_str(), i as u32))
+            .collect::<HashMap<_, _>>();
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| {
+                let cfg = build_cfg::build_cfg(function, false)
+                    .whatever_context("Failed to build cfg")?;
+                encode(&cfg, &indices).whatever_context(format!(
+                    "Failed to encode `{}` as bytecode",
+                    function.name
+                ))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { functions })
+    }
+
+    /// The index of the function `name`.
+    pub fn function(&self, name: &str) -> Option<u32> {
+        self.functions
+            .iter()
+            .position(|function| function.name == name)
+            .map(|i| i as u32)
+    }
+}
+
+struct Encoder<'a> {
+    cfg: &'a FunctionCfg,
+    functions: &'a HashMap<&'a str, u32>,
+    /// The position of each block in the layout, which is the order of
+    /// [`FunctionCfg::into_function`].
+    positions: HashMap<BasicBlockIdx, u32>,
+    code: Vec<Op>,
+    operands: Vec<Register>,
+    allocation_sites: Vec<Instruction>,
+}
+
+impl Encoder<'_> {
+    fn register(&self, name: &str) -> Register {
+        self.cfg.symbols.intern(name).index() as Register
+    }
+
+    fn argument(
+        &self,
+        args: &[String],
+        i: usize,
+    ) -> Result<Register, Whatever> {
+        let name = args.get(i).whatever_context(format!(
+            "Expected at least {} arguments",
+            i + 1
+        ))?;
+        Ok(self.register(name))
+    }
+
+    fn operands(&mut self, args: &[String]) -> Operands {
+        let start = self.operands.len() as u32;
+        for arg in args {
+            let register = self.register(arg);
+            self.operands.push(register);
+        }
+        Operands {
+            start,
+            len: args.len() as u32,
+        }
+    }
+
+    fn function(&self, funcs: &[String]) -> Result<u32, Whatever> {
+        let name = funcs.first().whatever_context("Expected a function")?;
+        self.functions
+            .get(name.as_str())
+            .copied()
+            .whatever_context(format!("Undefined function `{name}`"))
+    }
+
+    /// Encodes `instruction`, which ends `block` if it is a jump or branch.
+    fn encode(
+        &mut self,
+        block: BasicBlockIdx,
+        instruction: &Instruction,
+    ) -> Result<Op, Whatever> {
+        Ok(match instruction {
+            Instruction::Constant {
+                dest,
+                const_type,
+                value,
+                ..
+            } => Op::Const {
+                dest: self.register(dest),
+                value: Value::from_literal(value, const_type),
+            },
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                op,
+                ..
+            } => {
+                let dest = self.register(dest);
+                match op {
+                    ValueOps::Call => Op::Call {
+                        dest: Some(dest),
+                        function: self.function(funcs)?,
+                        arguments: self.operands(args),
+                    },
+                    ValueOps::Alloc => {
+                        self.allocation_sites.push(instruction.clone());
+                        Op::Alloc {
+                            dest,
+                            size: self.argument(args, 0)?,
+                            site: self.allocation_sites.len() as u32 - 1,
+                        }
+                    }
+                    ValueOps::Load => Op::Load {
+                        dest,
+                        pointer: self.argument(args, 0)?,
+                    },
+                    ValueOps::Get => Op::Get { dest },
+                    ValueOps::Undef => Op::Undef { dest },
+                    ValueOps::Id
+                    | ValueOps::Not
+                    | ValueOps::Char2int
+                    | ValueOps::Int2char => Op::Unary {
+                        op: *op,
+                        dest,
+                        arg: self.argument(args, 0)?,
+                    },
+                    _ => Op::Binary {
+                        op: *op,
+                        dest,
+                        lhs: self.argument(args, 0)?,
+                        rhs: self.argument(args, 1)?,
+                    },
+                }
+            }
+            Instruction::Effect {
+                args, funcs, op, ..
+            } => match (op, &self.cfg.edges[block]) {
+                (EffectOps::Jump, Exit::Unconditional(target)) => Op::Jump {
+                    target: self.positions[target],
+                },
+                (
+                    EffectOps::Branch,
+                    Exit::Conditional {
+                        if_true, if_false, ..
+                    },
+                ) => Op::Branch {
+                    condition: self.argument(args, 0)?,
+                    if_true: self.positions[if_true],
+                    if_false: self.positions[if_false],
+                },
+                (EffectOps::Return, _) => Op::Return {
+                    value: args.first().map(|arg| self.register(arg)),
+                },
+                (EffectOps::Print, _) => Op::Print {
+                    arguments: self.operands(args),
+                },
+                (EffectOps::Nop, _) => Op::Nop,
+                (EffectOps::Call, _) => Op::Call {
+                    dest: None,
+                    function: self.function(funcs)?,
+                    arguments: self.operands(args),
+                },
+                (EffectOps::Store, _) => Op::Store {
+                    pointer: self.argument(args, 0)?,
+                    value: self.argument(args, 1)?,
+                },
+                (EffectOps::Free, _) => Op::Free {
+                    pointer: self.argument(args, 0)?,
+                },
+                (EffectOps::Set, _) => Op::Set {
+                    dest: self.argument(args, 0)?,
+                    value: self.argument(args, 1)?,
+                },
+                _ => whatever!("`{op}` is not supported in bytecode"),
+            },
+        })
+    }
+}
+
+/// Encodes the function of `cfg`, where `functions` numbers every function in
+/// the program.
+fn encode(
+    cfg: &FunctionCfg,
+    functions: &HashMap<&str, u32>,
+) -> Result<BytecodeFunction, Whatever> {
+    let layout = iter::once(cfg.entry)
+        .chain(cfg.vertices.keys().filter(|block| *block != cfg.entry))
+        .collect::<Vec<_>>();
+    let mut encoder = Encoder {
+        cfg,
+        functions,
+        positions: layout
+            .iter()
+            .enumerate()
+            .map(|(position, block)| (*block, position as u32))
+            .collect(),
+        code: vec![],
+        operands: vec![],
+        allocation_sites: vec![],
+    };
+
+    // targets are first encoded as positions in the layout, then resolved to
+    // the start of each block once all are encoded
+    let mut starts = vec![];
+    for (position, block) in layout.iter().enumerate() {
+        starts.push(encoder.code.len() as u32);
+        for instruction in &cfg.vertices[*block].instructions {
+            let op = encoder.encode(*block, instruction)?;
+            encoder.code.push(op);
+        }
+        match cfg.edges.get(*block) {
+            Some(Exit::Fallthrough(Some(next)))
+                if layout.get(position + 1) != Some(next) =>
+            {
+                encoder.code.push(Op::Goto {
+                    target: encoder.positions[next],
+                });
+            }
+            Some(Exit::Fallthrough(None)) | None => {
+                encoder.code.push(Op::End);
+            }
+            _ => {}
+        }
+    }
+    encoder.code.push(Op::End);
+    for op in &mut encoder.code {
+        match op {
+            Op::Jump { target } | Op::Goto { target } => {
+                *target = starts[*target as usize];
+            }
+            Op::Branch {
+                if_true, if_false, ..
+            } => {
+                *if_true = starts[*if_true as usize];
+                *if_false = starts[*if_false as usize];
+            }
+            _ => {}
+        }
+    }
+
+    Ok(BytecodeFunction {
+        name: cfg.signature.name.clone(),
+        parameters: cfg
+            .signature
+            .arguments
+            .iter()
+            .map(|argument| argument.arg_type.clone())
+            .collect(),
+        registers: cfg
+            .symbols
+            .names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        code: encoder.code,
+        operands: encoder.operands,
+        allocation_sites: encoder.allocation_sites,
+    })
+}
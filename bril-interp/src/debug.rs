@@ -0,0 +1,214 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{BufRead, Write},
+    str::FromStr,
+};
+
+use bril_rs::Instruction;
+use snafu::{ResultExt, Whatever, whatever};
+
+use crate::{heap::Heap, value::Value};
+
+const HELP: &str = "\
+commands:
+  step, s             run the next instruction and stop again
+  continue, c         run until the next breakpoint
+  break, b <where>    stop at `@function` or at `.label` in any function
+  delete, d <where>   remove a breakpoint
+  print, p [name]     print every variable, or only `name`
+  heap                print every allocation that has not been freed
+  quit, q             stop the program
+  help                print this list";
+
+/// Where the [`Debugger`] stops: at the start of every call to a function or
+/// whenever control reaches a label in any function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    Function(String),
+    Label(String),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(function) = s.strip_prefix('@') {
+            Ok(Self::Function(function.into()))
+        } else if let Some(label) = s.strip_prefix('.') {
+            Ok(Self::Label(label.into()))
+        } else {
+            Err(format!(
+                "Breakpoint '{}' is neither `@function` nor `.label`",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Function(function) => write!(f, "@{function}"),
+            Self::Label(label) => write!(f, ".{label}"),
+        }
+    }
+}
+
+/// Where the interpreter is stopped: before `instruction` in `function`, in
+/// the block labeled `block` unless it is the unlabeled entry block.
+pub(crate) struct Location<'a> {
+    pub function: &'a str,
+    pub block: Option<&'a str>,
+    pub instruction: &'a Instruction,
+}
+
+/// Stops the [`crate::Interpreter`] at breakpoints and after single steps,
+/// reading commands to inspect the variables and the heap from `input` and
+/// answering on `output`. Once `input` ends, the program runs to completion.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    stepping: bool,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Debugger {
+    /// A debugger stopping at `breakpoints`, and before the first instruction
+    /// if `stop_at_start`.
+    pub fn new(
+        breakpoints: Vec<Breakpoint>,
+        stop_at_start: bool,
+        input: impl BufRead + 'static,
+        output: impl Write + 'static,
+    ) -> Self {
+        Self {
+            breakpoints,
+            stepping: stop_at_start,
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+
+    /// Whether to stop on entering `function`.
+    pub(crate) fn breaks_at_function(&self, function: &str) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            matches!(breakpoint, Breakpoint::Function(name) if name == function)
+        })
+    }
+
+    /// Whether to stop on reaching `label`.
+    pub(crate) fn breaks_at_label(&self, label: &str) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            matches!(breakpoint, Breakpoint::Label(name) if name == label)
+        })
+    }
+
+    /// Whether to stop before the next instruction regardless of breakpoints.
+    pub(crate) fn is_stepping(&self) -> bool {
+        self.stepping
+    }
+
+    /// Stops at `location`, answering commands until one resumes execution.
+    pub(crate) fn stop(
+        &mut self,
+        location: &Location,
+        variables: &HashMap<&str, Value>,
+        heap: &Heap,
+    ) -> Result<(), Whatever> {
+        let block = location
+            .block
+            .map(|label| format!(".{label}"))
+            .unwrap_or_else(|| "the entry block".into());
+        self.say(&format!(
+            "stopped in @{} at {}, before `{}`",
+            location.function, block, location.instruction
+        ))?;
+
+        loop {
+            write!(self.output, "(debug) ")
+                .and_then(|_| self.output.flush())
+                .whatever_context("Failed to write to the debugger")?;
+            let mut line = String::new();
+            let read = self
+                .input
+                .read_line(&mut line)
+                .whatever_context("Failed to read a debugger command")?;
+            if read == 0 {
+                // without commands, nothing can resume after a stop
+                self.breakpoints.clear();
+                self.stepping = false;
+                return Ok(());
+            }
+
+            let mut words = line.split_whitespace();
+            let command = words.next();
+            let argument = words.next();
+            match (command, argument) {
+                (None, _) => {}
+                (Some("step" | "s"), None) => {
+                    self.stepping = true;
+                    return Ok(());
+                }
+                (Some("continue" | "c"), None) => {
+                    self.stepping = false;
+                    return Ok(());
+                }
+                (Some("break" | "b"), Some(argument)) => {
+                    match argument.parse::<Breakpoint>() {
+                        Ok(breakpoint) => {
+                            self.say(&format!("breakpoint at {breakpoint}"))?;
+                            if !self.breakpoints.contains(&breakpoint) {
+                                self.breakpoints.push(breakpoint);
+                            }
+                        }
+                        Err(error) => self.say(&error)?,
+                    }
+                }
+                (Some("delete" | "d"), Some(argument)) => {
+                    let before = self.breakpoints.len();
+                    self.breakpoints.retain(|breakpoint| {
+                        breakpoint.to_string() != argument
+                    });
+                    if self.breakpoints.len() == before {
+                        self.say(&format!("no breakpoint at {argument}"))?;
+                    }
+                }
+                (Some("print" | "p"), None) => {
+                    // sorted so the listing does not change between stops
+                    let variables =
+                        variables.iter().collect::<BTreeMap<_, _>>();
+                    for (name, value) in variables {
+                        self.say(&format!("{name} = {value}"))?;
+                    }
+                }
+                (Some("print" | "p"), Some(name)) => {
+                    match variables.get(name) {
+                        Some(value) => {
+                            self.say(&format!("{name} = {value}"))?
+                        }
+                        None => self.say(&format!("`{name}` is undefined"))?,
+                    }
+                }
+                (Some("heap"), None) => {
+                    for line in heap.dump() {
+                        self.say(&line)?;
+                    }
+                }
+                (Some("quit" | "q"), None) => {
+                    whatever!("Stopped by the debugger");
+                }
+                (Some("help"), None) => self.say(HELP)?,
+                (Some(command), _) => self.say(&format!(
+                    "unknown command `{}`; try `help`",
+                    command
+                ))?,
+            }
+        }
+    }
+
+    fn say(&mut self, message: &str) -> Result<(), Whatever> {
+        writeln!(self.output, "{message}")
+            .whatever_context("Failed to write to the debugger")
+    }
+}
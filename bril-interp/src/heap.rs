@@ -0,0 +1,213 @@
+use std::fmt;
+
+use bril_rs::Instruction;
+use snafu::{OptionExt, Whatever, whatever};
+
+use crate::value::{Pointer, Value};
+
+/// The `alloc` instruction that made an allocation.
+#[derive(Clone, Copy)]
+pub struct AllocationSite<'a> {
+    pub function: &'a str,
+    pub instruction: &'a Instruction,
+}
+
+/// Names the instruction and function, with the position of the instruction if
+/// the program has positions.
+impl fmt::Display for AllocationSite<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` in `{}`", self.instruction, self.function)?;
+        let (Instruction::Constant { pos, .. }
+        | Instruction::Value { pos, .. }
+        | Instruction::Effect { pos, .. }) = self.instruction;
+        if let Some(pos) = pos {
+            write!(f, " at {}:{}", pos.pos.row, pos.pos.col)?;
+        }
+        Ok(())
+    }
+}
+
+/// An allocation that was never freed.
+pub struct Leak<'a> {
+    pub site: AllocationSite<'a>,
+    pub cells: usize,
+}
+
+impl fmt::Display for Leak<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cells from {}", self.cells, self.site)
+    }
+}
+
+/// The memory of a Bril program. Allocations are never reused, so a pointer
+/// into a freed allocation stays invalid.
+#[derive(Default)]
+pub struct Heap<'a> {
+    /// Each allocation, or `None` once it is freed, with each of its cells,
+    /// or `None` if the cell was never stored to.
+    allocations: Vec<Option<Vec<Option<Value>>>>,
+    /// Where each allocation was made.
+    sites: Vec<AllocationSite<'a>>,
+    /// Whether errors about a pointer say where its allocation was made.
+    sanitize: bool,
+}
+
+impl<'a> Heap<'a> {
+    /// Makes errors about invalid pointers say where the allocation they
+    /// point into was made, which finds the bug faster than the pointer.
+    pub fn enable_sanitizer(&mut self) {
+        self.sanitize = true;
+    }
+
+    /// With the sanitizer, where the allocation `pointer` points into was
+    /// made, to end an error about it with.
+    fn provenance(&self, pointer: Pointer) -> String {
+        match self.sites.get(pointer.allocation) {
+            Some(site) if self.sanitize => {
+                format!("; it points into the allocation from {site}")
+            }
+            _ => String::new(),
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        size: i64,
+        site: AllocationSite<'a>,
+    ) -> Result<Pointer, Whatever> {
+        if size <= 0 {
+            whatever!("Cannot allocate {} cells, which is not positive", size);
+        }
+        self.allocations.push(Some(vec![None; size as usize]));
+        self.sites.push(site);
+        Ok(Pointer {
+            allocation: self.allocations.len() - 1,
+            offset: 0,
+        })
+    }
+
+    pub fn free(&mut self, pointer: Pointer) -> Result<(), Whatever> {
+        if pointer.offset != 0 {
+            whatever!(
+                "Cannot free {}, which is not the start of an allocation{}",
+                Value::Pointer(pointer),
+                self.provenance(pointer)
+            );
+        }
+        let allocation = self
+            .allocations
+            .get_mut(pointer.allocation)
+            .and_then(Option::take);
+        if allocation.is_none() {
+            whatever!(
+                "Cannot free {}, which was already freed{}",
+                Value::Pointer(pointer),
+                self.provenance(pointer)
+            );
+        }
+        Ok(())
+    }
+
+    fn cell(
+        &mut self,
+        pointer: Pointer,
+    ) -> Result<&mut Option<Value>, Whatever> {
+        let provenance = self.provenance(pointer);
+        let allocation = self
+            .allocations
+            .get_mut(pointer.allocation)
+            .and_then(Option::as_mut)
+            .whatever_context(format!(
+                "Cannot access {}, which was freed{provenance}",
+                Value::Pointer(pointer)
+            ))?;
+        let size = allocation.len();
+        usize::try_from(pointer.offset)
+            .ok()
+            .and_then(|offset| allocation.get_mut(offset))
+            .whatever_context(format!(
+                "Cannot access {}, which is out of bounds of {size} cells{provenance}",
+                Value::Pointer(pointer)
+            ))
+    }
+
+    pub fn load(&mut self, pointer: Pointer) -> Result<Value, Whatever> {
+        let provenance = self.provenance(pointer);
+        (*self.cell(pointer)?).whatever_context(format!(
+            "Cannot load from {}, which was never stored to{provenance}",
+            Value::Pointer(pointer)
+        ))
+    }
+
+    pub fn store(
+        &mut self,
+        pointer: Pointer,
+        value: Value,
+    ) -> Result<(), Whatever> {
+        *self.cell(pointer)? = Some(value);
+        Ok(())
+    }
+
+    /// The allocations that have not been freed, oldest first.
+    pub fn leaks(&self) -> Vec<Leak<'a>> {
+        self.allocations
+            .iter()
+            .zip(&self.sites)
+            .filter_map(|(allocation, site)| {
+                allocation.as_ref().map(|cells| Leak {
+                    site: *site,
+                    cells: cells.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// A line for each allocation that has not been freed, with where it was
+    /// made and its cells, where `_` is a cell that was never stored to.
+    pub fn dump(&self) -> Vec<String> {
+        self.allocations
+            .iter()
+            .zip(&self.sites)
+            .enumerate()
+            .filter_map(|(allocation, (cells, site))| {
+                let cells = cells
+                    .as_ref()?
+                    .iter()
+                    .map(|cell| {
+                        cell.map_or_else(
+                            || "_".into(),
+                            |value| value.to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                Some(format!(
+                    "ptr({allocation}, 0) from {site}: [{}]",
+                    cells.join(", ")
+                ))
+            })
+            .collect()
+    }
+
+    /// Fails if any allocation has not been freed, listing where each was
+    /// made if `report` is set.
+    pub fn check_freed(&self, report: bool) -> Result<(), Whatever> {
+        let leaks = self.leaks();
+        if !leaks.is_empty() && report {
+            whatever!(
+                "{} allocations were not freed by the end of execution:\n{}",
+                leaks.len(),
+                leaks
+                    .iter()
+                    .map(|leak| format!("  {leak}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        } else if !leaks.is_empty() {
+            whatever!(
+                "{} allocations were not freed by the end of execution",
+                leaks.len()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+//! An interpreter for Bril programs using the core, memory, float, char, SSA,
+//! and speculation extensions, which counts dynamic instructions like
+//! `brili -p` does. For measurements, programs without speculation can instead
+//! be compiled to [`Bytecode`] and run much faster on a [`Machine`].
+//!
+//! The interpreter can also stop at breakpoints to inspect the program with a
+//! [`Debugger`], and log every instruction it executes as JSON for other
+//! tools to read.
+
+use std::{collections::HashMap, io::Write};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+use bril_util::profile::Profile;
+use serde_json::json;
+use snafu::{OptionExt, ResultExt, Whatever, whatever};
+
+pub use crate::{
+    bytecode::{Bytecode, BytecodeFunction, Op, Operands, Register},
+    debug::{Breakpoint, Debugger},
+    heap::{AllocationSite, Leak},
+    machine::Machine,
+    trace::{Trace, TraceStep},
+    value::{Pointer, Value},
+};
+use crate::{debug::Location, heap::Heap, trace::Tracer, value::evaluate};
+
+mod bytecode;
+mod debug;
+mod heap;
+mod machine;
+mod trace;
+mod value;
+
+/// Where control goes after an instruction.
+enum Flow<'a> {
+    Next,
+    Jump(&'a str),
+    Return(Option<Value>),
+}
+
+/// The variables of a function call. SSA `set` instructions write to a
+/// separate set of shadow variables, which `get` instructions read from.
+#[derive(Default)]
+struct Frame<'a> {
+    variables: HashMap<&'a str, Value>,
+    shadow_variables: HashMap<&'a str, Value>,
+    /// The variables when each ongoing `speculate` started, innermost last,
+    /// which a failed `guard` restores. Memory is not restored.
+    speculations: Vec<HashMap<&'a str, Value>>,
+}
+
+impl Frame<'_> {
+    fn get(&self, name: &str) -> Result<Value, Whatever> {
+        self.variables
+            .get(name)
+            .copied()
+            .whatever_context(format!("Undefined variable `{name}`"))
+    }
+}
+
+pub struct Interpreter<'a, W: Write> {
+    functions: HashMap<&'a str, &'a Function>,
+    /// The index of each label in the instructions of each function.
+    labels: HashMap<&'a str, HashMap<&'a str, usize>>,
+    heap: Heap<'a>,
+    output: W,
+    dynamic_instructions: u64,
+    block_profile: Option<Profile>,
+    tracer: Option<Tracer>,
+    debugger: Option<Debugger>,
+    /// Where each executed instruction is logged as a line of JSON.
+    events: Option<Box<dyn Write>>,
+    /// The number of ongoing calls.
+    depth: usize,
+    /// Whether leaked allocations are listed when `main` returns.
+    report_leaks: bool,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    /// Prepares to run `program`, writing the output of its `print`
+    /// instructions to `output` as they run.
+    pub fn new(program: &'a Program, output: W) -> Self {
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function))
+            .collect();
+        let labels = program
+            .functions
+            .iter()
+            .map(|function| {
+                let labels = function
+                    .instrs
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, code)| match code {
+                        Code::Label { label, .. } => Some((label.as_str(), i)),
+                        Code::Instruction(_) => None,
+                    })
+                    .collect();
+                (function.name.as_str(), labels)
+            })
+            .collect();
+        Self {
+            functions,
+            labels,
+            heap: Heap::default(),
+            output,
+            dynamic_instructions: 0,
+            block_profile: None,
+            tracer: None,
+            debugger: None,
+            events: None,
+            depth: 0,
+            report_leaks: false,
+        }
+    }
+
+    /// Starts recording a [`Trace`] of up to `max_blocks` blocks from the
+    /// start of the outermost call to `main`.
+    pub fn enable_tracing(&mut self, max_blocks: usize) {
+        self.tracer = Some(Tracer::new(max_blocks));
+    }
+
+    /// The trace recorded since [`Interpreter::enable_tracing`], unless no
+    /// block at the start of `main` could be traced.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.tracer.take()?.finish()
+    }
+
+    /// Stops at the breakpoints of `debugger` and lets it inspect the program
+    /// there.
+    pub fn enable_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Writes a line of JSON to `events` before each instruction executes,
+    /// with the call depth, starting at 1 for `main`, the function, the label
+    /// of the block, or `null` for an unlabeled entry block, and the
+    /// instruction. Branches also have whether they go to their first label
+    /// as `taken`.
+    pub fn enable_event_log(&mut self, events: impl Write + 'static) {
+        self.events = Some(Box::new(events));
+    }
+
+    /// Starts counting how many times each block executes, where blocks begin
+    /// at labels and at the start of each function.
+    pub fn enable_block_profile(&mut self) {
+        self.block_profile.get_or_insert_with(Profile::default);
+    }
+
+    /// The block counts so far, if [`Interpreter::enable_block_profile`] was
+    /// called.
+    pub fn block_profile(&self) -> Option<&Profile> {
+        self.block_profile.as_ref()
+    }
+
+    /// Makes [`Interpreter::run_main`] list where each leaked allocation was
+    /// made rather than only how many there were.
+    pub fn enable_leak_report(&mut self) {
+        self.report_leaks = true;
+    }
+
+    /// The allocations that have not been freed so far.
+    pub fn leaks(&self) -> Vec<Leak<'a>> {
+        self.heap.leaks()
+    }
+
+    /// Makes memory errors say where the allocation involved was made and
+    /// [`Interpreter::run_main`] list where each leaked allocation was made.
+    pub fn enable_sanitizer(&mut self) {
+        self.heap.enable_sanitizer();
+        self.report_leaks = true;
+    }
+
+    /// The number of instructions executed so far, not counting labels.
+    pub fn dynamic_instructions(&self) -> u64 {
+        self.dynamic_instructions
+    }
+
+    /// Runs `main` with `arguments` parsed according to its parameter types
+    /// and checks that every allocation was freed.
+    pub fn run_main(&mut self, arguments: &[String]) -> Result<(), Whatever> {
+        let main = *self
+            .functions
+            .get("main")
+            .whatever_context("The program has no `main` function")?;
+        if arguments.len() != main.args.len() {
+            whatever!(
+                "`main` takes {} arguments but got {}",
+                main.args.len(),
+                arguments.len()
+            );
+        }
+        let arguments = main
+            .args
+            .iter()
+            .zip(arguments)
+            .map(|(parameter, argument)| {
+                Value::parse(argument, &parameter.arg_type)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.call("main", arguments)?;
+        if let Some(events) = &mut self.events {
+            events
+                .flush()
+                .whatever_context("Failed to write the event log")?;
+        }
+        self.heap.check_freed(self.report_leaks)
+    }
+
+    /// Calls the function `name` with `arguments` and returns what it returns.
+    pub fn call(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Option<Value>, Whatever> {
+        let function = *self
+            .functions
+            .get(name)
+            .whatever_context(format!("Undefined function `{name}`"))?;
+        if arguments.len() != function.args.len() {
+            whatever!(
+                "`{}` takes {} arguments but got {}",
+                name,
+                function.args.len(),
+                arguments.len()
+            );
+        }
+
+        let mut frame = Frame::default();
+        for (parameter, argument) in function.args.iter().zip(arguments) {
+            frame.variables.insert(&parameter.name, argument);
+        }
+
+        self.depth += 1;
+        let result = self.run(function, frame);
+        self.depth -= 1;
+        result
+    }
+
+    fn run(
+        &mut self,
+        function: &'a Function,
+        mut frame: Frame<'a>,
+    ) -> Result<Option<Value>, Whatever> {
+        let tracing = self.depth == 1 && function.name == "main";
+        if let (Some(tracer), Some(Code::Instruction(_)) | None) = (
+            self.tracer.as_mut().filter(|_| tracing),
+            function.instrs.first(),
+        ) {
+            tracer.enter_block(None, &function.instrs, 0);
+        }
+
+        // a labeled entry block is counted when its label is reached below
+        if let (Some(profile), Some(Code::Instruction(_)) | None) =
+            (&mut self.block_profile, function.instrs.first())
+        {
+            profile.record(&function.name, "-");
+        }
+
+        let mut block = None;
+        let mut at_breakpoint =
+            self.debugger.as_ref().is_some_and(|debugger| {
+                debugger.breaks_at_function(&function.name)
+            });
+
+        let mut index = 0;
+        while let Some(code) = function.instrs.get(index) {
+            let instruction = match code {
+                Code::Instruction(instruction) => instruction,
+                Code::Label { label, .. } => {
+                    block = Some(label.as_str());
+                    if let Some(debugger) = &self.debugger {
+                        at_breakpoint |= debugger.breaks_at_label(label);
+                    }
+                    if let Some(profile) = &mut self.block_profile {
+                        profile.record(&function.name, label);
+                    }
+                    if let Some(tracer) =
+                        self.tracer.as_mut().filter(|_| tracing)
+                    {
+                        tracer.enter_block(
+                            Some(label.as_str()),
+                            &function.instrs,
+                            index + 1,
+                        );
+                    }
+                    index += 1;
+                    continue;
+                }
+            };
+            if let Some(debugger) = &mut self.debugger {
+                if at_breakpoint || debugger.is_stepping() {
+                    let location = Location {
+                        function: &function.name,
+                        block,
+                        instruction,
+                    };
+                    debugger.stop(&location, &frame.variables, &self.heap)?;
+                }
+            }
+            at_breakpoint = false;
+
+            let condition = match instruction {
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Branch,
+                    ..
+                } => frame.get(&args[0]).and_then(Value::as_bool).ok(),
+                _ => None,
+            };
+            if let Some(tracer) = self.tracer.as_mut().filter(|_| tracing) {
+                tracer.record(instruction, condition);
+            }
+            if let Some(events) = &mut self.events {
+                let mut event = json!({
+                    "depth": self.depth,
+                    "function": function.name,
+                    "block": block,
+                    "instruction": instruction,
+                });
+                if let Some(taken) = condition {
+                    event["taken"] = taken.into();
+                }
+                writeln!(events, "{event}")
+                    .whatever_context("Failed to write the event log")?;
+            }
+            self.dynamic_instructions += 1;
+            match self
+                .execute(function, &mut frame, instruction)
+                .whatever_context(format!("Error in `{}`", function.name))?
+            {
+                Flow::Next => index += 1,
+                Flow::Jump(label) => {
+                    index = *self.labels[function.name.as_str()]
+                        .get(label)
+                        .whatever_context(format!(
+                            "Undefined label `{label}` in `{}`",
+                            function.name
+                        ))?;
+                }
+                Flow::Return(value) => return Ok(value),
+            }
+        }
+        Ok(None)
+    }
+
+    fn execute(
+        &mut self,
+        function: &'a Function,
+        frame: &mut Frame<'a>,
+        instruction: &'a Instruction,
+    ) -> Result<Flow<'a>, Whatever> {
+        match instruction {
+            Instruction::Constant {
+                dest,
+                const_type,
+                value,
+                ..
+            } => {
+                frame
+                    .variables
+                    .insert(dest, Value::from_literal(value, const_type));
+            }
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                op,
+                ..
+            } => {
+                let value = match op {
+                    ValueOps::Get => *frame
+                        .shadow_variables
+                        .get(dest.as_str())
+                        .whatever_context(format!(
+                            "`get` of `{dest}` without a `set` before it"
+                        ))?,
+                    ValueOps::Undef => {
+                        frame.variables.remove(dest.as_str());
+                        return Ok(Flow::Next);
+                    }
+                    ValueOps::Call => {
+                        let arguments = args
+                            .iter()
+                            .map(|argument| frame.get(argument))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        self.call(&funcs[0], arguments)?.whatever_context(
+                            format!("`{}` did not return a value", funcs[0]),
+                        )?
+                    }
+                    ValueOps::Alloc => {
+                        let size = frame.get(&args[0])?.as_int()?;
+                        let site = AllocationSite {
+                            function: &function.name,
+                            instruction,
+                        };
+                        Value::Pointer(self.heap.allocate(size, site)?)
+                    }
+                    ValueOps::Load => {
+                        self.heap.load(frame.get(&args[0])?.as_pointer()?)?
+                    }
+                    _ => {
+                        let arguments = args
+                            .iter()
+                            .map(|argument| frame.get(argument))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        evaluate(op, &arguments)?
+                    }
+                };
+                frame.variables.insert(dest, value);
+            }
+            Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                ..
+            } => {
+                let arguments = args
+                    .iter()
+                    .map(|argument| frame.get(argument))
+                    .collect::<Result<Vec<_>, _>>();
+                match op {
+                    EffectOps::Jump => return Ok(Flow::Jump(&labels[0])),
+                    EffectOps::Branch => {
+                        let label = if arguments?[0].as_bool()? {
+                            &labels[0]
+                        } else {
+                            &labels[1]
+                        };
+                        return Ok(Flow::Jump(label));
+                    }
+                    EffectOps::Return => {
+                        return Ok(Flow::Return(arguments?.first().copied()));
+                    }
+                    EffectOps::Print => {
+                        let line = arguments?
+                            .iter()
+                            .map(Value::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        writeln!(self.output, "{line}")
+                            .whatever_context("Failed to print")?;
+                    }
+                    EffectOps::Nop => {}
+                    EffectOps::Call => {
+                        self.call(&funcs[0], arguments?)?;
+                    }
+                    EffectOps::Store => {
+                        let arguments = arguments?;
+                        self.heap
+                            .store(arguments[0].as_pointer()?, arguments[1])?;
+                    }
+                    EffectOps::Free => {
+                        self.heap.free(arguments?[0].as_pointer()?)?;
+                    }
+                    // the destination of a `set` is a shadow variable, which
+                    // may not have been assigned yet
+                    EffectOps::Set => {
+                        let value = frame.get(&args[1])?;
+                        frame.shadow_variables.insert(&args[0], value);
+                    }
+                    EffectOps::Speculate => {
+                        frame.speculations.push(frame.variables.clone());
+                    }
+                    EffectOps::Commit => {
+                        frame.speculations.pop().whatever_context(
+                            "`commit` outside of speculation",
+                        )?;
+                    }
+                    EffectOps::Guard => {
+                        if !arguments?[0].as_bool()? {
+                            frame.variables =
+                                frame.speculations.pop().whatever_context(
+                                    "`guard` outside of speculation",
+                                )?;
+                            return Ok(Flow::Jump(&labels[0]));
+                        }
+                    }
+                    _ => whatever!("Unsupported operation `{}`", op),
+                }
+            }
+        }
+        Ok(Flow::Next)
+    }
+}
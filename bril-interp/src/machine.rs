@@ -0,0 +1,254 @@
+use std::io::Write;
+
+use snafu::{OptionExt, ResultExt, Whatever, whatever};
+
+use crate::{
+    bytecode::{Bytecode, BytecodeFunction, Op, Register},
+    heap::{AllocationSite, Heap},
+    value::{Value, evaluate},
+};
+
+/// The registers of a function call. SSA `set` instructions write to a
+/// separate set of shadow registers, which `get` instructions read from.
+struct Frame<'a> {
+    function: &'a BytecodeFunction,
+    registers: Vec<Option<Value>>,
+    shadow_registers: Vec<Option<Value>>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(function: &'a BytecodeFunction, arguments: Vec<Value>) -> Self {
+        let mut registers = vec![None; function.registers.len()];
+        for (register, argument) in registers.iter_mut().zip(arguments) {
+            *register = Some(argument);
+        }
+        Self {
+            function,
+            registers,
+            shadow_registers: vec![],
+        }
+    }
+
+    fn get(&self, register: Register) -> Result<Value, Whatever> {
+        self.registers[register as usize].whatever_context(format!(
+            "Undefined variable `{}`",
+            self.function.registers[register as usize]
+        ))
+    }
+
+    fn set(&mut self, register: Register, value: Value) {
+        self.registers[register as usize] = Some(value);
+    }
+}
+
+/// Runs [`Bytecode`] with a dispatch loop, which is much faster than
+/// [`crate::Interpreter`] but can neither trace, profile blocks, nor
+/// speculate. Dynamic instructions are counted the same way.
+pub struct Machine<'a, W: Write> {
+    bytecode: &'a Bytecode,
+    heap: Heap<'a>,
+    output: W,
+    dynamic_instructions: u64,
+    /// Whether leaked allocations are listed when `main` returns.
+    report_leaks: bool,
+}
+
+impl<'a, W: Write> Machine<'a, W> {
+    /// Prepares to run `bytecode`, writing the output of its `print`
+    /// instructions to `output` as they run.
+    pub fn new(bytecode: &'a Bytecode, output: W) -> Self {
+        Self {
+            bytecode,
+            heap: Heap::default(),
+            output,
+            dynamic_instructions: 0,
+            report_leaks: false,
+        }
+    }
+
+    /// Makes [`Machine::run_main`] list where each leaked allocation was made
+    /// rather than only how many there were.
+    pub fn enable_leak_report(&mut self) {
+        self.report_leaks = true;
+    }
+
+    /// Makes memory errors say where the allocation involved was made and
+    /// [`Machine::run_main`] list where each leaked allocation was made.
+    pub fn enable_sanitizer(&mut self) {
+        self.heap.enable_sanitizer();
+        self.report_leaks = true;
+    }
+
+    /// The number of instructions executed so far, not counting labels.
+    pub fn dynamic_instructions(&self) -> u64 {
+        self.dynamic_instructions
+    }
+
+    /// Runs `main` with `arguments` parsed according to its parameter types
+    /// and checks that every allocation was freed.
+    pub fn run_main(&mut self, arguments: &[String]) -> Result<(), Whatever> {
+        let main = self
+            .bytecode
+            .function("main")
+            .whatever_context("The program has no `main` function")?;
+        let parameters = &self.bytecode.functions[main as usize].parameters;
+        if arguments.len() != parameters.len() {
+            whatever!(
+                "`main` takes {} arguments but got {}",
+                parameters.len(),
+                arguments.len()
+            );
+        }
+        let arguments = parameters
+            .iter()
+            .zip(arguments)
+            .map(|(parameter, argument)| Value::parse(argument, parameter))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.call(main, arguments)?;
+        self.heap.check_freed(self.report_leaks)
+    }
+
+    /// Calls the function at index `function` with `arguments` and returns
+    /// what it returns.
+    pub fn call(
+        &mut self,
+        function: u32,
+        arguments: Vec<Value>,
+    ) -> Result<Option<Value>, Whatever> {
+        let bytecode = self.bytecode;
+        let function = &bytecode.functions[function as usize];
+        if arguments.len() != function.parameters.len() {
+            whatever!(
+                "`{}` takes {} arguments but got {}",
+                function.name,
+                function.parameters.len(),
+                arguments.len()
+            );
+        }
+        self.run(Frame::new(function, arguments))
+            .whatever_context(format!("Error in `{}`", function.name))
+    }
+
+    fn run(&mut self, mut frame: Frame<'a>) -> Result<Option<Value>, Whatever> {
+        let function = frame.function;
+        let mut index = 0;
+        loop {
+            let op = &function.code[index];
+            index += 1;
+            if !matches!(op, Op::Goto { .. } | Op::End) {
+                self.dynamic_instructions += 1;
+            }
+            match *op {
+                Op::Const { dest, value } => frame.set(dest, value),
+                Op::Unary { op, dest, arg } => {
+                    let value = evaluate(&op, &[frame.get(arg)?])?;
+                    frame.set(dest, value);
+                }
+                Op::Binary { op, dest, lhs, rhs } => {
+                    let value =
+                        evaluate(&op, &[frame.get(lhs)?, frame.get(rhs)?])?;
+                    frame.set(dest, value);
+                }
+                Op::Call {
+                    dest,
+                    function: callee,
+                    arguments,
+                } => {
+                    let arguments = arguments
+                        .of(function)
+                        .iter()
+                        .map(|argument| frame.get(*argument))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let value = self.call(callee, arguments)?;
+                    if let Some(dest) = dest {
+                        let value = value.whatever_context(format!(
+                            "`{}` did not return a value",
+                            self.bytecode.functions[callee as usize].name
+                        ))?;
+                        frame.set(dest, value);
+                    }
+                }
+                Op::Alloc { dest, size, site } => {
+                    let size = frame.get(size)?.as_int()?;
+                    let site = AllocationSite {
+                        function: &function.name,
+                        instruction: &function.allocation_sites[site as usize],
+                    };
+                    let pointer = self.heap.allocate(size, site)?;
+                    frame.set(dest, Value::Pointer(pointer));
+                }
+                Op::Load { dest, pointer } => {
+                    let value =
+                        self.heap.load(frame.get(pointer)?.as_pointer()?)?;
+                    frame.set(dest, value);
+                }
+                Op::Store { pointer, value } => {
+                    self.heap.store(
+                        frame.get(pointer)?.as_pointer()?,
+                        frame.get(value)?,
+                    )?;
+                }
+                Op::Free { pointer } => {
+                    self.heap.free(frame.get(pointer)?.as_pointer()?)?;
+                }
+                Op::Print { arguments } => {
+                    let line = arguments
+                        .of(function)
+                        .iter()
+                        .map(|argument| {
+                            frame.get(*argument).map(|value| value.to_string())
+                        })
+                        .collect::<Result<Vec<_>, _>>()?
+                        .join(" ");
+                    writeln!(self.output, "{line}")
+                        .whatever_context("Failed to print")?;
+                }
+                Op::Nop => {}
+                Op::Jump { target } | Op::Goto { target } => {
+                    index = target as usize;
+                }
+                Op::Branch {
+                    condition,
+                    if_true,
+                    if_false,
+                } => {
+                    index = if frame.get(condition)?.as_bool()? {
+                        if_true
+                    } else {
+                        if_false
+                    } as usize;
+                }
+                Op::Return { value } => {
+                    return value.map(|value| frame.get(value)).transpose();
+                }
+                Op::Get { dest } => {
+                    let value = frame
+                        .shadow_registers
+                        .get(dest as usize)
+                        .copied()
+                        .flatten()
+                        .whatever_context(format!(
+                            "`get` of `{}` without a `set` before it",
+                            function.registers[dest as usize]
+                        ))?;
+                    frame.set(dest, value);
+                }
+                // the destination of a `set` is a shadow variable, which may
+                // not have been assigned yet
+                Op::Set { dest, value } => {
+                    let value = frame.get(value)?;
+                    if frame.shadow_registers.is_empty() {
+                        frame.shadow_registers =
+                            vec![None; function.registers.len()];
+                    }
+                    frame.shadow_registers[dest as usize] = Some(value);
+                }
+                Op::Undef { dest } => {
+                    frame.registers[dest as usize] = None;
+                }
+                Op::End => return Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use argh::FromArgs;
+use bril_interp::{Breakpoint, Bytecode, Debugger, Interpreter, Machine};
+use bril_rs::Program;
+use snafu::{ResultExt, Whatever, whatever};
+
+/// Interprets a Bril program read as JSON from standard input, like `brili`
+#[derive(FromArgs)]
+struct Opts {
+    /// print the number of dynamic instructions executed to standard error
+    #[argh(switch, short = 'p')]
+    profile: bool,
+
+    /// write how many times each block executed to the given file, in the
+    /// profile format `loop-opt --profile` reads
+    #[argh(option)]
+    profile_blocks: Option<PathBuf>,
+
+    /// compile the program to bytecode and run that instead, which is faster
+    /// but supports neither speculation nor `--profile-blocks`
+    #[argh(switch)]
+    bytecode: bool,
+
+    /// list where each allocation that was never freed was made
+    #[argh(switch)]
+    leaks: bool,
+
+    /// like `--leaks`, and also say where the allocation was made when a
+    /// pointer is out of bounds, freed, or never stored to: run `bril2json -p`
+    /// for positions
+    #[argh(switch)]
+    sanitize: bool,
+
+    /// read the program from the given file instead of standard input
+    #[argh(option)]
+    program: Option<PathBuf>,
+
+    /// stop before the first instruction and read debugger commands from
+    /// standard input, which requires `--program`: run `help` at the prompt
+    /// for the commands
+    #[argh(switch)]
+    debug: bool,
+
+    /// stop at `@function` or `.label` in any function, like `--debug` but
+    /// starting without stopping: may be repeated
+    #[argh(option, long = "break")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// write each instruction as it executes to the given file as a line of
+    /// JSON with the call depth, function, block label, and whether branches
+    /// were taken
+    #[argh(option)]
+    trace_events: Option<PathBuf>,
+
+    /// arguments to `main`: put them after `--` if any are negative
+    #[argh(positional)]
+    arguments: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+
+    let debugging = opts.debug || !opts.breakpoints.is_empty();
+    let program: Program = match &opts.program {
+        Some(path) => {
+            let file = File::open(path).whatever_context(format!(
+                "Failed to open {}",
+                path.to_string_lossy()
+            ))?;
+            serde_json::from_reader(BufReader::new(file)).whatever_context(
+                format!(
+                    "Failed to parse {} as a valid Bril program",
+                    path.to_string_lossy()
+                ),
+            )?
+        }
+        None if debugging => {
+            whatever!(
+                "The debugger reads commands from standard input, so pass the program with `--program`"
+            );
+        }
+        None => serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?,
+    };
+
+    if opts.bytecode {
+        if opts.profile_blocks.is_some() {
+            whatever!("`--profile-blocks` cannot be used with `--bytecode`");
+        }
+        if debugging || opts.trace_events.is_some() {
+            whatever!(
+                "Neither the debugger nor `--trace-events` can be used with `--bytecode`"
+            );
+        }
+        let bytecode = Bytecode::compile(&program)?;
+        let mut machine = Machine::new(&bytecode, io::stdout().lock());
+        if opts.leaks {
+            machine.enable_leak_report();
+        }
+        if opts.sanitize {
+            machine.enable_sanitizer();
+        }
+        machine.run_main(&opts.arguments)?;
+        if opts.profile {
+            eprintln!("total_dyn_inst: {}", machine.dynamic_instructions());
+        }
+        return Ok(());
+    }
+
+    let mut interpreter = Interpreter::new(&program, io::stdout().lock());
+    if opts.profile_blocks.is_some() {
+        interpreter.enable_block_profile();
+    }
+    if opts.leaks {
+        interpreter.enable_leak_report();
+    }
+    if opts.sanitize {
+        interpreter.enable_sanitizer();
+    }
+    if debugging {
+        interpreter.enable_debugger(Debugger::new(
+            opts.breakpoints.clone(),
+            opts.debug,
+            io::stdin().lock(),
+            io::stderr(),
+        ));
+    }
+    if let Some(path) = &opts.trace_events {
+        let file = File::create(path).whatever_context(format!(
+            "Failed to create {}",
+            path.to_string_lossy()
+        ))?;
+        interpreter.enable_event_log(BufWriter::new(file));
+    }
+    interpreter.run_main(&opts.arguments)?;
+
+    if opts.profile {
+        eprintln!("total_dyn_inst: {}", interpreter.dynamic_instructions());
+    }
+
+    if let (Some(path), Some(profile)) =
+        (&opts.profile_blocks, interpreter.block_profile())
+    {
+        fs::write(path, profile.to_string()).whatever_context(format!(
+            "Failed to write the block profile to {}",
+            path.to_string_lossy()
+        ))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use bril_rs::{Code, EffectOps, Instruction, ValueOps};
+
+/// One step of a trace through `main`.
+pub enum TraceStep {
+    Instruction(Instruction),
+    /// A branch on `condition`, which went to its first label if `taken`.
+    Branch {
+        condition: String,
+        taken: bool,
+    },
+}
+
+/// The instructions executed at the start of `main`, up to the block labeled
+/// `exit_label`, with jumps left out.
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    pub exit_label: String,
+}
+
+/// Whether `instruction` can be replayed speculatively, that is, it only
+/// assigns to a variable or transfers control within a function.
+fn can_trace(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Constant { .. } => true,
+        Instruction::Value { op, .. } => matches!(
+            op,
+            ValueOps::Add
+                | ValueOps::Sub
+                | ValueOps::Mul
+                | ValueOps::Div
+                | ValueOps::Eq
+                | ValueOps::Lt
+                | ValueOps::Gt
+                | ValueOps::Le
+                | ValueOps::Ge
+                | ValueOps::Not
+                | ValueOps::And
+                | ValueOps::Or
+                | ValueOps::Id
+                | ValueOps::Fadd
+                | ValueOps::Fsub
+                | ValueOps::Fmul
+                | ValueOps::Fdiv
+                | ValueOps::Feq
+                | ValueOps::Flt
+                | ValueOps::Fgt
+                | ValueOps::Fle
+                | ValueOps::Fge
+                | ValueOps::Ceq
+                | ValueOps::Clt
+                | ValueOps::Cgt
+                | ValueOps::Cle
+                | ValueOps::Cge
+                | ValueOps::Char2int
+                | ValueOps::Int2char
+                | ValueOps::PtrAdd
+        ),
+        Instruction::Effect { op, .. } => {
+            matches!(op, EffectOps::Jump | EffectOps::Branch | EffectOps::Nop)
+        }
+    }
+}
+
+/// Records a [`Trace`] block by block, stopping before the first block that
+/// cannot be traced or once it has `max_blocks` blocks.
+pub(crate) struct Tracer {
+    max_blocks: usize,
+    blocks: usize,
+    steps: Vec<TraceStep>,
+    /// Set once the trace ends, to the label it ends at, if any.
+    exit_label: Option<Option<String>>,
+}
+
+impl Tracer {
+    pub(crate) fn new(max_blocks: usize) -> Self {
+        Self {
+            max_blocks,
+            blocks: 0,
+            steps: vec![],
+            exit_label: None,
+        }
+    }
+
+    /// Called when control reaches the block starting at `start` in `instrs`,
+    /// which is labeled `label` unless it is the unlabeled entry block.
+    pub(crate) fn enter_block(
+        &mut self,
+        label: Option<&str>,
+        instrs: &[Code],
+        start: usize,
+    ) {
+        if self.exit_label.is_some() {
+            return;
+        }
+        let can_trace_block = instrs[start..]
+            .iter()
+            .map_while(|code| match code {
+                Code::Instruction(instruction) => Some(instruction),
+                Code::Label { .. } => None,
+            })
+            .all(can_trace);
+        if self.blocks == self.max_blocks || !can_trace_block {
+            self.exit_label = Some(label.map(str::to_string));
+        } else {
+            self.blocks += 1;
+        }
+    }
+
+    /// Called before `instruction` executes, where `condition` is the value of
+    /// the condition of a branch.
+    pub(crate) fn record(
+        &mut self,
+        instruction: &Instruction,
+        condition: Option<bool>,
+    ) {
+        if self.exit_label.is_some() {
+            return;
+        }
+        match (instruction, condition) {
+            (
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Branch,
+                    ..
+                },
+                Some(taken),
+            ) => self.steps.push(TraceStep::Branch {
+                condition: args[0].clone(),
+                taken,
+            }),
+            (
+                Instruction::Effect {
+                    op: EffectOps::Jump | EffectOps::Nop,
+                    ..
+                },
+                _,
+            ) => {}
+            _ => self.steps.push(TraceStep::Instruction(instruction.clone())),
+        }
+    }
+
+    /// The trace, if it ended at a label after at least one step.
+    pub(crate) fn finish(self) -> Option<Trace> {
+        let exit_label = self.exit_label??;
+        (!self.steps.is_empty()).then_some(Trace {
+            steps: self.steps,
+            exit_label,
+        })
+    }
+}
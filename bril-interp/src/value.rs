@@ -0,0 +1,197 @@
+use std::fmt;
+
+use bril_rs::{Literal, Type, ValueOps};
+use snafu::{OptionExt, ResultExt, Whatever, whatever};
+
+/// A location in the heap: an offset into the allocation with the given index.
+/// The offset may be out of bounds, which is only an error once the pointer is
+/// accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pointer {
+    pub allocation: usize,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+    Char(char),
+    Pointer(Pointer),
+}
+
+impl Value {
+    /// The value of a `const` instruction of type `const_type`. Float
+    /// constants can be written without a decimal point, in which case they
+    /// are parsed as integers.
+    pub fn from_literal(literal: &Literal, const_type: &Type) -> Self {
+        match (literal, const_type) {
+            (Literal::Int(value), Type::Float) => Self::Float(*value as f64),
+            (Literal::Int(value), _) => Self::Int(*value),
+            (Literal::Bool(value), _) => Self::Bool(*value),
+            (Literal::Float(value), _) => Self::Float(*value),
+            (Literal::Char(value), _) => Self::Char(*value),
+        }
+    }
+
+    /// Parses a command-line argument to `main` of type `argument_type`.
+    pub fn parse(
+        argument: &str,
+        argument_type: &Type,
+    ) -> Result<Self, Whatever> {
+        Ok(match argument_type {
+            Type::Int => Self::Int(argument.parse().whatever_context(
+                format!("Expected an int argument but got `{argument}`"),
+            )?),
+            Type::Bool => Self::Bool(argument.parse().whatever_context(
+                format!("Expected a bool argument but got `{argument}`"),
+            )?),
+            Type::Float => Self::Float(argument.parse().whatever_context(
+                format!("Expected a float argument but got `{argument}`"),
+            )?),
+            Type::Char => {
+                let mut chars = argument.chars();
+                let value = chars.next().filter(|_| chars.next().is_none());
+                Self::Char(value.whatever_context(format!(
+                    "Expected a char argument but got `{argument}`"
+                ))?)
+            }
+            Type::Pointer(_) => {
+                whatever!("Pointers cannot be passed as arguments to `main`")
+            }
+        })
+    }
+
+    pub fn as_int(self) -> Result<i64, Whatever> {
+        match self {
+            Self::Int(value) => Ok(value),
+            _ => whatever!("Expected an int but got {}", self),
+        }
+    }
+
+    pub fn as_bool(self) -> Result<bool, Whatever> {
+        match self {
+            Self::Bool(value) => Ok(value),
+            _ => whatever!("Expected a bool but got {}", self),
+        }
+    }
+
+    pub fn as_float(self) -> Result<f64, Whatever> {
+        match self {
+            Self::Float(value) => Ok(value),
+            _ => whatever!("Expected a float but got {}", self),
+        }
+    }
+
+    pub fn as_char(self) -> Result<char, Whatever> {
+        match self {
+            Self::Char(value) => Ok(value),
+            _ => whatever!("Expected a char but got {}", self),
+        }
+    }
+
+    pub fn as_pointer(self) -> Result<Pointer, Whatever> {
+        match self {
+            Self::Pointer(value) => Ok(value),
+            _ => whatever!("Expected a pointer but got {}", self),
+        }
+    }
+}
+
+/// Prints values the way `brili` does, so that outputs can be compared.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(value) => value.fmt(f),
+            Self::Bool(value) => value.fmt(f),
+            Self::Float(value) if value.is_nan() => "NaN".fmt(f),
+            Self::Float(value) if value.is_infinite() => {
+                if value.is_sign_positive() {
+                    "Infinity".fmt(f)
+                } else {
+                    "-Infinity".fmt(f)
+                }
+            }
+            Self::Float(value) => write!(f, "{value:.17}"),
+            Self::Char(value) => value.fmt(f),
+            Self::Pointer(Pointer { allocation, offset }) => {
+                write!(f, "ptr({allocation}, {offset})")
+            }
+        }
+    }
+}
+
+/// Evaluates the value operation `op`, which does not involve variables,
+/// memory, or control flow, on `arguments`.
+pub(crate) fn evaluate(
+    op: &ValueOps,
+    arguments: &[Value],
+) -> Result<Value, Whatever> {
+    let int = |i: usize| arguments[i].as_int();
+    let boolean = |i: usize| arguments[i].as_bool();
+    let float = |i: usize| arguments[i].as_float();
+    let character = |i: usize| arguments[i].as_char();
+
+    Ok(match op {
+        ValueOps::Id => arguments[0],
+
+        ValueOps::Add => Value::Int(int(0)?.wrapping_add(int(1)?)),
+        ValueOps::Sub => Value::Int(int(0)?.wrapping_sub(int(1)?)),
+        ValueOps::Mul => Value::Int(int(0)?.wrapping_mul(int(1)?)),
+        ValueOps::Div => {
+            let divisor = int(1)?;
+            if divisor == 0 {
+                whatever!("Division by zero");
+            }
+            Value::Int(int(0)?.wrapping_div(divisor))
+        }
+        ValueOps::Eq => Value::Bool(int(0)? == int(1)?),
+        ValueOps::Lt => Value::Bool(int(0)? < int(1)?),
+        ValueOps::Gt => Value::Bool(int(0)? > int(1)?),
+        ValueOps::Le => Value::Bool(int(0)? <= int(1)?),
+        ValueOps::Ge => Value::Bool(int(0)? >= int(1)?),
+
+        ValueOps::Not => Value::Bool(!boolean(0)?),
+        ValueOps::And => Value::Bool(boolean(0)? && boolean(1)?),
+        ValueOps::Or => Value::Bool(boolean(0)? || boolean(1)?),
+
+        ValueOps::Fadd => Value::Float(float(0)? + float(1)?),
+        ValueOps::Fsub => Value::Float(float(0)? - float(1)?),
+        ValueOps::Fmul => Value::Float(float(0)? * float(1)?),
+        ValueOps::Fdiv => Value::Float(float(0)? / float(1)?),
+        ValueOps::Feq => Value::Bool(float(0)? == float(1)?),
+        ValueOps::Flt => Value::Bool(float(0)? < float(1)?),
+        ValueOps::Fgt => Value::Bool(float(0)? > float(1)?),
+        ValueOps::Fle => Value::Bool(float(0)? <= float(1)?),
+        ValueOps::Fge => Value::Bool(float(0)? >= float(1)?),
+
+        ValueOps::Ceq => Value::Bool(character(0)? == character(1)?),
+        ValueOps::Clt => Value::Bool(character(0)? < character(1)?),
+        ValueOps::Cgt => Value::Bool(character(0)? > character(1)?),
+        ValueOps::Cle => Value::Bool(character(0)? <= character(1)?),
+        ValueOps::Cge => Value::Bool(character(0)? >= character(1)?),
+        ValueOps::Char2int => Value::Int(character(0)? as i64),
+        ValueOps::Int2char => {
+            let code_point = int(0)?;
+            Value::Char(
+                u32::try_from(code_point)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .whatever_context(format!(
+                        "{code_point} is not a valid Unicode code point"
+                    ))?,
+            )
+        }
+
+        ValueOps::PtrAdd => {
+            let pointer = arguments[0].as_pointer()?;
+            Value::Pointer(Pointer {
+                offset: pointer.offset.wrapping_add(int(1)?),
+                ..pointer
+            })
+        }
+
+        _ => whatever!("Unsupported operation `{}`", op),
+    })
+}
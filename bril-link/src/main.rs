@@ -0,0 +1,319 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use argh::FromArgs;
+use bril_rs::{Code, EffectOps, Instruction, Program, ValueOps};
+use bril_util::{call_graph::called_function, names::NameGenerator};
+use snafu::{OptionExt, ResultExt, Whatever, whatever};
+
+/// Links Bril modules, as JSON or as text converted with `bril2json`, and the
+/// modules they import into a single program without imports
+#[derive(FromArgs)]
+struct Opts {
+    /// give later functions with the same name as an earlier one a fresh name
+    /// instead of failing
+    #[argh(switch)]
+    rename_duplicates: bool,
+
+    /// print the linked program as JSON instead of text
+    #[argh(switch)]
+    json: bool,
+
+    /// input Bril files, where those ending in `.bril` are text: omit for a
+    /// JSON program on stdin
+    #[argh(positional)]
+    inputs: Vec<PathBuf>,
+}
+
+/// A program read by the linker, either named on the command line or imported
+/// by another module.
+struct Module {
+    /// How the module is referred to in errors.
+    name: String,
+    program: Program,
+    /// The index of the module each of `program.imports` refers to.
+    imports: Vec<usize>,
+    /// Whether the module was named on the command line, in which case all
+    /// its functions are linked, rather than only imported.
+    root: bool,
+}
+
+/// `path` as JSON, or, if it ends in `.bril`, as text converted by
+/// `bril2json`.
+fn read_program(path: &Path) -> Result<Program, Whatever> {
+    let contents = if path.extension().is_some_and(|ext| ext == "bril") {
+        let file = fs::File::open(path).whatever_context(format!(
+            "Failed to open {}",
+            path.to_string_lossy()
+        ))?;
+        let output = Command::new("bril2json")
+            .stdin(file)
+            .output()
+            .whatever_context("Failed to run bril2json: is it installed?")?;
+        if !output.status.success() {
+            whatever!(
+                "bril2json failed on {}:\n{}",
+                path.to_string_lossy(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        output.stdout
+    } else {
+        fs::read(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?
+    };
+    serde_json::from_slice(&contents).whatever_context(format!(
+        "Failed to parse {} as a valid Bril program",
+        path.to_string_lossy()
+    ))
+}
+
+/// Reads the modules in `inputs`, or on standard input if there are none, and
+/// every module they import, transitively. The modules in `inputs` come first,
+/// in order, and each file is read once however many times it is imported.
+fn load_modules(inputs: &[PathBuf]) -> Result<Vec<Module>, Whatever> {
+    let mut modules = vec![];
+    let mut indices = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    if inputs.is_empty() {
+        let program: Program = serde_json::from_reader(io::stdin())
+            .whatever_context(
+                "Failed to parse standard input as a valid Bril program",
+            )?;
+        modules.push(Module {
+            name: "standard input".into(),
+            program,
+            imports: vec![],
+            root: true,
+        });
+        // imports are relative to the working directory
+        queue.push_back((0, PathBuf::new()));
+    }
+    for input in inputs {
+        let path = fs::canonicalize(input).whatever_context(format!(
+            "Failed to find {}",
+            input.to_string_lossy()
+        ))?;
+        if indices.contains_key(&path) {
+            continue;
+        }
+        indices.insert(path.clone(), modules.len());
+        queue.push_back((modules.len(), parent(&path)));
+        modules.push(Module {
+            name: input.to_string_lossy().to_string(),
+            program: read_program(&path)?,
+            imports: vec![],
+            root: true,
+        });
+    }
+
+    while let Some((index, directory)) = queue.pop_front() {
+        // cloned since the imported modules are added to `modules`
+        let module_imports = modules[index].program.imports.clone();
+        let mut imports = vec![];
+        for import in &module_imports {
+            let path = fs::canonicalize(directory.join(&import.path))
+                .whatever_context(format!(
+                    "Failed to find {}, imported by {}",
+                    import.path.to_string_lossy(),
+                    modules[index].name
+                ))?;
+            let imported = match indices.get(&path) {
+                Some(imported) => *imported,
+                None => {
+                    let program = read_program(&path)?;
+                    indices.insert(path.clone(), modules.len());
+                    queue.push_back((modules.len(), parent(&path)));
+                    modules.push(Module {
+                        name: path.to_string_lossy().to_string(),
+                        program,
+                        imports: vec![],
+                        root: false,
+                    });
+                    modules.len() - 1
+                }
+            };
+            imports.push(imported);
+        }
+        modules[index].imports = imports;
+    }
+
+    Ok(modules)
+}
+
+fn parent(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// A function by the index of its module and its index in that module.
+type FunctionId = (usize, usize);
+
+/// The function called `callee` from `module`: one it defines, one it imports
+/// under that name, or otherwise the only function with that name in a module
+/// named on the command line.
+fn resolve(
+    modules: &[Module],
+    module: usize,
+    callee: &str,
+) -> Result<FunctionId, Whatever> {
+    let position = |module: usize, name: &str| {
+        modules[module]
+            .program
+            .functions
+            .iter()
+            .position(|function| function.name == name)
+    };
+
+    if let Some(function) = position(module, callee) {
+        return Ok((module, function));
+    }
+
+    for (import, imported_module) in modules[module]
+        .program
+        .imports
+        .iter()
+        .zip(&modules[module].imports)
+    {
+        for imported in &import.functions {
+            if imported.alias.as_deref().unwrap_or(&imported.name) != callee {
+                continue;
+            }
+            let function = position(*imported_module, &imported.name)
+                .whatever_context(format!(
+                    "{} imports @{} from {}, which does not define it",
+                    modules[module].name,
+                    imported.name,
+                    modules[*imported_module].name
+                ))?;
+            return Ok((*imported_module, function));
+        }
+    }
+
+    let candidates = modules
+        .iter()
+        .enumerate()
+        .filter(|(_, other)| other.root)
+        .filter_map(|(other, _)| Some((other, position(other, callee)?)))
+        .collect::<Vec<_>>();
+    match candidates.as_slice() {
+        [function] => Ok(*function),
+        [] => whatever!(
+            "@{} is called in {} but is never defined",
+            callee,
+            modules[module].name
+        ),
+        _ => whatever!(
+            "@{} is called in {} and defined in several modules: import it from one of them",
+            callee,
+            modules[module].name
+        ),
+    }
+}
+
+fn callee_mut(code: &mut Code) -> Option<&mut String> {
+    match code {
+        Code::Instruction(
+            Instruction::Value {
+                funcs,
+                op: ValueOps::Call,
+                ..
+            }
+            | Instruction::Effect {
+                funcs,
+                op: EffectOps::Call,
+                ..
+            },
+        ) => funcs.first_mut(),
+        _ => None,
+    }
+}
+
+/// Links every function of the modules named on the command line and the
+/// functions they call, transitively, into one program.
+fn link(
+    modules: &[Module],
+    rename_duplicates: bool,
+) -> Result<Program, Whatever> {
+    let mut worklist = modules
+        .iter()
+        .enumerate()
+        .filter(|(_, module)| module.root)
+        .flat_map(|(index, module)| {
+            (0..module.program.functions.len()).map(move |i| (index, i))
+        })
+        .collect::<Vec<_>>();
+    let mut linked = BTreeSet::new();
+    while let Some((module, function)) = worklist.pop() {
+        if !linked.insert((module, function)) {
+            continue;
+        }
+        for code in &modules[module].program.functions[function].instrs {
+            if let Some(callee) = called_function(code) {
+                worklist.push(resolve(modules, module, callee)?);
+            }
+        }
+    }
+
+    // functions keep their names unless an earlier module already used it
+    let mut names = HashMap::new();
+    let mut owners = HashMap::new();
+    let mut generator = NameGenerator::default();
+    for (module, function) in &linked {
+        let name = &modules[*module].program.functions[*function].name;
+        if let Some(owner) = owners.get(name.as_str()) {
+            if !rename_duplicates {
+                whatever!(
+                    "@{} is defined in both {} and {}: pass --rename-duplicates to rename the later one",
+                    name,
+                    modules[*owner].name,
+                    modules[*module].name
+                );
+            }
+        } else {
+            owners.insert(name.as_str(), *module);
+        }
+        names.insert((*module, *function), generator.fresh(name));
+    }
+
+    let mut functions = vec![];
+    for id @ (module, index) in &linked {
+        let mut function = modules[*module].program.functions[*index].clone();
+        function.name = names[id].clone();
+        for code in &mut function.instrs {
+            if let Some(callee) = callee_mut(code) {
+                *callee = names[&resolve(modules, *module, callee)?].clone();
+            }
+        }
+        functions.push(function);
+    }
+
+    Ok(Program {
+        functions,
+        imports: vec![],
+    })
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+
+    let modules = load_modules(&opts.inputs)?;
+    let program = link(&modules, opts.rename_duplicates)?;
+
+    if opts.json {
+        serde_json::to_writer_pretty(io::stdout(), &program)
+            .whatever_context("Failed to write the program as JSON")?;
+        println!();
+    } else {
+        print!("{}", program);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Times `bril-opt` on a synthetic program of many independent functions with
+//! an increasing number of threads, to show how optimizing functions in
+//! parallel scales. Run with `cargo bench -p bril-opt`.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+
+const FUNCTIONS: usize = 10_000;
+const PASSES: &str = "tdce,lvn,ssa,licm,from-ssa,tdce";
+
+fn constant(dest: &str, value: i64) -> Value {
+    json!({ "dest": dest, "op": "const", "type": "int", "value": value })
+}
+
+fn value(dest: &str, ty: &str, op: &str, args: &[&str]) -> Value {
+    json!({ "dest": dest, "op": op, "type": ty, "args": args })
+}
+
+/// A loop summing an invariant product `n` times, which gives every pass
+/// something to do.
+fn function(index: usize) -> Value {
+    json!({
+        "name": format!("f{index}"),
+        "args": [{ "name": "n", "type": "int" }],
+        "type": "int",
+        "instrs": [
+            constant("i", 0),
+            constant("acc", 0),
+            constant("one", 1),
+            constant("unused", index as i64),
+            { "label": "loop" },
+            value("cond", "bool", "lt", &["i", "n"]),
+            { "op": "br", "args": ["cond"], "labels": ["body", "done"] },
+            { "label": "body" },
+            value("product", "int", "mul", &["n", "one"]),
+            value("same", "int", "mul", &["one", "n"]),
+            value("acc", "int", "add", &["acc", "product"]),
+            value("acc", "int", "add", &["acc", "same"]),
+            value("i", "int", "add", &["i", "one"]),
+            { "op": "jmp", "labels": ["loop"] },
+            { "label": "done" },
+            { "op": "ret", "args": ["acc"] },
+        ],
+    })
+}
+
+fn program() -> Vec<u8> {
+    let mut functions = (0..FUNCTIONS).map(function).collect::<Vec<_>>();
+    functions.push(json!({
+        "name": "main",
+        "instrs": [
+            constant("x", 5),
+            {
+                "dest": "v", "op": "call", "type": "int",
+                "funcs": ["f0"], "args": ["x"],
+            },
+            { "op": "print", "args": ["v"] },
+        ],
+    }));
+    json!({ "functions": functions }).to_string().into_bytes()
+}
+
+fn run(program: &[u8], jobs: usize) -> Duration {
+    let start = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bril-opt"))
+        .args(["--json", "--passes", PASSES, "--jobs", &jobs.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to run bril-opt");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(program)
+        .expect("Failed to write the program to bril-opt");
+    let status = child.wait().expect("Failed to wait for bril-opt");
+    assert!(status.success(), "bril-opt failed with {status}");
+    start.elapsed()
+}
+
+fn main() {
+    let program = program();
+    let cpus = thread::available_parallelism().map_or(1, |cpus| cpus.get());
+
+    println!("{FUNCTIONS} functions through {PASSES}");
+    println!("{:>4}  {:>10}  {:>7}", "jobs", "seconds", "speedup");
+    let mut jobs = 1;
+    let mut baseline = None;
+    while jobs <= cpus {
+        // the fastest of a few runs is the least disturbed by other processes
+        let time = (0..3)
+            .map(|_| run(&program, jobs))
+            .min()
+            .expect("there is at least one run");
+        let baseline = *baseline.get_or_insert(time);
+        println!(
+            "{jobs:>4}  {:>10.3}  {:>6.2}x",
+            time.as_secs_f64(),
+            baseline.as_secs_f64() / time.as_secs_f64()
+        );
+        jobs *= 2;
+    }
+}
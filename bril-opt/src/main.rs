@@ -0,0 +1,280 @@
+use std::{cell::RefCell, env, io, mem, path::PathBuf, rc::Rc, sync::Arc};
+
+use argh::FromArgs;
+use bril_rs::Function;
+use bril_util::{
+    error::ToolError,
+    filter::{FunctionFilter, FunctionPattern},
+    input::read_program,
+};
+use build_cfg::{
+    disk_cache::DiskCache,
+    pass::{FunctionPass, PassManager, SkippedPass, UnsupportedPolicy},
+    timing::{CountingAllocator, PassTimings},
+};
+use jump_threading::JumpThreadingPass;
+use loop_opt::{licm::LicmPass, schedule::SchedulePass};
+use lvn::LvnPass;
+use purity::PuritySummaries;
+use rayon::prelude::*;
+use snafu::{OptionExt, ResultExt, Whatever};
+use ssa::{FromSsaPass, IntoSsaPass};
+use tdce::TdcePass;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs a pipeline of optimizations from each lesson on a Bril program read as
+/// JSON, e.g., from `bril2json`
+#[derive(FromArgs)]
+struct Opts {
+    /// comma-separated passes to run in order, e.g., `tdce,lvn,licm`
+    #[argh(option, default = "String::new()")]
+    passes: String,
+
+    /// print the optimized program as JSON instead of text
+    #[argh(switch)]
+    json: bool,
+
+    /// print the available passes and exit
+    #[argh(switch)]
+    list_passes: bool,
+
+    /// show the events of a pass at a level, e.g., `licm=debug`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+
+    /// directory in which to save expensive analyses, like purity summaries,
+    /// so later runs on unchanged functions reuse them: defaults to
+    /// `$BRIL_ANALYSIS_CACHE`, if set
+    #[argh(option)]
+    analysis_cache: Option<PathBuf>,
+
+    /// print the time and peak allocations of each pass and analysis on each
+    /// function to stderr: peak allocations are only accurate with `--jobs 1`
+    #[argh(switch)]
+    time_passes: bool,
+
+    /// leave a function unchanged by a pass that does not support the Bril
+    /// extensions it uses, instead of failing
+    #[argh(switch)]
+    skip_unsupported: bool,
+
+    /// only optimize functions matching this name, like `@main` or `@test_*`,
+    /// printing the rest unchanged: may be repeated, and omit for every
+    /// function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
+    /// optimize no functions, printing the program unchanged, e.g., to check
+    /// that reading and printing it round-trips
+    #[argh(switch)]
+    no_functions: bool,
+
+    /// number of threads optimizing functions in parallel: defaults to the
+    /// number of CPUs
+    #[argh(option)]
+    jobs: Option<usize>,
+
+    /// rerun the pipeline whenever the input file changes
+    #[argh(switch)]
+    watch: bool,
+
+    /// input Bril file: omit for stdin
+    #[argh(positional)]
+    input: Option<PathBuf>,
+}
+
+/// The passes, where those that handle calls use `summaries` to tell which
+/// calls have no side effects.
+fn available_passes(
+    summaries: &Arc<PuritySummaries>,
+) -> Vec<Box<dyn FunctionPass>> {
+    vec![
+        Box::new(TdcePass {
+            summaries: summaries.clone(),
+        }),
+        Box::new(LvnPass {
+            summaries: summaries.clone(),
+        }),
+        Box::new(IntoSsaPass),
+        Box::new(FromSsaPass),
+        Box::new(LicmPass {
+            summaries: summaries.clone(),
+        }),
+        Box::new(JumpThreadingPass),
+        Box::new(SchedulePass),
+    ]
+}
+
+fn parse_pipeline(
+    passes: &str,
+    summaries: &Arc<PuritySummaries>,
+) -> Result<PassManager, Whatever> {
+    let mut pass_manager = PassManager::default();
+    for name in passes.split(',').map(str::trim) {
+        if name.is_empty() {
+            continue;
+        }
+        let pass = available_passes(summaries)
+            .into_iter()
+            .find(|pass| pass.name() == name)
+            .whatever_context(format!(
+                "Unknown pass `{name}`: see --list-passes for the available passes"
+            ))?;
+        pass_manager.add_pass(pass);
+    }
+    Ok(pass_manager)
+}
+
+/// What every function is optimized with, shared between threads.
+struct Pipeline<'a> {
+    passes: &'a str,
+    summaries: Arc<PuritySummaries>,
+    analysis_cache: Option<Arc<DiskCache>>,
+    time_passes: bool,
+    unsupported: UnsupportedPolicy,
+    filter: FunctionFilter,
+}
+
+/// A function after running the pipeline on it.
+struct Optimized {
+    function: Function,
+    /// Empty unless timing passes.
+    timings: PassTimings,
+    skipped: Vec<SkippedPass>,
+}
+
+/// Optimizes `function` with its own pass manager, since passes and their
+/// analyses are not shared between threads, unless the pipeline's filter
+/// leaves it unchanged.
+fn optimize(
+    function: &Function,
+    pipeline: &Pipeline,
+) -> Result<Optimized, ToolError> {
+    if !pipeline.filter.selects(&function.name) {
+        return Ok(Optimized {
+            function: function.clone(),
+            timings: PassTimings::default(),
+            skipped: vec![],
+        });
+    }
+
+    let mut pass_manager =
+        parse_pipeline(pipeline.passes, &pipeline.summaries)?;
+    pass_manager.set_unsupported_policy(pipeline.unsupported);
+    if let Some(analysis_cache) = &pipeline.analysis_cache {
+        pass_manager.set_disk_cache(analysis_cache.clone());
+    }
+    let timings = Rc::new(RefCell::new(PassTimings::default()));
+    if pipeline.time_passes {
+        pass_manager.set_timings(timings.clone());
+    }
+
+    let mut cfg = build_cfg::build_cfg(function, true)?;
+    pass_manager.run(&mut cfg)?;
+    let timings = mem::take(&mut *timings.borrow_mut());
+    Ok(Optimized {
+        function: cfg.into_function(),
+        timings,
+        skipped: pass_manager.skipped().to_vec(),
+    })
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+    snafu::ensure_whatever!(
+        !opts.no_functions || opts.function.is_empty(),
+        "--no-functions cannot be combined with --function"
+    );
+
+    if opts.list_passes {
+        for pass in available_passes(&Arc::default()) {
+            println!("{}", pass.name());
+        }
+        return Ok(());
+    }
+
+    watch::run(opts.input.as_deref(), opts.watch, || {
+        let mut program = read_program(opts.input.as_deref())
+            .whatever_context("Failed to read the program")?;
+
+        let analysis_cache = opts
+            .analysis_cache
+            .clone()
+            .or_else(|| env::var_os("BRIL_ANALYSIS_CACHE").map(PathBuf::from))
+            .map(DiskCache::new)
+            .transpose()?
+            .map(Arc::new);
+
+        let summaries = Arc::new(match &analysis_cache {
+            Some(analysis_cache) => {
+                PuritySummaries::cached(&program, analysis_cache)
+            }
+            None => PuritySummaries::new(&program),
+        });
+        // reports an unknown pass even if there are no functions
+        parse_pipeline(&opts.passes, &summaries)?;
+        let pipeline = Pipeline {
+            passes: &opts.passes,
+            summaries,
+            analysis_cache,
+            time_passes: opts.time_passes,
+            unsupported: if opts.skip_unsupported {
+                UnsupportedPolicy::Skip
+            } else {
+                UnsupportedPolicy::Reject
+            },
+            filter: if opts.no_functions {
+                FunctionFilter::none()
+            } else {
+                FunctionFilter::new(opts.function.clone())
+            },
+        };
+
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(opts.jobs.unwrap_or(0))
+            .build()
+            .whatever_context("Failed to start the thread pool")?;
+        // collecting in parallel keeps the functions in order
+        let optimized = thread_pool
+            .install(|| {
+                program
+                    .functions
+                    .par_iter()
+                    .map(|function| optimize(function, &pipeline))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .whatever_context("Failed to optimize the program")?;
+        let mut timings = PassTimings::default();
+        program.functions = optimized
+            .into_iter()
+            .map(|optimized| {
+                for skipped in optimized.skipped {
+                    eprintln!(
+                        "Skipped {} on @{}, which uses {}",
+                        skipped.pass, skipped.function, skipped.missing
+                    );
+                }
+                timings.merge(optimized.timings);
+                optimized.function
+            })
+            .collect();
+        if opts.time_passes {
+            eprint!("{timings}");
+        }
+
+        if opts.json {
+            serde_json::to_writer_pretty(io::stdout(), &program)
+                .whatever_context("Failed to write the program as JSON")?;
+            println!();
+        } else {
+            print!("{}", program);
+        }
+
+        Ok(())
+    })
+}
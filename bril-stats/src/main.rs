@@ -0,0 +1,236 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use argh::FromArgs;
+use bril_rs::{Code, Function, Instruction, Program};
+use bril_util::output::{OutputFormat, print_envelope};
+use dominators::compute_dominators;
+use loop_opt::loops::{LoopForest, find_irreducible_edges};
+use serde::Serialize;
+use snafu::{ResultExt, Whatever, whatever};
+
+enum Format {
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = Whatever;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "json" => Self::Json,
+            "csv" => Self::Csv,
+            _ => whatever!("Unknown format '{}'", s),
+        })
+    }
+}
+
+/// Reports static metrics of Bril programs for evaluations
+#[derive(FromArgs)]
+struct Opts {
+    /// output format: `json` (the default) or `csv`
+    #[argh(option, default = "Format::Json")]
+    format: Format,
+
+    /// print the metrics of each program as `json` or `text`, overriding
+    /// `--format`
+    #[argh(option)]
+    output: Option<OutputFormat>,
+
+    /// input Bril files: omit for stdin
+    #[argh(positional)]
+    inputs: Vec<PathBuf>,
+}
+
+/// The metrics of one program, summed over its functions.
+#[derive(Serialize, Default)]
+struct Stats {
+    program: String,
+    functions: usize,
+    /// Functions in which every variable, including arguments, is assigned by
+    /// at most one instruction.
+    ssa_functions: usize,
+    /// Functions with a cycle that is not a natural loop.
+    irreducible_functions: usize,
+    /// Reachable blocks.
+    blocks: usize,
+    instructions: usize,
+    /// Natural loops, counting those sharing a header once.
+    loops: usize,
+    /// The number of instructions with each opcode.
+    opcodes: BTreeMap<String, usize>,
+    /// The number of reachable blocks in each number of nested loops.
+    loop_depths: BTreeMap<usize, usize>,
+}
+
+impl Stats {
+    fn add_function(&mut self, function: &Function) -> Result<(), Whatever> {
+        self.functions += 1;
+
+        let mut assignments = HashMap::<&str, usize>::new();
+        for argument in &function.args {
+            *assignments.entry(argument.name.as_str()).or_default() += 1;
+        }
+        for code in &function.instrs {
+            let Code::Instruction(instruction) = code else {
+                continue;
+            };
+            self.instructions += 1;
+            let opcode = match instruction {
+                Instruction::Constant { .. } => "const".to_string(),
+                Instruction::Value { op, .. } => op.to_string(),
+                Instruction::Effect { op, .. } => op.to_string(),
+            };
+            *self.opcodes.entry(opcode).or_default() += 1;
+            if let Instruction::Constant { dest, .. }
+            | Instruction::Value { dest, .. } = instruction
+            {
+                *assignments.entry(dest.as_str()).or_default() += 1;
+            }
+        }
+        if assignments.values().all(|count| *count <= 1) {
+            self.ssa_functions += 1;
+        }
+
+        let cfg =
+            build_cfg::build_cfg(function, true).whatever_context(format!(
+                "Failed to build control-flow graph for function `{}`",
+                function.name
+            ))?;
+        let dominators = compute_dominators(&cfg);
+        if !find_irreducible_edges(&cfg, &dominators).is_empty() {
+            self.irreducible_functions += 1;
+        }
+        let forest = LoopForest::find(&cfg, &dominators);
+        self.blocks += cfg.vertices.len();
+        self.loops += forest.loops.len();
+        for block_idx in cfg.vertices.keys() {
+            let depth = forest
+                .loops
+                .iter()
+                .filter(|natural_loop| natural_loop.body.contains(&block_idx))
+                .count();
+            *self.loop_depths.entry(depth).or_default() += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Each metric with its value, naming each entry of a histogram like
+    /// `opcode.add` or `loop_depth.1`.
+    fn metrics(&self) -> Vec<(String, usize)> {
+        let mut rows = vec![
+            ("functions".to_string(), self.functions),
+            ("ssa_functions".to_string(), self.ssa_functions),
+            (
+                "irreducible_functions".to_string(),
+                self.irreducible_functions,
+            ),
+            ("blocks".to_string(), self.blocks),
+            ("instructions".to_string(), self.instructions),
+            ("loops".to_string(), self.loops),
+        ];
+        rows.extend(
+            self.opcodes
+                .iter()
+                .map(|(opcode, count)| (format!("opcode.{opcode}"), *count)),
+        );
+        rows.extend(
+            self.loop_depths
+                .iter()
+                .map(|(depth, count)| (format!("loop_depth.{depth}"), *count)),
+        );
+        rows
+    }
+
+    /// The [`Stats::metrics`] as `program,metric,value` rows.
+    fn csv_rows(&self) -> Vec<String> {
+        self.metrics()
+            .into_iter()
+            .map(|(metric, value)| format!("{},{metric},{value}", self.program))
+            .collect()
+    }
+}
+
+fn stats(name: String, program: &Program) -> Result<Stats, Whatever> {
+    let mut stats = Stats {
+        program: name,
+        ..Default::default()
+    };
+    for function in &program.functions {
+        stats.add_function(function)?;
+    }
+    Ok(stats)
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+
+    let mut results = vec![];
+    if opts.inputs.is_empty() {
+        let program: Program = serde_json::from_reader(io::stdin())
+            .whatever_context(
+                "Failed to parse standard input as a valid Bril program",
+            )?;
+        results.push(stats("-".to_string(), &program)?);
+    }
+    for path in &opts.inputs {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        let program: Program = serde_json::from_str(&contents)
+            .whatever_context(
+                "Failed to parse input file as a valid Bril program",
+            )?;
+        results.push(stats(path.to_string_lossy().to_string(), &program)?);
+    }
+
+    match opts.output {
+        Some(OutputFormat::Json) => {
+            for stats in &results {
+                let results = serde_json::to_value(stats)
+                    .whatever_context("Failed to serialize the metrics")?;
+                print_envelope(env!("CARGO_PKG_NAME"), None, results);
+            }
+            return Ok(());
+        }
+        Some(OutputFormat::Text) => {
+            for stats in &results {
+                println!("{}", stats.program);
+                for (metric, value) in stats.metrics() {
+                    println!("  {metric}: {value}");
+                }
+            }
+            return Ok(());
+        }
+        Some(OutputFormat::Dot) => {
+            whatever!("The metrics of a program cannot be printed as a graph")
+        }
+        None => {}
+    }
+
+    match opts.format {
+        Format::Json => {
+            let json = serde_json::to_string_pretty(&results)
+                .whatever_context("Failed to serialize the metrics")?;
+            println!("{json}");
+        }
+        Format::Csv => {
+            println!("program,metric,value");
+            for stats in &results {
+                for row in stats.csv_rows() {
+                    println!("{row}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
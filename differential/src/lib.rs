@@ -0,0 +1,385 @@
+//! Differential tests against the reference Bril tools. Each benchmark is
+//! converted with `bril2json` and then
+//!
+//! - interpreted by `brili -p`, [`Interpreter`], and [`Machine`], whose
+//!   outputs and dynamic instruction counts must agree;
+//! - deserialized into a [`Program`], which must serialize back to the same
+//!   JSON;
+//! - printed as text, which `bril2json` must convert back to the same JSON.
+//!
+//! Programs are only read as text by `bril2json`, so the printer is the only
+//! textual part of the in-tree tools under test. When a check fails, the
+//! benchmark is shrunk to a smaller program on which it still fails.
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bril_interp::{Bytecode, Interpreter, Machine};
+use bril_rs::Program;
+use serde_json::Value;
+
+/// How long a reference tool may run before its result is discarded. The
+/// in-tree interpreters are only run on programs `brili` finished, so shrinking
+/// a benchmark into an infinite loop does not hang the tests.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether `tool` is an executable on the `PATH`.
+pub fn installed(tool: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|directory| directory.join(tool).is_file())
+    })
+}
+
+/// Every `.bril` file under `directory`, sorted.
+pub fn benchmarks(directory: &Path) -> Vec<PathBuf> {
+    let mut benchmarks = vec![];
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let entries = fs::read_dir(&directory).unwrap_or_else(|_| {
+            panic!("Failed to read {}", directory.to_string_lossy())
+        });
+        for entry in entries {
+            let path = entry.expect("Failed to read a directory entry").path();
+            if path.is_dir() {
+                directories.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "bril")
+            {
+                benchmarks.push(path);
+            }
+        }
+    }
+    benchmarks.sort();
+    benchmarks
+}
+
+/// A property the in-tree tools must share with the reference tools.
+#[derive(Debug, Clone, Copy)]
+pub enum Check {
+    /// [`Interpreter`] behaves like `brili -p`.
+    Interpreter,
+    /// [`Machine`] behaves like `brili -p`, for programs it can compile.
+    Bytecode,
+    /// Deserializing and serializing a program preserves its JSON.
+    Parser,
+    /// `bril2json` reads the text of a program back as the same JSON.
+    Printer,
+}
+
+impl Check {
+    pub const ALL: [Check; 4] = [
+        Self::Interpreter,
+        Self::Bytecode,
+        Self::Parser,
+        Self::Printer,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Interpreter => "interpreter",
+            Self::Bytecode => "bytecode",
+            Self::Parser => "parser",
+            Self::Printer => "printer",
+        }
+    }
+
+    /// How the in-tree tools diverge from the reference tools on the
+    /// program `json` when given `arguments`, if they do.
+    pub fn run(self, json: &Value, arguments: &[String]) -> Option<String> {
+        match self {
+            Self::Interpreter | Self::Bytecode => {
+                let program =
+                    serde_json::from_value::<Program>(json.clone()).ok()?;
+                let expected = brili(json, arguments)?;
+                let actual = match self {
+                    Self::Interpreter => interpret(&program, arguments),
+                    _ => interpret_bytecode(&program, arguments)?,
+                };
+                (actual != expected).then(|| {
+                    format!("expected {expected:?}\n  but got {actual:?}")
+                })
+            }
+            Self::Parser => {
+                let program = serde_json::from_value::<Program>(json.clone())
+                    .map_err(|error| error.to_string());
+                let actual = program.and_then(|program| {
+                    serde_json::to_value(program)
+                        .map_err(|error| error.to_string())
+                });
+                match actual.map(without_positions) {
+                    Ok(actual) => (actual != *json)
+                        .then(|| format!("serialized as {actual}")),
+                    Err(error) => Some(error),
+                }
+            }
+            Self::Printer => {
+                let program =
+                    serde_json::from_value::<Program>(json.clone()).ok()?;
+                let text = program.to_string();
+                let output =
+                    run(&mut Command::new("bril2json"), text.as_bytes())?;
+                if !output.status.success() {
+                    return Some(format!(
+                        "bril2json rejected\n{text}{}",
+                        String::from_utf8_lossy(&output.stderr)
+                    ));
+                }
+                let actual = serde_json::from_slice::<Value>(&output.stdout)
+                    .ok()
+                    .map(without_positions);
+                (actual.as_ref() != Some(json))
+                    .then(|| format!("printed as\n{text}"))
+            }
+        }
+    }
+}
+
+/// A check that failed on a benchmark.
+pub struct Divergence {
+    pub benchmark: PathBuf,
+    pub check: Check,
+    /// How the tools diverge on the reproducer.
+    pub detail: String,
+    /// The smallest program found on which the check still fails, as JSON.
+    pub reproducer: Value,
+}
+
+/// Runs every check on the benchmark at `path`, which must have been
+/// converted with `bril2json` successfully.
+pub fn check(path: &Path) -> Vec<Divergence> {
+    let source = fs::read_to_string(path).unwrap_or_else(|_| {
+        panic!("Failed to read the contents of {}", path.to_string_lossy())
+    });
+    let arguments: Vec<String> = source
+        .lines()
+        .find_map(|line| line.strip_prefix("# ARGS:"))
+        .map(|arguments| {
+            arguments.split_whitespace().map(str::to_string).collect()
+        })
+        .unwrap_or_default();
+    let output = run(&mut Command::new("bril2json"), source.as_bytes())
+        .filter(|output| output.status.success())
+        .unwrap_or_else(|| {
+            panic!("bril2json failed on {}", path.to_string_lossy())
+        });
+    let json = serde_json::from_slice::<Value>(&output.stdout)
+        .map(without_positions)
+        .expect("bril2json printed invalid JSON");
+
+    Check::ALL
+        .into_iter()
+        .filter(|check| check.run(&json, &arguments).is_some())
+        .map(|check| {
+            let reproducer = minimize(&json, |candidate| {
+                check.run(candidate, &arguments).is_some()
+            });
+            Divergence {
+                benchmark: path.to_path_buf(),
+                check,
+                detail: check
+                    .run(&reproducer, &arguments)
+                    .expect("The reproducer diverges"),
+                reproducer,
+            }
+        })
+        .collect()
+}
+
+/// The standard output of a run and its dynamic instruction count, if it
+/// succeeded.
+#[derive(Debug, PartialEq)]
+struct Run {
+    output: String,
+    dynamic_instructions: Option<u64>,
+}
+
+fn brili(json: &Value, arguments: &[String]) -> Option<Run> {
+    let input = serde_json::to_vec(json).expect("Failed to serialize program");
+    let output = run(Command::new("brili").arg("-p").args(arguments), &input)?;
+    let dynamic_instructions = output
+        .status
+        .success()
+        .then(|| {
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .find_map(|line| line.strip_prefix("total_dyn_inst: "))
+                .and_then(|count| count.trim().parse().ok())
+        })
+        .flatten();
+    Some(Run {
+        output: String::from_utf8_lossy(&output.stdout).into_owned(),
+        dynamic_instructions,
+    })
+}
+
+fn interpret(program: &Program, arguments: &[String]) -> Run {
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(program, &mut output);
+    let dynamic_instructions = interpreter
+        .run_main(arguments)
+        .ok()
+        .map(|_| interpreter.dynamic_instructions());
+    Run {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        dynamic_instructions,
+    }
+}
+
+/// Like [`interpret`], but `None` if `program` cannot be compiled to
+/// [`Bytecode`].
+fn interpret_bytecode(program: &Program, arguments: &[String]) -> Option<Run> {
+    let bytecode = Bytecode::compile(program).ok()?;
+    let mut output = vec![];
+    let mut machine = Machine::new(&bytecode, &mut output);
+    let dynamic_instructions = machine
+        .run_main(arguments)
+        .ok()
+        .map(|_| machine.dynamic_instructions());
+    Some(Run {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        dynamic_instructions,
+    })
+}
+
+/// Runs `command` with `input` on standard input, or returns `None` if it ran
+/// for longer than [`TIMEOUT`].
+fn run(command: &mut Command, input: &[u8]) -> Option<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn command");
+
+    // write and read concurrently so the command cannot block on a full pipe
+    let mut stdin = child.stdin.take().expect("Standard input is piped");
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let mut stdout = child.stdout.take().expect("Standard output is piped");
+    let stdout = thread::spawn(move || {
+        let mut output = vec![];
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let mut stderr = child.stderr.take().expect("Standard error is piped");
+    let stderr = thread::spawn(move || {
+        let mut output = vec![];
+        stderr.read_to_end(&mut output).map(|_| output)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("Failed to wait") {
+            break Some(status);
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    // the command may exit without reading all of its input
+    let _ = writer.join();
+    let stdout = stdout.join().ok()?.ok()?;
+    let stderr = stderr.join().ok()?.ok()?;
+    Some(Output {
+        status: status?,
+        stdout,
+        stderr,
+    })
+}
+
+/// `json` without the source positions `bril2json` may record.
+fn without_positions(mut json: Value) -> Value {
+    fn strip(json: &mut Value) {
+        match json {
+            Value::Object(object) => {
+                object.remove("pos");
+                object.remove("pos_end");
+                object.values_mut().for_each(strip);
+            }
+            Value::Array(array) => array.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+    strip(&mut json);
+    json
+}
+
+/// The number of instructions and labels in the program `json`.
+fn code_len(json: &Value) -> usize {
+    json["functions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|function| function["instrs"].as_array())
+        .map(Vec::len)
+        .sum()
+}
+
+/// `json` without the `len` instructions and labels starting at the `start`th
+/// one, counting across functions in order.
+fn without_code(json: &Value, start: usize, len: usize) -> Value {
+    let mut json = json.clone();
+    let mut index = 0;
+    if let Some(functions) = json["functions"].as_array_mut() {
+        for function in functions {
+            if let Some(instrs) = function["instrs"].as_array_mut() {
+                instrs.retain(|_| {
+                    index += 1;
+                    !(start..start + len).contains(&(index - 1))
+                });
+            }
+        }
+    }
+    json
+}
+
+/// Shrinks the program `json` while `diverges` holds, first by deleting
+/// functions other than `main` and then by deleting ever smaller runs of
+/// instructions and labels, as in delta debugging.
+pub fn minimize(json: &Value, diverges: impl Fn(&Value) -> bool) -> Value {
+    let mut json = json.clone();
+
+    let mut i = 0;
+    while i < json["functions"].as_array().map_or(0, Vec::len) {
+        if json["functions"][i]["name"] == "main" {
+            i += 1;
+            continue;
+        }
+        let mut candidate = json.clone();
+        if let Some(functions) = candidate["functions"].as_array_mut() {
+            functions.remove(i);
+        }
+        if diverges(&candidate) {
+            json = candidate;
+        } else {
+            i += 1;
+        }
+    }
+
+    let mut len = (code_len(&json) / 2).max(1);
+    loop {
+        let mut start = 0;
+        while start < code_len(&json) {
+            let candidate = without_code(&json, start, len);
+            if diverges(&candidate) {
+                json = candidate;
+            } else {
+                start += len;
+            }
+        }
+        if len == 1 {
+            break json;
+        }
+        len /= 2;
+    }
+}
@@ -0,0 +1,47 @@
+use std::{env, fs, path::PathBuf};
+
+use differential::{benchmarks, check, installed};
+
+/// Cross-checks the in-tree tools on every benchmark in `$BRIL_BENCHMARKS`,
+/// or `bril/benchmarks/` by default, writing a reproducer for each divergence
+/// to the target directory. Skipped unless `brili` and `bril2json` are
+/// installed.
+#[test]
+fn reference_tools() {
+    if !installed("brili") || !installed("bril2json") {
+        eprintln!("skipping: `brili` and `bril2json` are not both installed");
+        return;
+    }
+    let directory = env::var_os("BRIL_BENCHMARKS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../bril/benchmarks")
+        });
+    if !directory.is_dir() {
+        eprintln!("skipping: {} does not exist", directory.to_string_lossy());
+        return;
+    }
+
+    let reproducers = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let mut report = vec![];
+    for benchmark in benchmarks(&directory) {
+        for divergence in check(&benchmark) {
+            let stem = benchmark
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let path = reproducers
+                .join(format!("{stem}-{}.json", divergence.check.name()));
+            fs::write(&path, divergence.reproducer.to_string())
+                .expect("Failed to write reproducer");
+            report.push(format!(
+                "{}: {} diverges on {}\n  {}",
+                benchmark.to_string_lossy(),
+                divergence.check.name(),
+                path.to_string_lossy(),
+                divergence.detail
+            ));
+        }
+    }
+    assert!(report.is_empty(), "{}", report.join("\n"));
+}
@@ -0,0 +1,206 @@
+//! Golden-output regression tests for every lesson's binaries. Each program
+//! in `corpus/` is converted with `bril2json`, piped through each [`Tool`]
+//! like in the Turnt configurations, and compared against the snapshots in
+//! `tests/snapshots/`. After an intended change in behavior, review and accept
+//! the new outputs with `cargo insta test --review --package golden`.
+
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// A binary in the workspace and the arguments it is run with.
+pub struct Tool {
+    /// The name of the snapshots for this tool.
+    pub name: &'static str,
+    pub package: &'static str,
+    pub arguments: &'static [&'static str],
+}
+
+/// Every tool under test. Analyses are run once per mode and optimizations
+/// with their default settings.
+pub const TOOLS: &[Tool] = &[
+    Tool {
+        name: "build-cfg",
+        package: "build-cfg",
+        arguments: &["--mode", "passthrough"],
+    },
+    Tool {
+        name: "tdce",
+        package: "tdce",
+        arguments: &[],
+    },
+    Tool {
+        name: "lvn",
+        package: "lvn",
+        arguments: &[],
+    },
+    Tool {
+        name: "dataflow-def",
+        package: "dataflow",
+        arguments: &["--analysis", "def"],
+    },
+    Tool {
+        name: "dataflow-live",
+        package: "dataflow",
+        arguments: &["--analysis", "live"],
+    },
+    Tool {
+        name: "dataflow-const",
+        package: "dataflow",
+        arguments: &["--analysis", "const"],
+    },
+    Tool {
+        name: "lcm",
+        package: "lcm",
+        arguments: &[],
+    },
+    Tool {
+        name: "dominators-dom",
+        package: "dominators",
+        arguments: &["--algo", "dom"],
+    },
+    Tool {
+        name: "dominators-tree",
+        package: "dominators",
+        arguments: &["--algo", "tree"],
+    },
+    Tool {
+        name: "dominators-front",
+        package: "dominators",
+        arguments: &["--algo", "front"],
+    },
+    Tool {
+        name: "dominators-merge-sets",
+        package: "dominators",
+        arguments: &["--algo", "merge-sets"],
+    },
+    Tool {
+        name: "jump-threading",
+        package: "jump-threading",
+        arguments: &[],
+    },
+    Tool {
+        name: "into-ssa",
+        package: "ssa",
+        arguments: &["--into-ssa"],
+    },
+    Tool {
+        name: "loop-opt",
+        package: "loop-opt",
+        arguments: &[],
+    },
+    Tool {
+        name: "loop-opt-licm",
+        package: "loop-opt",
+        arguments: &["--stage", "1"],
+    },
+    Tool {
+        name: "loop-opt-interchange",
+        package: "loop-opt",
+        arguments: &["--stage", "7"],
+    },
+    Tool {
+        name: "global-dce",
+        package: "global-dce",
+        arguments: &[],
+    },
+    Tool {
+        name: "tco",
+        package: "tco",
+        arguments: &[],
+    },
+    Tool {
+        name: "refcount",
+        package: "refcount",
+        arguments: &[],
+    },
+    Tool {
+        name: "inline",
+        package: "inline",
+        arguments: &[],
+    },
+    Tool {
+        name: "specialize",
+        package: "specialize",
+        arguments: &[],
+    },
+    Tool {
+        name: "regalloc",
+        package: "regalloc",
+        arguments: &["-k", "4"],
+    },
+    Tool {
+        name: "bril-opt",
+        package: "bril-opt",
+        arguments: &["--passes", "tdce,lvn,licm,thread"],
+    },
+];
+
+/// The arguments to `main` given on a `# ARGS:` line of `program`, as in the
+/// Bril benchmarks.
+pub fn program_arguments(program: &Path) -> Vec<String> {
+    fs::read_to_string(program)
+        .expect("Failed to read program")
+        .lines()
+        .find_map(|line| line.strip_prefix("# ARGS:"))
+        .map(|arguments| {
+            arguments.split_whitespace().map(str::to_string).collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Runs `command` with `input` on standard input, returning its standard
+/// output followed by its standard error, if any, and its exit status, if it
+/// failed.
+fn run(command: &mut Command, input: &[u8]) -> String {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn command");
+    child
+        .stdin
+        .take()
+        .expect("Standard input is piped")
+        .write_all(input)
+        .expect("Failed to write standard input");
+    let output = child.wait_with_output().expect("Failed to wait on command");
+
+    let mut result = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.stderr.is_empty() {
+        result.push_str("--- stderr ---\n");
+        result.push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+    if !output.status.success() {
+        result.push_str(&format!("--- {} ---\n", output.status));
+    }
+    result
+}
+
+/// `program` as JSON, converted by `bril2json`.
+pub fn bril2json(program: &Path) -> Vec<u8> {
+    let output = Command::new("bril2json")
+        .stdin(fs::File::open(program).expect("Failed to open program"))
+        .output()
+        .expect("Failed to run bril2json: is it installed?");
+    assert!(
+        output.status.success(),
+        "bril2json failed on {}",
+        program.to_string_lossy()
+    );
+    output.stdout
+}
+
+/// Runs the binary of `package` through Cargo with `arguments` on `json`.
+pub fn run_package(package: &str, arguments: &[&str], json: &[u8]) -> String {
+    run(
+        Command::new(env!("CARGO"))
+            .args(["run", "--quiet", "--package", package, "--"])
+            .args(arguments),
+        json,
+    )
+}
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use golden::{TOOLS, bril2json, program_arguments, run_package};
+
+#[test]
+fn tools() {
+    for tool in TOOLS {
+        insta::glob!("../corpus/*.bril", |path| {
+            let output =
+                run_package(tool.package, tool.arguments, &bril2json(path));
+            insta::assert_snapshot!(tool.name, output);
+        });
+    }
+}
+
+#[test]
+fn interpreter() {
+    insta::glob!("../corpus/*.bril", |path| {
+        let arguments = program_arguments(path);
+        let arguments = ["--"]
+            .into_iter()
+            .chain(arguments.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        let output = run_package("bril-interp", &arguments, &bril2json(path));
+        insta::assert_snapshot!(output);
+    });
+}
+
+/// Building the CFG of each function and converting it back must not change
+/// its control flow.
+#[test]
+fn cfg_roundtrip() {
+    insta::glob!("../corpus/*.bril", |path| {
+        let output =
+            run_package("build-cfg", &["--mode", "check"], &bril2json(path));
+        assert!(
+            output.is_empty(),
+            "round trip check failed for {}:\n{output}",
+            path.to_string_lossy()
+        );
+    });
+}
+
+/// The merge set of each block must be the iterated dominance frontier of the
+/// block, since both describe where a definition in it needs a Phi node.
+#[test]
+fn merge_sets() {
+    insta::glob!("../corpus/*.bril", |path| {
+        let json = bril2json(path);
+        let frontiers = run_package("dominators", &["--algo", "front"], &json);
+        let merge_sets =
+            run_package("dominators", &["--algo", "merge-sets"], &json);
+        for (frontiers, merge_sets) in frontiers.lines().zip(merge_sets.lines())
+        {
+            let frontiers: BTreeMap<String, BTreeSet<String>> =
+                serde_json::from_str(frontiers)
+                    .expect("dominators prints JSON");
+            let merge_sets: BTreeMap<String, BTreeSet<String>> =
+                serde_json::from_str(merge_sets)
+                    .expect("dominators prints JSON");
+
+            let iterated = frontiers
+                .keys()
+                .map(|block| {
+                    let mut reached = BTreeSet::new();
+                    let mut worklist = vec![block];
+                    while let Some(block) = worklist.pop() {
+                        for frontier in
+                            frontiers.get(block).into_iter().flatten()
+                        {
+                            if reached.insert(frontier.clone()) {
+                                worklist.push(frontier);
+                            }
+                        }
+                    }
+                    (block.clone(), reached)
+                })
+                .collect::<BTreeMap<_, _>>();
+            assert_eq!(
+                iterated,
+                merge_sets,
+                "merge sets differ from iterated dominance frontiers for {}",
+                path.to_string_lossy()
+            );
+        }
+    });
+}
+
+/// Each process seeds hash-based containers differently, so any output that
+/// depends on their iteration order differs between two runs of a tool.
+#[test]
+fn deterministic() {
+    for tool in TOOLS {
+        insta::glob!("../corpus/*.bril", |path| {
+            let json = bril2json(path);
+            let first = run_package(tool.package, tool.arguments, &json);
+            let second = run_package(tool.package, tool.arguments, &json);
+            assert_eq!(
+                first,
+                second,
+                "{} printed different outputs for {}",
+                tool.name,
+                path.to_string_lossy()
+            );
+        });
+    }
+}
+
+/// `bril-opt` optimizes functions in parallel, which must not change the
+/// order they are printed in.
+#[test]
+fn parallel_bril_opt() {
+    let passes = "tdce,lvn,ssa,licm,from-ssa,thread";
+    insta::glob!("../corpus/*.bril", |path| {
+        let json = bril2json(path);
+        let serial = run_package(
+            "bril-opt",
+            &["--passes", passes, "--jobs", "1"],
+            &json,
+        );
+        let parallel = run_package(
+            "bril-opt",
+            &["--passes", passes, "--jobs", "4"],
+            &json,
+        );
+        assert_eq!(
+            serial,
+            parallel,
+            "bril-opt printed different outputs for {} in parallel",
+            path.to_string_lossy()
+        );
+    });
+}
+
+/// Functions that are not selected are printed unchanged, so selecting none
+/// with `--no-functions` must be the same as running no passes.
+#[test]
+fn unselected_functions() {
+    let passes = "tdce,lvn,ssa,licm,from-ssa,thread";
+    insta::glob!("../corpus/*.bril", |path| {
+        let json = bril2json(path);
+        let unoptimized = run_package("bril-opt", &[], &json);
+        let unselected = run_package(
+            "bril-opt",
+            &["--passes", passes, "--no-functions"],
+            &json,
+        );
+        assert_eq!(
+            unoptimized,
+            unselected,
+            "bril-opt changed an unselected function in {}",
+            path.to_string_lossy()
+        );
+    });
+}
@@ -0,0 +1,64 @@
+use bril_rs::{Code, Function};
+use bril_util::{call_graph::CallGraph, profile::Profile};
+
+/// Decides which calls to inline. Calls within a strongly connected component
+/// of the call graph are never inlined, whatever the heuristic says.
+pub trait InlineHeuristic {
+    fn should_inline(
+        &self,
+        caller: &Function,
+        callee: &Function,
+        call_graph: &CallGraph,
+    ) -> bool;
+}
+
+/// The number of instructions in `function`, not counting labels.
+pub fn instruction_count(function: &Function) -> usize {
+    function
+        .instrs
+        .iter()
+        .filter(|code| matches!(code, Code::Instruction(_)))
+        .count()
+}
+
+/// Inlines every callee with at most `max_instructions` instructions.
+pub struct SizeThreshold {
+    pub max_instructions: usize,
+}
+
+impl InlineHeuristic for SizeThreshold {
+    fn should_inline(
+        &self,
+        _caller: &Function,
+        callee: &Function,
+        _call_graph: &CallGraph,
+    ) -> bool {
+        instruction_count(callee) <= self.max_instructions
+    }
+}
+
+/// Inlines callees accepted by `size` that were called at least `threshold`
+/// times in `profile`, going by how many times their entry block executed.
+pub struct HotCallees<'a> {
+    pub profile: &'a Profile,
+    pub threshold: u64,
+    pub size: SizeThreshold,
+}
+
+impl InlineHeuristic for HotCallees<'_> {
+    fn should_inline(
+        &self,
+        caller: &Function,
+        callee: &Function,
+        call_graph: &CallGraph,
+    ) -> bool {
+        let entry_label = match callee.instrs.first() {
+            Some(Code::Label { label, .. }) => label.as_str(),
+            _ => "-",
+        };
+        self.profile
+            .count(&callee.name, entry_label)
+            .is_some_and(|count| count >= self.threshold)
+            && self.size.should_inline(caller, callee, call_graph)
+    }
+}
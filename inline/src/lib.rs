@@ -0,0 +1,173 @@
+//! Inlines calls to small functions, renaming the variables and labels of
+//! each inlined body so that they cannot clash with those of the caller.
+
+use std::collections::{BTreeSet, HashMap};
+
+use bril_rs::{
+    Code, EffectOps, Function, Instruction, Program, Type, ValueOps,
+};
+use bril_util::{
+    InstructionExt,
+    call_graph::{CallGraph, called_function},
+    names::NameGenerator,
+};
+
+use crate::heuristic::InlineHeuristic;
+
+pub mod heuristic;
+
+fn rename_instruction(
+    instruction: &Instruction,
+    rename: &mut impl FnMut(&str) -> String,
+) -> Instruction {
+    let mut instruction = instruction.clone();
+    instruction.map_dest(|dest| *dest = rename(dest));
+    instruction.map_args(&mut |arg| *arg = rename(arg));
+    instruction.map_labels(&mut |label| *label = rename(label));
+    instruction
+}
+
+/// Appends the body of `callee` to `output` in place of `call`. Arguments are
+/// copied into the renamed parameters, and each `ret` copies its value into
+/// the destination of `call` and jumps to a new label after the body.
+fn inline_call(
+    call: &Instruction,
+    callee: &Function,
+    names: &mut NameGenerator,
+    output: &mut Vec<Code>,
+) {
+    let (args, destination) = match call {
+        Instruction::Value {
+            args,
+            dest,
+            op_type,
+            ..
+        } => (args, Some((dest, op_type))),
+        Instruction::Effect { args, .. } => (args, None),
+        Instruction::Constant { .. } => unreachable!("constants are not calls"),
+    };
+
+    let return_label = names.fresh(&format!("{}.return", callee.name));
+    let mut renamed = HashMap::<String, String>::new();
+    let mut rename = |name: &str| {
+        renamed
+            .entry(name.to_string())
+            .or_insert_with(|| names.fresh(&format!("{}.{name}", callee.name)))
+            .clone()
+    };
+    let copy = |dest: String, op_type: &Type, argument: String| {
+        Code::Instruction(Instruction::Value {
+            args: vec![argument],
+            dest,
+            funcs: vec![],
+            labels: vec![],
+            op: ValueOps::Id,
+            pos: None,
+            op_type: op_type.clone(),
+        })
+    };
+
+    for (parameter, argument) in callee.args.iter().zip(args) {
+        output.push(copy(
+            rename(&parameter.name),
+            &parameter.arg_type,
+            argument.clone(),
+        ));
+    }
+    for code in &callee.instrs {
+        match code {
+            Code::Label { label, pos } => output.push(Code::Label {
+                label: rename(label),
+                pos: pos.clone(),
+            }),
+            Code::Instruction(Instruction::Effect {
+                args,
+                op: EffectOps::Return,
+                pos,
+                ..
+            }) => {
+                if let (Some((dest, op_type)), [value]) =
+                    (destination, args.as_slice())
+                {
+                    output.push(copy(dest.clone(), op_type, rename(value)));
+                }
+                output.push(Code::Instruction(Instruction::Effect {
+                    args: vec![],
+                    funcs: vec![],
+                    labels: vec![return_label.clone()],
+                    op: EffectOps::Jump,
+                    pos: pos.clone(),
+                }));
+            }
+            Code::Instruction(instruction) => output.push(Code::Instruction(
+                rename_instruction(instruction, &mut rename),
+            )),
+        }
+    }
+    output.push(Code::Label {
+        label: return_label,
+        pos: None,
+    });
+}
+
+/// Inlines the calls in `program` that `heuristic` accepts, visiting the call
+/// graph bottom-up so that callees already have their own calls inlined.
+/// Calls between functions in the same strongly connected component are never
+/// inlined, which cuts off recursion. Returns the number of calls inlined.
+pub fn inline_calls(
+    program: &mut Program,
+    heuristic: &dyn InlineHeuristic,
+) -> usize {
+    let mut call_graph = CallGraph::new(program);
+    let indices = program
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, function)| (function.name.clone(), i))
+        .collect::<HashMap<_, _>>();
+
+    let mut total_inlined = 0;
+    for component in call_graph.strongly_connected_components() {
+        for caller_name in &component {
+            let caller = &program.functions[indices[caller_name]];
+            let callee_to_inline = |code: &Code| {
+                let callee = called_function(code)?;
+                if component.iter().any(|member| member == callee) {
+                    return None;
+                }
+                let callee = &program.functions[*indices.get(callee)?];
+                heuristic
+                    .should_inline(caller, callee, &call_graph)
+                    .then_some(callee)
+            };
+
+            let mut names = NameGenerator::new(caller);
+            let mut instrs = vec![];
+            let mut inlined_callees = BTreeSet::new();
+            for code in &caller.instrs {
+                match (code, callee_to_inline(code)) {
+                    (Code::Instruction(call), Some(callee)) => {
+                        inline_call(call, callee, &mut names, &mut instrs);
+                        inlined_callees.insert(callee.name.clone());
+                        total_inlined += 1;
+                    }
+                    _ => instrs.push(code.clone()),
+                }
+            }
+
+            for callee in &inlined_callees {
+                let still_calls_callee = instrs
+                    .iter()
+                    .filter_map(called_function)
+                    .any(|called| called == *callee);
+                call_graph.record_inlining(
+                    caller_name,
+                    callee,
+                    still_calls_callee,
+                );
+            }
+            program.functions[indices[caller_name]].instrs = instrs;
+        }
+    }
+    total_inlined
+}
@@ -0,0 +1,77 @@
+use std::{fs, io, path::PathBuf};
+
+use argh::FromArgs;
+use bril_rs::Program;
+use bril_util::profile::read_profile;
+use inline::{
+    heuristic::{HotCallees, InlineHeuristic, SizeThreshold},
+    inline_calls,
+};
+use snafu::{ResultExt, Whatever};
+
+/// Inlines calls to small functions
+#[derive(FromArgs)]
+struct Opts {
+    /// input Bril file: omit for stdin
+    #[argh(positional)]
+    input: Option<PathBuf>,
+
+    /// largest callee to inline, in instructions
+    #[argh(option, default = "20")]
+    max_instructions: usize,
+
+    /// profile from `bril-interp --profile-blocks` used to inline only hot
+    /// callees
+    #[argh(option)]
+    profile: Option<PathBuf>,
+
+    /// minimum number of times a callee must have been called in the profile
+    /// for calls to it to be inlined
+    #[argh(option, default = "1")]
+    hot_threshold: u64,
+
+    /// show the events of a pass at a level, e.g., `inline=info`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+
+    let mut program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        serde_json::from_str(&contents).whatever_context(
+            "Failed to parse input file as a valid Bril program",
+        )?
+    } else {
+        serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?
+    };
+
+    let size = SizeThreshold {
+        max_instructions: opts.max_instructions,
+    };
+    let profile = opts.profile.as_deref().map(read_profile).transpose()?;
+    let heuristic: Box<dyn InlineHeuristic> = match &profile {
+        Some(profile) => Box::new(HotCallees {
+            profile,
+            threshold: opts.hot_threshold,
+            size,
+        }),
+        None => Box::new(size),
+    };
+
+    let inlined = inline_calls(&mut program, heuristic.as_ref());
+    tracing::info!(target: "inline", "inlined {inlined} calls");
+
+    print!("{}", program);
+
+    Ok(())
+}
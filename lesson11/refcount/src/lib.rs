@@ -0,0 +1,451 @@
+//! Replaces manual `free`s with reference counting. Each pointer variable `p`
+//! gets two companion variables: `p.base`, the start of the allocation `p`
+//! points into, and `p.rc`, a one-cell allocation holding the number of
+//! variables referring to it. Copies retain a reference and the last use of
+//! each variable, found with liveness, releases it, freeing the allocation
+//! once no variable refers to it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    iter,
+};
+
+use bril_rs::{
+    Argument, Code, EffectOps, Function, Instruction, Program, Type, ValueOps,
+};
+use bril_util::{
+    InstructionExt,
+    builder::{
+        make_branch, make_call, make_const_int, make_effect, make_value,
+    },
+    names::NameGenerator,
+};
+use build_cfg::{BasicBlock, FunctionCfg, Label};
+use dataflow::live_variables::compute_live_variables;
+use snafu::{ResultExt, Whatever, whatever};
+use ssa::compute_definition_sites;
+
+const RETAIN: &str = "rc.retain";
+
+fn class_name(ty: &Type) -> String {
+    match ty {
+        Type::Pointer(pointee) => format!("ptr.{}", class_name(pointee)),
+        other => other.to_string(),
+    }
+}
+
+/// The function releasing a reference to an allocation of `pointee`s.
+fn release_name(pointee: &Type) -> String {
+    format!("rc.release.{}", class_name(pointee))
+}
+
+fn pointer(pointee: Type) -> Type {
+    Type::Pointer(Box::new(pointee))
+}
+
+/// `@rc.retain(count: ptr<int>)`, which increments `count`.
+fn retain_function() -> Function {
+    let instrs = vec![
+        make_const_int("one", 1),
+        make_value("n", Type::Int, ValueOps::Load, vec!["count".into()]),
+        make_value(
+            "n",
+            Type::Int,
+            ValueOps::Add,
+            vec!["n".into(), "one".into()],
+        ),
+        make_effect(EffectOps::Store, vec!["count".into(), "n".into()]),
+    ];
+    Function {
+        args: vec![Argument {
+            name: "count".into(),
+            arg_type: pointer(Type::Int),
+        }],
+        instrs: instrs.into_iter().map(Code::Instruction).collect(),
+        name: RETAIN.into(),
+        pos: None,
+        return_type: None,
+    }
+}
+
+/// `@rc.release.<pointee>(base: ptr<pointee>, count: ptr<int>)`, which
+/// decrements `count` and frees `base` and `count` once it reaches zero.
+fn release_function(pointee: &Type) -> Function {
+    let label = |label: &str| Code::Label {
+        label: label.into(),
+        pos: None,
+    };
+    let mut instrs = vec![
+        make_const_int("one", 1),
+        make_const_int("zero", 0),
+        make_value("n", Type::Int, ValueOps::Load, vec!["count".into()]),
+        make_value(
+            "n",
+            Type::Int,
+            ValueOps::Sub,
+            vec!["n".into(), "one".into()],
+        ),
+        make_effect(EffectOps::Store, vec!["count".into(), "n".into()]),
+        make_value(
+            "dead",
+            Type::Bool,
+            ValueOps::Eq,
+            vec!["n".into(), "zero".into()],
+        ),
+        make_branch("dead", "free", "done"),
+    ]
+    .into_iter()
+    .map(Code::Instruction)
+    .collect::<Vec<_>>();
+    instrs.push(label("free"));
+    instrs.push(Code::Instruction(make_effect(
+        EffectOps::Free,
+        vec!["base".into()],
+    )));
+    instrs.push(Code::Instruction(make_effect(
+        EffectOps::Free,
+        vec!["count".into()],
+    )));
+    instrs.push(label("done"));
+    Function {
+        args: vec![
+            Argument {
+                name: "base".into(),
+                arg_type: pointer(pointee.clone()),
+            },
+            Argument {
+                name: "count".into(),
+                arg_type: pointer(Type::Int),
+            },
+        ],
+        instrs,
+        name: release_name(pointee),
+        pos: None,
+        return_type: None,
+    }
+}
+
+/// The pointee type of each pointer variable in `cfg`.
+fn pointer_variables(cfg: &FunctionCfg) -> BTreeMap<String, Type> {
+    compute_definition_sites(cfg)
+        .0
+        .into_iter()
+        .map(|(variable, (ty, _))| (variable, ty))
+        .chain(
+            cfg.signature.arguments.iter().map(|argument| {
+                (argument.name.clone(), argument.arg_type.clone())
+            }),
+        )
+        .filter_map(|(variable, ty)| match ty {
+            Type::Pointer(pointee) => Some((variable, *pointee)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a pointer in `pointers` can be reached other than through a
+/// variable of the function, since the transformation only counts references
+/// held by variables. Pointers may only be made by `alloc`, copied by `id`
+/// and `ptradd`, and used by `load`, `store` as the address, `free`, and
+/// `print`.
+fn pointers_escape(
+    cfg: &FunctionCfg,
+    pointers: &BTreeMap<String, Type>,
+) -> bool {
+    let is_pointer = |variable: &String| pointers.contains_key(variable);
+    if cfg
+        .signature
+        .arguments
+        .iter()
+        .any(|argument| matches!(argument.arg_type, Type::Pointer(_)))
+    {
+        return true;
+    }
+    cfg.vertices
+        .values()
+        .flat_map(|block| &block.instructions)
+        .any(|instruction| match instruction {
+            Instruction::Constant { .. } => false,
+            Instruction::Value {
+                op: ValueOps::Alloc | ValueOps::Id | ValueOps::PtrAdd,
+                ..
+            } => false,
+            Instruction::Value {
+                op: ValueOps::Load,
+                op_type,
+                ..
+            } => matches!(op_type, Type::Pointer(_)),
+            Instruction::Value { args, op_type, .. } => {
+                matches!(op_type, Type::Pointer(_))
+                    || args.iter().any(is_pointer)
+            }
+            Instruction::Effect {
+                op: EffectOps::Free | EffectOps::Print,
+                ..
+            } => false,
+            Instruction::Effect {
+                op: EffectOps::Store,
+                args,
+                ..
+            } => args.get(1).is_some_and(is_pointer),
+            Instruction::Effect { args, .. } => args.iter().any(is_pointer),
+        })
+}
+
+/// Names of the companion variables of a pointer variable.
+struct Companions {
+    base: String,
+    count: String,
+}
+
+/// Releases the references held by `variables`.
+fn releases<'a>(
+    variables: impl IntoIterator<Item = &'a String>,
+    pointers: &BTreeMap<String, Type>,
+    companions: &BTreeMap<String, Companions>,
+) -> Vec<Instruction> {
+    variables
+        .into_iter()
+        .map(|variable| {
+            let companions = &companions[variable];
+            make_call(
+                release_name(&pointers[variable]),
+                vec![companions.base.clone(), companions.count.clone()],
+            )
+        })
+        .collect()
+}
+
+/// Rewrites `function` to count references, returning whether it could. The
+/// pointee types of the releases it calls are added to `pointees`, keyed by
+/// the names of their releases.
+///
+/// Requires: `function` is not in SSA form.
+pub fn count_references(
+    function: &mut Function,
+    pointees: &mut BTreeMap<String, Type>,
+) -> Result<bool, Whatever> {
+    let mut names = NameGenerator::new(function);
+    let mut cfg = build_cfg::build_cfg(function, true)
+        .whatever_context("Failed to build cfg")?;
+    let pointers = pointer_variables(&cfg);
+    if pointers.is_empty() {
+        return Ok(true);
+    }
+    if pointers_escape(&cfg, &pointers) {
+        return Ok(false);
+    }
+    cfg.make_fallthroughs_explicit();
+
+    // the variables now free their allocations when their references die
+    for block in cfg.vertices.values_mut() {
+        block.instructions.retain(|instruction| {
+            !matches!(
+                instruction,
+                Instruction::Effect {
+                    op: EffectOps::Free,
+                    ..
+                }
+            )
+        });
+    }
+
+    let companions = pointers
+        .keys()
+        .map(|variable| {
+            let companions = Companions {
+                base: names.fresh(&format!("{variable}.base")),
+                count: names.fresh(&format!("{variable}.rc")),
+            };
+            (variable.clone(), companions)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let one = names.fresh("rc.one");
+    pointees.extend(
+        pointers
+            .values()
+            .map(|pointee| (release_name(pointee), pointee.clone())),
+    );
+
+    let live_in = compute_live_variables(&cfg);
+    let live_out = cfg
+        .vertices
+        .keys()
+        .map(|block_idx| {
+            let live_out = cfg
+                .successors(block_idx)
+                .into_iter()
+                .flat_map(|successor| &live_in[successor])
+                .map(|variable| cfg.symbols.name(*variable).to_string())
+                .filter(|variable| pointers.contains_key(variable))
+                .collect::<BTreeSet<_>>();
+            (block_idx, live_out)
+        })
+        .collect::<Vec<_>>();
+
+    let mut edge_releases = vec![];
+    for (block_idx, block_live_out) in live_out {
+        for successor in cfg.successors(block_idx) {
+            let dying = block_live_out
+                .iter()
+                .filter(|variable| {
+                    !live_in[successor].contains(&cfg.symbols.intern(variable))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if !dying.is_empty() {
+                edge_releases.push((block_idx, successor, dying));
+            }
+        }
+
+        // the variables live after each instruction, from last to first
+        let instructions = &cfg.vertices[block_idx].instructions;
+        let mut live = block_live_out;
+        let mut live_after = vec![];
+        for instruction in instructions.iter().rev() {
+            live_after.push(live.clone());
+            if let Some(kill) = instruction.kill() {
+                live.remove(kill);
+            }
+            live.extend(
+                instruction
+                    .gen_set()
+                    .iter()
+                    .filter(|variable| pointers.contains_key(*variable))
+                    .cloned(),
+            );
+        }
+        live_after.reverse();
+
+        let mut rewritten = vec![];
+        for (instruction, live) in instructions.iter().zip(live_after) {
+            rewritten.push(instruction.clone());
+            match instruction {
+                Instruction::Value {
+                    op: ValueOps::Alloc,
+                    dest,
+                    ..
+                } => {
+                    let companions = &companions[dest];
+                    rewritten.push(make_value(
+                        &companions.base,
+                        pointer(pointers[dest].clone()),
+                        ValueOps::Id,
+                        vec![dest.clone()],
+                    ));
+                    rewritten.push(make_value(
+                        &companions.count,
+                        pointer(Type::Int),
+                        ValueOps::Alloc,
+                        vec![one.clone()],
+                    ));
+                    rewritten.push(make_effect(
+                        EffectOps::Store,
+                        vec![companions.count.clone(), one.clone()],
+                    ));
+                }
+                // copying a pointer into itself holds the same reference
+                Instruction::Value {
+                    op: ValueOps::Id | ValueOps::PtrAdd,
+                    dest,
+                    args,
+                    ..
+                } if pointers.contains_key(dest) && args[0] != *dest => {
+                    let (to, from) = (&companions[dest], &companions[&args[0]]);
+                    rewritten.push(make_value(
+                        &to.base,
+                        pointer(pointers[dest].clone()),
+                        ValueOps::Id,
+                        vec![from.base.clone()],
+                    ));
+                    rewritten.push(make_value(
+                        &to.count,
+                        pointer(Type::Int),
+                        ValueOps::Id,
+                        vec![from.count.clone()],
+                    ));
+                    rewritten.push(make_call(RETAIN, vec![to.count.clone()]));
+                }
+                _ => {}
+            }
+
+            let dying = instruction
+                .gen_set()
+                .iter()
+                .chain(instruction.kill())
+                .filter(|variable| {
+                    pointers.contains_key(*variable)
+                        && !live.contains(*variable)
+                })
+                .collect::<BTreeSet<_>>();
+            rewritten.extend(releases(dying, &pointers, &companions));
+        }
+        cfg.vertices[block_idx].instructions = rewritten.into();
+    }
+
+    for (block_idx, successor, dying) in edge_releases {
+        let releases = releases(&dying, &pointers, &companions);
+        if cfg.predecessors(successor).len() == 1 {
+            cfg.vertices[successor]
+                .instructions
+                .insert_many(0, releases);
+        } else {
+            let label = names.fresh(&format!(
+                "{}.release",
+                cfg.vertices[successor]
+                    .label
+                    .as_ref()
+                    .expect("all but entry must have labels")
+                    .name
+            ));
+            let release_block = cfg.add_block(BasicBlock {
+                label: Some(Label { name: label }),
+                instructions: releases.into(),
+                ..Default::default()
+            });
+            cfg.reorient_edge(block_idx, successor, release_block);
+            cfg.set_unconditional_edge(release_block, successor);
+        }
+    }
+
+    cfg.vertices[cfg.entry]
+        .instructions
+        .insert(0, make_const_int(&one, 1));
+    *function = cfg.into_function();
+    Ok(true)
+}
+
+/// Rewrites every function of `program` whose pointers do not escape to count
+/// references and adds the functions they call, returning the names of the
+/// functions left unchanged.
+pub fn count_program_references(
+    program: &mut Program,
+) -> Result<Vec<String>, Whatever> {
+    let mut pointees = BTreeMap::new();
+    let mut unchanged = vec![];
+    for function in &mut program.functions {
+        if !count_references(function, &mut pointees)? {
+            unchanged.push(function.name.clone());
+        }
+    }
+
+    if pointees.is_empty() {
+        return Ok(unchanged);
+    }
+    let helpers = iter::once(retain_function())
+        .chain(pointees.values().map(release_function))
+        .collect::<Vec<_>>();
+    let used = program
+        .functions
+        .iter()
+        .map(|function| function.name.as_str())
+        .collect::<HashSet<_>>();
+    if let Some(helper) = helpers
+        .iter()
+        .find(|helper| used.contains(helper.name.as_str()))
+    {
+        whatever!("The program already has a function named `{}`", helper.name);
+    }
+    program.functions.extend(helpers);
+    Ok(unchanged)
+}
@@ -0,0 +1,50 @@
+use std::{fs, io, path::PathBuf};
+
+use argh::FromArgs;
+use bril_rs::Program;
+use refcount::count_program_references;
+use snafu::{ResultExt, Whatever};
+
+/// Replaces `free`s with reference counting
+#[derive(FromArgs)]
+struct Opts {
+    /// input Bril file: omit for stdin
+    #[argh(positional)]
+    input: Option<PathBuf>,
+
+    /// show the events of a pass at a level, e.g., `refcount=warn`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+
+    let mut program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        serde_json::from_str(&contents).whatever_context(
+            "Failed to parse input file as a valid Bril program",
+        )?
+    } else {
+        serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?
+    };
+
+    for name in count_program_references(&mut program)? {
+        tracing::warn!(
+            target: "refcount",
+            "left @{name} unchanged since pointers escape it"
+        );
+    }
+
+    print!("{}", program);
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use bril_interp::{Trace, TraceStep};
+use bril_rs::{Code, EffectOps, Function, Type, ValueOps};
+use bril_util::{
+    builder::{make_effect, make_guard, make_jump, make_value},
+    names::NameGenerator,
+};
+use build_cfg::{BasicBlock, symbol::SymbolTable};
+use purity::PuritySummaries;
+
+/// Puts a speculative copy of `trace` at the start of `main`. The copy runs
+/// inside `speculate` and `commit` with each branch replaced by a `guard` on
+/// the direction it went, and is optimized with LVN and dead code elimination
+/// as a single block. If a guard fails, the original `main` runs from the
+/// start instead.
+pub fn insert_trace(main: &mut Function, trace: &Trace) {
+    let mut names = NameGenerator::new(main);
+    let bailout_label = names.fresh("trace.bailout");
+
+    let mut block = BasicBlock::default();
+    for step in &trace.steps {
+        match step {
+            TraceStep::Instruction(instruction) => {
+                block.instructions.push(instruction.clone());
+            }
+            TraceStep::Branch { condition, taken } => {
+                let condition = if *taken {
+                    condition.clone()
+                } else {
+                    let negated = names.fresh(&format!("{condition}.negated"));
+                    block.instructions.push(make_value(
+                        &negated,
+                        Type::Bool,
+                        ValueOps::Not,
+                        vec![condition.clone()],
+                    ));
+                    negated
+                };
+                block
+                    .instructions
+                    .push(make_guard(condition, &bailout_label));
+            }
+        }
+    }
+
+    // traces never contain calls
+    let summaries = PuritySummaries::default();
+    lvn::lvn(&mut block, &SymbolTable::default(), &summaries);
+    tdce::drop_killed_locals(&mut block, &summaries);
+
+    let mut instrs = vec![make_effect(EffectOps::Speculate, vec![])];
+    instrs.extend(block.instructions);
+    instrs.push(make_effect(EffectOps::Commit, vec![]));
+    instrs.push(make_jump(&trace.exit_label));
+    let mut instrs = instrs
+        .into_iter()
+        .map(Code::Instruction)
+        .collect::<Vec<_>>();
+    instrs.push(Code::Label {
+        label: bailout_label,
+        pos: None,
+    });
+    instrs.append(&mut main.instrs);
+    main.instrs = instrs;
+}
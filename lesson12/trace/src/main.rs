@@ -0,0 +1,86 @@
+use std::{fs, io, path::PathBuf};
+
+use argh::FromArgs;
+use bril_interp::Interpreter;
+use bril_rs::Program;
+use snafu::{OptionExt, ResultExt, Whatever};
+
+/// Runs a Bril program to record a trace from the start of `main`, then puts
+/// an optimized, speculative copy of the trace at the start of `main`
+#[derive(FromArgs)]
+struct Opts {
+    /// input Bril file: omit for stdin
+    #[argh(option)]
+    input: Option<PathBuf>,
+
+    /// most blocks to include in the trace
+    #[argh(option, default = "64")]
+    max_blocks: usize,
+
+    /// print the program as JSON instead of text
+    #[argh(switch)]
+    json: bool,
+
+    /// show the events of a pass at a level, e.g., `trace=warn`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+
+    /// arguments to `main` for the tracing run: put them after `--` if any
+    /// are negative
+    #[argh(positional)]
+    arguments: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+
+    let mut program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        serde_json::from_str(&contents).whatever_context(
+            "Failed to parse input file as a valid Bril program",
+        )?
+    } else {
+        serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?
+    };
+
+    let trace = {
+        let mut interpreter = Interpreter::new(&program, io::sink());
+        interpreter.enable_tracing(opts.max_blocks);
+        interpreter
+            .run_main(&opts.arguments)
+            .whatever_context("Failed to run the program to record a trace")?;
+        interpreter.take_trace()
+    };
+
+    if let Some(trace) = trace {
+        let main = program
+            .functions
+            .iter_mut()
+            .find(|function| function.name == "main")
+            .whatever_context("The program has no `main` function")?;
+        trace::insert_trace(main, &trace);
+    } else {
+        tracing::warn!(
+            target: "trace",
+            "no block at the start of `main` could be traced"
+        );
+    }
+
+    if opts.json {
+        serde_json::to_writer_pretty(io::stdout(), &program)
+            .whatever_context("Failed to write the program as JSON")?;
+        println!();
+    } else {
+        print!("{}", program);
+    }
+
+    Ok(())
+}
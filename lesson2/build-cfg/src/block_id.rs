@@ -0,0 +1,92 @@
+use std::fmt;
+
+use slotmap::SecondaryMap;
+
+use crate::{BasicBlockIdx, FunctionCfg};
+
+/// A dense number for a block, for output that should be readable and should
+/// not depend on how slotmap lays out its keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(u32);
+
+impl BlockId {
+    /// The number of blocks numbered before this one.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Numbers the blocks of a CFG in reverse postorder from the entry, so the
+/// entry is always `0`, followed by any unreachable blocks in the order they
+/// are stored. The numbers are stable for a given CFG, but blocks added after
+/// the map is made have none.
+pub struct BlockIdMap {
+    ids: SecondaryMap<BasicBlockIdx, BlockId>,
+    blocks: Vec<BasicBlockIdx>,
+}
+
+impl BlockIdMap {
+    pub fn new(cfg: &FunctionCfg) -> Self {
+        // iterative, since CFGs can be deep enough to overflow the stack
+        let mut visited = SecondaryMap::new();
+        let mut postorder = vec![];
+        let mut stack = vec![(cfg.entry, cfg.successors(cfg.entry), 0)];
+        visited.insert(cfg.entry, ());
+        while let Some((block, successors, next)) = stack.last_mut() {
+            if let Some(successor) = successors.get(*next).copied() {
+                *next += 1;
+                if visited.insert(successor, ()).is_none() {
+                    stack.push((successor, cfg.successors(successor), 0));
+                }
+            } else {
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+
+        let mut blocks = postorder;
+        blocks.reverse();
+        blocks.extend(
+            cfg.vertices
+                .keys()
+                .filter(|block| !visited.contains_key(*block)),
+        );
+        let ids = blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (*block, BlockId(index as u32)))
+            .collect();
+        Self { ids, blocks }
+    }
+
+    /// The number of `block`.
+    ///
+    /// Requires: `block` was in the CFG when this map was made.
+    pub fn id(&self, block: BasicBlockIdx) -> BlockId {
+        self.ids[block]
+    }
+
+    /// The block numbered `id`.
+    pub fn block(&self, id: BlockId) -> BasicBlockIdx {
+        self.blocks[id.index()]
+    }
+
+    /// The blocks in the order of their numbers.
+    pub fn blocks(&self) -> &[BasicBlockIdx] {
+        &self.blocks
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use snafu::{ResultExt, Whatever};
+
+use crate::fingerprint::Fingerprint;
+
+/// A directory of results saved as JSON by the fingerprint of what they were
+/// computed from, so that later runs, e.g., of other pipelines in a
+/// benchmark, can load them instead of recomputing them. The cache is best
+/// effort: entries that cannot be read or written are simply recomputed.
+pub struct DiskCache {
+    directory: PathBuf,
+}
+
+impl DiskCache {
+    /// Uses `directory` as the cache, creating it if it does not exist.
+    pub fn new(directory: PathBuf) -> Result<Self, Whatever> {
+        fs::create_dir_all(&directory).whatever_context(format!(
+            "Failed to create the analysis cache {}",
+            directory.to_string_lossy()
+        ))?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, key: &str, fingerprint: Fingerprint) -> PathBuf {
+        self.directory.join(format!("{key}-{fingerprint}.json"))
+    }
+
+    /// The result saved under `key` for `fingerprint` and decoded by `load`,
+    /// or otherwise the result of `compute`, which is saved as encoded by
+    /// `save`. `key` must change whenever the result or its encoding does.
+    pub fn get_or_compute<T>(
+        &self,
+        key: &str,
+        fingerprint: Fingerprint,
+        save: impl FnOnce(&T) -> serde_json::Value,
+        load: impl FnOnce(serde_json::Value) -> Option<T>,
+        compute: impl FnOnce() -> T,
+    ) -> T {
+        let path = self.path(key, fingerprint);
+        if let Some(result) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .and_then(load)
+        {
+            return result;
+        }
+
+        let result = compute();
+        // another process or thread may be writing the same entry, so it is
+        // written elsewhere first and then renamed into place
+        static WRITES: AtomicUsize = AtomicUsize::new(0);
+        let temporary = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        if fs::write(&temporary, save(&result).to_string()).is_ok() {
+            let _ = fs::rename(&temporary, &path);
+        }
+        result
+    }
+}
@@ -0,0 +1,69 @@
+use std::{collections::HashMap, fmt};
+
+use crate::FunctionCfg;
+
+/// A hash of the contents of a CFG or program that, unlike [`std::hash`], is
+/// the same across runs and builds, so it can name results saved to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub u64);
+
+impl Fingerprint {
+    /// The 64-bit FNV-1a hash of `text`.
+    pub fn new(text: &str) -> Self {
+        const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const PRIME: u64 = 0x100000001b3;
+        Self(text.bytes().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        }))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FunctionCfg {
+    /// Fingerprints the signature, blocks, and edges of this CFG, with blocks
+    /// identified by their order in [`FunctionCfg::vertices`]. Two CFGs with
+    /// the same fingerprint therefore also agree on the order of their blocks,
+    /// so results referring to blocks by that order can be shared.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let positions = self
+            .vertices
+            .keys()
+            .enumerate()
+            .map(|(position, block_idx)| (block_idx, position))
+            .collect::<HashMap<_, _>>();
+
+        let mut text = format!("@{}(", self.signature.name);
+        for argument in &self.signature.arguments {
+            text.push_str(&format!(
+                "{}: {}, ",
+                argument.name, argument.arg_type
+            ));
+        }
+        text.push(')');
+        if let Some(return_type) = &self.signature.return_type {
+            text.push_str(&format!(": {return_type}"));
+        }
+        text.push_str(&format!("\nentry {}\n", positions[&self.entry]));
+
+        for (block_idx, block) in &self.vertices {
+            if let Some(label) = &block.label {
+                text.push_str(&format!(".{}:\n", label.name));
+            }
+            for instruction in &block.instructions {
+                text.push_str(&format!("{instruction}\n"));
+            }
+            text.push_str("->");
+            for successor in self.successors(block_idx) {
+                text.push_str(&format!(" {}", positions[&successor]));
+            }
+            text.push('\n');
+        }
+
+        Fingerprint::new(&text)
+    }
+}
@@ -2,17 +2,37 @@
 //
 // Please see the LICENSE file in the project root directory.
 
-use std::{collections::HashMap, mem};
+use std::{
+    collections::{HashMap, HashSet},
+    iter, mem,
+};
 
 use bril_rs::{
-    Argument, Code, EffectOps, Function, Instruction, Position, Type,
+    Argument, Code, EffectOps, Function, Import, Instruction, Position,
+    Program, Type,
+};
+use bril_util::{
+    builder::{make_branch, make_jump, make_return},
+    capabilities::Capabilities,
+    error::ToolError,
 };
-use slotmap::{Key, SecondaryMap, SlotMap, new_key_type};
-use snafu::{OptionExt, Whatever, whatever};
+use slotmap::{SecondaryMap, SlotMap, new_key_type};
+use smallvec::SmallVec;
+use snafu::{OptionExt, Snafu, ensure};
+use symbol::{Symbol, SymbolTable};
 
+pub mod block_id;
+pub mod disk_cache;
+pub mod fingerprint;
+pub mod pass;
 pub mod print;
+pub mod program;
+pub mod roundtrip;
+pub mod symbol;
+pub mod timing;
 
 pub use slotmap;
+pub use smallvec;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Label {
@@ -21,23 +41,20 @@ pub struct Label {
 
 new_key_type! { pub struct BasicBlockIdx; }
 
-impl BasicBlockIdx {
-    pub fn as_number(&self) -> u64 {
-        // hacky, but it doesn't really matter here
-        self.data().as_ffi()
-    }
-
-    pub fn as_index_for_slotmap_version_1_0_7_only(&self) -> u64 {
-        self.data().as_ffi() & 0xffff_ffff
-    }
-}
+/// The instructions of a basic block, stored inline when there are few of
+/// them, as in most blocks, to save an allocation per block.
+pub type Instructions = SmallVec<[Instruction; 4]>;
 
 #[derive(Debug, Default)]
 pub struct BasicBlock {
     pub is_entry: bool,
     pub label: Option<Label>,
-    pub instructions: Vec<Instruction>,
+    pub instructions: Instructions,
     pub exit: LabeledExit,
+    /// The source region of the block when the CFG was built, from the start
+    /// of its label or first instruction to the end of its last instruction,
+    /// if the input had positions. Blocks added by passes have none.
+    pub span: Option<Position>,
 }
 
 impl BasicBlock {
@@ -101,6 +118,17 @@ pub struct FunctionSignature {
     pub return_type: Option<Type>,
 }
 
+/// An argument of the function, modeled as a definition before the first
+/// instruction of the entry block. It reaches the entry block only from outside
+/// the function, not through a loop back to the entry block.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArgumentDefinition {
+    pub variable: Symbol,
+    pub ty: Type,
+    /// The position of the argument in the signature.
+    pub position: usize,
+}
+
 #[derive(Default)]
 pub struct FunctionCfg {
     pub signature: FunctionSignature,
@@ -108,6 +136,9 @@ pub struct FunctionCfg {
     pub vertices: SlotMap<BasicBlockIdx, BasicBlock>,
     pub edges: SecondaryMap<BasicBlockIdx, Exit>,
     pub rev_edges: SecondaryMap<BasicBlockIdx, Vec<BasicBlockIdx>>,
+    /// The variable names in this CFG, for analyses to refer to variables by
+    /// [`symbol::Symbol`].
+    pub symbols: SymbolTable,
 }
 
 impl FunctionCfg {
@@ -115,8 +146,24 @@ impl FunctionCfg {
         self.vertices.insert(block)
     }
 
+    /// The arguments of the function as definitions at the start of the entry
+    /// block, in the order of the signature.
+    pub fn argument_definitions(&self) -> Vec<ArgumentDefinition> {
+        self.signature
+            .arguments
+            .iter()
+            .enumerate()
+            .map(|(position, argument)| ArgumentDefinition {
+                variable: self.symbols.intern(&argument.name),
+                ty: argument.arg_type.clone(),
+                position,
+            })
+            .collect()
+    }
+
     /// Replaces a `(start_block, old_end_block)` edge with `(start_block,
-    /// end_block)` edge.
+    /// end_block)` edge. If a branch goes to `old_end_block` both ways, only
+    /// its true side is replaced.
     ///
     /// Requires: there are no fallthrough edges.
     pub fn reorient_edge(
@@ -133,30 +180,14 @@ impl FunctionCfg {
             panic!("Destination block does not have a label");
         };
 
-        self.rev_edges[old_end_block]
-            .retain(|predecessor| *predecessor != start_block);
-
-        let new_end_rev_edges =
-            self.rev_edges.entry(end_block).unwrap().or_default();
-        if !new_end_rev_edges.contains(&start_block) {
-            new_end_rev_edges.push(start_block);
-        }
-
-        match &self.vertices[start_block].exit {
-            LabeledExit::Unconditional { .. } => {
-                *self.vertices[start_block]
-                    .instructions
-                    .last_mut()
-                    .expect("Call FunctionCfg::make_fallthroughs_explicit") =
-                    Instruction::Effect {
-                        args: vec![],
-                        funcs: vec![],
-                        labels: vec![end_label.name],
-                        op: EffectOps::Jump,
-                        pos: None,
-                    };
-                self.edges[start_block] = Exit::Unconditional(end_block);
-            }
+        let (exit, edge) = match &self.vertices[start_block].exit {
+            LabeledExit::Unconditional { .. } => (
+                LabeledExit::Unconditional {
+                    label: end_label.name,
+                    pos: None,
+                },
+                Exit::Unconditional(end_block),
+            ),
             LabeledExit::Conditional {
                 if_true_label,
                 if_false_label,
@@ -187,24 +218,50 @@ impl FunctionCfg {
                 } else {
                     (if_true_label.clone(), end_label.name, if_true, end_block)
                 };
-                *self.vertices[start_block]
-                    .instructions
-                    .last_mut()
-                    .expect("Call FunctionCfg::make_fallthroughs_explicit") =
-                    Instruction::Effect {
-                        args: vec![condition.clone()],
-                        funcs: vec![],
-                        labels: vec![new_if_true_label, new_if_false_label],
-                        op: EffectOps::Branch,
+                (
+                    LabeledExit::Conditional {
+                        condition: condition.clone(),
+                        if_true_label: new_if_true_label,
+                        if_false_label: new_if_false_label,
                         pos: None,
-                    };
-                self.edges[start_block] = Exit::Conditional {
-                    condition,
-                    if_true: new_if_true,
-                    if_false: new_if_false,
-                }
+                    },
+                    Exit::Conditional {
+                        condition,
+                        if_true: new_if_true,
+                        if_false: new_if_false,
+                    },
+                )
             }
-            _ => {}
+            _ => return,
+        };
+
+        let instruction = match &exit {
+            LabeledExit::Unconditional { label, .. } => make_jump(label),
+            LabeledExit::Conditional {
+                condition,
+                if_true_label,
+                if_false_label,
+                ..
+            } => make_branch(condition, if_true_label, if_false_label),
+            _ => unreachable!(),
+        };
+        *self.vertices[start_block]
+            .instructions
+            .last_mut()
+            .expect("Call FunctionCfg::make_fallthroughs_explicit") =
+            instruction;
+        self.vertices[start_block].exit = exit;
+        self.edges[start_block] = edge;
+
+        // the other side of a branch may still go to the old block
+        if !self.successors(start_block).contains(&old_end_block) {
+            self.rev_edges[old_end_block]
+                .retain(|predecessor| *predecessor != start_block);
+        }
+        let new_end_rev_edges =
+            self.rev_edges.entry(end_block).unwrap().or_default();
+        if !new_end_rev_edges.contains(&start_block) {
+            new_end_rev_edges.push(start_block);
         }
     }
 
@@ -233,25 +290,13 @@ impl FunctionCfg {
 
         match &self.vertices[start_block].exit {
             LabeledExit::Fallthrough => {
-                self.vertices[start_block].instructions.push(
-                    Instruction::Effect {
-                        args: vec![],
-                        funcs: vec![],
-                        labels: vec![end_label.name.clone()],
-                        op: EffectOps::Jump,
-                        pos: None,
-                    },
-                );
+                self.vertices[start_block]
+                    .instructions
+                    .push(make_jump(end_label.name.clone()));
             }
             LabeledExit::Unconditional { .. } => {
                 *self.vertices[start_block].instructions.last_mut().expect("branching LabeledExit implies existence of corresponding instruction at end of block") =
-                    Instruction::Effect {
-                        args: vec![],
-                        funcs: vec![],
-                        labels: vec![end_label.name.clone()],
-                        op: EffectOps::Jump,
-                        pos: None,
-                    };
+                    make_jump(end_label.name.clone());
             }
             _ => unreachable!(),
         }
@@ -271,6 +316,57 @@ impl FunctionCfg {
         }
     }
 
+    /// Ends `start_block`, which must not have an exit yet, with a branch on
+    /// `condition` to `if_true` or `if_false`.
+    pub fn set_conditional_edge(
+        &mut self,
+        start_block: BasicBlockIdx,
+        condition: String,
+        if_true: BasicBlockIdx,
+        if_false: BasicBlockIdx,
+    ) {
+        if !matches!(self.vertices[start_block].exit, LabeledExit::Fallthrough)
+            || self.edges.contains_key(start_block)
+        {
+            panic!("Already has an exit: {:?}", self.vertices[start_block]);
+        }
+
+        let Some(if_true_label) = self.vertices[if_true].label.clone() else {
+            panic!("Destination block does not have a label");
+        };
+        let Some(if_false_label) = self.vertices[if_false].label.clone() else {
+            panic!("Destination block does not have a label");
+        };
+
+        self.vertices[start_block].instructions.push(make_branch(
+            condition.clone(),
+            if_true_label.name.clone(),
+            if_false_label.name.clone(),
+        ));
+        self.vertices[start_block].exit = LabeledExit::Conditional {
+            condition: condition.clone(),
+            if_true_label: if_true_label.name,
+            if_false_label: if_false_label.name,
+            pos: None,
+        };
+
+        self.edges.insert(
+            start_block,
+            Exit::Conditional {
+                condition,
+                if_true,
+                if_false,
+            },
+        );
+        for end_block in [if_true, if_false] {
+            let rev_edges =
+                self.rev_edges.entry(end_block).unwrap().or_default();
+            if !rev_edges.contains(&start_block) {
+                rev_edges.push(start_block);
+            }
+        }
+    }
+
     pub fn successors(&self, block: BasicBlockIdx) -> Vec<BasicBlockIdx> {
         match &self.edges[block] {
             Exit::Fallthrough(destination_idx) => {
@@ -292,6 +388,253 @@ impl FunctionCfg {
             .map_or(&[] as &[BasicBlockIdx], |edges| edges.as_slice())
     }
 
+    /// Removes `block` and every edge into or out of it, returning its
+    /// instructions. The predecessors of `block` other than itself are
+    /// redirected to `replacement`, or there must be none if there is no
+    /// replacement.
+    ///
+    /// Requires: `block` is not the entry and, if there is a replacement, it
+    /// has a label.
+    pub fn remove_block(
+        &mut self,
+        block: BasicBlockIdx,
+        replacement: Option<BasicBlockIdx>,
+    ) -> Instructions {
+        assert!(block != self.entry, "Cannot remove the entry block");
+
+        let predecessors = self
+            .predecessors(block)
+            .iter()
+            .copied()
+            .filter(|predecessor| *predecessor != block)
+            .collect::<Vec<_>>();
+        match replacement {
+            Some(replacement) => {
+                for predecessor in predecessors {
+                    if let Exit::Fallthrough(_) = self.edges[predecessor] {
+                        self.set_unconditional_edge(predecessor, replacement);
+                        continue;
+                    }
+                    // a branch may go to `block` both ways
+                    while self.successors(predecessor).contains(&block) {
+                        self.reorient_edge(predecessor, block, replacement);
+                    }
+                }
+            }
+            None => assert!(
+                predecessors.is_empty(),
+                "Removed a block that is still jumped to without a replacement"
+            ),
+        }
+
+        for successor in self.successors(block) {
+            if let Some(predecessors) = self.rev_edges.get_mut(successor) {
+                predecessors.retain(|predecessor| *predecessor != block);
+            }
+        }
+        self.edges.remove(block);
+        self.rev_edges.remove(block);
+        self.vertices
+            .remove(block)
+            .expect("The block to remove is in the CFG")
+            .instructions
+    }
+
+    /// Removes the blocks no longer reachable from the entry, like building
+    /// the CFG with pruning does, but for blocks that became unreachable
+    /// later, such as after folding a branch. Returns how many were removed
+    /// and the remaining blocks that lost predecessors as a result, each once.
+    pub fn remove_unreachable_blocks(&mut self) -> (usize, Vec<BasicBlockIdx>) {
+        let mut reachable = HashSet::from([self.entry]);
+        let mut stack = vec![self.entry];
+        while let Some(block) = stack.pop() {
+            for successor in self.successors(block) {
+                if reachable.insert(successor) {
+                    stack.push(successor);
+                }
+            }
+        }
+        let unreachable = self
+            .vertices
+            .keys()
+            .filter(|block| !reachable.contains(block))
+            .collect::<HashSet<_>>();
+
+        let mut orphaned = vec![];
+        for block in &unreachable {
+            orphaned.extend(self.successors(*block));
+            self.vertices.remove(*block);
+            self.edges.remove(*block);
+            self.rev_edges.remove(*block);
+        }
+        for (_, predecessors) in self.rev_edges.iter_mut() {
+            predecessors
+                .retain(|predecessor| !unreachable.contains(predecessor));
+        }
+        orphaned.retain(|block| self.vertices.contains_key(*block));
+        orphaned.sort();
+        orphaned.dedup();
+        (unreachable.len(), orphaned)
+    }
+
+    /// Merges each block into its predecessor when it is the only predecessor
+    /// and ends in a jump to it, so the jump and the label of the block go
+    /// away. Blocks whose label is referenced anywhere else, like by a `guard`,
+    /// are left alone. Chains of such blocks become a single block. Returns
+    /// the number of blocks removed.
+    ///
+    /// Requires: there are no fallthrough edges.
+    pub fn merge_straightline_blocks(&mut self) -> usize {
+        self.assert_no_fallthroughs();
+
+        // merging moves instructions without changing which labels they
+        // refer to, so the counts stay correct for the blocks left
+        let mut label_references = HashMap::<String, usize>::new();
+        for block in self.vertices.values() {
+            for instruction in &block.instructions {
+                if let Instruction::Value { labels, .. }
+                | Instruction::Effect { labels, .. } = instruction
+                {
+                    for label in labels {
+                        *label_references.entry(label.clone()).or_default() +=
+                            1;
+                    }
+                }
+            }
+        }
+
+        let mut merged_count = 0;
+        for block in self.vertices.keys().collect::<Vec<_>>() {
+            // `block` may itself have been merged into an earlier block
+            if !self.vertices.contains_key(block) {
+                continue;
+            }
+            while let Exit::Unconditional(successor) = self.edges[block] {
+                // the jump from `block` is the one reference allowed
+                let is_referenced_elsewhere = self.vertices[successor]
+                    .label
+                    .as_ref()
+                    .and_then(|label| label_references.get(&label.name))
+                    .is_some_and(|count| *count > 1);
+                if successor == block
+                    || successor == self.entry
+                    || self.predecessors(successor) != [block]
+                    || is_referenced_elsewhere
+                {
+                    break;
+                }
+
+                let successor_block = self
+                    .vertices
+                    .remove(successor)
+                    .expect("Successors are in the CFG");
+                let exit = self
+                    .edges
+                    .remove(successor)
+                    .expect("Every block has an exit");
+                self.rev_edges.remove(successor);
+                let current = &mut self.vertices[block];
+                current.instructions.pop();
+                current.instructions.extend(successor_block.instructions);
+                current.exit = successor_block.exit;
+                if let (Some(span), Some(last)) =
+                    (&mut current.span, successor_block.span)
+                {
+                    span.pos_end = Some(last.pos_end.unwrap_or(last.pos));
+                }
+                self.edges[block] = exit;
+                for next in self.successors(block) {
+                    for predecessor in &mut self.rev_edges[next] {
+                        if *predecessor == successor {
+                            *predecessor = block;
+                        }
+                    }
+                }
+                merged_count += 1;
+            }
+        }
+        merged_count
+    }
+
+    /// Lays out the blocks in `order`, which [`FunctionCfg::into_function`]
+    /// and [`print::print_cfg_as_bril_text`] follow after the entry. Every
+    /// block gets a new index, so this returns the new index of each block.
+    ///
+    /// Requires: there are no fallthrough edges, and `order` has every block
+    /// exactly once.
+    pub fn reorder_blocks(
+        &mut self,
+        order: &[BasicBlockIdx],
+    ) -> SecondaryMap<BasicBlockIdx, BasicBlockIdx> {
+        self.assert_no_fallthroughs();
+        assert_eq!(
+            order.len(),
+            self.vertices.len(),
+            "The order must have every block exactly once"
+        );
+
+        let mut vertices = SlotMap::with_capacity_and_key(order.len());
+        let mut renamed = SecondaryMap::new();
+        for block in order {
+            let contents = self
+                .vertices
+                .remove(*block)
+                .expect("The order must have every block exactly once");
+            renamed.insert(*block, vertices.insert(contents));
+        }
+
+        let mut edges = SecondaryMap::new();
+        let mut rev_edges = SecondaryMap::new();
+        for block in order {
+            let exit = match self.edges[*block].clone() {
+                Exit::Unconditional(destination) => {
+                    Exit::Unconditional(renamed[destination])
+                }
+                Exit::Conditional {
+                    condition,
+                    if_true,
+                    if_false,
+                } => Exit::Conditional {
+                    condition,
+                    if_true: renamed[if_true],
+                    if_false: renamed[if_false],
+                },
+                exit => exit,
+            };
+            edges.insert(renamed[*block], exit);
+            rev_edges.insert(
+                renamed[*block],
+                self.predecessors(*block)
+                    .iter()
+                    .map(|predecessor| renamed[*predecessor])
+                    .collect(),
+            );
+        }
+
+        self.entry = renamed[self.entry];
+        self.vertices = vertices;
+        self.edges = edges;
+        self.rev_edges = rev_edges;
+        renamed
+    }
+
+    /// The Bril extensions this function uses. Exits are always core
+    /// operations, so only the signature and instructions are considered.
+    pub fn capabilities(&self) -> Capabilities {
+        self.vertices
+            .values()
+            .flat_map(|block| &block.instructions)
+            .fold(
+                Capabilities::of_signature(
+                    &self.signature.arguments,
+                    self.signature.return_type.as_ref(),
+                ),
+                |capabilities, instruction| {
+                    capabilities | Capabilities::of_instruction(instruction)
+                },
+            )
+    }
+
     /// Replaces al fallthroughs with unconditional jumps or returns.
     pub fn make_fallthroughs_explicit(&mut self) {
         for block_idx in self.vertices.keys().collect::<Vec<_>>() {
@@ -309,27 +652,15 @@ impl FunctionCfg {
                             pos: None,
                         };
                     self.edges[block_idx] = Exit::Unconditional(destination);
-                    self.vertices[block_idx].instructions.push(
-                        Instruction::Effect {
-                            args: vec![],
-                            funcs: vec![],
-                            labels: vec![label.name],
-                            op: EffectOps::Jump,
-                            pos: None,
-                        },
-                    );
+                    self.vertices[block_idx]
+                        .instructions
+                        .push(make_jump(label.name));
                 } else {
                     self.vertices[block_idx].exit = LabeledExit::Return(None);
                     self.edges[block_idx] = Exit::Return(None);
-                    self.vertices[block_idx].instructions.push(
-                        Instruction::Effect {
-                            args: vec![],
-                            funcs: vec![],
-                            labels: vec![],
-                            op: EffectOps::Return,
-                            pos: None,
-                        },
-                    );
+                    self.vertices[block_idx]
+                        .instructions
+                        .push(make_return(None));
                 }
             }
         }
@@ -368,6 +699,175 @@ impl FunctionCfg {
         }
     }
 
+    /// Converts this CFG back into a function, with the entry block first and
+    /// the rest in the order of their slots, like
+    /// [`print::print_cfg_as_bril_text`]. That is the order the blocks were
+    /// added in only if none were removed before, since a new block may reuse
+    /// the slot of a removed one; use [`FunctionCfg::reorder_blocks`] to pick
+    /// the layout.
+    pub fn into_function(mut self) -> Function {
+        let mut instrs = vec![];
+        let entry = self.vertices.remove(self.entry).unwrap();
+        for block in iter::once(entry)
+            .chain(self.vertices.into_iter().map(|(_, block)| block))
+        {
+            if let Some(label) = block.label {
+                instrs.push(Code::Label {
+                    label: label.name,
+                    pos: None,
+                });
+            }
+            instrs
+                .extend(block.instructions.into_iter().map(Code::Instruction));
+        }
+        Function {
+            args: self.signature.arguments,
+            instrs,
+            name: self.signature.name,
+            pos: None,
+            return_type: self.signature.return_type,
+        }
+    }
+
+    /// Checks the invariants every pass relies on, returning the first one
+    /// broken: the entry is a block, every block has an [`Exit`] agreeing with
+    /// its [`LabeledExit`] and going to blocks in the CFG, the predecessors of
+    /// each block are exactly the blocks with edges to it, and no two blocks
+    /// have the same label. Passes that edit edges by hand can call this to
+    /// catch a corrupted CFG where it was corrupted.
+    pub fn verify(&self) -> Result<(), CfgVerifyError> {
+        ensure!(self.vertices.contains_key(self.entry), MissingEntrySnafu);
+
+        let mut labels = HashSet::new();
+        for block in self.vertices.values() {
+            if let Some(label) = &block.label {
+                ensure!(
+                    labels.insert(label.name.as_str()),
+                    DuplicateLabelSnafu {
+                        label: label.name.clone()
+                    }
+                );
+            }
+        }
+
+        let mut predecessors = SecondaryMap::<_, HashSet<_>>::new();
+        for block_idx in self.vertices.keys() {
+            predecessors.insert(block_idx, HashSet::new());
+        }
+        for (block_idx, block) in &self.vertices {
+            let exit = self.edges.get(block_idx).context(MissingExitSnafu {
+                block: self.block_name(block_idx),
+            })?;
+            let successors = self.successors(block_idx);
+            for successor in &successors {
+                ensure!(
+                    self.vertices.contains_key(*successor),
+                    DanglingEdgeSnafu {
+                        block: self.block_name(block_idx)
+                    }
+                );
+                predecessors[*successor].insert(block_idx);
+            }
+
+            let label_of = |destination: BasicBlockIdx| {
+                self.vertices[destination]
+                    .label
+                    .as_ref()
+                    .map(|label| label.name.as_str())
+            };
+            let agrees = match (&block.exit, exit) {
+                (LabeledExit::Fallthrough, Exit::Fallthrough(_)) => true,
+                // a jump simplified to a fallthrough keeps its label
+                (
+                    LabeledExit::Unconditional { label, .. },
+                    Exit::Unconditional(destination)
+                    | Exit::Fallthrough(Some(destination)),
+                ) => label_of(*destination) == Some(label.as_str()),
+                (
+                    LabeledExit::Conditional {
+                        condition,
+                        if_true_label,
+                        if_false_label,
+                        ..
+                    },
+                    Exit::Conditional {
+                        condition: exit_condition,
+                        if_true,
+                        if_false,
+                    },
+                ) => {
+                    condition == exit_condition
+                        && label_of(*if_true) == Some(if_true_label.as_str())
+                        && label_of(*if_false) == Some(if_false_label.as_str())
+                }
+                (LabeledExit::Return(value), Exit::Return(exit_value)) => {
+                    value == exit_value
+                }
+                _ => false,
+            };
+            ensure!(
+                agrees,
+                ExitMismatchSnafu {
+                    block: self.block_name(block_idx),
+                    labeled_exit: describe_labeled_exit(&block.exit),
+                    exit: self.describe_exit(exit),
+                }
+            );
+        }
+
+        for (block_idx, expected) in &predecessors {
+            let actual = self.predecessors(block_idx);
+            ensure!(
+                actual.len() == expected.len()
+                    && actual.iter().all(|block| expected.contains(block)),
+                PredecessorMismatchSnafu {
+                    block: self.block_name(block_idx)
+                }
+            );
+        }
+        for block_idx in self.edges.keys().chain(self.rev_edges.keys()) {
+            ensure!(self.vertices.contains_key(block_idx), StaleBlockSnafu);
+        }
+        Ok(())
+    }
+
+    /// The label of `block` with a leading `.`, or `entry` if it has none,
+    /// for messages.
+    fn block_name(&self, block: BasicBlockIdx) -> String {
+        self.vertices[block]
+            .label
+            .as_ref()
+            .map(|label| format!(".{}", label.name))
+            .unwrap_or_else(|| "entry".into())
+    }
+
+    /// `exit` in words, naming blocks like the messages of [`CfgVerifyError`]
+    /// do.
+    fn describe_exit(&self, exit: &Exit) -> String {
+        match exit {
+            Exit::Fallthrough(Some(next)) => {
+                format!("a fallthrough to {}", self.block_name(*next))
+            }
+            Exit::Fallthrough(None) => {
+                "a fallthrough out of the function".into()
+            }
+            Exit::Unconditional(destination) => {
+                format!("a jump to {}", self.block_name(*destination))
+            }
+            Exit::Conditional {
+                condition,
+                if_true,
+                if_false,
+            } => format!(
+                "a branch on {condition} to {} or {}",
+                self.block_name(*if_true),
+                self.block_name(*if_false)
+            ),
+            Exit::Return(Some(value)) => format!("a return of {value}"),
+            Exit::Return(None) => "a return".into(),
+        }
+    }
+
     /// Asserts that this CFG has no fallthrough edges.
     pub fn assert_no_fallthroughs(&self) {
         for block_idx in self.vertices.keys() {
@@ -399,6 +899,10 @@ impl FunctionCfgBuilder {
         arguments: Vec<Argument>,
         return_type: Option<Type>,
     ) -> Self {
+        let symbols = SymbolTable::default();
+        for argument in &arguments {
+            symbols.intern(&argument.name);
+        }
         Self {
             cfg: FunctionCfg {
                 signature: FunctionSignature {
@@ -406,6 +910,7 @@ impl FunctionCfgBuilder {
                     arguments,
                     return_type,
                 },
+                symbols,
                 ..Default::default()
             },
             entry_is_init: false,
@@ -417,9 +922,51 @@ impl FunctionCfgBuilder {
     }
 
     pub fn add_to_current(&mut self, instruction: Instruction) {
+        match &instruction {
+            Instruction::Constant { dest, .. } => {
+                self.cfg.symbols.intern(dest);
+            }
+            Instruction::Value { args, dest, .. } => {
+                for arg in args {
+                    self.cfg.symbols.intern(arg);
+                }
+                self.cfg.symbols.intern(dest);
+            }
+            Instruction::Effect { args, .. } => {
+                for arg in args {
+                    self.cfg.symbols.intern(arg);
+                }
+            }
+        }
+        if let Some(pos) = instruction_pos(&instruction) {
+            self.extend_current_span(pos);
+        }
         self.current_block.instructions.push(instruction);
     }
 
+    /// Widens the span of the current block to include `pos`.
+    pub fn extend_current_span(&mut self, pos: &Position) {
+        let start = |pos: &Position| (pos.pos.row, pos.pos.col);
+        let end = |pos: &Position| {
+            let end = pos.pos_end.as_ref().unwrap_or(&pos.pos);
+            (end.row, end.col)
+        };
+        let span = match self.current_block.span.take() {
+            None => pos.clone(),
+            Some(mut span) => {
+                if start(pos) < start(&span) {
+                    span.pos = pos.pos.clone();
+                }
+                if end(pos) > end(&span) {
+                    span.pos_end =
+                        Some(pos.pos_end.clone().unwrap_or(pos.pos.clone()));
+                }
+                span
+            }
+        };
+        self.current_block.span = Some(span);
+    }
+
     pub fn set_current_label(&mut self, name: String) {
         self.current_block.label = Some(Label { name });
     }
@@ -453,7 +1000,7 @@ impl FunctionCfgBuilder {
         }
     }
 
-    pub fn finish(mut self, prune: bool) -> Result<FunctionCfg, Whatever> {
+    pub fn finish(mut self, prune: bool) -> Result<FunctionCfg, CfgBuildError> {
         for (block_idx, block) in &self.cfg.vertices {
             match &block.exit {
                 LabeledExit::Fallthrough => {
@@ -476,11 +1023,10 @@ impl FunctionCfgBuilder {
                     let destination_index = *self
                         .labels_to_blocks
                         .get(always)
-                        .whatever_context(format!(
-                            "Unknown label {} referenced at {}",
-                            always,
-                            pos_to_string(pos.as_ref())
-                        ))?;
+                        .context(UnknownLabelSnafu {
+                            label: always,
+                            span: pos.clone(),
+                        })?;
                     self.cfg.edges.insert(
                         block_idx,
                         Exit::Unconditional(destination_index),
@@ -501,19 +1047,17 @@ impl FunctionCfgBuilder {
                     let if_true_index = *self
                         .labels_to_blocks
                         .get(if_true_label)
-                        .whatever_context(format!(
-                            "Unknown label {} referenced at {}",
-                            if_true_label,
-                            pos_to_string(pos.as_ref())
-                        ))?;
+                        .context(UnknownLabelSnafu {
+                            label: if_true_label,
+                            span: pos.clone(),
+                        })?;
                     let if_false_index = *self
                         .labels_to_blocks
                         .get(if_false_label)
-                        .whatever_context(format!(
-                            "Unknown label {} referenced at {}",
-                            if_false_label,
-                            pos_to_string(pos.as_ref())
-                        ))?;
+                        .context(UnknownLabelSnafu {
+                            label: if_false_label,
+                            span: pos.clone(),
+                        })?;
                     self.cfg.edges.insert(
                         block_idx,
                         Exit::Conditional {
@@ -568,15 +1112,148 @@ impl FunctionCfgBuilder {
     }
 }
 
+fn instruction_pos(instruction: &Instruction) -> Option<&Position> {
+    match instruction {
+        Instruction::Constant { pos, .. }
+        | Instruction::Value { pos, .. }
+        | Instruction::Effect { pos, .. } => pos.as_ref(),
+    }
+}
+
 fn pos_to_string(pos: Option<&Position>) -> String {
     pos.map(|pos| format!("{}:{}", pos.pos.row, pos.pos.col))
         .unwrap_or("<unknown>".into())
 }
 
+/// Why a function could not be split into a CFG, which is always because the
+/// function is malformed.
+#[derive(Debug, Snafu)]
+pub enum CfgBuildError {
+    #[snafu(display(
+        "Unknown label {label} referenced at {}",
+        pos_to_string(span.as_ref())
+    ))]
+    UnknownLabel {
+        label: String,
+        span: Option<Position>,
+    },
+
+    #[snafu(display(
+        "Jump operation at {} should take one label",
+        pos_to_string(span.as_ref())
+    ))]
+    JumpLabels { span: Option<Position> },
+
+    #[snafu(display(
+        "Branch operation at {} should take one condition argument",
+        pos_to_string(span.as_ref())
+    ))]
+    BranchArguments { span: Option<Position> },
+
+    #[snafu(display(
+        "Branch operation at {} should take two labels",
+        pos_to_string(span.as_ref())
+    ))]
+    BranchLabels { span: Option<Position> },
+
+    #[snafu(display(
+        "Return operation at {} should take at most one argument",
+        pos_to_string(span.as_ref())
+    ))]
+    ReturnArguments { span: Option<Position> },
+
+    #[snafu(display(
+        "Return operation at {} should take no labels",
+        pos_to_string(span.as_ref())
+    ))]
+    ReturnLabels { span: Option<Position> },
+}
+
+impl CfgBuildError {
+    /// The position of the offending instruction, if the input has positions.
+    pub fn span(&self) -> Option<&Position> {
+        match self {
+            Self::UnknownLabel { span, .. }
+            | Self::JumpLabels { span }
+            | Self::BranchArguments { span }
+            | Self::BranchLabels { span }
+            | Self::ReturnArguments { span }
+            | Self::ReturnLabels { span } => span.as_ref(),
+        }
+    }
+}
+
+/// `exit` as the final instruction it stands for, or `a fallthrough`.
+fn describe_labeled_exit(exit: &LabeledExit) -> String {
+    match exit {
+        LabeledExit::Fallthrough => "a fallthrough".into(),
+        LabeledExit::Unconditional { label, .. } => format!("`jmp .{label}`"),
+        LabeledExit::Conditional {
+            condition,
+            if_true_label,
+            if_false_label,
+            ..
+        } => format!("`br {condition} .{if_true_label} .{if_false_label}`"),
+        LabeledExit::Return(Some(value)) => format!("`ret {value}`"),
+        LabeledExit::Return(None) => "`ret`".into(),
+    }
+}
+
+/// An invariant of a [`FunctionCfg`] that [`FunctionCfg::verify`] found
+/// broken, which is always a bug in whatever built or last changed the CFG.
+#[derive(Debug, Snafu)]
+pub enum CfgVerifyError {
+    #[snafu(display("The entry block is not in the CFG"))]
+    MissingEntry,
+
+    #[snafu(display("Block {block} has no exit"))]
+    MissingExit { block: String },
+
+    #[snafu(display("Block {block} has an edge to a block not in the CFG"))]
+    DanglingEdge { block: String },
+
+    #[snafu(display(
+        "The labeled exit of block {block}, {labeled_exit}, does not agree with its edges, which are {exit}"
+    ))]
+    ExitMismatch {
+        block: String,
+        labeled_exit: String,
+        exit: String,
+    },
+
+    #[snafu(display(
+        "The predecessors of block {block} are not the blocks with edges to it"
+    ))]
+    PredecessorMismatch { block: String },
+
+    #[snafu(display("Edges are recorded for a block not in the CFG"))]
+    StaleBlock,
+
+    #[snafu(display("More than one block is labeled .{label}"))]
+    DuplicateLabel { label: String },
+}
+
+impl From<CfgVerifyError> for ToolError {
+    fn from(error: CfgVerifyError) -> Self {
+        ToolError::InvariantViolated {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<CfgBuildError> for ToolError {
+    fn from(error: CfgBuildError) -> Self {
+        ToolError::MalformedInput {
+            message: error.to_string(),
+            span: error.span().cloned(),
+        }
+    }
+}
+
 pub fn build_cfg(
     function: &Function,
     prune: bool,
-) -> Result<FunctionCfg, Whatever> {
+) -> Result<FunctionCfg, CfgBuildError> {
     let mut builder = FunctionCfgBuilder::new(
         function.name.clone(),
         function.args.clone(),
@@ -587,13 +1264,16 @@ pub fn build_cfg(
 
     for instruction in &function.instrs {
         match instruction {
-            Code::Label { label, .. } => {
+            Code::Label { label, pos } => {
                 if !builder.current_block.instructions.is_empty()
                     || builder.current_block.label.is_some()
                 {
                     builder.finish_current_and_start_new_block();
                 }
                 builder.set_current_label(label.clone());
+                if let Some(pos) = pos {
+                    builder.extend_current_span(pos);
+                }
             }
             Code::Instruction(instruction) => match instruction {
                 Instruction::Effect {
@@ -605,10 +1285,7 @@ pub fn build_cfg(
                     builder.add_to_current(instruction.clone());
 
                     let [destination_label] = labels.as_slice() else {
-                        whatever!(
-                            "Jump operation at {} should take one label",
-                            pos_to_string(pos.as_ref())
-                        );
+                        return JumpLabelsSnafu { span: pos.clone() }.fail();
                     };
 
                     builder.set_current_exit(LabeledExit::Unconditional {
@@ -628,17 +1305,12 @@ pub fn build_cfg(
                     builder.add_to_current(instruction.clone());
 
                     let [condition] = args.as_slice() else {
-                        whatever!(
-                            "Branch operation at {} should take one condition argument",
-                            pos_to_string(pos.as_ref())
-                        );
+                        return BranchArgumentsSnafu { span: pos.clone() }
+                            .fail();
                     };
                     let [if_true_label, if_false_label] = labels.as_slice()
                     else {
-                        whatever!(
-                            "Branch operation at {} should take two labels",
-                            pos_to_string(pos.as_ref())
-                        );
+                        return BranchLabelsSnafu { span: pos.clone() }.fail();
                     };
 
                     builder.set_current_exit(LabeledExit::Conditional {
@@ -662,16 +1334,13 @@ pub fn build_cfg(
                     let value = match args.as_slice() {
                         [] => None,
                         [value] => Some(value.clone()),
-                        _ => whatever!(
-                            "Branch operation at {} should take one condition argument",
-                            pos_to_string(pos.as_ref())
-                        ),
+                        _ => {
+                            return ReturnArgumentsSnafu { span: pos.clone() }
+                                .fail();
+                        }
                     };
                     if !labels.is_empty() {
-                        whatever!(
-                            "Return operation at {} should take no labels",
-                            pos_to_string(pos.as_ref())
-                        );
+                        return ReturnLabelsSnafu { span: pos.clone() }.fail();
                     };
 
                     builder.set_current_exit(LabeledExit::Return(value));
@@ -692,3 +1361,29 @@ pub fn build_cfg(
 
     builder.finish(prune)
 }
+
+/// Builds the CFG of every function in `program`, in order, with
+/// [`build_cfg`].
+pub fn build_program_cfgs(
+    program: &Program,
+    prune: bool,
+) -> Result<Vec<FunctionCfg>, CfgBuildError> {
+    program
+        .functions
+        .iter()
+        .map(|function| build_cfg(function, prune))
+        .collect()
+}
+
+/// Converts `cfgs` back into a program with `imports`, as
+/// [`FunctionCfg::into_function`] does for each function, so that a pass can
+/// serialize it or hand it to another pass without printing it as text.
+pub fn into_program(
+    cfgs: impl IntoIterator<Item = FunctionCfg>,
+    imports: Vec<Import>,
+) -> Program {
+    Program {
+        functions: cfgs.into_iter().map(FunctionCfg::into_function).collect(),
+        imports,
+    }
+}
@@ -11,7 +11,11 @@ use std::{
 
 use argh::FromArgs;
 use bril_rs::Program;
-use build_cfg::{Exit, build_cfg, print::print_cfg_as_bril_text};
+use bril_util::filter::{FunctionFilter, FunctionPattern};
+use build_cfg::{
+    Exit, block_id::BlockIdMap, build_cfg, print::print_cfg_as_bril_text,
+    roundtrip::roundtrip_check,
+};
 use inform::{common::IndentWriterCommon, io::IndentWriter};
 use owo_colors::OwoColorize;
 use snafu::{ResultExt, Whatever};
@@ -19,6 +23,7 @@ use snafu::{ResultExt, Whatever};
 enum Mode {
     Passthrough,
     Pretty,
+    Check,
 }
 
 impl FromStr for Mode {
@@ -28,6 +33,7 @@ impl FromStr for Mode {
         match s {
             "passthrough" => Ok(Self::Passthrough),
             "pretty" => Ok(Self::Pretty),
+            "check" => Ok(Self::Check),
             other => Err(format!("Unknown printing mode '{}'", other)),
         }
     }
@@ -36,21 +42,37 @@ impl FromStr for Mode {
 /// Extracts the control-flow graph from a Bril program.
 #[derive(FromArgs)]
 struct Opts {
-    /// type of printing
+    /// type of printing: `passthrough`, `pretty`, or `check`, which prints
+    /// nothing but fails if converting a CFG back into a function changes it
     #[argh(option)]
     mode: Mode,
 
+    /// reprint whenever the input file changes
+    #[argh(switch)]
+    watch: bool,
+
+    /// only print functions matching this name, like `@main` or `@test_*`:
+    /// may be repeated, and omit for every function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
     /// input Bril file: omit for stdin
     #[argh(positional)]
     input: Option<PathBuf>,
 }
 
-fn print_reconstructed(program: Program) -> Result<(), Whatever> {
+fn print_reconstructed(
+    program: Program,
+    filter: &FunctionFilter,
+) -> Result<(), Whatever> {
     for import in program.imports {
         println!("{}", import);
     }
 
     for function in &program.functions {
+        if !filter.selects(&function.name) {
+            continue;
+        }
         let cfg = build_cfg(function, false).whatever_context(format!(
             "Failed to build control-flow graph for function `{}`",
             function.name
@@ -61,11 +83,17 @@ fn print_reconstructed(program: Program) -> Result<(), Whatever> {
     Ok(())
 }
 
-fn print_pretty(program: Program) -> Result<(), Whatever> {
+fn print_pretty(
+    program: Program,
+    filter: &FunctionFilter,
+) -> Result<(), Whatever> {
     let mut stdout = io::stdout();
     let mut f = IndentWriter::new(&mut stdout, 4);
 
     for function in &program.functions {
+        if !filter.selects(&function.name) {
+            continue;
+        }
         let cfg = build_cfg(function, true).whatever_context(format!(
             "Failed to build control-flow graph for function `{}`",
             function.name
@@ -75,7 +103,9 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
             .whatever_context("Writing to stdout failed")?;
         f.increase_indent();
 
-        for (i, (block_idx, block)) in cfg.vertices.iter().enumerate() {
+        let ids = BlockIdMap::new(&cfg);
+        for (i, block_idx) in ids.blocks().iter().copied().enumerate() {
+            let block = &cfg.vertices[block_idx];
             if i > 0 {
                 writeln!(f).whatever_context("Writing to stdout failed")?;
             }
@@ -89,12 +119,26 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
                 write!(f, ".{} ", label.name.on_truecolor(64, 64, 64))
                     .whatever_context("Writing to stdout failed")?;
             }
-            writeln!(
+            write!(
                 f,
-                "[{}] {{",
-                block_idx.as_number().to_string().bold().bright_green()
+                "[{}] ",
+                ids.id(block_idx).to_string().bold().bright_green()
             )
             .whatever_context("Writing to stdout failed")?;
+            if let Some(span) = &block.span {
+                let end = span.pos_end.as_ref().unwrap_or(&span.pos);
+                write!(
+                    f,
+                    "{} ",
+                    format!(
+                        "({}:{}-{}:{})",
+                        span.pos.row, span.pos.col, end.row, end.col
+                    )
+                    .truecolor(128, 128, 128)
+                )
+                .whatever_context("Writing to stdout failed")?;
+            }
+            writeln!(f, "{{").whatever_context("Writing to stdout failed")?;
 
             f.increase_indent();
             for instruction in &block.instructions {
@@ -115,8 +159,7 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
                             write!(
                                 f,
                                 "-> {}",
-                                destination
-                                    .as_number()
+                                ids.id(destination)
                                     .to_string()
                                     .bold()
                                     .bright_green()
@@ -140,8 +183,7 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
                         write!(
                             f,
                             "-> {}",
-                            destination
-                                .as_number()
+                            ids.id(destination)
                                 .to_string()
                                 .bold()
                                 .bright_green()
@@ -167,11 +209,7 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
                             f,
                             "({}) -> {}",
                             condition.truecolor(128, 128, 128),
-                            if_true
-                                .as_number()
-                                .to_string()
-                                .bold()
-                                .bright_green()
+                            ids.id(if_true).to_string().bold().bright_green()
                         )
                         .whatever_context("Writing to stdout failed")?;
                         if let Some(label) = &cfg.vertices[if_true].label {
@@ -188,11 +226,7 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
                             f,
                             "  ({}) -> {}",
                             format!("!{}", condition).truecolor(128, 128, 128),
-                            if_false
-                                .as_number()
-                                .to_string()
-                                .bold()
-                                .bright_green()
+                            ids.id(if_false).to_string().bold().bright_green()
                         )
                         .whatever_context("Writing to stdout failed")?;
                         if let Some(label) = &cfg.vertices[if_false].label {
@@ -236,25 +270,38 @@ fn print_pretty(program: Program) -> Result<(), Whatever> {
 #[snafu::report]
 fn main() -> Result<(), Whatever> {
     let opts = argh::from_env::<Opts>();
+    let filter = FunctionFilter::new(opts.function.clone());
 
-    let program: Program = if let Some(path) = opts.input {
-        let contents = fs::read_to_string(&path).whatever_context(format!(
-            "Failed to read the contents of {}",
-            path.to_string_lossy()
-        ))?;
-        serde_json::from_str(&contents).whatever_context(
-            "Failed to parse input file as a valid Bril program",
-        )?
-    } else {
-        serde_json::from_reader(io::stdin()).whatever_context(
-            "Failed to parse standard input as a valid Bril program",
-        )?
-    };
+    watch::run(opts.input.as_deref(), opts.watch, || {
+        let program: Program = if let Some(path) = &opts.input {
+            let contents =
+                fs::read_to_string(path).whatever_context(format!(
+                    "Failed to read the contents of {}",
+                    path.to_string_lossy()
+                ))?;
+            serde_json::from_str(&contents).whatever_context(
+                "Failed to parse input file as a valid Bril program",
+            )?
+        } else {
+            serde_json::from_reader(io::stdin()).whatever_context(
+                "Failed to parse standard input as a valid Bril program",
+            )?
+        };
 
-    match opts.mode {
-        Mode::Passthrough => print_reconstructed(program)?,
-        Mode::Pretty => print_pretty(program)?,
-    };
+        match &opts.mode {
+            Mode::Passthrough => print_reconstructed(program, &filter)?,
+            Mode::Pretty => print_pretty(program, &filter)?,
+            Mode::Check => {
+                for function in &program.functions {
+                    if !filter.selects(&function.name) {
+                        continue;
+                    }
+                    roundtrip_check(function)
+                        .whatever_context("CFG round trip check failed")?;
+                }
+            }
+        };
 
-    Ok(())
+        Ok(())
+    })
 }
@@ -0,0 +1,290 @@
+use std::{
+    any::{self, Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    sync::Arc,
+};
+
+use bril_util::{
+    capabilities::Capabilities,
+    error::{PassSnafu, ToolError, UnsupportedSnafu},
+};
+use snafu::ResultExt;
+
+use crate::{
+    FunctionCfg,
+    disk_cache::DiskCache,
+    timing::{PassTimings, Timer},
+};
+
+/// Whether a pass may have modified the CFG it ran on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Changed {
+    No,
+    Yes,
+}
+
+impl From<bool> for Changed {
+    fn from(changed: bool) -> Self {
+        if changed { Self::Yes } else { Self::No }
+    }
+}
+
+/// Information computed from a CFG without modifying it, like dominators or
+/// live variables, which an [`AnalysisCache`] can reuse between passes.
+pub trait Analysis: 'static {
+    type Output: 'static;
+
+    /// Computes the analysis on `cfg`, looking up the analyses it depends on
+    /// in `cache`.
+    fn compute(cfg: &FunctionCfg, cache: &mut AnalysisCache) -> Self::Output;
+}
+
+/// An [`Analysis`] depending only on the contents of the CFG, which a
+/// [`DiskCache`] can therefore save between runs, keyed by
+/// [`FunctionCfg::fingerprint`].
+pub trait PersistentAnalysis: Analysis {
+    /// Names this analysis in the cache: it must change whenever the analysis
+    /// or its encoding does.
+    const KEY: &'static str;
+
+    /// Encodes `output`, which was computed on `cfg`.
+    fn save(output: &Self::Output, cfg: &FunctionCfg) -> serde_json::Value;
+
+    /// Decodes an output saved by [`PersistentAnalysis::save`] for a CFG with
+    /// the same fingerprint as `cfg`, if it is valid.
+    fn load(
+        value: serde_json::Value,
+        cfg: &FunctionCfg,
+    ) -> Option<Self::Output>;
+}
+
+/// The analyses computed for a single CFG since it was last modified.
+#[derive(Default)]
+pub struct AnalysisCache {
+    results: HashMap<TypeId, Rc<dyn Any>>,
+    disk: Option<Arc<DiskCache>>,
+    timings: Option<Rc<RefCell<PassTimings>>>,
+}
+
+/// The name of analysis `A` in timing reports, like `Dominators`.
+fn analysis_name<A: Analysis>() -> &'static str {
+    let name = any::type_name::<A>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+impl AnalysisCache {
+    /// The result of `A` on `cfg`, computed only if it is not cached.
+    ///
+    /// Requires: `cfg` is the only CFG this cache has been used for, and it has
+    /// not been modified since [`AnalysisCache::invalidate`] was last called.
+    pub fn get<A: Analysis>(&mut self, cfg: &FunctionCfg) -> Rc<A::Output> {
+        if let Some(result) = self.results.get(&TypeId::of::<A>()) {
+            return result
+                .clone()
+                .downcast::<A::Output>()
+                .expect("analyses are cached by their own type");
+        }
+        let timer = self.timings.is_some().then(Timer::start);
+        let result = Rc::new(A::compute(cfg, self));
+        self.record::<A>(cfg, timer);
+        self.results.insert(TypeId::of::<A>(), result.clone());
+        result
+    }
+
+    fn record<A: Analysis>(&self, cfg: &FunctionCfg, timer: Option<Timer>) {
+        if let (Some(timings), Some(timer)) = (&self.timings, timer) {
+            timings.borrow_mut().record_analysis(
+                analysis_name::<A>(),
+                &cfg.signature.name,
+                timer.stop(),
+            );
+        }
+    }
+
+    /// A cache that also looks up [`PersistentAnalysis`] results in `disk`.
+    pub fn with_disk_cache(disk: Arc<DiskCache>) -> Self {
+        Self {
+            disk: Some(disk),
+            ..Default::default()
+        }
+    }
+
+    /// Like [`AnalysisCache::get`], but if `A` is not cached in memory, it is
+    /// loaded from or saved to the disk cache, if there is one.
+    pub fn get_persistent<A: PersistentAnalysis>(
+        &mut self,
+        cfg: &FunctionCfg,
+    ) -> Rc<A::Output> {
+        if let Some(result) = self.results.get(&TypeId::of::<A>()) {
+            return result
+                .clone()
+                .downcast::<A::Output>()
+                .expect("analyses are cached by their own type");
+        }
+        let Some(disk) = self.disk.clone() else {
+            return self.get::<A>(cfg);
+        };
+        let timer = self.timings.is_some().then(Timer::start);
+        let result = Rc::new(disk.get_or_compute(
+            A::KEY,
+            cfg.fingerprint(),
+            |output| A::save(output, cfg),
+            |value| A::load(value, cfg),
+            || A::compute(cfg, self),
+        ));
+        self.record::<A>(cfg, timer);
+        self.results.insert(TypeId::of::<A>(), result.clone());
+        result
+    }
+
+    /// Forgets every cached analysis, which must be done whenever the CFG is
+    /// modified.
+    pub fn invalidate(&mut self) {
+        self.results.clear();
+    }
+}
+
+/// An optimization that transforms one function at a time, which a
+/// [`PassManager`] can run as part of a pipeline of passes.
+pub trait FunctionPass {
+    /// The name used to select this pass in a pipeline, like `tdce`.
+    fn name(&self) -> &'static str;
+
+    /// The Bril extensions this pass handles correctly. A [`PassManager`] does
+    /// not run it on a function using any others.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Transforms `cfg`, using `cache` for any analyses it needs. A pass that
+    /// modifies `cfg` and then needs further analyses must invalidate `cache`
+    /// first.
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError>;
+}
+
+/// What a [`PassManager`] does with a pass that does not support a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnsupportedPolicy {
+    /// Fails with [`ToolError::Unsupported`].
+    #[default]
+    Reject,
+    /// Leaves the function as it is and continues with the next pass.
+    Skip,
+}
+
+/// A pass a [`PassManager`] did not run on a function because the function
+/// uses extensions the pass does not support.
+#[derive(Debug, Clone)]
+pub struct SkippedPass {
+    pub pass: &'static str,
+    pub function: String,
+    pub missing: Capabilities,
+}
+
+/// Runs a sequence of passes, sharing analyses between consecutive passes that
+/// do not modify the CFG.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn FunctionPass>>,
+    disk: Option<Arc<DiskCache>>,
+    timings: Option<Rc<RefCell<PassTimings>>>,
+    unsupported: UnsupportedPolicy,
+    skipped: Vec<SkippedPass>,
+}
+
+impl PassManager {
+    pub fn add_pass(&mut self, pass: Box<dyn FunctionPass>) {
+        self.passes.push(pass);
+    }
+
+    /// Lets passes load and save [`PersistentAnalysis`] results in `disk`.
+    pub fn set_disk_cache(&mut self, disk: Arc<DiskCache>) {
+        self.disk = Some(disk);
+    }
+
+    /// Records how long each pass and the analyses it computes take on each
+    /// function in `timings`.
+    pub fn set_timings(&mut self, timings: Rc<RefCell<PassTimings>>) {
+        self.timings = Some(timings);
+    }
+
+    /// Sets what happens when a pass does not support the function it is about
+    /// to run on.
+    pub fn set_unsupported_policy(&mut self, policy: UnsupportedPolicy) {
+        self.unsupported = policy;
+    }
+
+    /// The passes skipped so far under [`UnsupportedPolicy::Skip`].
+    pub fn skipped(&self) -> &[SkippedPass] {
+        &self.skipped
+    }
+
+    /// Runs every pass on `cfg` in the order they were added.
+    pub fn run(&mut self, cfg: &mut FunctionCfg) -> Result<Changed, ToolError> {
+        let mut cache = match &self.disk {
+            Some(disk) => AnalysisCache::with_disk_cache(disk.clone()),
+            None => AnalysisCache::default(),
+        };
+        cache.timings = self.timings.clone();
+        let mut changed = Changed::No;
+        let mut capabilities = cfg.capabilities();
+        for pass in &mut self.passes {
+            let missing = capabilities - pass.capabilities();
+            if !missing.is_empty() {
+                match self.unsupported {
+                    UnsupportedPolicy::Reject => {
+                        return UnsupportedSnafu {
+                            pass: pass.name(),
+                            function: cfg.signature.name.clone(),
+                            missing,
+                        }
+                        .fail();
+                    }
+                    UnsupportedPolicy::Skip => {
+                        self.skipped.push(SkippedPass {
+                            pass: pass.name(),
+                            function: cfg.signature.name.clone(),
+                            missing,
+                        });
+                        continue;
+                    }
+                }
+            }
+
+            let timer = self.timings.is_some().then(Timer::start);
+            let pass_changed =
+                pass.run(cfg, &mut cache).with_context(|_| PassSnafu {
+                    pass: pass.name(),
+                    function: cfg.signature.name.clone(),
+                })?;
+            if let (Some(timings), Some(timer)) = (&self.timings, timer) {
+                timings.borrow_mut().record_pass(
+                    pass.name(),
+                    &cfg.signature.name,
+                    timer.stop(),
+                );
+            }
+            if pass_changed == Changed::Yes {
+                // catch a pass corrupting the CFG before a later pass trips
+                // over it
+                if cfg!(debug_assertions) {
+                    cfg.verify().map_err(ToolError::from).with_context(
+                        |_| PassSnafu {
+                            pass: pass.name(),
+                            function: cfg.signature.name.clone(),
+                        },
+                    )?;
+                }
+                cache.invalidate();
+                // a pass like `ssa` may introduce an extension
+                capabilities = cfg.capabilities();
+                changed = Changed::Yes;
+            }
+        }
+        Ok(changed)
+    }
+}
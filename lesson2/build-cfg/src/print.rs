@@ -1,10 +1,12 @@
-use std::iter;
+use std::{iter, str::FromStr};
+
+use owo_colors::OwoColorize;
 
 use crate::FunctionCfg;
 
-/// The entry block will always be printed first.
-pub fn print_cfg_as_bril_text(cfg: FunctionCfg) {
-    println!(
+/// The lines [`print_cfg_as_bril_text`] prints for `cfg`.
+pub fn cfg_to_bril_lines(cfg: &FunctionCfg) -> Vec<String> {
+    let mut lines = vec![format!(
         "@{}({}){} {{",
         cfg.signature.name,
         cfg.signature
@@ -13,12 +15,12 @@ pub fn print_cfg_as_bril_text(cfg: FunctionCfg) {
             .map(|argument| argument.to_string())
             .collect::<Vec<_>>()
             .join(", "),
-        if let Some(return_type) = cfg.signature.return_type {
+        if let Some(return_type) = &cfg.signature.return_type {
             format!(": {}", return_type)
         } else {
             "".into()
         }
-    );
+    )];
 
     // we do this thing so that if a project introduces a new entry block it'll
     // always be guaranteed to be printed first, so they can end the block
@@ -30,11 +32,157 @@ pub fn print_cfg_as_bril_text(cfg: FunctionCfg) {
     );
     for block in blocks {
         if let Some(label) = &block.label {
-            println!(".{}:", label.name);
+            lines.push(format!(".{}:", label.name));
         }
         for instruction in &block.instructions {
-            println!("  {}", instruction);
+            lines.push(format!("  {}", instruction));
+        }
+    }
+    lines.push("}".into());
+    lines
+}
+
+/// The entry block will always be printed first.
+pub fn print_cfg_as_bril_text(cfg: FunctionCfg) {
+    for line in cfg_to_bril_lines(&cfg) {
+        println!("{line}");
+    }
+}
+
+/// How [`print_cfg_diff`] lays out the lines before and after a pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStyle {
+    /// One column, with removed lines marked `-` and added lines `+`.
+    Unified,
+    /// The lines before on the left and after on the right.
+    SideBySide,
+}
+
+impl FromStr for DiffStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unified" => Ok(Self::Unified),
+            "side-by-side" => Ok(Self::SideBySide),
+            other => Err(format!("Unknown diff style '{}'", other)),
+        }
+    }
+}
+
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The shortest edit from `before` to `after`, found from their longest
+/// common subsequence of lines.
+fn diff_lines<'a>(
+    before: &'a [String],
+    after: &'a [String],
+) -> Vec<DiffLine<'a>> {
+    // common[i][j] is the length of the longest common subsequence of
+    // before[i..] and after[j..]
+    let mut common = vec![vec![0usize; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            lines.push(DiffLine::Same(&before[i]));
+            i += 1;
+            j += 1;
+        } else if j == after.len()
+            || (i < before.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            lines.push(DiffLine::Removed(&before[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(&after[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// A row of a side-by-side diff: the line before, the line after, and whether
+/// it changed.
+type Row<'a> = (Option<&'a str>, Option<&'a str>, bool);
+
+/// Moves a run of changed lines into `rows`, with the removed lines next to the
+/// added lines replacing them.
+fn pair_changed_lines<'a>(
+    rows: &mut Vec<Row<'a>>,
+    removed: &mut Vec<&'a str>,
+    added: &mut Vec<&'a str>,
+) {
+    for k in 0..removed.len().max(added.len()) {
+        rows.push((removed.get(k).copied(), added.get(k).copied(), true));
+    }
+    removed.clear();
+    added.clear();
+}
+
+/// Prints `cfg` like [`print_cfg_as_bril_text`], but compared against
+/// `before`, the lines of the same function before a pass ran, with removed
+/// lines in red and added lines in green.
+pub fn print_cfg_diff(before: &[String], cfg: &FunctionCfg, style: DiffStyle) {
+    let after = cfg_to_bril_lines(cfg);
+    let lines = diff_lines(before, &after);
+    match style {
+        DiffStyle::Unified => {
+            for line in lines {
+                match line {
+                    DiffLine::Same(line) => println!("  {line}"),
+                    DiffLine::Removed(line) => {
+                        println!("{}", format!("- {line}").red())
+                    }
+                    DiffLine::Added(line) => {
+                        println!("{}", format!("+ {line}").green())
+                    }
+                }
+            }
+        }
+        DiffStyle::SideBySide => {
+            let width = before
+                .iter()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0);
+            let mut rows = vec![];
+            let mut removed = vec![];
+            let mut added = vec![];
+            for line in lines {
+                match line {
+                    DiffLine::Same(line) => {
+                        pair_changed_lines(&mut rows, &mut removed, &mut added);
+                        rows.push((Some(line), Some(line), false));
+                    }
+                    DiffLine::Removed(line) => removed.push(line),
+                    DiffLine::Added(line) => added.push(line),
+                }
+            }
+            pair_changed_lines(&mut rows, &mut removed, &mut added);
+
+            for (left, right, changed) in rows {
+                let left = format!("{:width$}", left.unwrap_or(""));
+                let right = right.unwrap_or("");
+                if changed {
+                    println!("{} | {}", left.red(), right.green());
+                } else {
+                    println!("{left} | {right}");
+                }
+            }
         }
     }
-    println!("}}");
 }
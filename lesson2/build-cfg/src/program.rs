@@ -0,0 +1,139 @@
+//! The CFGs of every function in a program together with its call graph, for
+//! interprocedural analyses and passes that need to see more than one
+//! function at a time.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bril_rs::{EffectOps, Import, Instruction, Program, ValueOps};
+
+use crate::{CfgBuildError, FunctionCfg, build_cfg, into_program};
+
+/// The CFG of each function in a program, in the order the program defines
+/// them, and which functions each calls. Calls to functions the program does
+/// not define, such as imported ones, are not in the call graph.
+pub struct ProgramCfg {
+    functions: Vec<FunctionCfg>,
+    /// The position of each function in `functions` by name.
+    indices: HashMap<String, usize>,
+    pub imports: Vec<Import>,
+    callees: BTreeMap<String, BTreeSet<String>>,
+    callers: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl ProgramCfg {
+    /// Builds the CFG of each function in `program` as
+    /// [`build_cfg`](crate::build_cfg) does, and its call graph.
+    pub fn new(program: &Program, prune: bool) -> Result<Self, CfgBuildError> {
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| build_cfg(function, prune))
+            .collect::<Result<Vec<_>, _>>()?;
+        let indices = functions
+            .iter()
+            .enumerate()
+            .map(|(index, cfg)| (cfg.signature.name.clone(), index))
+            .collect();
+        let mut program_cfg = Self {
+            functions,
+            indices,
+            imports: program.imports.clone(),
+            callees: BTreeMap::new(),
+            callers: BTreeMap::new(),
+        };
+        program_cfg.rebuild_call_graph();
+        Ok(program_cfg)
+    }
+
+    /// The CFG of the function `name`, if the program defines it.
+    pub fn get(&self, name: &str) -> Option<&FunctionCfg> {
+        self.indices.get(name).map(|index| &self.functions[*index])
+    }
+
+    /// The CFG of the function `name`, if the program defines it. Changing its
+    /// calls leaves the call graph out of date until
+    /// [`ProgramCfg::rebuild_call_graph`].
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut FunctionCfg> {
+        self.indices
+            .get(name)
+            .map(|index| &mut self.functions[*index])
+    }
+
+    /// The CFG of each function, in the order the program defines them.
+    pub fn iter(&self) -> impl Iterator<Item = &FunctionCfg> {
+        self.functions.iter()
+    }
+
+    /// The CFG of each function, in the order the program defines them, with
+    /// the same caveat as [`ProgramCfg::get_mut`]. Functions must not be
+    /// renamed.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut FunctionCfg> {
+        self.functions.iter_mut()
+    }
+
+    /// The functions `function` calls, by name.
+    pub fn callees(&self, function: &str) -> impl Iterator<Item = &str> {
+        self.callees
+            .get(function)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// The functions that call `function`, by name.
+    pub fn callers(&self, function: &str) -> impl Iterator<Item = &str> {
+        self.callers
+            .get(function)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Recomputes the call graph from the `call` instructions in the CFGs,
+    /// after passes have added or removed calls.
+    pub fn rebuild_call_graph(&mut self) {
+        self.callees.clear();
+        self.callers.clear();
+        for cfg in &self.functions {
+            let caller = &cfg.signature.name;
+            let callees = cfg
+                .vertices
+                .values()
+                .flat_map(|block| &block.instructions)
+                .filter_map(called_function)
+                .filter(|callee| self.indices.contains_key(*callee))
+                .map(str::to_string)
+                .collect::<BTreeSet<_>>();
+            for callee in &callees {
+                self.callers
+                    .entry(callee.clone())
+                    .or_default()
+                    .insert(caller.clone());
+            }
+            self.callees.insert(caller.clone(), callees);
+        }
+    }
+
+    /// Converts the CFGs back into a program, as
+    /// [`into_program`](crate::into_program) does.
+    pub fn into_program(self) -> Program {
+        into_program(self.functions, self.imports)
+    }
+}
+
+/// The function `instruction` calls, if it is a call.
+fn called_function(instruction: &Instruction) -> Option<&str> {
+    match instruction {
+        Instruction::Value {
+            funcs,
+            op: ValueOps::Call,
+            ..
+        }
+        | Instruction::Effect {
+            funcs,
+            op: EffectOps::Call,
+            ..
+        } => funcs.first().map(String::as_str),
+        _ => None,
+    }
+}
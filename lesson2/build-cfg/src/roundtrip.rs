@@ -0,0 +1,314 @@
+//! Checks that building a CFG and converting it back into a function keeps the
+//! control flow of the function. The instruction streams before and after are
+//! split into blocks here independently of [`build_cfg`], so that a bug in
+//! splitting, like dropping a trailing unlabeled block or duplicating a jump,
+//! is not repeated by the check.
+
+use std::collections::HashMap;
+
+use bril_rs::{Code, EffectOps, Function, Instruction};
+use bril_util::error::ToolError;
+use snafu::{ResultExt, Snafu};
+
+use crate::{CfgBuildError, build_cfg};
+
+#[derive(Debug, Snafu)]
+pub enum RoundtripError {
+    #[snafu(display("Failed to build the CFG of @{function}"))]
+    Build {
+        function: String,
+        source: CfgBuildError,
+    },
+
+    #[snafu(display(
+        "The rebuilt @{function} refers to .{label}, which it does not define"
+    ))]
+    MissingLabel { function: String, label: String },
+
+    #[snafu(display(
+        "In @{function}, the instructions of {block} changed in the round trip"
+    ))]
+    BodyChanged { function: String, block: String },
+
+    #[snafu(display(
+        "In @{function}, {block} ends with {original}, but with {rebuilt} after the round trip"
+    ))]
+    TerminatorChanged {
+        function: String,
+        block: String,
+        original: String,
+        rebuilt: String,
+    },
+
+    #[snafu(display(
+        "In @{function}, {first} and {second} became the same block in the round trip"
+    ))]
+    BlocksMerged {
+        function: String,
+        first: String,
+        second: String,
+    },
+
+    #[snafu(display(
+        "In @{function}, {block} became more than one block in the round trip"
+    ))]
+    BlockDuplicated { function: String, block: String },
+
+    #[snafu(display(
+        "@{function} has {original} unreachable blocks, but {rebuilt} after the round trip"
+    ))]
+    UnreachableBlocks {
+        function: String,
+        original: usize,
+        rebuilt: usize,
+    },
+}
+
+impl From<RoundtripError> for ToolError {
+    fn from(error: RoundtripError) -> Self {
+        match error {
+            RoundtripError::Build { source, .. } => source.into(),
+            _ => ToolError::InvariantViolated {
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Where control goes after a block, with blocks by their index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow<'a> {
+    /// Continues to a block by falling through or jumping.
+    Goto(usize),
+    Branch {
+        condition: &'a str,
+        if_true: usize,
+        if_false: usize,
+    },
+    /// Returns, including by falling off the end of the function.
+    Return(Option<&'a str>),
+}
+
+struct StreamBlock<'a> {
+    label: Option<&'a str>,
+    /// The instructions other than a final jump, branch, or return.
+    body: Vec<&'a Instruction>,
+    flow: Flow<'a>,
+}
+
+impl StreamBlock<'_> {
+    fn name(&self, index: usize) -> String {
+        match self.label {
+            Some(label) => format!(".{label}"),
+            None if index == 0 => "the entry block".into(),
+            None => format!("unlabeled block #{index}"),
+        }
+    }
+}
+
+/// Splits `function` into blocks at labels and after each jump, branch, or
+/// return. The first block is the entry, even if it is empty.
+fn split<'a>(
+    function: &'a Function,
+) -> Result<Vec<StreamBlock<'a>>, RoundtripError> {
+    // the flows are resolved once every label has been seen
+    enum Exit<'a> {
+        Fallthrough,
+        Jump(&'a str),
+        Branch(&'a str, &'a str, &'a str),
+        Return(Option<&'a str>),
+    }
+
+    let mut blocks = vec![];
+    let mut label = None;
+    let mut body = vec![];
+    for code in &function.instrs {
+        let exit = match code {
+            Code::Label { label: next, .. } => {
+                if label.is_some() || !body.is_empty() || blocks.is_empty() {
+                    blocks.push((label, body, Exit::Fallthrough));
+                    body = vec![];
+                }
+                label = Some(next.as_str());
+                continue;
+            }
+            Code::Instruction(
+                instruction @ Instruction::Effect {
+                    args, labels, op, ..
+                },
+            ) => match (op, args.as_slice(), labels.as_slice()) {
+                (EffectOps::Jump, _, [destination]) => Exit::Jump(destination),
+                (EffectOps::Branch, [condition], [if_true, if_false]) => {
+                    Exit::Branch(condition, if_true, if_false)
+                }
+                (EffectOps::Return, args, _) => {
+                    Exit::Return(args.first().map(String::as_str))
+                }
+                _ => {
+                    body.push(instruction);
+                    continue;
+                }
+            },
+            Code::Instruction(instruction) => {
+                body.push(instruction);
+                continue;
+            }
+        };
+        blocks.push((label.take(), body, exit));
+        body = vec![];
+    }
+    if label.is_some() || !body.is_empty() || blocks.is_empty() {
+        blocks.push((label, body, Exit::Fallthrough));
+    }
+
+    let indices = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (label, _, _))| Some(((*label)?, index)))
+        .collect::<HashMap<_, _>>();
+    let resolve = |label: &str| {
+        indices.get(label).copied().context(MissingLabelSnafu {
+            function: &function.name,
+            label,
+        })
+    };
+    let count = blocks.len();
+    blocks
+        .into_iter()
+        .enumerate()
+        .map(|(index, (label, body, exit))| {
+            let flow = match exit {
+                Exit::Fallthrough if index + 1 < count => Flow::Goto(index + 1),
+                Exit::Fallthrough => Flow::Return(None),
+                Exit::Jump(destination) => Flow::Goto(resolve(destination)?),
+                Exit::Branch(condition, if_true, if_false) => Flow::Branch {
+                    condition,
+                    if_true: resolve(if_true)?,
+                    if_false: resolve(if_false)?,
+                },
+                Exit::Return(value) => Flow::Return(value),
+            };
+            Ok(StreamBlock { label, body, flow })
+        })
+        .collect()
+}
+
+fn describe(flow: Flow, blocks: &[StreamBlock]) -> String {
+    match flow {
+        Flow::Goto(destination) => {
+            format!("a jump to {}", blocks[destination].name(destination))
+        }
+        Flow::Branch { condition, .. } => format!("a branch on `{condition}`"),
+        Flow::Return(Some(value)) => format!("a return of `{value}`"),
+        Flow::Return(None) => "a return".into(),
+    }
+}
+
+/// Builds the CFG of `function`, converts it back with
+/// [`FunctionCfg::into_function`](crate::FunctionCfg::into_function), and
+/// checks that both functions reach corresponding blocks from their entries,
+/// with the same instructions and terminators, and have as many unreachable
+/// blocks. Jumps may become fallthroughs and back.
+pub fn roundtrip_check(function: &Function) -> Result<(), RoundtripError> {
+    let rebuilt_function = build_cfg(function, false)
+        .context(BuildSnafu {
+            function: &function.name,
+        })?
+        .into_function();
+    let original_blocks = split(function)?;
+    let rebuilt_blocks = split(&rebuilt_function)?;
+
+    let mut to_rebuilt = HashMap::new();
+    let mut to_original = HashMap::new();
+    let mut stack = vec![(0, 0)];
+    while let Some((original, rebuilt)) = stack.pop() {
+        match to_rebuilt.get(&original) {
+            Some(previous) if *previous == rebuilt => continue,
+            Some(_) => {
+                return BlockDuplicatedSnafu {
+                    function: &function.name,
+                    block: original_blocks[original].name(original),
+                }
+                .fail();
+            }
+            None => {}
+        }
+        if let Some(first) = to_original.get(&rebuilt) {
+            return BlocksMergedSnafu {
+                function: &function.name,
+                first: original_blocks[*first].name(*first),
+                second: original_blocks[original].name(original),
+            }
+            .fail();
+        }
+        to_rebuilt.insert(original, rebuilt);
+        to_original.insert(rebuilt, original);
+
+        let original_block = &original_blocks[original];
+        let rebuilt_block = &rebuilt_blocks[rebuilt];
+        if original_block.body != rebuilt_block.body {
+            return BodyChangedSnafu {
+                function: &function.name,
+                block: original_block.name(original),
+            }
+            .fail();
+        }
+        match (original_block.flow, rebuilt_block.flow) {
+            (Flow::Goto(destination), Flow::Goto(rebuilt_destination)) => {
+                stack.push((destination, rebuilt_destination));
+            }
+            (
+                Flow::Branch {
+                    condition,
+                    if_true,
+                    if_false,
+                },
+                Flow::Branch {
+                    condition: rebuilt_condition,
+                    if_true: rebuilt_if_true,
+                    if_false: rebuilt_if_false,
+                },
+            ) if condition == rebuilt_condition => {
+                stack.push((if_true, rebuilt_if_true));
+                stack.push((if_false, rebuilt_if_false));
+            }
+            (Flow::Return(value), Flow::Return(rebuilt_value))
+                if value == rebuilt_value => {}
+            (original_flow, rebuilt_flow) => {
+                return TerminatorChangedSnafu {
+                    function: &function.name,
+                    block: original_block.name(original),
+                    original: describe(original_flow, &original_blocks),
+                    rebuilt: describe(rebuilt_flow, &rebuilt_blocks),
+                }
+                .fail();
+            }
+        }
+    }
+
+    // only blocks that do something count, since an empty block may be added
+    // or removed after a return without changing anything
+    let unreachable = |blocks: &[StreamBlock], reached: &HashMap<_, _>| {
+        blocks
+            .iter()
+            .enumerate()
+            .filter(|(index, block)| {
+                !reached.contains_key(index)
+                    && !(block.body.is_empty()
+                        && matches!(block.flow, Flow::Return(None)))
+            })
+            .count()
+    };
+    let original = unreachable(&original_blocks, &to_rebuilt);
+    let rebuilt = unreachable(&rebuilt_blocks, &to_original);
+    if original != rebuilt {
+        return UnreachableBlocksSnafu {
+            function: &function.name,
+            original,
+            rebuilt,
+        }
+        .fail();
+    }
+
+    Ok(())
+}
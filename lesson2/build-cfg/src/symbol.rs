@@ -0,0 +1,69 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// An interned variable name, which is cheap to copy, hash, and compare,
+/// unlike the `String`s in instructions. Symbols are ordered by when they were
+/// interned, not by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The number of symbols interned before this one.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    names: Vec<Rc<str>>,
+    symbols: HashMap<Rc<str>, Symbol>,
+}
+
+/// The variable names of a CFG. Passes add variables without access to the
+/// table, so names not yet interned are interned on demand: interning only
+/// needs a shared reference.
+#[derive(Default)]
+pub struct SymbolTable {
+    interner: RefCell<Interner>,
+}
+
+impl SymbolTable {
+    /// The symbol for `name`, interning it if it is new.
+    pub fn intern(&self, name: &str) -> Symbol {
+        if let Some(symbol) = self.get(name) {
+            return symbol;
+        }
+        let mut interner = self.interner.borrow_mut();
+        let symbol = Symbol(interner.names.len() as u32);
+        let name = Rc::<str>::from(name);
+        interner.names.push(name.clone());
+        interner.symbols.insert(name, symbol);
+        symbol
+    }
+
+    /// The symbol for `name`, if it has been interned.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.interner.borrow().symbols.get(name).copied()
+    }
+
+    /// The number of symbols interned so far, which are numbered from zero.
+    pub fn len(&self) -> usize {
+        self.interner.borrow().names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The names interned so far, in the order of their symbols.
+    pub fn names(&self) -> Vec<Rc<str>> {
+        self.interner.borrow().names.clone()
+    }
+
+    /// The name `symbol` was interned from.
+    ///
+    /// Requires: `symbol` was interned by this table.
+    pub fn name(&self, symbol: Symbol) -> Rc<str> {
+        self.interner.borrow().names[symbol.0 as usize].clone()
+    }
+}
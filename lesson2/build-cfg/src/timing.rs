@@ -0,0 +1,256 @@
+//! Wall-clock time and peak allocations of passes and analyses, reported like
+//! LLVM's `-time-passes`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::BTreeMap,
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator, but counting the bytes allocated through it so that
+/// [`PassTimings`] can report peak allocations. A binary opts in with
+/// `#[global_allocator] static ALLOCATOR: CountingAllocator =
+/// CountingAllocator;`, and otherwise only time is reported.
+pub struct CountingAllocator;
+
+fn record_allocation(size: usize) {
+    INSTALLED.store(true, Ordering::Relaxed);
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc(layout) };
+        if !pointer.is_null() {
+            record_allocation(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc_zeroed(layout) };
+        if !pointer.is_null() {
+            record_allocation(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(pointer, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(
+        &self,
+        pointer: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_pointer = unsafe { System.realloc(pointer, layout, new_size) };
+        if !new_pointer.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            record_allocation(new_size);
+        }
+        new_pointer
+    }
+}
+
+/// Measures the time and peak allocations from its creation until
+/// [`Timer::stop`]. Timers may be nested, like an analysis computed during a
+/// pass, without hiding the inner peak from the outer timer.
+pub struct Timer {
+    start: Instant,
+    baseline: usize,
+    outer_peak: usize,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        let baseline = ALLOCATED.load(Ordering::Relaxed);
+        Self {
+            start: Instant::now(),
+            baseline,
+            outer_peak: PEAK.swap(baseline, Ordering::Relaxed),
+        }
+    }
+
+    /// The time elapsed and, if a [`CountingAllocator`] is installed, the most
+    /// bytes allocated at once beyond those allocated when the timer started.
+    pub fn stop(self) -> Measurement {
+        let time = self.start.elapsed();
+        let peak = PEAK.fetch_max(self.outer_peak, Ordering::Relaxed);
+        Measurement {
+            time,
+            peak_bytes: INSTALLED
+                .load(Ordering::Relaxed)
+                .then(|| peak.saturating_sub(self.baseline)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Measurement {
+    pub time: Duration,
+    pub peak_bytes: Option<usize>,
+}
+
+impl Measurement {
+    /// Sums the times of `self` and `other`, keeping the larger peak.
+    fn combine(self, other: Measurement) -> Measurement {
+        Measurement {
+            time: self.time + other.time,
+            peak_bytes: match (self.peak_bytes, other.peak_bytes) {
+                (Some(lhs), Some(rhs)) => Some(lhs.max(rhs)),
+                (lhs, rhs) => lhs.or(rhs),
+            },
+        }
+    }
+}
+
+/// The measurements of each pass and analysis on each function, summed over
+/// repeated runs. Analyses are computed during passes, so their time is also
+/// counted in the time of those passes.
+#[derive(Default)]
+pub struct PassTimings {
+    passes: BTreeMap<&'static str, BTreeMap<String, Measurement>>,
+    analyses: BTreeMap<&'static str, BTreeMap<String, Measurement>>,
+}
+
+impl PassTimings {
+    pub fn record_pass(
+        &mut self,
+        pass: &'static str,
+        function: &str,
+        measurement: Measurement,
+    ) {
+        record(&mut self.passes, pass, function, measurement);
+    }
+
+    pub fn record_analysis(
+        &mut self,
+        analysis: &'static str,
+        function: &str,
+        measurement: Measurement,
+    ) {
+        record(&mut self.analyses, analysis, function, measurement);
+    }
+
+    /// Adds the measurements in `other`, like those of functions optimized on
+    /// another thread.
+    pub fn merge(&mut self, other: PassTimings) {
+        for (pass, functions) in other.passes {
+            for (function, measurement) in functions {
+                self.record_pass(pass, &function, measurement);
+            }
+        }
+        for (analysis, functions) in other.analyses {
+            for (function, measurement) in functions {
+                self.record_analysis(analysis, &function, measurement);
+            }
+        }
+    }
+}
+
+fn record(
+    measurements: &mut BTreeMap<&'static str, BTreeMap<String, Measurement>>,
+    name: &'static str,
+    function: &str,
+    measurement: Measurement,
+) {
+    let entry = measurements
+        .entry(name)
+        .or_default()
+        .entry(function.to_string())
+        .or_default();
+    *entry = entry.combine(measurement);
+}
+
+/// Writes one report, with each name followed by its functions, all sorted by
+/// decreasing time.
+fn write_report(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    measurements: &BTreeMap<&'static str, BTreeMap<String, Measurement>>,
+) -> fmt::Result {
+    let ruler = format!("==={}===", "-".repeat(73));
+    writeln!(f, "{ruler}")?;
+    writeln!(f, "{:^79}", title)?;
+    writeln!(f, "{ruler}")?;
+
+    let mut totals = measurements
+        .iter()
+        .map(|(name, functions)| {
+            let total = functions
+                .values()
+                .fold(Measurement::default(), |total, measurement| {
+                    total.combine(*measurement)
+                });
+            (*name, total, functions)
+        })
+        .collect::<Vec<_>>();
+    totals.sort_by(|lhs, rhs| rhs.1.time.cmp(&lhs.1.time));
+    let total = totals
+        .iter()
+        .fold(Measurement::default(), |total, (_, measurement, _)| {
+            total.combine(*measurement)
+        });
+    writeln!(
+        f,
+        "  Total Execution Time: {:.4} seconds\n",
+        total.time.as_secs_f64()
+    )?;
+    writeln!(f, "   ---Wall Time---  --Peak Memory--  --- Name ---")?;
+
+    let write_row = |f: &mut fmt::Formatter<'_>,
+                     measurement: &Measurement,
+                     name: &str|
+     -> fmt::Result {
+        let percent = if total.time.is_zero() {
+            0.0
+        } else {
+            100.0 * measurement.time.as_secs_f64() / total.time.as_secs_f64()
+        };
+        let peak = measurement
+            .peak_bytes
+            .map(|bytes| format!("{bytes} B"))
+            .unwrap_or_else(|| "-".to_string());
+        writeln!(
+            f,
+            "   {:.4} ({:5.1}%)  {:>15}  {name}",
+            measurement.time.as_secs_f64(),
+            percent,
+            peak
+        )
+    };
+    for (name, measurement, functions) in &totals {
+        write_row(f, measurement, name)?;
+        let mut functions = functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|lhs, rhs| rhs.1.time.cmp(&lhs.1.time));
+        for (function, measurement) in functions {
+            write_row(f, measurement, &format!("  @{function}"))?;
+        }
+    }
+    write_row(f, &total, "Total")
+}
+
+impl fmt::Display for PassTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_report(f, "Pass execution timing report", &self.passes)?;
+        if !self.analyses.is_empty() {
+            writeln!(f)?;
+            write_report(
+                f,
+                "Analysis execution timing report",
+                &self.analyses,
+            )?;
+        }
+        Ok(())
+    }
+}
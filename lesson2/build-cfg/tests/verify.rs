@@ -0,0 +1,230 @@
+use bril_rs::Function;
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, CfgVerifyError, Exit, FunctionCfg, Label,
+    LabeledExit, build_cfg,
+};
+use serde_json::json;
+
+/// `@diamond(c: bool)`, branching on `c` to `.then` or `.else`, both of which
+/// jump to `.done`.
+fn diamond() -> FunctionCfg {
+    let function: Function = serde_json::from_value(json!({
+        "name": "diamond",
+        "args": [{ "name": "c", "type": "bool" }],
+        "instrs": [
+            { "op": "br", "args": ["c"], "labels": ["then", "else"] },
+            { "label": "then" },
+            { "op": "jmp", "labels": ["done"] },
+            { "label": "else" },
+            { "op": "jmp", "labels": ["done"] },
+            { "label": "done" },
+            { "op": "ret" },
+        ],
+    }))
+    .expect("The function is valid Bril");
+    build_cfg(&function, false).expect("The function has a CFG")
+}
+
+fn block(cfg: &FunctionCfg, label: &str) -> BasicBlockIdx {
+    cfg.vertices
+        .iter()
+        .find(|(_, block)| {
+            block.label.as_ref().is_some_and(|name| name.name == label)
+        })
+        .map(|(block_idx, _)| block_idx)
+        .expect("The block is in the CFG")
+}
+
+/// A labeled block with no exit yet.
+fn add_block(cfg: &mut FunctionCfg, label: &str) -> BasicBlockIdx {
+    cfg.add_block(BasicBlock {
+        label: Some(Label { name: label.into() }),
+        ..Default::default()
+    })
+}
+
+/// An index that no longer refers to a block of `cfg`.
+fn removed_block(cfg: &mut FunctionCfg) -> BasicBlockIdx {
+    let block = cfg.add_block(BasicBlock::default());
+    cfg.vertices.remove(block);
+    block
+}
+
+#[test]
+fn built_cfg_verifies() {
+    let mut cfg = diamond();
+    assert!(cfg.verify().is_ok());
+    cfg.make_fallthroughs_explicit();
+    assert!(cfg.verify().is_ok());
+}
+
+#[test]
+fn missing_entry() {
+    let mut cfg = diamond();
+    let entry = cfg.entry;
+    cfg.vertices.remove(entry);
+    assert!(matches!(cfg.verify(), Err(CfgVerifyError::MissingEntry)));
+}
+
+#[test]
+fn missing_exit() {
+    let mut cfg = diamond();
+    let done = block(&cfg, "done");
+    cfg.edges.remove(done);
+    assert!(matches!(
+        cfg.verify(),
+        Err(CfgVerifyError::MissingExit { block }) if block == ".done"
+    ));
+}
+
+#[test]
+fn dangling_edge() {
+    let mut cfg = diamond();
+    let then = block(&cfg, "then");
+    let removed = removed_block(&mut cfg);
+    cfg.edges[then] = Exit::Unconditional(removed);
+    assert!(matches!(
+        cfg.verify(),
+        Err(CfgVerifyError::DanglingEdge { block }) if block == ".then"
+    ));
+}
+
+#[test]
+fn exit_mismatch() {
+    let mut cfg = diamond();
+    let then = block(&cfg, "then");
+    cfg.vertices[then].exit = LabeledExit::Unconditional {
+        label: "else".into(),
+        pos: None,
+    };
+    assert!(matches!(
+        cfg.verify(),
+        Err(CfgVerifyError::ExitMismatch { block, labeled_exit, exit })
+            if block == ".then"
+                && labeled_exit == "`jmp .else`"
+                && exit == "a jump to .done"
+    ));
+}
+
+#[test]
+fn predecessor_mismatch() {
+    let mut cfg = diamond();
+    let done = block(&cfg, "done");
+    cfg.rev_edges[done].clear();
+    assert!(matches!(
+        cfg.verify(),
+        Err(CfgVerifyError::PredecessorMismatch { block }) if block == ".done"
+    ));
+}
+
+#[test]
+fn stale_block() {
+    let mut cfg = diamond();
+    let removed = removed_block(&mut cfg);
+    cfg.rev_edges.insert(removed, vec![]);
+    assert!(matches!(cfg.verify(), Err(CfgVerifyError::StaleBlock)));
+}
+
+#[test]
+fn duplicate_label() {
+    let mut cfg = diamond();
+    let else_block = block(&cfg, "else");
+    cfg.vertices[else_block].label = Some(Label {
+        name: "then".into(),
+    });
+    assert!(matches!(
+        cfg.verify(),
+        Err(CfgVerifyError::DuplicateLabel { label }) if label == "then"
+    ));
+}
+
+/// Reorienting a jump and either side of a branch must rewrite the exit along
+/// with the edges.
+#[test]
+fn reorient_edge_verifies() {
+    let mut cfg = diamond();
+    cfg.make_fallthroughs_explicit();
+    let entry = cfg.entry;
+    let then = block(&cfg, "then");
+    let else_block = block(&cfg, "else");
+    let done = block(&cfg, "done");
+
+    let landing = add_block(&mut cfg, "landing");
+    cfg.reorient_edge(then, done, landing);
+    cfg.set_unconditional_edge(landing, done);
+    assert!(cfg.verify().is_ok());
+
+    let guard = add_block(&mut cfg, "guard");
+    cfg.reorient_edge(entry, else_block, guard);
+    cfg.set_unconditional_edge(guard, else_block);
+    assert!(cfg.verify().is_ok());
+    assert!(matches!(
+        &cfg.vertices[entry].exit,
+        LabeledExit::Conditional { if_true_label, if_false_label, .. }
+            if if_true_label == "then" && if_false_label == "guard"
+    ));
+}
+
+/// A branch going to the same block both ways still goes there after one
+/// side is reoriented.
+#[test]
+fn reorient_one_side_of_branch() {
+    let mut cfg = diamond();
+    cfg.make_fallthroughs_explicit();
+    let entry = cfg.entry;
+    let then = block(&cfg, "then");
+    let else_block = block(&cfg, "else");
+    cfg.reorient_edge(entry, else_block, then);
+    assert!(cfg.verify().is_ok());
+
+    let landing = add_block(&mut cfg, "landing");
+    cfg.reorient_edge(entry, then, landing);
+    cfg.set_unconditional_edge(landing, then);
+    cfg.remove_unreachable_blocks();
+    assert!(cfg.verify().is_ok());
+    assert!(cfg.predecessors(then).contains(&entry));
+    assert!(cfg.predecessors(then).contains(&landing));
+}
+
+/// Removing a branch side leaves its block unreachable, and only the block
+/// after it, which survives, lost a predecessor.
+#[test]
+fn remove_unreachable_blocks_reports_survivors() {
+    let mut cfg = diamond();
+    cfg.make_fallthroughs_explicit();
+    let entry = cfg.entry;
+    let then = block(&cfg, "then");
+    let else_block = block(&cfg, "else");
+    let done = block(&cfg, "done");
+    cfg.reorient_edge(entry, else_block, then);
+
+    let (removed, orphaned) = cfg.remove_unreachable_blocks();
+    assert_eq!(removed, 1);
+    assert_eq!(orphaned, [done]);
+    assert!(cfg.verify().is_ok());
+}
+
+/// A block whose only predecessor jumps to it is not merged into that
+/// predecessor while a `guard` still names its label, since guards are not
+/// edges of the CFG.
+#[test]
+fn merge_keeps_labels_named_by_guards() {
+    let function: Function = serde_json::from_value(json!({
+        "name": "speculative",
+        "args": [{ "name": "c", "type": "bool" }],
+        "instrs": [
+            { "op": "speculate" },
+            { "op": "guard", "args": ["c"], "labels": ["retry"] },
+            { "op": "commit" },
+            { "op": "jmp", "labels": ["retry"] },
+            { "label": "retry" },
+            { "op": "ret" },
+        ],
+    }))
+    .expect("The function is valid Bril");
+    let mut cfg = build_cfg(&function, false).expect("The function has a CFG");
+
+    assert_eq!(cfg.merge_straightline_blocks(), 0);
+    block(&cfg, "retry");
+    assert!(cfg.verify().is_ok());
+}
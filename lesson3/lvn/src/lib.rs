@@ -0,0 +1,517 @@
+use std::{cmp::Ordering, collections::HashMap, hash::Hash, sync::Arc};
+
+use bril_rs::{ConstOps, EffectOps, Instruction, Literal, Type, ValueOps};
+use bril_util::{
+    EffectKind, InstructionExt, capabilities::Capabilities, error::ToolError,
+    is_commutative,
+};
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, FunctionCfg,
+    pass::{AnalysisCache, Changed, FunctionPass},
+    symbol::{Symbol, SymbolTable},
+};
+use purity::{Purity, PuritySummaries};
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+enum OpArg {
+    Value(usize),
+    Unknown(Symbol),
+}
+
+impl OpArg {
+    /// Orders values by number before unknown variables by name, so that
+    /// commutative operations are numbered the same however their arguments
+    /// are written.
+    fn compare(&self, other: &Self, symbols: &SymbolTable) -> Ordering {
+        match (self, other) {
+            (Self::Value(lhs), Self::Value(rhs)) => lhs.cmp(rhs),
+            (Self::Value(_), Self::Unknown(_)) => Ordering::Less,
+            (Self::Unknown(_), Self::Value(_)) => Ordering::Greater,
+            (Self::Unknown(lhs), Self::Unknown(rhs)) => {
+                symbols.name(*lhs).cmp(&symbols.name(*rhs))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct NeverEqual;
+
+impl PartialEq for NeverEqual {
+    fn eq(&self, _other: &Self) -> bool {
+        false
+    }
+}
+
+impl Eq for NeverEqual {}
+
+impl Hash for NeverEqual {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+enum Value {
+    Float(String),
+    OtherConst(String),
+    Op(ValueOps, Vec<OpArg>),
+    /// A call to a pure function.
+    Call(String, Vec<OpArg>),
+    /// The result of a `get` in SSA form, by the variable each predecessor
+    /// sets it to.
+    Get(Vec<(BasicBlockIdx, Symbol)>),
+    LeftAlone(NeverEqual),
+}
+
+struct ValueTable<'a> {
+    symbols: &'a SymbolTable,
+    /// `(value, canonical_variable)` pairs
+    values: Vec<(Value, Symbol)>,
+    intern: HashMap<Value, usize>,
+    counter: usize,
+    variables_to_values: HashMap<Symbol, usize>,
+
+    constant_folder: HashMap<usize, Literal>,
+}
+
+impl<'a> ValueTable<'a> {
+    fn new(symbols: &'a SymbolTable) -> Self {
+        Self {
+            symbols,
+            values: vec![],
+            intern: HashMap::new(),
+            counter: 0,
+            variables_to_values: HashMap::new(),
+            constant_folder: HashMap::new(),
+        }
+    }
+
+    fn add_value_and_get_existing_variable(
+        &mut self,
+        value: Value,
+        constant: Option<Literal>,
+        current_variable: &str,
+        is_overwritten: bool,
+    ) -> (String, Option<String>) {
+        let current_symbol = self.symbols.intern(current_variable);
+        if let Some(existing_value_index) = self.intern.get(&value).copied() {
+            self.variables_to_values
+                .insert(current_symbol, existing_value_index);
+            (
+                current_variable.to_owned(),
+                Some(
+                    self.symbols
+                        .name(self.values[existing_value_index].1)
+                        .to_string(),
+                ),
+            )
+        } else {
+            let new_name = if is_overwritten {
+                self.counter += 1;
+                format!("{}__t{}", current_variable, self.counter)
+            } else {
+                current_variable.to_owned()
+            };
+
+            self.values
+                .push((value.clone(), self.symbols.intern(&new_name)));
+            let new_value_index = self.values.len() - 1;
+            self.intern.insert(value, new_value_index);
+
+            if let Some(constant) = constant {
+                self.constant_folder.insert(new_value_index, constant);
+            }
+
+            self.variables_to_values
+                .insert(current_symbol, new_value_index);
+            (new_name, None)
+        }
+    }
+
+    /// Numbers `dest` as `value` without an instruction computing it, like the
+    /// result of a `get`, returning its index.
+    fn number_get(&mut self, dest: &str, value: Value) -> usize {
+        let dest = self.symbols.intern(dest);
+        let index = match self.intern.get(&value) {
+            Some(index) => *index,
+            None => {
+                self.values.push((value.clone(), dest));
+                self.intern.insert(value, self.values.len() - 1);
+                self.values.len() - 1
+            }
+        };
+        self.variables_to_values.insert(dest, index);
+        index
+    }
+
+    fn get_value(&self, variable: &str) -> Option<usize> {
+        let variable = self.symbols.get(variable)?;
+        self.variables_to_values.get(&variable).copied()
+    }
+
+    /// The value of `variable` if it has one, otherwise the variable itself.
+    fn get_arg(&self, variable: &str) -> OpArg {
+        self.get_value(variable)
+            .map(OpArg::Value)
+            .unwrap_or_else(|| OpArg::Unknown(self.symbols.intern(variable)))
+    }
+
+    fn get_canonical_name(&self, value: OpArg) -> String {
+        let symbol = match value {
+            OpArg::Value(value) => self.values[value].1,
+            OpArg::Unknown(other) => other,
+        };
+        self.symbols.name(symbol).to_string()
+    }
+
+    fn get_constant(&self, value: OpArg) -> Option<&Literal> {
+        match value {
+            OpArg::Value(value) => self.constant_folder.get(&value),
+            OpArg::Unknown(_) => None,
+        }
+    }
+}
+
+/// The variables each predecessor sets the result of each `get` to, ordered
+/// by predecessor.
+type SetSources = HashMap<Symbol, Vec<(BasicBlockIdx, Symbol)>>;
+
+/// Numbers the values in `block`, where calls to functions `summaries` shows
+/// to be pure are numbered like any other operation. Variables are interned in
+/// `symbols`.
+pub fn lvn(
+    block: &mut BasicBlock,
+    symbols: &SymbolTable,
+    summaries: &PuritySummaries,
+) {
+    number_values(block, symbols, summaries, None);
+}
+
+/// Like [`lvn`] on every block of `cfg`, but numbers the result of each `get`
+/// by what its `set`s write: the same value as their source if they all set
+/// it to the same variable, and otherwise the same value as any other `get`
+/// set to the same variables along the same edges.
+///
+/// Requires: `cfg` is in SSA form, so that a source set along every edge
+/// into a block is defined on entry to it.
+pub fn ssa_lvn(cfg: &mut FunctionCfg, summaries: &PuritySummaries) {
+    let mut set_sources = SetSources::new();
+    for (block_idx, block) in cfg.vertices.iter() {
+        for instruction in &block.instructions {
+            if let Instruction::Effect {
+                args,
+                op: EffectOps::Set,
+                ..
+            } = instruction
+            {
+                set_sources
+                    .entry(cfg.symbols.intern(&args[0]))
+                    .or_default()
+                    .push((block_idx, cfg.symbols.intern(&args[1])));
+            }
+        }
+    }
+    for sources in set_sources.values_mut() {
+        sources.sort();
+    }
+
+    for block in cfg.vertices.values_mut() {
+        number_values(block, &cfg.symbols, summaries, Some(&set_sources));
+    }
+}
+
+fn number_values(
+    block: &mut BasicBlock,
+    symbols: &SymbolTable,
+    summaries: &PuritySummaries,
+    set_sources: Option<&SetSources>,
+) {
+    let mut table = ValueTable::new(symbols);
+
+    let mut last_assignment = HashMap::new();
+
+    for (i, instruction) in block.instructions.iter().enumerate() {
+        if let Instruction::Constant { dest, .. }
+        | Instruction::Value { dest, .. } = &instruction
+        {
+            last_assignment.insert(symbols.intern(dest), i);
+        }
+    }
+
+    for (i, instruction) in block.instructions.iter_mut().enumerate() {
+        *instruction = match &instruction {
+            Instruction::Value {
+                dest,
+                op: ValueOps::Get,
+                pos,
+                op_type,
+                ..
+            } => {
+                let sources = set_sources.and_then(|set_sources| {
+                    set_sources.get(&symbols.intern(dest))
+                });
+                if let Some(sources) = sources {
+                    match sources.as_slice() {
+                        [(_, first), rest @ ..]
+                            if rest
+                                .iter()
+                                .all(|(_, source)| source == first) =>
+                        {
+                            // the source may be defined in another block
+                            let source = symbols.name(*first);
+                            let index =
+                                table.get_value(&source).unwrap_or_else(|| {
+                                    table.number_get(
+                                        &source,
+                                        Value::Op(
+                                            ValueOps::Id,
+                                            vec![OpArg::Unknown(*first)],
+                                        ),
+                                    )
+                                });
+                            table
+                                .variables_to_values
+                                .insert(symbols.intern(dest), index);
+                        }
+                        _ => {
+                            table.number_get(dest, Value::Get(sources.clone()));
+                        }
+                    }
+                }
+                Instruction::Value {
+                    args: vec![],
+                    dest: dest.clone(),
+                    funcs: vec![],
+                    labels: vec![],
+                    op: ValueOps::Get,
+                    pos: pos.clone(),
+                    op_type: op_type.clone(),
+                }
+            }
+            Instruction::Constant {
+                dest,
+                pos,
+                value,
+                const_type,
+                op,
+            } => {
+                let is_overwritten = last_assignment[&symbols.intern(dest)] > i;
+                match table.add_value_and_get_existing_variable(
+                    if matches!(const_type, Type::Float) {
+                        Value::Float(value.to_string())
+                    } else {
+                        Value::OtherConst(value.to_string())
+                    },
+                    Some(value.clone()),
+                    dest,
+                    is_overwritten,
+                ) {
+                    (destination, Some(replacement_variable)) => {
+                        Instruction::Value {
+                            dest: destination,
+                            op: ValueOps::Id,
+                            pos: pos.clone(),
+                            args: vec![replacement_variable.clone()],
+                            funcs: vec![],
+                            labels: vec![],
+                            op_type: const_type.clone(),
+                        }
+                    }
+
+                    (destination, None) => Instruction::Constant {
+                        dest: destination,
+                        op: *op,
+                        pos: pos.clone(),
+                        const_type: const_type.clone(),
+                        value: value.clone(),
+                    },
+                }
+            }
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op: ValueOps::Call,
+                pos,
+                op_type,
+            } => {
+                let is_overwritten = last_assignment[&symbols.intern(dest)] > i;
+                let new_args = args
+                    .iter()
+                    .map(|arg| table.get_arg(arg))
+                    .collect::<Vec<_>>();
+                let value = if summaries.purity(&funcs[0]) <= Purity::Pure {
+                    Value::Call(funcs[0].clone(), new_args.clone())
+                } else {
+                    Value::LeftAlone(NeverEqual)
+                };
+                match table.add_value_and_get_existing_variable(
+                    value,
+                    None,
+                    dest,
+                    is_overwritten,
+                ) {
+                    (destination, None) => Instruction::Value {
+                        args: new_args
+                            .into_iter()
+                            .map(|value| table.get_canonical_name(value))
+                            .collect(),
+                        dest: destination,
+                        funcs: funcs.clone(),
+                        labels: labels.clone(),
+                        op: ValueOps::Call,
+                        pos: pos.clone(),
+                        op_type: op_type.clone(),
+                    },
+                    (destination, Some(replacement_variable)) => {
+                        Instruction::Value {
+                            dest: destination,
+                            op: ValueOps::Id,
+                            pos: pos.clone(),
+                            args: vec![replacement_variable],
+                            funcs: vec![],
+                            labels: vec![],
+                            op_type: op_type.clone(),
+                        }
+                    }
+                }
+            }
+            Instruction::Value {
+                args,
+                dest,
+                funcs,
+                labels,
+                op,
+                pos,
+                op_type,
+            } => {
+                let is_overwritten = last_assignment[&symbols.intern(dest)] > i;
+                let mut new_args = args
+                    .iter()
+                    .map(|arg| table.get_arg(arg))
+                    .collect::<Vec<_>>();
+                if is_commutative(*op) {
+                    new_args.sort_by(|lhs, rhs| lhs.compare(rhs, symbols));
+                }
+                // only pure operations on equal values give equal results
+                let value = if instruction.effect_kind() == EffectKind::Pure {
+                    Value::Op(*op, new_args.clone())
+                } else {
+                    Value::LeftAlone(NeverEqual)
+                };
+                match table.add_value_and_get_existing_variable(
+                    value,
+                    None,
+                    dest,
+                    is_overwritten,
+                ) {
+                    (destination, Some(replacement_variable)) => {
+                        Instruction::Value {
+                            dest: destination,
+                            op: ValueOps::Id,
+                            pos: pos.clone(),
+                            args: vec![replacement_variable.clone()],
+                            funcs: vec![],
+                            labels: vec![],
+                            op_type: op_type.clone(),
+                        }
+                    }
+                    (destination, None) => {
+                        let constant_folded = if let Ok(constant_fold_attempt) =
+                            new_args
+                                .iter()
+                                .map(|arg| {
+                                    table
+                                        .get_constant(arg.clone())
+                                        .cloned()
+                                        .ok_or(())
+                                })
+                                .collect::<Result<Vec<Literal>, ()>>()
+                        {
+                            {
+                                match op {
+                                    ValueOps::Add => {
+                                        if let Ok(ints) = constant_fold_attempt
+                                            .into_iter()
+                                            .map(|literal| match literal {
+                                                Literal::Int(int) => Ok(int),
+                                                _ => Err(()),
+                                            })
+                                            .collect::<Result<Vec<i64>, ()>>()
+                                        {
+                                            Some(Instruction::Constant {
+                                                dest: destination.clone(),
+                                                op: ConstOps::Const,
+                                                pos: pos.clone(),
+                                                const_type: op_type.clone(),
+                                                value: Literal::Int(
+                                                    ints.into_iter().sum(),
+                                                ),
+                                            })
+                                        } else {
+                                            None
+                                        }
+                                    }
+                                    _ => None,
+                                }
+                            }
+                        } else {
+                            None
+                        };
+
+                        constant_folded.unwrap_or(Instruction::Value {
+                            args: new_args
+                                .into_iter()
+                                .map(|value| table.get_canonical_name(value))
+                                .collect(),
+                            dest: destination,
+                            funcs: funcs.clone(),
+                            labels: labels.clone(),
+                            op: *op,
+                            pos: pos.clone(),
+                            op_type: op_type.clone(),
+                        })
+                    }
+                }
+            }
+            Instruction::Effect { .. } => {
+                let mut effect = instruction.clone();
+                effect.map_args(&mut |arg| {
+                    *arg = table.get_canonical_name(table.get_arg(arg));
+                });
+                effect
+            }
+        };
+    }
+}
+
+/// Runs local value numbering on every block.
+#[derive(Default)]
+pub struct LvnPass {
+    pub summaries: Arc<PuritySummaries>,
+}
+
+impl FunctionPass for LvnPass {
+    fn name(&self) -> &'static str {
+        "lvn"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // values computed while speculating do not survive an abort
+        Capabilities::all() - Capabilities::SPECULATION
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        _cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        let mut changed = false;
+        for block in cfg.vertices.values_mut() {
+            let old_instructions = block.instructions.clone();
+            lvn(block, &cfg.symbols, &self.summaries);
+            changed |= block.instructions != old_instructions;
+        }
+        Ok(changed.into())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use bril_rs::Instruction;
+use bril_util::{
+    EffectKind, InstructionExt, capabilities::Capabilities, error::ToolError,
+};
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, FunctionCfg,
+    pass::{AnalysisCache, Changed, FunctionPass},
+    slotmap::SlotMap,
+};
+use purity::{Purity, PuritySummaries};
+
+/// Whether `instruction` must be kept even if what it defines is never used.
+fn has_side_effects(
+    instruction: &Instruction,
+    summaries: &PuritySummaries,
+) -> bool {
+    match instruction.effect_kind() {
+        EffectKind::Pure | EffectKind::ReadsMemory => false,
+        // a call that may trap or never return must still be made
+        EffectKind::Call => {
+            summaries.instruction_purity(instruction) != Purity::PureTotal
+        }
+        EffectKind::WritesMemory | EffectKind::Control | EffectKind::IO => true,
+    }
+}
+
+pub fn trivial_dead_code_elimination(
+    blocks: &mut SlotMap<BasicBlockIdx, BasicBlock>,
+    summaries: &PuritySummaries,
+) -> bool {
+    let mut used_variables = HashSet::new();
+
+    for block in blocks.values() {
+        for instruction in &block.instructions {
+            if let Instruction::Value { args, .. }
+            | Instruction::Effect { args, .. } = &instruction
+            {
+                used_variables.extend(args.clone());
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in blocks.values_mut() {
+        let old_length = block.instructions.len();
+        block
+            .instructions
+            .retain(|instruction| match &*instruction {
+                Instruction::Constant { dest, .. }
+                | Instruction::Value { dest, .. } => {
+                    used_variables.contains(dest)
+                        || has_side_effects(instruction, summaries)
+                }
+                Instruction::Effect { .. } => true,
+            });
+        changed |= old_length != block.instructions.len();
+    }
+    changed
+}
+
+pub fn drop_killed_locals(
+    block: &mut BasicBlock,
+    summaries: &PuritySummaries,
+) -> bool {
+    let mut unused_definitions = HashMap::new();
+    let mut dead_instructions = vec![];
+
+    for (i, instruction) in block.instructions.iter().enumerate() {
+        let (kill, gen_set): (Option<&String>, &[String]) = match instruction {
+            Instruction::Constant { dest, .. } => (Some(dest), &[]),
+            Instruction::Value { args, dest, .. } => {
+                (Some(dest), args.as_slice())
+            }
+            Instruction::Effect { args, .. } => (None, args.as_slice()),
+        };
+
+        for usage in gen_set {
+            unused_definitions.remove(usage);
+        }
+        if let Some(kill) = kill {
+            if let Some(dead_instruction_index) =
+                unused_definitions.remove(kill)
+            {
+                dead_instructions.push(dead_instruction_index);
+            }
+            if !has_side_effects(instruction, summaries) {
+                unused_definitions.insert(kill.clone(), i);
+            }
+        }
+    }
+
+    dead_instructions.sort_unstable();
+    for i in dead_instructions.iter().rev().copied() {
+        block.instructions.remove(i);
+    }
+
+    !dead_instructions.is_empty()
+}
+
+pub fn drop_lots_of_killed_local(
+    blocks: &mut SlotMap<BasicBlockIdx, BasicBlock>,
+    summaries: &PuritySummaries,
+) -> bool {
+    let mut changed = false;
+    for block in blocks.values_mut() {
+        changed |= drop_killed_locals(block, summaries);
+    }
+    changed
+}
+
+/// Runs both kinds of dead code elimination until neither changes anything.
+/// Calls are only removed when `summaries` shows them to have no side effects.
+#[derive(Default)]
+pub struct TdcePass {
+    pub summaries: Arc<PuritySummaries>,
+}
+
+impl FunctionPass for TdcePass {
+    fn name(&self) -> &'static str {
+        "tdce"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // removes only instructions without effects, whatever their types
+        Capabilities::all()
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        _cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        let mut changed = false;
+        while trivial_dead_code_elimination(&mut cfg.vertices, &self.summaries)
+            || drop_lots_of_killed_local(&mut cfg.vertices, &self.summaries)
+        {
+            changed = true;
+        }
+        Ok(changed.into())
+    }
+}
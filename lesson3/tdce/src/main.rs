@@ -1,96 +1,46 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fs, io,
-    path::PathBuf,
-};
+use std::{cell::RefCell, fs, io, path::PathBuf, rc::Rc, sync::Arc};
 
 use argh::FromArgs;
-use bril_rs::{Instruction, Program};
+use bril_rs::Program;
+use bril_util::filter::{FunctionFilter, FunctionPattern};
 use build_cfg::{
-    BasicBlock, BasicBlockIdx, print::print_cfg_as_bril_text, slotmap::SlotMap,
+    pass::PassManager,
+    print::{
+        DiffStyle, cfg_to_bril_lines, print_cfg_as_bril_text, print_cfg_diff,
+    },
+    timing::{CountingAllocator, PassTimings},
 };
+use purity::PuritySummaries;
 use snafu::{ResultExt, Whatever};
+use tdce::TdcePass;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 /// does trivial dead code elimination
 #[derive(FromArgs)]
 struct Opts {
+    /// print the time and peak allocations of the pass on each function to
+    /// stderr
+    #[argh(switch)]
+    time_passes: bool,
+
+    /// print each function as a diff against the function before TDCE,
+    /// either `unified` or `side-by-side`, with changes colored
+    #[argh(option)]
+    diff: Option<DiffStyle>,
+
+    /// only optimize functions matching this name, like `@main` or `@test_*`,
+    /// printing the rest unchanged: may be repeated, and omit for every
+    /// function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
     /// input Bril file: omit for stdin
     #[argh(positional)]
     input: Option<PathBuf>,
 }
 
-fn trivial_dead_code_elimination(
-    blocks: &mut SlotMap<BasicBlockIdx, BasicBlock>,
-) -> bool {
-    let mut used_variables = HashSet::new();
-
-    for block in blocks.values() {
-        for instruction in &block.instructions {
-            if let Instruction::Value { args, .. }
-            | Instruction::Effect { args, .. } = &instruction
-            {
-                used_variables.extend(args.clone());
-            }
-        }
-    }
-
-    let mut changed = false;
-    for block in blocks.values_mut() {
-        let old_length = block.instructions.len();
-        block.instructions.retain(|instruction| match instruction {
-            Instruction::Constant { dest, .. }
-            | Instruction::Value { dest, .. } => used_variables.contains(dest),
-            Instruction::Effect { .. } => true,
-        });
-        changed |= old_length != block.instructions.len();
-    }
-    changed
-}
-
-fn drop_killed_locals(block: &mut BasicBlock) -> bool {
-    let mut unused_definitions = HashMap::new();
-    let mut dead_instructions = vec![];
-
-    for (i, instruction) in block.instructions.iter().enumerate() {
-        let (kill, gen_set): (Option<&String>, &[String]) = match instruction {
-            Instruction::Constant { dest, .. } => (Some(dest), &[]),
-            Instruction::Value { args, dest, .. } => {
-                (Some(dest), args.as_slice())
-            }
-            Instruction::Effect { args, .. } => (None, args.as_slice()),
-        };
-
-        for usage in gen_set {
-            unused_definitions.remove(usage);
-        }
-        if let Some(kill) = kill {
-            if let Some(dead_instruction_index) =
-                unused_definitions.get(kill).copied()
-            {
-                dead_instructions.push(dead_instruction_index);
-            }
-            unused_definitions.insert(kill.clone(), i);
-        }
-    }
-
-    dead_instructions.sort_unstable();
-    for i in dead_instructions.iter().rev().copied() {
-        block.instructions.remove(i);
-    }
-
-    !dead_instructions.is_empty()
-}
-
-fn drop_lots_of_killed_local(
-    blocks: &mut SlotMap<BasicBlockIdx, BasicBlock>,
-) -> bool {
-    let mut changed = false;
-    for block in blocks.values_mut() {
-        changed |= drop_killed_locals(block);
-    }
-    changed
-}
-
 #[snafu::report]
 fn main() -> Result<(), Whatever> {
     let opts = argh::from_env::<Opts>();
@@ -109,19 +59,39 @@ fn main() -> Result<(), Whatever> {
         )?
     };
 
+    let mut pass_manager = PassManager::default();
+    pass_manager.add_pass(Box::new(TdcePass {
+        summaries: Arc::new(PuritySummaries::new(&program)),
+    }));
+    let timings = Rc::new(RefCell::new(PassTimings::default()));
+    if opts.time_passes {
+        pass_manager.set_timings(timings.clone());
+    }
+
     for import in program.imports {
         println!("{}", import);
     }
+    let filter = FunctionFilter::new(opts.function);
     for function in program.functions {
+        if !filter.selects(&function.name) {
+            println!("{}", function);
+            continue;
+        }
         let mut cfg = build_cfg::build_cfg(&function, false)
             .whatever_context("Failed to build cfg")?;
 
-        //trivial_dead_code_elimination(&mut cfg.vertices);
-        while trivial_dead_code_elimination(&mut cfg.vertices)
-            || drop_lots_of_killed_local(&mut cfg.vertices)
-        {}
+        let before = opts.diff.map(|_| cfg_to_bril_lines(&cfg));
+        pass_manager
+            .run(&mut cfg)
+            .whatever_context("Failed to run the passes")?;
 
-        print_cfg_as_bril_text(cfg);
+        match (opts.diff, before) {
+            (Some(style), Some(before)) => print_cfg_diff(&before, &cfg, style),
+            _ => print_cfg_as_bril_text(cfg),
+        }
+    }
+    if opts.time_passes {
+        eprint!("{}", timings.borrow());
     }
 
     Ok(())
@@ -0,0 +1,136 @@
+//! Constructors for instructions that passes insert, which have no source
+//! position and leave unused fields empty.
+
+use bril_rs::{
+    ConstOps, EffectOps, Instruction, Literal, Position, Type, ValueOps,
+};
+
+/// `dest: const_type = const value`.
+pub fn make_const(
+    dest: impl Into<String>,
+    const_type: Type,
+    value: Literal,
+) -> Instruction {
+    Instruction::Constant {
+        dest: dest.into(),
+        op: ConstOps::Const,
+        pos: None,
+        const_type,
+        value,
+    }
+}
+
+/// `dest: int = const value`.
+pub fn make_const_int(dest: impl Into<String>, value: i64) -> Instruction {
+    make_const(dest, Type::Int, Literal::Int(value))
+}
+
+/// `dest: op_type = op args`, for operations other than calls.
+pub fn make_value(
+    dest: impl Into<String>,
+    op_type: Type,
+    op: ValueOps,
+    args: Vec<String>,
+) -> Instruction {
+    Instruction::Value {
+        args,
+        dest: dest.into(),
+        funcs: vec![],
+        labels: vec![],
+        op,
+        pos: None,
+        op_type,
+    }
+}
+
+/// `dest: op_type = id src`.
+pub fn make_id(
+    dest: impl Into<String>,
+    op_type: Type,
+    src: impl Into<String>,
+) -> Instruction {
+    make_value(dest, op_type, ValueOps::Id, vec![src.into()])
+}
+
+/// `op args`, for operations other than calls, jumps, and branches.
+pub fn make_effect(op: EffectOps, args: Vec<String>) -> Instruction {
+    Instruction::Effect {
+        args,
+        funcs: vec![],
+        labels: vec![],
+        op,
+        pos: None,
+    }
+}
+
+/// `call @function args`, discarding any result.
+pub fn make_call(
+    function: impl Into<String>,
+    args: Vec<String>,
+) -> Instruction {
+    Instruction::Effect {
+        args,
+        funcs: vec![function.into()],
+        labels: vec![],
+        op: EffectOps::Call,
+        pos: None,
+    }
+}
+
+/// `jmp .label`.
+pub fn make_jump(label: impl Into<String>) -> Instruction {
+    Instruction::Effect {
+        args: vec![],
+        funcs: vec![],
+        labels: vec![label.into()],
+        op: EffectOps::Jump,
+        pos: None,
+    }
+}
+
+/// `br condition .if_true .if_false`.
+pub fn make_branch(
+    condition: impl Into<String>,
+    if_true: impl Into<String>,
+    if_false: impl Into<String>,
+) -> Instruction {
+    Instruction::Effect {
+        args: vec![condition.into()],
+        funcs: vec![],
+        labels: vec![if_true.into(), if_false.into()],
+        op: EffectOps::Branch,
+        pos: None,
+    }
+}
+
+/// `ret value`, or `ret` without a value.
+pub fn make_return(value: Option<String>) -> Instruction {
+    make_effect(EffectOps::Return, value.into_iter().collect())
+}
+
+/// `guard condition .label`.
+pub fn make_guard(
+    condition: impl Into<String>,
+    label: impl Into<String>,
+) -> Instruction {
+    Instruction::Effect {
+        args: vec![condition.into()],
+        funcs: vec![],
+        labels: vec![label.into()],
+        op: EffectOps::Guard,
+        pos: None,
+    }
+}
+
+/// `instruction` at `pos`, for an instruction replacing one from the source.
+pub fn with_pos(
+    mut instruction: Instruction,
+    pos: Option<Position>,
+) -> Instruction {
+    match &mut instruction {
+        Instruction::Constant { pos: old, .. }
+        | Instruction::Value { pos: old, .. }
+        | Instruction::Effect { pos: old, .. } => *old = pos,
+    }
+    instruction
+}
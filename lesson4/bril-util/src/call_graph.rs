@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Instruction, Program, ValueOps};
+
+/// The functions each function in a program calls, ignoring calls to
+/// functions the program does not define.
+pub struct CallGraph {
+    callees: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl CallGraph {
+    pub fn new(program: &Program) -> Self {
+        let defined = program
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect::<HashSet<_>>();
+        let callees = program
+            .functions
+            .iter()
+            .map(|function| {
+                let callees = function
+                    .instrs
+                    .iter()
+                    .filter_map(called_function)
+                    .filter(|callee| defined.contains(callee))
+                    .map(str::to_string)
+                    .collect();
+                (function.name.clone(), callees)
+            })
+            .collect();
+        Self { callees }
+    }
+
+    pub fn callees(&self, function: &str) -> &BTreeSet<String> {
+        &self.callees[function]
+    }
+
+    /// Records that the body of `callee` was inlined into `caller`, which now
+    /// calls whatever `callee` calls, and calls `callee` itself only if
+    /// `still_calls_callee`.
+    pub fn record_inlining(
+        &mut self,
+        caller: &str,
+        callee: &str,
+        still_calls_callee: bool,
+    ) {
+        let inherited = self.callees[callee].clone();
+        let callees = self.callees.get_mut(caller).unwrap();
+        if !still_calls_callee {
+            callees.remove(callee);
+        }
+        callees.extend(inherited);
+    }
+
+    /// The strongly connected components of the call graph, callees before
+    /// callers, found with Tarjan's algorithm. Functions in the same component
+    /// are mutually recursive.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<String>> {
+        let mut search = StronglyConnectedComponents {
+            call_graph: self,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: vec![],
+            on_stack: HashSet::new(),
+            components: vec![],
+        };
+        for function in self.callees.keys() {
+            if !search.index.contains_key(function.as_str()) {
+                search.visit(function);
+            }
+        }
+        search.components
+    }
+}
+
+/// The function `code` calls, if it is a call.
+pub fn called_function(code: &Code) -> Option<&str> {
+    match code {
+        Code::Instruction(
+            Instruction::Value {
+                funcs,
+                op: ValueOps::Call,
+                ..
+            }
+            | Instruction::Effect {
+                funcs,
+                op: EffectOps::Call,
+                ..
+            },
+        ) => funcs.first().map(String::as_str),
+        _ => None,
+    }
+}
+
+struct StronglyConnectedComponents<'a> {
+    call_graph: &'a CallGraph,
+    index: HashMap<&'a str, usize>,
+    low_link: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: HashSet<&'a str>,
+    components: Vec<Vec<String>>,
+}
+
+impl<'a> StronglyConnectedComponents<'a> {
+    fn visit(&mut self, function: &'a str) {
+        let index = self.index.len();
+        self.index.insert(function, index);
+        self.low_link.insert(function, index);
+        self.stack.push(function);
+        self.on_stack.insert(function);
+
+        let call_graph = self.call_graph;
+        for callee in call_graph.callees(function) {
+            let callee = callee.as_str();
+            let callee_low_link = if !self.index.contains_key(callee) {
+                self.visit(callee);
+                self.low_link[callee]
+            } else if self.on_stack.contains(callee) {
+                self.index[callee]
+            } else {
+                continue;
+            };
+            let low_link = self.low_link.get_mut(function).unwrap();
+            *low_link = (*low_link).min(callee_low_link);
+        }
+
+        if self.low_link[function] == self.index[function] {
+            let mut component = vec![];
+            loop {
+                let member = self.stack.pop().unwrap();
+                self.on_stack.remove(member);
+                component.push(member.to_string());
+                if member == function {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! The Bril extensions a program uses, so that a tool can refuse a program it
+//! does not handle up front instead of failing partway through it.
+
+use std::fmt;
+
+use bril_rs::{
+    Argument, Code, EffectOps, Function, Instruction, Program, Type, ValueOps,
+};
+
+bitflags::bitflags! {
+    /// A set of Bril extensions: what a program uses, or what a pass handles.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Capabilities: u8 {
+        /// Integers, booleans, arithmetic, control flow, calls, and `print`,
+        /// which every program uses.
+        const CORE = 1 << 0;
+        /// The `float` type and its operations.
+        const FLOAT = 1 << 1;
+        /// Pointers, `alloc`, `load`, `store`, `free`, and `ptradd`.
+        const MEMORY = 1 << 2;
+        /// The `char` type and its operations.
+        const CHAR = 1 << 3;
+        /// `set`, `get`, and `undef`.
+        const SSA = 1 << 4;
+        /// `speculate`, `commit`, and `guard`.
+        const SPECULATION = 1 << 5;
+    }
+}
+
+impl Capabilities {
+    /// The extensions needed for values of type `ty`.
+    pub fn of_type(ty: &Type) -> Self {
+        match ty {
+            Type::Int | Type::Bool => Self::CORE,
+            Type::Float => Self::CORE | Self::FLOAT,
+            Type::Char => Self::CORE | Self::CHAR,
+            Type::Pointer(pointee) => Self::MEMORY | Self::of_type(pointee),
+        }
+    }
+
+    pub fn of_instruction(instruction: &Instruction) -> Self {
+        match instruction {
+            Instruction::Constant { const_type, .. } => {
+                Self::of_type(const_type)
+            }
+            Instruction::Value { op, op_type, .. } => {
+                Self::of_type(op_type) | Self::of_value_op(*op)
+            }
+            Instruction::Effect { op, .. } => match op {
+                EffectOps::Store | EffectOps::Free => Self::MEMORY,
+                EffectOps::Set => Self::SSA,
+                EffectOps::Speculate | EffectOps::Commit | EffectOps::Guard => {
+                    Self::SPECULATION
+                }
+                _ => Self::CORE,
+            },
+        }
+    }
+
+    fn of_value_op(op: ValueOps) -> Self {
+        match op {
+            ValueOps::Fadd
+            | ValueOps::Fsub
+            | ValueOps::Fmul
+            | ValueOps::Fdiv
+            | ValueOps::Feq
+            | ValueOps::Flt
+            | ValueOps::Fgt
+            | ValueOps::Fle
+            | ValueOps::Fge => Self::FLOAT,
+            ValueOps::Ceq
+            | ValueOps::Clt
+            | ValueOps::Cgt
+            | ValueOps::Cle
+            | ValueOps::Cge
+            | ValueOps::Char2int
+            | ValueOps::Int2char => Self::CHAR,
+            ValueOps::Alloc | ValueOps::Load | ValueOps::PtrAdd => Self::MEMORY,
+            ValueOps::Get | ValueOps::Undef => Self::SSA,
+            _ => Self::CORE,
+        }
+    }
+
+    /// The extensions used by a function with `arguments` and `return_type`,
+    /// before looking at its body.
+    pub fn of_signature(
+        arguments: &[Argument],
+        return_type: Option<&Type>,
+    ) -> Self {
+        arguments
+            .iter()
+            .map(|argument| &argument.arg_type)
+            .chain(return_type)
+            .fold(Self::CORE, |capabilities, ty| {
+                capabilities | Self::of_type(ty)
+            })
+    }
+
+    pub fn of_function(function: &Function) -> Self {
+        function.instrs.iter().fold(
+            Self::of_signature(&function.args, function.return_type.as_ref()),
+            |capabilities, code| match code {
+                Code::Instruction(instruction) => {
+                    capabilities | Self::of_instruction(instruction)
+                }
+                Code::Label { .. } => capabilities,
+            },
+        )
+    }
+
+    pub fn of_program(program: &Program) -> Self {
+        program
+            .functions
+            .iter()
+            .fold(Self::CORE, |capabilities, function| {
+                capabilities | Self::of_function(function)
+            })
+    }
+}
+
+/// Lists the extensions by their lowercase names, like `float, memory`.
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, _)) in self.iter_names().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", name.to_lowercase())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! The errors every tool reports, so that callers like the driver or a
+//! language server can tell malformed input apart from a bug in a tool without
+//! matching on messages. Each crate has its own error enum, like
+//! `build_cfg::CfgBuildError`, which converts into a [`ToolError`].
+
+use std::io;
+
+use bril_rs::Position;
+use snafu::{Snafu, Whatever};
+
+use crate::capabilities::Capabilities;
+
+/// What kind of problem a [`ToolError`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The input is not a valid program, which the user has to fix.
+    MalformedInput,
+    /// A tool broke an invariant another part of it relies on, which is a bug
+    /// in the tool rather than in the input.
+    InvariantViolated,
+    /// The input uses an extension a tool does not handle.
+    Unsupported,
+    /// Reading or writing a file failed.
+    Io,
+    /// An error that has not been given a kind yet.
+    Other,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum ToolError {
+    #[snafu(display("{message}"))]
+    MalformedInput {
+        message: String,
+        /// Where in the source program the problem is, if known.
+        span: Option<Position>,
+    },
+
+    #[snafu(display("Internal invariant violated: {message}"))]
+    InvariantViolated { message: String },
+
+    #[snafu(display(
+        "{pass} does not support {missing}, which @{function} uses"
+    ))]
+    Unsupported {
+        pass: &'static str,
+        function: String,
+        missing: Capabilities,
+    },
+
+    #[snafu(display("{message}"))]
+    Io { message: String, source: io::Error },
+
+    #[snafu(display("Failed to run {pass} on @{function}"))]
+    Pass {
+        pass: &'static str,
+        function: String,
+        #[snafu(source(from(ToolError, Box::new)))]
+        source: Box<ToolError>,
+    },
+
+    /// A [`Whatever`] from code not yet migrated to structured errors, kept as
+    /// its report so that a `ToolError` can be sent between threads.
+    #[snafu(display("{report}"))]
+    Other { report: String },
+}
+
+impl ToolError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::MalformedInput { .. } => ErrorKind::MalformedInput,
+            Self::InvariantViolated { .. } => ErrorKind::InvariantViolated,
+            Self::Unsupported { .. } => ErrorKind::Unsupported,
+            Self::Io { .. } => ErrorKind::Io,
+            Self::Pass { source, .. } => source.kind(),
+            Self::Other { .. } => ErrorKind::Other,
+        }
+    }
+
+    /// Where in the source program the problem is, if it is malformed input
+    /// with a known position.
+    pub fn span(&self) -> Option<&Position> {
+        match self {
+            Self::MalformedInput { span, .. } => span.as_ref(),
+            Self::Pass { source, .. } => source.span(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Whatever> for ToolError {
+    fn from(error: Whatever) -> Self {
+        Self::Other {
+            report: snafu::Report::from_error(error).to_string(),
+        }
+    }
+}
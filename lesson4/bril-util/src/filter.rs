@@ -0,0 +1,88 @@
+//! Selecting functions by name from the command line, so that a tool can run
+//! on one function of a large program at a time.
+
+use std::str::FromStr;
+
+/// A function name to select, written with or without its leading `@`, where
+/// `*` matches any run of characters and `?` any single character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionPattern {
+    pattern: Vec<char>,
+}
+
+impl FunctionPattern {
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.chars().collect::<Vec<_>>();
+
+        // the usual greedy glob match, backtracking to the last `*` on a
+        // mismatch
+        let (mut p, mut n) = (0, 0);
+        let mut last_star = None;
+        while n < name.len() {
+            match self.pattern.get(p) {
+                Some('*') => {
+                    last_star = Some((p, n));
+                    p += 1;
+                }
+                Some(c) if *c == '?' || *c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match last_star {
+                    Some((star, matched)) => {
+                        last_star = Some((star, matched + 1));
+                        p = star + 1;
+                        n = matched + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.pattern[p..].iter().all(|c| *c == '*')
+    }
+}
+
+impl FromStr for FunctionPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = s.strip_prefix('@').unwrap_or(s);
+        if pattern.is_empty() {
+            return Err(format!("'{}' does not name a function", s));
+        }
+        Ok(Self {
+            pattern: pattern.chars().collect(),
+        })
+    }
+}
+
+/// The functions selected by `--function` options: all of them if there are
+/// none, and otherwise those matching any of the patterns.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionFilter {
+    patterns: Vec<FunctionPattern>,
+    select_none: bool,
+}
+
+impl FunctionFilter {
+    pub fn new(patterns: Vec<FunctionPattern>) -> Self {
+        Self {
+            patterns,
+            select_none: false,
+        }
+    }
+
+    /// Selects no functions at all, which no list of patterns can express.
+    pub fn none() -> Self {
+        Self {
+            patterns: vec![],
+            select_none: true,
+        }
+    }
+
+    pub fn selects(&self, name: &str) -> bool {
+        !self.select_none
+            && (self.patterns.is_empty()
+                || self.patterns.iter().any(|pattern| pattern.matches(name)))
+    }
+}
@@ -0,0 +1,71 @@
+//! Reading the Bril program a tool is given, as JSON from a file or standard
+//! input.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bril_rs::Program;
+use snafu::{ResultExt, Snafu};
+
+use crate::error::ToolError;
+
+#[derive(Debug, Snafu)]
+pub enum ParseError {
+    #[snafu(display("Failed to read the contents of {}", path.to_string_lossy()))]
+    Read { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to parse {input} as a valid Bril program"))]
+    Json {
+        /// `input file` or `standard input`.
+        input: &'static str,
+        source: serde_json::Error,
+    },
+}
+
+impl ParseError {
+    /// The line and column in the JSON at which parsing failed, if it did.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Read { .. } => None,
+            Self::Json { source, .. } => Some((source.line(), source.column())),
+        }
+    }
+}
+
+impl From<ParseError> for ToolError {
+    fn from(error: ParseError) -> Self {
+        match error {
+            ParseError::Read { path, source } => ToolError::Io {
+                message: format!(
+                    "Failed to read the contents of {}",
+                    path.to_string_lossy()
+                ),
+                source,
+            },
+            // the span is in the JSON, not the source program
+            ParseError::Json { .. } => ToolError::MalformedInput {
+                message: snafu::Report::from_error(error).to_string(),
+                span: None,
+            },
+        }
+    }
+}
+
+/// The program in the file at `path`, or on standard input if there is none.
+pub fn read_program(path: Option<&Path>) -> Result<Program, ParseError> {
+    match path {
+        Some(path) => {
+            let contents = fs::read_to_string(path).context(ReadSnafu {
+                path: path.to_path_buf(),
+            })?;
+            serde_json::from_str(&contents).context(JsonSnafu {
+                input: "input file",
+            })
+        }
+        None => serde_json::from_reader(io::stdin()).context(JsonSnafu {
+            input: "standard input",
+        }),
+    }
+}
@@ -1,4 +1,14 @@
-use bril_rs::{Instruction, Literal};
+use bril_rs::{EffectOps, Instruction, Literal, ValueOps};
+
+pub mod builder;
+pub mod call_graph;
+pub mod capabilities;
+pub mod error;
+pub mod filter;
+pub mod input;
+pub mod names;
+pub mod output;
+pub mod profile;
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum InstructionValue {
@@ -8,12 +18,81 @@ pub enum InstructionValue {
     Op(String, Vec<String>, Vec<String>, Vec<String>),
 }
 
+/// What an instruction may do besides assigning its destination, which decides
+/// whether it may be removed, reordered, or hoisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectKind {
+    /// Computes its destination from its arguments alone, although it may
+    /// still trap, like `div`. Removable when its destination is unused.
+    Pure,
+    /// Reads state other instructions write: `load` reads memory and `get`
+    /// reads the shadow variable of a `set`. Removable when its destination
+    /// is unused, but not movable across writes.
+    ReadsMemory,
+    /// `store`, `free`, `alloc`, and `set`.
+    WritesMemory,
+    /// Jumps, branches, returns, `nop`, and speculation, which must stay in
+    /// place.
+    Control,
+    /// `print`.
+    IO,
+    /// Calls, which have the effects of the callee; see
+    /// `purity::PuritySummaries` for what those are.
+    Call,
+}
+
+/// Whether the arguments of `op` may be swapped without changing its result,
+/// so value numbering can treat them as unordered.
+pub fn is_commutative(op: ValueOps) -> bool {
+    matches!(
+        op,
+        ValueOps::Add
+            | ValueOps::Fadd
+            | ValueOps::Mul
+            | ValueOps::Fmul
+            | ValueOps::Eq
+            | ValueOps::Feq
+            | ValueOps::And
+            | ValueOps::Or
+            | ValueOps::Ceq
+    )
+}
+
 pub trait InstructionExt {
     fn kill(&self) -> Option<&String>;
 
     fn gen_set(&self) -> &[String];
 
     fn value(&self) -> Option<InstructionValue>;
+
+    /// Like [`InstructionExt::value`], but with the arguments of commutative
+    /// operations sorted, so that `add a b` and `add b a` have the same value.
+    fn canonical_value(&self) -> Option<InstructionValue>;
+
+    /// The labels this instruction refers to, such as the targets of a branch.
+    fn labels(&self) -> &[String];
+
+    /// Rewrites each variable this instruction reads in place.
+    fn map_args(&mut self, f: &mut impl FnMut(&mut String));
+
+    /// Rewrites each label this instruction refers to in place.
+    fn map_labels(&mut self, f: &mut impl FnMut(&mut String));
+
+    /// Rewrites the variable this instruction assigns in place, if any.
+    fn map_dest(&mut self, f: impl FnOnce(&mut String));
+
+    fn effect_kind(&self) -> EffectKind;
+
+    fn is_jump(&self) -> bool;
+
+    fn is_branch(&self) -> bool;
+
+    fn is_return(&self) -> bool;
+
+    /// Whether this instruction ends a basic block: a jump, branch, or return.
+    fn is_terminator(&self) -> bool {
+        self.is_jump() || self.is_branch() || self.is_return()
+    }
 }
 
 impl InstructionExt for Instruction {
@@ -56,4 +135,102 @@ impl InstructionExt for Instruction {
             Instruction::Effect { .. } => None,
         }
     }
+
+    fn canonical_value(&self) -> Option<InstructionValue> {
+        let mut value = self.value()?;
+        if let (
+            Instruction::Value { op, .. },
+            InstructionValue::Op(_, args, _, _),
+        ) = (self, &mut value)
+        {
+            if is_commutative(*op) {
+                args.sort();
+            }
+        }
+        Some(value)
+    }
+
+    fn labels(&self) -> &[String] {
+        match self {
+            Instruction::Value { labels, .. }
+            | Instruction::Effect { labels, .. } => labels,
+            Instruction::Constant { .. } => &[],
+        }
+    }
+
+    fn map_args(&mut self, f: &mut impl FnMut(&mut String)) {
+        match self {
+            Instruction::Value { args, .. }
+            | Instruction::Effect { args, .. } => args.iter_mut().for_each(f),
+            Instruction::Constant { .. } => {}
+        }
+    }
+
+    fn map_labels(&mut self, f: &mut impl FnMut(&mut String)) {
+        match self {
+            Instruction::Value { labels, .. }
+            | Instruction::Effect { labels, .. } => {
+                labels.iter_mut().for_each(f)
+            }
+            Instruction::Constant { .. } => {}
+        }
+    }
+
+    fn map_dest(&mut self, f: impl FnOnce(&mut String)) {
+        match self {
+            Instruction::Constant { dest, .. }
+            | Instruction::Value { dest, .. } => f(dest),
+            Instruction::Effect { .. } => {}
+        }
+    }
+
+    fn effect_kind(&self) -> EffectKind {
+        match self {
+            Instruction::Constant { .. } => EffectKind::Pure,
+            Instruction::Value { op, .. } => match op {
+                ValueOps::Call => EffectKind::Call,
+                ValueOps::Load | ValueOps::Get => EffectKind::ReadsMemory,
+                ValueOps::Alloc => EffectKind::WritesMemory,
+                _ => EffectKind::Pure,
+            },
+            Instruction::Effect { op, .. } => match op {
+                EffectOps::Call => EffectKind::Call,
+                EffectOps::Print => EffectKind::IO,
+                EffectOps::Store | EffectOps::Free | EffectOps::Set => {
+                    EffectKind::WritesMemory
+                }
+                _ => EffectKind::Control,
+            },
+        }
+    }
+
+    fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Effect {
+                op: EffectOps::Jump,
+                ..
+            }
+        )
+    }
+
+    fn is_branch(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Effect {
+                op: EffectOps::Branch,
+                ..
+            }
+        )
+    }
+
+    fn is_return(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Effect {
+                op: EffectOps::Return,
+                ..
+            }
+        )
+    }
 }
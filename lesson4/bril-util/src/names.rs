@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+
+use bril_rs::{Code, Function, Instruction};
+
+/// Generates variable names and labels that are not yet used in a function,
+/// or, by default, names not yet generated.
+#[derive(Default)]
+pub struct NameGenerator {
+    used: HashSet<String>,
+}
+
+impl NameGenerator {
+    pub fn new(function: &Function) -> Self {
+        let mut used = function
+            .args
+            .iter()
+            .map(|argument| argument.name.clone())
+            .collect::<HashSet<_>>();
+        for code in &function.instrs {
+            match code {
+                Code::Label { label, .. } => {
+                    used.insert(label.clone());
+                }
+                Code::Instruction(Instruction::Constant { dest, .. }) => {
+                    used.insert(dest.clone());
+                }
+                Code::Instruction(Instruction::Value {
+                    args,
+                    dest,
+                    labels,
+                    ..
+                }) => {
+                    used.insert(dest.clone());
+                    used.extend(args.iter().cloned());
+                    used.extend(labels.iter().cloned());
+                }
+                Code::Instruction(Instruction::Effect {
+                    args, labels, ..
+                }) => {
+                    used.extend(args.iter().cloned());
+                    used.extend(labels.iter().cloned());
+                }
+            }
+        }
+        Self { used }
+    }
+
+    /// `name` if it is unused, otherwise `name` with the smallest numeric
+    /// suffix that makes it unused. The result counts as used afterward.
+    pub fn fresh(&mut self, name: &str) -> String {
+        let name = if self.used.contains(name) {
+            (1..)
+                .map(|i| format!("{name}.{i}"))
+                .find(|candidate| !self.used.contains(candidate))
+                .expect("There are infinitely many candidate names")
+        } else {
+            name.to_string()
+        };
+        self.used.insert(name.clone());
+        name
+    }
+}
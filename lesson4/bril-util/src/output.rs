@@ -0,0 +1,124 @@
+//! The `--output` formats shared by the analysis tools. JSON results are
+//! wrapped in the same envelope by every tool, so that grading scripts and the
+//! visualizer can read any of them without knowing which tool ran.
+
+use std::{fmt, str::FromStr};
+
+use serde_json::{Value, json};
+
+/// The version of the envelope printed by [`print_envelope`], incremented
+/// whenever its fields or the results of a tool change incompatibly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One [`envelope`] per line.
+    Json,
+    /// The format the tool prints for people to read.
+    Text,
+    /// A Graphviz graph per function.
+    Dot,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            "dot" => Ok(Self::Dot),
+            other => Err(format!("Unknown output format '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json => "json",
+            Self::Text => "text",
+            Self::Dot => "dot",
+        }
+        .fmt(f)
+    }
+}
+
+/// The `results` of `tool` for `function`, or for the whole program if there
+/// is none, as `{tool, version, function, results}`.
+pub fn envelope(tool: &str, function: Option<&str>, results: Value) -> Value {
+    json!({
+        "tool": tool,
+        "version": SCHEMA_VERSION,
+        "function": function,
+        "results": results,
+    })
+}
+
+/// Prints [`envelope`] on one line.
+pub fn print_envelope(tool: &str, function: Option<&str>, results: Value) {
+    println!("{}", envelope(tool, function, results));
+}
+
+/// A directed graph printed in the Graphviz DOT language, with nodes by an
+/// identifier and shown with a label of any number of lines.
+#[derive(Debug, Clone, Default)]
+pub struct DotGraph {
+    name: String,
+    nodes: Vec<(String, String)>,
+    edges: Vec<(String, String)>,
+}
+
+impl DotGraph {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn node(&mut self, id: impl Into<String>, label: impl Into<String>) {
+        self.nodes.push((id.into(), label.into()));
+    }
+
+    pub fn edge(&mut self, from: impl Into<String>, to: impl Into<String>) {
+        self.edges.push((from.into(), to.into()));
+    }
+}
+
+/// `s` as a quoted DOT string, with each line left-justified.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\l"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl fmt::Display for DotGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "digraph {} {{", quote(&self.name))?;
+        writeln!(f, "  node [shape=box];")?;
+        for (id, label) in &self.nodes {
+            // a trailing `\l` left-justifies the last line as well
+            writeln!(
+                f,
+                "  {} [label={}];",
+                quote(id),
+                quote(&format!("{label}\n"))
+            )?;
+        }
+        for (from, to) in &self.edges {
+            writeln!(f, "  {} -> {};", quote(from), quote(to))?;
+        }
+        write!(f, "}}")
+    }
+}
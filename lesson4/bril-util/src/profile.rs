@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use snafu::{ResultExt, Whatever, whatever};
+
+/// How many times each block executed on a profiling run, as collected by
+/// `bril-interp --profile-blocks` or by lesson 8's `profile.py`. The label of
+/// an unlabeled entry block is `-`.
+#[derive(Default)]
+pub struct Profile {
+    counts: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl Profile {
+    /// Parses lines of the form `function label count`.
+    pub fn parse(contents: &str) -> Result<Self, Whatever> {
+        let mut profile = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let [function, label, count] =
+                line.split_whitespace().collect::<Vec<_>>()[..]
+            else {
+                whatever!("Line {} of the profile is malformed", i + 1);
+            };
+            let count = count.parse().whatever_context(format!(
+                "Line {} of the profile has an invalid count",
+                i + 1
+            ))?;
+            profile
+                .counts
+                .entry(function.to_string())
+                .or_default()
+                .insert(label.to_string(), count);
+        }
+        Ok(profile)
+    }
+
+    /// How many times the block labeled `label` in `function` executed, if it
+    /// was profiled.
+    pub fn count(&self, function: &str, label: &str) -> Option<u64> {
+        self.counts.get(function)?.get(label).copied()
+    }
+
+    /// Counts another execution of the block labeled `label` in `function`.
+    pub fn record(&mut self, function: &str, label: &str) {
+        let labels = match self.counts.get_mut(function) {
+            Some(labels) => labels,
+            None => self.counts.entry(function.to_string()).or_default(),
+        };
+        match labels.get_mut(label) {
+            Some(count) => *count += 1,
+            None => {
+                labels.insert(label.to_string(), 1);
+            }
+        }
+    }
+}
+
+/// Prints the profile in the format [`Profile::parse`] reads.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (function, labels) in &self.counts {
+            for (label, count) in labels {
+                writeln!(f, "{function} {label} {count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a profile from `path`.
+pub fn read_profile(path: &Path) -> Result<Profile, Whatever> {
+    let contents = fs::read_to_string(path).whatever_context(format!(
+        "Failed to read the profile {}",
+        path.to_string_lossy()
+    ))?;
+    Profile::parse(&contents)
+}
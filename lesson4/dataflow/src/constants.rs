@@ -0,0 +1,101 @@
+use bril_rs::{Instruction, Literal, ValueOps};
+use bril_util::InstructionExt;
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, FunctionCfg, slotmap::SecondaryMap,
+};
+
+use crate::{
+    Direction,
+    lattice::{Flat, Lattice, MapLattice},
+    solve_lattice_dataflow,
+};
+
+/// The constant each variable is known to be, or [`Flat::Top`] if it may be
+/// more than one value.
+pub type Constants = MapLattice<String, Flat<Literal>>;
+
+/// The result of `op` on constant `arguments`, if it is an integer or boolean
+/// operation that cannot fail.
+fn fold(op: &ValueOps, arguments: &[Literal]) -> Option<Literal> {
+    Some(match (op, arguments) {
+        (ValueOps::Add, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Int(a.wrapping_add(*b))
+        }
+        (ValueOps::Sub, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Int(a.wrapping_sub(*b))
+        }
+        (ValueOps::Mul, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Int(a.wrapping_mul(*b))
+        }
+        (ValueOps::Eq, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Bool(a == b)
+        }
+        (ValueOps::Lt, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Bool(a < b)
+        }
+        (ValueOps::Gt, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Bool(a > b)
+        }
+        (ValueOps::Le, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Bool(a <= b)
+        }
+        (ValueOps::Ge, [Literal::Int(a), Literal::Int(b)]) => {
+            Literal::Bool(a >= b)
+        }
+        (ValueOps::Not, [Literal::Bool(a)]) => Literal::Bool(!a),
+        (ValueOps::And, [Literal::Bool(a), Literal::Bool(b)]) => {
+            Literal::Bool(*a && *b)
+        }
+        (ValueOps::Or, [Literal::Bool(a), Literal::Bool(b)]) => {
+            Literal::Bool(*a || *b)
+        }
+        (ValueOps::Id, [a]) => a.clone(),
+        _ => return None,
+    })
+}
+
+fn transfer(block: &BasicBlock, mut constants: Constants) -> Constants {
+    for instruction in &block.instructions {
+        let value = match instruction {
+            Instruction::Constant { value, .. } => Flat::Value(value.clone()),
+            Instruction::Value { args, op, .. } => {
+                // an argument still at bottom is not defined along any path
+                // seen so far, so it is not folded either
+                let arguments = args
+                    .iter()
+                    .map(|arg| match constants.get(arg) {
+                        Flat::Value(value) => Some(value),
+                        Flat::Bottom | Flat::Top => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+                arguments
+                    .and_then(|arguments| fold(op, &arguments))
+                    .map_or(Flat::Top, Flat::Value)
+            }
+            Instruction::Effect { .. } => continue,
+        };
+        if let Some(dest) = instruction.kill() {
+            constants.set(dest.clone(), value);
+        }
+    }
+    constants
+}
+
+/// The constant each variable is known to be at the end of each block, as
+/// assembled from the lattices in [`crate::lattice`]. Arguments may be any
+/// value, and a variable defined along only some paths is the constant it is
+/// along those.
+pub fn compute_constants(
+    cfg: &FunctionCfg,
+) -> SecondaryMap<BasicBlockIdx, Constants> {
+    let mut boundary = Constants::bottom();
+    for argument in &cfg.signature.arguments {
+        boundary.set(argument.name.clone(), Flat::Top);
+    }
+    solve_lattice_dataflow(
+        cfg,
+        Direction::Forward,
+        boundary,
+        |block, _block_idx, constants| transfer(block, constants),
+    )
+}
@@ -0,0 +1,112 @@
+//! Lattices for [`solve_lattice_dataflow`](crate::solve_lattice_dataflow),
+//! with combinators that build a lattice out of others, so that an analysis
+//! only has to pick its lattice and write its transfer function. For example,
+//! constant propagation is a [`MapLattice`] from variables to [`Flat`]
+//! constants, and running two analyses at once is their product, a pair.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    hash::Hash,
+};
+
+/// A join-semilattice of finite height, ordered from [`Lattice::bottom`], the
+/// fact no block has before the analysis reaches it.
+pub trait Lattice: Clone + PartialEq {
+    fn bottom() -> Self;
+
+    /// Replaces `self` with the least upper bound of `self` and `other`.
+    fn join(&mut self, other: &Self);
+}
+
+/// The values of `T`, none comparable to another, between a bottom meaning
+/// no value yet and a top meaning possibly more than one value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Flat<T> {
+    Bottom,
+    Value(T),
+    Top,
+}
+
+impl<T: Clone + PartialEq> Lattice for Flat<T> {
+    fn bottom() -> Self {
+        Self::Bottom
+    }
+
+    fn join(&mut self, other: &Self) {
+        match (&*self, other) {
+            (_, Self::Bottom) | (Self::Top, _) => {}
+            (Self::Value(value), Self::Value(other_value))
+                if value == other_value => {}
+            (Self::Bottom, _) => *self = other.clone(),
+            _ => *self = Self::Top,
+        }
+    }
+}
+
+/// Sets ordered by inclusion, as in "may" analyses.
+impl<T: Clone + Eq + Hash> Lattice for HashSet<T> {
+    fn bottom() -> Self {
+        HashSet::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.extend(other.iter().cloned());
+    }
+}
+
+/// The product of two lattices, ordered componentwise.
+impl<A: Lattice, B: Lattice> Lattice for (A, B) {
+    fn bottom() -> Self {
+        (A::bottom(), B::bottom())
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.0.join(&other.0);
+        self.1.join(&other.1);
+    }
+}
+
+/// Maps from keys to the lattice `V`, ordered pointwise, where a key not in
+/// the map is bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapLattice<K, V> {
+    /// Never contains bottom, so that equal maps have equal entries.
+    entries: BTreeMap<K, V>,
+}
+
+impl<K: Ord, V: Lattice> MapLattice<K, V> {
+    /// The value of `key`, which is bottom if it was never set.
+    pub fn get(&self, key: &K) -> V {
+        self.entries.get(key).cloned().unwrap_or_else(V::bottom)
+    }
+
+    pub fn set(&mut self, key: K, value: V) {
+        if value == V::bottom() {
+            self.entries.remove(&key);
+        } else {
+            self.entries.insert(key, value);
+        }
+    }
+
+    /// The keys whose values are not bottom, in order, with their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter()
+    }
+}
+
+impl<K: Ord + Clone, V: Lattice> Lattice for MapLattice<K, V> {
+    fn bottom() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    fn join(&mut self, other: &Self) {
+        for (key, value) in &other.entries {
+            self.entries
+                .entry(key.clone())
+                .or_insert_with(V::bottom)
+                .join(value);
+        }
+    }
+}
@@ -7,6 +7,10 @@ use build_cfg::{
     BasicBlock, BasicBlockIdx, FunctionCfg, slotmap::SecondaryMap,
 };
 
+use crate::lattice::Lattice;
+
+pub mod constants;
+pub mod lattice;
 pub mod live_variables;
 pub mod reaching_definitions;
 
@@ -37,10 +41,14 @@ pub fn construct_postorder(cfg: &FunctionCfg) -> Vec<BasicBlockIdx> {
     traversal
 }
 
+/// Solves a "may" dataflow problem whose merge is `merge`, starting every
+/// block from nothing. The input of the entry going forward, or of each block
+/// that leaves the function going backward, also includes `boundary_values`,
+/// e.g., the variables live after the function returns.
 pub fn solve_dataflow<T: Clone + PartialEq + Eq + Hash>(
     cfg: &FunctionCfg,
     direction: Direction,
-    entry_inputs: HashSet<T>,
+    boundary_values: HashSet<T>,
     merge: impl Fn(HashSet<T>, &HashSet<T>) -> HashSet<T>,
     transfer: impl Fn(&BasicBlock, BasicBlockIdx, HashSet<T>) -> HashSet<T>,
 ) -> SecondaryMap<BasicBlockIdx, HashSet<T>> {
@@ -56,8 +64,16 @@ pub fn solve_dataflow<T: Clone + PartialEq + Eq + Hash>(
     for block_idx in cfg.vertices.keys() {
         solution.insert(block_idx, HashSet::new());
     }
-    let mut initial_in = entry_inputs;
     while let Some(current) = blocks.pop_front() {
+        let at_boundary = match direction {
+            Direction::Forward => current == cfg.entry,
+            Direction::Backward => cfg.successors(current).is_empty(),
+        };
+        let mut initial_in = if at_boundary {
+            boundary_values.clone()
+        } else {
+            HashSet::new()
+        };
         match direction {
             Direction::Forward => {
                 for predecessor in cfg.predecessors(current) {
@@ -84,8 +100,115 @@ pub fn solve_dataflow<T: Clone + PartialEq + Eq + Hash>(
                 }
             }
         }
+    }
+    solution
+}
 
-        initial_in = HashSet::new();
+/// Like [`solve_dataflow`], but for facts in any [`Lattice`]: every block
+/// starts from [`Lattice::bottom`], and the facts flowing into a block are
+/// joined, with `boundary` at the same blocks as the boundary values there.
+pub fn solve_lattice_dataflow<L: Lattice>(
+    cfg: &FunctionCfg,
+    direction: Direction,
+    boundary: L,
+    transfer: impl Fn(&BasicBlock, BasicBlockIdx, L) -> L,
+) -> SecondaryMap<BasicBlockIdx, L> {
+    let postorder_traversal = construct_postorder(cfg);
+    let mut blocks = match direction {
+        Direction::Forward => {
+            VecDeque::from_iter(postorder_traversal.into_iter().rev())
+        }
+        Direction::Backward => VecDeque::from_iter(postorder_traversal),
+    };
+
+    let mut solution = SecondaryMap::with_capacity(cfg.vertices.capacity());
+    for block_idx in cfg.vertices.keys() {
+        solution.insert(block_idx, L::bottom());
+    }
+    while let Some(current) = blocks.pop_front() {
+        let (at_boundary, neighbors) = match direction {
+            Direction::Forward => (
+                current == cfg.entry,
+                cfg.predecessors(current).to_vec(),
+            ),
+            Direction::Backward => {
+                let successors = cfg.successors(current);
+                (successors.is_empty(), successors)
+            }
+        };
+        let mut input = if at_boundary {
+            boundary.clone()
+        } else {
+            L::bottom()
+        };
+        for neighbor in neighbors {
+            input.join(&solution[neighbor]);
+        }
+
+        let output = transfer(&cfg.vertices[current], current, input);
+        if output != solution[current] {
+            solution[current] = output;
+            match direction {
+                Direction::Forward => {
+                    blocks.extend(cfg.successors(current));
+                }
+                Direction::Backward => {
+                    blocks.extend(cfg.predecessors(current).iter().copied());
+                }
+            }
+        }
+    }
+    solution
+}
+
+/// Like [`solve_dataflow`], but for "must" analyses whose merge is
+/// intersection, so each block starts from `universe` and the solution is the
+/// greatest fixpoint. Blocks without predecessors (or successors, going
+/// backward) instead take `boundary` as input, as does the entry going
+/// forward.
+pub fn solve_must_dataflow<T: Clone + PartialEq + Eq + Hash>(
+    cfg: &FunctionCfg,
+    direction: Direction,
+    boundary: HashSet<T>,
+    universe: &HashSet<T>,
+    transfer: impl Fn(&BasicBlock, BasicBlockIdx, HashSet<T>) -> HashSet<T>,
+) -> SecondaryMap<BasicBlockIdx, HashSet<T>> {
+    let mut order = construct_postorder(cfg);
+    if matches!(direction, Direction::Forward) {
+        order.reverse();
+    }
+
+    let mut solution = SecondaryMap::with_capacity(cfg.vertices.capacity());
+    for block_idx in cfg.vertices.keys() {
+        solution.insert(block_idx, universe.clone());
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for current in order.iter().copied() {
+            let neighbors = match direction {
+                Direction::Forward => cfg.predecessors(current).to_vec(),
+                Direction::Backward => cfg.successors(current),
+            };
+            let at_boundary = neighbors.is_empty()
+                || (matches!(direction, Direction::Forward)
+                    && current == cfg.entry);
+            let mut input = if at_boundary {
+                boundary.clone()
+            } else {
+                universe.clone()
+            };
+            for neighbor in neighbors {
+                input.retain(|element| solution[neighbor].contains(element));
+            }
+
+            let new_out = transfer(&cfg.vertices[current], current, input);
+            if new_out != solution[current] {
+                solution[current] = new_out;
+                changed = true;
+            }
+        }
     }
     solution
 }
@@ -1,18 +1,20 @@
 use std::collections::HashSet;
 
 use bril_util::InstructionExt;
-use build_cfg::{BasicBlock, BasicBlockIdx, FunctionCfg};
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, FunctionCfg,
+    pass::{Analysis, AnalysisCache},
+    slotmap::SecondaryMap,
+    symbol::{Symbol, SymbolTable},
+};
 
 use crate::{Direction, solve_dataflow};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct Variable(String);
-
 fn transfer(
+    symbols: &SymbolTable,
     block: &BasicBlock,
-    _block_idx: BasicBlockIdx,
-    mut outputs: HashSet<Variable>,
-) -> HashSet<Variable> {
+    mut outputs: HashSet<Symbol>,
+) -> HashSet<Symbol> {
     let mut kill_set = HashSet::new();
     let mut gen_set = HashSet::new();
     for instruction in &block.instructions {
@@ -20,42 +22,179 @@ fn transfer(
             instruction
                 .gen_set()
                 .iter()
-                .filter(|variable| !kill_set.contains(variable))
-                .map(|variable| Variable(variable.to_string())),
+                .map(|variable| symbols.intern(variable))
+                .filter(|variable| !kill_set.contains(variable)),
         );
         if let Some(kill) = instruction.kill() {
+            let kill = symbols.intern(kill);
             kill_set.insert(kill);
-            outputs.remove(&Variable(kill.clone()));
+            outputs.remove(&kill);
         }
     }
     outputs.extend(gen_set);
     outputs
 }
 
-pub fn live_variables(cfg: &FunctionCfg) {
-    println!("@{} {{", cfg.signature.name);
-    for (block, solution) in solve_dataflow(
+/// The variables live on entry to each block, as symbols in
+/// [`FunctionCfg::symbols`].
+pub fn compute_live_variables(
+    cfg: &FunctionCfg,
+) -> SecondaryMap<BasicBlockIdx, HashSet<Symbol>> {
+    solve_dataflow(
         cfg,
         Direction::Backward,
         HashSet::new(),
-        |lhs, rhs| lhs.union(rhs).cloned().collect(),
-        transfer,
-    ) {
-        if let Some(label) = &cfg.vertices[block].label {
-            println!("  .{}", label.name);
-        }
-        let mut variables = solution
+        |lhs, rhs| lhs.union(rhs).copied().collect(),
+        |block, _block_idx, outputs| transfer(&cfg.symbols, block, outputs),
+    )
+}
+
+/// The variables live on entry to each block, as computed by
+/// [`compute_live_variables`].
+pub struct LiveVariables;
+
+impl Analysis for LiveVariables {
+    type Output = SecondaryMap<BasicBlockIdx, HashSet<Symbol>>;
+
+    fn compute(cfg: &FunctionCfg, _cache: &mut AnalysisCache) -> Self::Output {
+        compute_live_variables(cfg)
+    }
+}
+
+/// The variables live at each instruction boundary of each block, as symbols
+/// in [`FunctionCfg::symbols`]: the `i`th set is live before the `i`th
+/// instruction, and the last is live on exit from the block.
+pub fn compute_instruction_liveness(
+    cfg: &FunctionCfg,
+) -> SecondaryMap<BasicBlockIdx, Vec<HashSet<Symbol>>> {
+    let live_in = compute_live_variables(cfg);
+    let mut liveness = SecondaryMap::with_capacity(cfg.vertices.capacity());
+    for (block_idx, block) in &cfg.vertices {
+        let mut live = cfg
+            .successors(block_idx)
             .into_iter()
-            .map(|variable| variable.0)
-            .collect::<Vec<_>>();
-        variables.sort();
-        println!(
-            "  in:  {}",
-            if variables.is_empty() {
-                "∅".to_string()
-            } else {
-                variables.join(", ")
+            .filter_map(|successor| live_in.get(successor))
+            .flatten()
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut boundaries = vec![live.clone()];
+        for instruction in block.instructions.iter().rev() {
+            if let Some(kill) = instruction.kill() {
+                live.remove(&cfg.symbols.intern(kill));
             }
+            live.extend(
+                instruction
+                    .gen_set()
+                    .iter()
+                    .map(|variable| cfg.symbols.intern(variable)),
+            );
+            boundaries.push(live.clone());
+        }
+        boundaries.reverse();
+        liveness.insert(block_idx, boundaries);
+    }
+    liveness
+}
+
+/// The variables live at each instruction boundary, as computed by
+/// [`compute_instruction_liveness`].
+pub struct InstructionLiveness;
+
+impl Analysis for InstructionLiveness {
+    type Output = SecondaryMap<BasicBlockIdx, Vec<HashSet<Symbol>>>;
+
+    fn compute(cfg: &FunctionCfg, _cache: &mut AnalysisCache) -> Self::Output {
+        compute_instruction_liveness(cfg)
+    }
+}
+
+/// An instruction assigning a variable that is dead right after it, so the
+/// value it computes is never used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadStore {
+    pub block: BasicBlockIdx,
+    /// The index of the instruction in the block.
+    pub index: usize,
+    pub variable: Symbol,
+}
+
+/// The [`DeadStore`]s of `cfg`, in block order, given its `liveness` from
+/// [`compute_instruction_liveness`]. Whether the instruction may be removed
+/// is up to the caller, since a call with an unused result still has effects.
+pub fn find_dead_stores(
+    cfg: &FunctionCfg,
+    liveness: &SecondaryMap<BasicBlockIdx, Vec<HashSet<Symbol>>>,
+) -> Vec<DeadStore> {
+    let mut dead_stores = vec![];
+    for (block_idx, block) in &cfg.vertices {
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            let Some(kill) = instruction.kill() else {
+                continue;
+            };
+            let variable = cfg.symbols.intern(kill);
+            if !liveness[block_idx][index + 1].contains(&variable) {
+                dead_stores.push(DeadStore {
+                    block: block_idx,
+                    index,
+                    variable,
+                });
+            }
+        }
+    }
+    dead_stores
+}
+
+/// Emits a warning for each of the `dead_stores` of `cfg`.
+pub fn warn_dead_stores(cfg: &FunctionCfg, dead_stores: &[DeadStore]) {
+    for dead_store in dead_stores {
+        tracing::warn!(
+            target: "dataflow::live_variables",
+            "@{}: the value assigned to `{}` by `{}` is never used",
+            cfg.signature.name,
+            cfg.symbols.name(dead_store.variable),
+            cfg.vertices[dead_store.block].instructions[dead_store.index]
         );
     }
 }
+
+fn format_variables(cfg: &FunctionCfg, variables: &HashSet<Symbol>) -> String {
+    let mut variables = variables
+        .iter()
+        .map(|variable| cfg.symbols.name(*variable).to_string())
+        .collect::<Vec<_>>();
+    variables.sort();
+    if variables.is_empty() {
+        "∅".to_string()
+    } else {
+        variables.join(", ")
+    }
+}
+
+/// Prints the variables live on entry to each block, or, if
+/// `per_instruction`, those live between each instruction.
+pub fn live_variables(cfg: &FunctionCfg, per_instruction: bool) {
+    println!("@{} {{", cfg.signature.name);
+    if per_instruction {
+        for (block, boundaries) in compute_instruction_liveness(cfg) {
+            if let Some(label) = &cfg.vertices[block].label {
+                println!("  .{}", label.name);
+            }
+            for (instruction, live) in
+                cfg.vertices[block].instructions.iter().zip(&boundaries)
+            {
+                println!("    {{{}}}", format_variables(cfg, live));
+                println!("    {}", instruction);
+            }
+            if let Some(live) = boundaries.last() {
+                println!("    {{{}}}", format_variables(cfg, live));
+            }
+        }
+        return;
+    }
+    for (block, solution) in compute_live_variables(cfg) {
+        if let Some(label) = &cfg.vertices[block].label {
+            println!("  .{}", label.name);
+        }
+        println!("  in:  {}", format_variables(cfg, &solution));
+    }
+}
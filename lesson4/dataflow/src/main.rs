@@ -2,17 +2,39 @@ use std::{fs, io, path::PathBuf, str::FromStr};
 
 use argh::FromArgs;
 use bril_rs::Program;
+use bril_util::{
+    filter::{FunctionFilter, FunctionPattern},
+    output::{DotGraph, OutputFormat, print_envelope},
+};
+use build_cfg::{BasicBlockIdx, FunctionCfg};
 use dataflow::{
-    live_variables::live_variables,
+    constants::compute_constants,
+    lattice::Flat,
+    live_variables::{
+        compute_instruction_liveness, compute_live_variables, find_dead_stores,
+        live_variables, warn_dead_stores,
+    },
     reaching_definitions::{
         compute_reaching_definitions, definition_is_reachable,
     },
 };
+use serde_json::json;
 use snafu::{ResultExt, Whatever, whatever};
 
 enum Analysis {
     ReachingDefinitions,
     LiveVariables,
+    Constants,
+}
+
+impl Analysis {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::ReachingDefinitions => "def",
+            Self::LiveVariables => "live",
+            Self::Constants => "const",
+        }
+    }
 }
 
 impl FromStr for Analysis {
@@ -22,6 +44,7 @@ impl FromStr for Analysis {
         Ok(match s {
             "def" => Self::ReachingDefinitions,
             "live" => Self::LiveVariables,
+            "const" => Self::Constants,
             _ => whatever!("Unknown analysis '{}'", s),
         })
     }
@@ -30,70 +53,208 @@ impl FromStr for Analysis {
 /// Performs dataflow analysis on the given Bril program
 #[derive(FromArgs)]
 struct Opts {
-    /// the type of dataflow analysis to perform
+    /// the type of dataflow analysis to perform: `def`, `live`, or `const`
     #[argh(option)]
     analysis: Analysis,
 
+    /// with `--analysis live`, print the variables live between each pair of
+    /// instructions instead of on entry to each block
+    #[argh(switch)]
+    per_instruction: bool,
+
+    /// print each function as `json`, `text` (the default), or a `dot` graph of
+    /// its blocks labeled with their facts
+    #[argh(option, default = "OutputFormat::Text")]
+    output: OutputFormat,
+
+    /// rerun the analysis whenever the input file changes
+    #[argh(switch)]
+    watch: bool,
+
+    /// only analyze functions matching this name, like `@main` or `@test_*`:
+    /// may be repeated, and omit for every function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
     /// input Bril file; omit for stdin
     #[argh(positional)]
     input: Option<PathBuf>,
+
+    /// show the events of an analysis at a level, e.g.,
+    /// `dataflow::live_variables=off`: may be repeated
+    #[argh(option)]
+    trace: Vec<String>,
 }
 
 #[snafu::report]
 fn main() -> Result<(), Whatever> {
     let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+    let filter = FunctionFilter::new(opts.function.clone());
+    if opts.per_instruction && opts.output != OutputFormat::Text {
+        whatever!("--per-instruction only applies to `--output text`");
+    }
 
-    let program: Program = if let Some(path) = opts.input {
-        let contents = fs::read_to_string(&path).whatever_context(format!(
-            "Failed to read the contents of {}",
-            path.to_string_lossy()
-        ))?;
-        serde_json::from_str(&contents).whatever_context(
-            "Failed to parse input file as a valid Bril program",
-        )?
-    } else {
-        serde_json::from_reader(io::stdin()).whatever_context(
-            "Failed to parse standard input as a valid Bril program",
-        )?
-    };
+    watch::run(opts.input.as_deref(), opts.watch, || {
+        let program: Program = if let Some(path) = &opts.input {
+            let contents =
+                fs::read_to_string(path).whatever_context(format!(
+                    "Failed to read the contents of {}",
+                    path.to_string_lossy()
+                ))?;
+            serde_json::from_str(&contents).whatever_context(
+                "Failed to parse input file as a valid Bril program",
+            )?
+        } else {
+            serde_json::from_reader(io::stdin()).whatever_context(
+                "Failed to parse standard input as a valid Bril program",
+            )?
+        };
 
-    for function in program.functions {
-        let cfg = build_cfg::build_cfg(&function, true)
-            .whatever_context("Failed to build cfg")?;
-
-        match opts.analysis {
-            Analysis::ReachingDefinitions => {
-                let solution = compute_reaching_definitions(&cfg);
-                println!("@{} {{", cfg.signature.name);
-                for (block, solution) in solution {
-                    if let Some(label) = &cfg.vertices[block].label {
-                        println!("  .{}", label.name);
+        for function in program.functions {
+            if !filter.selects(&function.name) {
+                continue;
+            }
+            let cfg = build_cfg::build_cfg(&function, true)
+                .whatever_context("Failed to build cfg")?;
+
+            match opts.analysis {
+                Analysis::ReachingDefinitions => {
+                    let solution = compute_reaching_definitions(&cfg);
+                    for (block, solution) in &solution {
+                        for definition in solution {
+                            if !definition_is_reachable(&cfg, block, definition)
+                            {
+                                panic!(
+                                    "No reachable definition found for {:?} = {:?}",
+                                    cfg.symbols.name(definition.variable),
+                                    definition.value
+                                );
+                            }
+                        }
                     }
-                    let mut printouts = solution
+
+                    let facts = solution
                         .iter()
-                        .map(|definition| {
-                            format!("    {} = {:?}", definition.0, definition.1)
+                        .map(|(block, solution)| {
+                            let mut printouts = solution
+                                .iter()
+                                .map(|definition| {
+                                    format!(
+                                        "{} = {:?}",
+                                        cfg.symbols.name(definition.variable),
+                                        definition.value
+                                    )
+                                })
+                                .collect::<Vec<_>>();
+                            printouts.sort();
+                            (block, printouts)
                         })
                         .collect::<Vec<_>>();
-                    printouts.sort();
-                    for printout in printouts {
-                        println!("{}", printout);
+                    print_facts(&cfg, &opts.analysis, facts, opts.output);
+                }
+                Analysis::LiveVariables => {
+                    if opts.output == OutputFormat::Text {
+                        live_variables(&cfg, opts.per_instruction);
+                    } else {
+                        let facts = compute_live_variables(&cfg)
+                            .into_iter()
+                            .map(|(block, live)| {
+                                let mut names = live
+                                    .iter()
+                                    .map(|variable| {
+                                        cfg.symbols.name(*variable).to_string()
+                                    })
+                                    .collect::<Vec<_>>();
+                                names.sort();
+                                (block, names)
+                            })
+                            .collect();
+                        print_facts(&cfg, &opts.analysis, facts, opts.output);
                     }
+                    let liveness = compute_instruction_liveness(&cfg);
+                    warn_dead_stores(&cfg, &find_dead_stores(&cfg, &liveness));
+                }
+                Analysis::Constants => {
+                    let facts = compute_constants(&cfg)
+                        .into_iter()
+                        .map(|(block, constants)| {
+                            let printouts = constants
+                                .iter()
+                                .map(|(variable, value)| match value {
+                                    Flat::Value(value) => {
+                                        format!("{variable} = {value}")
+                                    }
+                                    _ => format!("{variable} = ?"),
+                                })
+                                .collect::<Vec<_>>();
+                            (block, printouts)
+                        })
+                        .collect::<Vec<_>>();
+                    print_facts(&cfg, &opts.analysis, facts, opts.output);
+                }
+            }
+        }
 
-                    for definition in solution {
-                        if !definition_is_reachable(&cfg, block, &definition) {
-                            panic!(
-                                "No reachable definition found for {:?} = {:?}",
-                                definition.0, definition.1
-                            );
-                        }
-                    }
+        Ok(())
+    })
+}
+
+/// Prints the facts of `analysis` for each block of `cfg` as text, JSON, or a
+/// graph.
+fn print_facts(
+    cfg: &FunctionCfg,
+    analysis: &Analysis,
+    facts: Vec<(BasicBlockIdx, Vec<String>)>,
+    output: OutputFormat,
+) {
+    let label = |block: BasicBlockIdx| {
+        cfg.vertices[block]
+            .label
+            .as_ref()
+            .map(|label| label.name.clone())
+    };
+    match output {
+        OutputFormat::Json => {
+            let blocks = facts
+                .into_iter()
+                .map(|(block, facts)| {
+                    json!({ "label": label(block), "facts": facts })
+                })
+                .collect::<Vec<_>>();
+            print_envelope(
+                env!("CARGO_PKG_NAME"),
+                Some(cfg.signature.name.as_str()),
+                json!({ "analysis": analysis.name(), "blocks": blocks }),
+            );
+        }
+        OutputFormat::Text => {
+            println!("@{} {{", cfg.signature.name);
+            for (block, printouts) in facts {
+                if let Some(label) = label(block) {
+                    println!("  .{}", label);
+                }
+                for printout in printouts {
+                    println!("    {}", printout);
                 }
-                println!("}}");
             }
-            Analysis::LiveVariables => live_variables(&cfg),
+            println!("}}");
+        }
+        OutputFormat::Dot => {
+            let id = |block: BasicBlockIdx| format!("{block:?}");
+            let mut graph = DotGraph::new(&cfg.signature.name);
+            for (block, facts) in facts {
+                let name = label(block)
+                    .map(|label| format!(".{label}"))
+                    .unwrap_or_else(|| "entry".into());
+                let mut lines = vec![name];
+                lines.extend(facts);
+                graph.node(id(block), lines.join("\n"));
+                for successor in cfg.successors(block) {
+                    graph.edge(id(block), id(successor));
+                }
+            }
+            println!("{graph}");
         }
     }
-
-    Ok(())
 }
@@ -1,20 +1,32 @@
 use std::collections::{HashSet, VecDeque};
 
+use bril_rs::Instruction;
 use bril_util::{InstructionExt, InstructionValue};
 use build_cfg::{
     BasicBlock, BasicBlockIdx, FunctionCfg, slotmap::SecondaryMap,
+    symbol::Symbol,
 };
 
 use crate::{Direction, solve_dataflow};
 
-/// (`definition`, `value`, `basic_block`, `index_in_block`).
+/// Where a [`Definition`] comes from.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum DefSite {
+    /// An argument of the function, defined on entry to the function but not
+    /// each time the entry block is reached again through a loop.
+    Argument,
+    /// The instruction at `index` in `block`.
+    Instruction { block: BasicBlockIdx, index: usize },
+}
+
+/// A definition of `variable`, a symbol in [`FunctionCfg::symbols`], to
+/// `value` at `site`.
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct Definition(
-    pub String,
-    pub InstructionValue,
-    pub BasicBlockIdx,
-    pub isize,
-);
+pub struct Definition {
+    pub variable: Symbol,
+    pub value: InstructionValue,
+    pub site: DefSite,
+}
 
 /// Whether `definition` is reachable backward from `block`.
 pub fn definition_is_reachable(
@@ -22,30 +34,32 @@ pub fn definition_is_reachable(
     block: BasicBlockIdx,
     definition: &Definition,
 ) -> bool {
-    if matches!(definition.1, InstructionValue::Argument) {
+    let DefSite::Instruction {
+        block: defining_block,
+        index,
+    } = definition.site
+    else {
         return true;
+    };
+    let defines = |instruction: &Instruction| {
+        instruction.kill().is_some_and(|kill| {
+            cfg.symbols.intern(kill) == definition.variable
+                && instruction.value().as_ref() == Some(&definition.value)
+        })
+    };
+    if !cfg.vertices[defining_block]
+        .instructions
+        .get(index)
+        .is_some_and(defines)
+    {
+        return false;
     }
 
     let mut bfs = VecDeque::new();
     let mut visited = HashSet::new();
     bfs.push_back(block);
     while let Some(current) = bfs.pop_front() {
-        if cfg.vertices[current]
-            .instructions
-            .iter()
-            .enumerate()
-            .rev()
-            .any(|(i, instruction)| {
-                if let (Some(kill), Some(value)) =
-                    (instruction.kill(), instruction.value())
-                {
-                    definition
-                        == &Definition(kill.clone(), value, current, i as isize)
-                } else {
-                    false
-                }
-            })
-        {
+        if current == defining_block {
             return true;
         }
         for predecessor in cfg.predecessors(current) {
@@ -59,44 +73,95 @@ pub fn definition_is_reachable(
     false
 }
 
+fn argument_definitions(cfg: &FunctionCfg) -> HashSet<Definition> {
+    cfg.argument_definitions()
+        .into_iter()
+        .map(|argument| Definition {
+            variable: argument.variable,
+            value: InstructionValue::Argument,
+            site: DefSite::Argument,
+        })
+        .collect()
+}
+
+/// Updates `definitions` to those reaching past `instruction`, the `index`th
+/// instruction of `block_idx`.
+fn define(
+    cfg: &FunctionCfg,
+    definitions: &mut HashSet<Definition>,
+    block_idx: BasicBlockIdx,
+    index: usize,
+    instruction: &Instruction,
+) {
+    if let Some(kill) = instruction.kill() {
+        let kill = cfg.symbols.intern(kill);
+        definitions.retain(|definition| definition.variable != kill);
+        definitions.insert(Definition {
+            variable: kill,
+            value: instruction.value().expect("kill without value somehow"),
+            site: DefSite::Instruction {
+                block: block_idx,
+                index,
+            },
+        });
+    }
+}
+
+/// The definitions reaching the end of each block.
 pub fn compute_reaching_definitions(
     cfg: &FunctionCfg,
 ) -> SecondaryMap<BasicBlockIdx, HashSet<Definition>> {
-    fn transfer(
-        block: &BasicBlock,
-        block_idx: BasicBlockIdx,
-        mut inputs: HashSet<Definition>,
-    ) -> HashSet<Definition> {
-        for (i, instruction) in block.instructions.iter().enumerate() {
-            if let Some(kill) = instruction.kill() {
-                inputs.retain(|input| &input.0 != kill);
-                inputs.insert(Definition(
-                    kill.clone(),
-                    instruction.value().expect("kill without value somehow"),
-                    block_idx,
-                    i as isize,
-                ));
-            }
+    let arguments = argument_definitions(cfg);
+
+    let transfer = |block: &BasicBlock,
+                    block_idx: BasicBlockIdx,
+                    mut inputs: HashSet<Definition>| {
+        // the arguments reach the entry block from outside the function,
+        // whether or not it is also reached through a loop, so they are added
+        // every time rather than only as the initial input
+        if block_idx == cfg.entry {
+            inputs.extend(arguments.iter().cloned());
+        }
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            define(cfg, &mut inputs, block_idx, index, instruction);
         }
         inputs
-    }
+    };
 
     solve_dataflow(
         cfg,
         Direction::Forward,
-        cfg.signature
-            .arguments
-            .iter()
-            .map(|argument| {
-                Definition(
-                    argument.name.clone(),
-                    InstructionValue::Argument,
-                    cfg.entry,
-                    -1,
-                )
-            })
-            .collect(),
+        HashSet::new(),
         |lhs, rhs| lhs.union(rhs).cloned().collect(),
         transfer,
     )
 }
+
+/// The definitions reaching each instruction of `block_idx`, where the `i`th
+/// set reaches the `i`th instruction, given the `solution` of
+/// [`compute_reaching_definitions`].
+pub fn reaching_definitions_in_block(
+    cfg: &FunctionCfg,
+    solution: &SecondaryMap<BasicBlockIdx, HashSet<Definition>>,
+    block_idx: BasicBlockIdx,
+) -> Vec<HashSet<Definition>> {
+    let mut definitions = cfg
+        .predecessors(block_idx)
+        .iter()
+        .filter_map(|predecessor| solution.get(*predecessor))
+        .flatten()
+        .cloned()
+        .collect::<HashSet<_>>();
+    if block_idx == cfg.entry {
+        definitions.extend(argument_definitions(cfg));
+    }
+
+    let mut reaching = vec![];
+    for (index, instruction) in
+        cfg.vertices[block_idx].instructions.iter().enumerate()
+    {
+        reaching.push(definitions.clone());
+        define(cfg, &mut definitions, block_idx, index, instruction);
+    }
+    reaching
+}
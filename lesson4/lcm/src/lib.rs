@@ -0,0 +1,385 @@
+//! Partial redundancy elimination by lazy code motion, following the
+//! formulation in the Dragon Book (section 9.5.5). Each expression is computed
+//! into a temporary at the latest points that still cover every redundant
+//! computation of it, which then become copies of the temporary.
+
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Function, Instruction, ValueOps};
+use bril_util::{InstructionExt, is_commutative, names::NameGenerator};
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, FunctionCfg, Label, slotmap::SecondaryMap,
+};
+use dataflow::{Direction, solve_dataflow, solve_must_dataflow};
+use snafu::{ResultExt, Whatever};
+
+/// An operation and its arguments, sorted if the operation is commutative.
+/// Expressions are identified by how they are written, so two computations of
+/// the same expression only have the same value if no argument is assigned in
+/// between.
+type Expression = (ValueOps, Vec<String>);
+
+/// The expression computed by `instruction`, if it can be moved: it must have
+/// no side effects and depend only on its arguments. Division is excluded
+/// since moving a trap earlier could also move it before a `print`, and copies
+/// are excluded since there is nothing to save.
+fn movable_expression(instruction: &Instruction) -> Option<Expression> {
+    match instruction {
+        Instruction::Value { args, op, .. }
+            if !matches!(
+                op,
+                ValueOps::Id
+                    | ValueOps::Div
+                    | ValueOps::Load
+                    | ValueOps::Call
+                    | ValueOps::Alloc
+                    | ValueOps::Get
+                    | ValueOps::Undef
+            ) =>
+        {
+            let mut args = args.clone();
+            if is_commutative(*op) {
+                args.sort();
+            }
+            Some((*op, args))
+        }
+        _ => None,
+    }
+}
+
+/// The expressions computed in a block before any of their arguments are
+/// assigned there, `e_use` in the Dragon Book.
+fn upward_exposed(
+    block: &BasicBlock,
+    indices: &HashMap<Expression, usize>,
+) -> HashSet<usize> {
+    let mut assigned = HashSet::new();
+    let mut exposed = HashSet::new();
+    for instruction in &block.instructions {
+        if let Some(expression) = movable_expression(instruction)
+            .filter(|(_, args)| args.iter().all(|arg| !assigned.contains(arg)))
+        {
+            exposed.insert(indices[&expression]);
+        }
+        if let Some(dest) = instruction.kill() {
+            assigned.insert(dest.clone());
+        }
+    }
+    exposed
+}
+
+/// The expressions with an argument assigned in a block, `e_kill` in the
+/// Dragon Book.
+fn killed(
+    block: &BasicBlock,
+    readers: &HashMap<String, Vec<usize>>,
+) -> HashSet<usize> {
+    block
+        .instructions
+        .iter()
+        .filter_map(|instruction| instruction.kill())
+        .flat_map(|dest| readers.get(dest).into_iter().flatten().copied())
+        .collect()
+}
+
+/// The intersection of `sets` over the predecessors of `block`, which is
+/// empty for the entry.
+fn meet_predecessors(
+    cfg: &FunctionCfg,
+    sets: &SecondaryMap<BasicBlockIdx, HashSet<usize>>,
+    block: BasicBlockIdx,
+) -> HashSet<usize> {
+    let mut predecessors = cfg.predecessors(block).iter();
+    let Some(first) = predecessors.next() else {
+        return HashSet::new();
+    };
+    predecessors.fold(sets[*first].clone(), |meet, predecessor| {
+        &meet & &sets[*predecessor]
+    })
+}
+
+/// Ensures the entry has no predecessors and puts an empty block on each edge
+/// into a block with several predecessors, so that a computation can be
+/// placed on any edge that needs it by placing it at the start of a block.
+/// Returns the blocks put on edges.
+fn split_join_edges(
+    cfg: &mut FunctionCfg,
+    names: &mut NameGenerator,
+) -> Vec<BasicBlockIdx> {
+    if !cfg.predecessors(cfg.entry).is_empty() {
+        let entry = cfg.entry;
+        let new_entry = cfg.add_block(BasicBlock {
+            is_entry: true,
+            ..Default::default()
+        });
+        cfg.set_unconditional_edge(new_entry, entry);
+        cfg.vertices[entry].is_entry = false;
+        cfg.entry = new_entry;
+    }
+
+    let mut edge_blocks = vec![];
+    for block in cfg.vertices.keys().collect::<Vec<_>>() {
+        let predecessors = cfg.predecessors(block).to_vec();
+        if predecessors.len() < 2 {
+            continue;
+        }
+        let name = cfg.vertices[block]
+            .label
+            .as_ref()
+            .expect("Blocks with predecessors have labels")
+            .name
+            .clone();
+        for predecessor in predecessors {
+            // the two edges of a branch with the same targets cannot be told
+            // apart, so it is left alone, which is safe but may not be optimal
+            if cfg.successors(predecessor) == [block, block] {
+                continue;
+            }
+            let edge_block = cfg.add_block(BasicBlock {
+                label: Some(Label {
+                    name: names.fresh(&format!("{name}.edge")),
+                }),
+                ..Default::default()
+            });
+            cfg.reorient_edge(predecessor, block, edge_block);
+            cfg.set_unconditional_edge(edge_block, block);
+            edge_blocks.push(edge_block);
+        }
+    }
+    edge_blocks
+}
+
+/// Removes the blocks put on edges by [`split_join_edges`] that are not
+/// needed: those still empty, and those after a block with no other successor,
+/// whose computations move to the end of that block instead.
+fn merge_edge_blocks(cfg: &mut FunctionCfg, edge_blocks: Vec<BasicBlockIdx>) {
+    for edge_block in edge_blocks {
+        let predecessor = cfg.predecessors(edge_block)[0];
+        let successor = cfg.successors(edge_block)[0];
+
+        // the jump is the only instruction of an empty block
+        if cfg.vertices[edge_block].instructions.len() > 1
+            && cfg.successors(predecessor).len() > 1
+        {
+            continue;
+        }
+
+        let mut instructions = cfg.remove_block(edge_block, Some(successor));
+        instructions.pop();
+        let before_exit = cfg.vertices[predecessor].index_before_exit();
+        cfg.vertices[predecessor]
+            .instructions
+            .insert_many(before_exit, instructions);
+    }
+}
+
+/// The number of computations [`lazy_code_motion`] placed and the number of
+/// original computations it replaced with copies.
+#[derive(Default)]
+pub struct Motion {
+    pub inserted: usize,
+    pub replaced: usize,
+}
+
+/// Performs lazy code motion on `function`, which removes every partially
+/// redundant computation of an expression that can be removed without
+/// lengthening any path, including loop invariant ones, while keeping each
+/// temporary live for as short as possible.
+pub fn lazy_code_motion(function: &mut Function) -> Result<Motion, Whatever> {
+    let mut names = NameGenerator::new(function);
+    let mut cfg = build_cfg::build_cfg(function, true)
+        .whatever_context("Failed to build cfg")?;
+    cfg.make_fallthroughs_explicit();
+
+    // the type and temporary of each expression, indexed by `indices`
+    let mut indices = HashMap::new();
+    let mut expressions = vec![];
+    for instruction in
+        cfg.vertices.values().flat_map(|block| &block.instructions)
+    {
+        if let (Some(expression), Instruction::Value { dest, op_type, .. }) =
+            (movable_expression(instruction), instruction)
+        {
+            indices.entry(expression.clone()).or_insert_with(|| {
+                expressions.push((
+                    expression,
+                    op_type.clone(),
+                    names.fresh(&format!("{dest}.lcm")),
+                ));
+                expressions.len() - 1
+            });
+        }
+    }
+    if expressions.is_empty() {
+        return Ok(Motion::default());
+    }
+
+    // the expressions that read each variable
+    let mut readers = HashMap::<_, Vec<_>>::new();
+    for (i, ((_, args), _, _)) in expressions.iter().enumerate() {
+        for arg in args {
+            readers.entry(arg.clone()).or_default().push(i);
+        }
+    }
+
+    let edge_blocks = split_join_edges(&mut cfg, &mut names);
+
+    let universe = (0..expressions.len()).collect::<HashSet<_>>();
+    let uses = cfg
+        .vertices
+        .iter()
+        .map(|(block_idx, block)| (block_idx, upward_exposed(block, &indices)))
+        .collect::<SecondaryMap<_, _>>();
+    let kills = cfg
+        .vertices
+        .iter()
+        .map(|(block_idx, block)| (block_idx, killed(block, &readers)))
+        .collect::<SecondaryMap<_, _>>();
+
+    // the expressions that will be computed on every path from the start of
+    // each block before an argument is assigned
+    let anticipated_in = solve_must_dataflow(
+        &cfg,
+        Direction::Backward,
+        HashSet::new(),
+        &universe,
+        |_, block_idx, out| &(&out - &kills[block_idx]) | &uses[block_idx],
+    );
+
+    // the expressions that would be available at the end of each block if
+    // every anticipated expression were computed as early as possible
+    let available_out = solve_must_dataflow(
+        &cfg,
+        Direction::Forward,
+        HashSet::new(),
+        &universe,
+        |_, block_idx, in_| {
+            &(&anticipated_in[block_idx] | &in_) - &kills[block_idx]
+        },
+    );
+    let earliest = cfg
+        .vertices
+        .keys()
+        .map(|block_idx| {
+            let available_in =
+                meet_predecessors(&cfg, &available_out, block_idx);
+            (block_idx, &anticipated_in[block_idx] - &available_in)
+        })
+        .collect::<SecondaryMap<_, _>>();
+
+    // the expressions whose placement can be delayed past the end of each
+    // block without being used first
+    let postponable_out = solve_must_dataflow(
+        &cfg,
+        Direction::Forward,
+        HashSet::new(),
+        &universe,
+        |_, block_idx, in_| &(&earliest[block_idx] | &in_) - &uses[block_idx],
+    );
+    let placeable = cfg
+        .vertices
+        .keys()
+        .map(|block_idx| {
+            let postponable_in =
+                meet_predecessors(&cfg, &postponable_out, block_idx);
+            (block_idx, &earliest[block_idx] | &postponable_in)
+        })
+        .collect::<SecondaryMap<_, _>>();
+
+    // an expression is placed at the start of a block if it cannot be delayed
+    // any further, either because the block uses it or because some successor
+    // could not place it
+    let latest = cfg
+        .vertices
+        .keys()
+        .map(|block_idx| {
+            let mut placeable_after = universe.clone();
+            for successor in cfg.successors(block_idx) {
+                placeable_after.retain(|expression| {
+                    placeable[successor].contains(expression)
+                });
+            }
+            let must_place = &uses[block_idx] | &(&universe - &placeable_after);
+            (block_idx, &placeable[block_idx] & &must_place)
+        })
+        .collect::<SecondaryMap<_, _>>();
+
+    // the expressions whose temporary is used after the end of each block
+    let used_in = solve_dataflow(
+        &cfg,
+        Direction::Backward,
+        HashSet::new(),
+        |lhs, rhs| lhs.union(rhs).cloned().collect(),
+        |_, block_idx, out| &(&uses[block_idx] | &out) - &latest[block_idx],
+    );
+    let used_out = cfg
+        .vertices
+        .keys()
+        .map(|block_idx| {
+            let used_out = cfg
+                .successors(block_idx)
+                .into_iter()
+                .flat_map(|successor| used_in[successor].iter().copied())
+                .collect::<HashSet<_>>();
+            (block_idx, used_out)
+        })
+        .collect::<SecondaryMap<_, _>>();
+
+    let mut motion = Motion::default();
+    for (block_idx, block) in &mut cfg.vertices {
+        let mut placed = (&latest[block_idx] & &used_out[block_idx])
+            .into_iter()
+            .collect::<Vec<_>>();
+        placed.sort();
+        motion.inserted += placed.len();
+
+        // computations left in place are those of expressions placed here
+        // but not used later
+        let redundant = &uses[block_idx]
+            & &(&(&universe - &latest[block_idx]) | &used_out[block_idx]);
+        let mut assigned = HashSet::new();
+        for instruction in &mut block.instructions {
+            let index = movable_expression(instruction)
+                .filter(|(_, args)| {
+                    args.iter().all(|arg| !assigned.contains(arg))
+                })
+                .map(|expression| indices[&expression]);
+            if let (Some(index), Instruction::Value { args, op, .. }) =
+                (index, &mut *instruction)
+            {
+                if redundant.contains(&index) {
+                    *op = ValueOps::Id;
+                    *args = vec![expressions[index].2.clone()];
+                    motion.replaced += 1;
+                }
+            }
+            if let Some(dest) = instruction.kill() {
+                assigned.insert(dest.clone());
+            }
+        }
+
+        block.instructions.insert_many(
+            0,
+            placed.into_iter().map(|index| {
+                let ((op, args), op_type, temporary) = &expressions[index];
+                Instruction::Value {
+                    args: args.clone(),
+                    dest: temporary.clone(),
+                    funcs: vec![],
+                    labels: vec![],
+                    op: *op,
+                    pos: None,
+                    op_type: op_type.clone(),
+                }
+            }),
+        );
+    }
+
+    if motion.inserted == 0 && motion.replaced == 0 {
+        return Ok(motion);
+    }
+
+    merge_edge_blocks(&mut cfg, edge_blocks);
+    *function = cfg.into_function();
+    Ok(motion)
+}
@@ -0,0 +1,189 @@
+//! Exports the CFG, dominator tree, and dataflow facts of a function as a
+//! single HTML page, which renders them with a small script so they can be
+//! explored in a browser without any other files.
+
+use std::str::FromStr;
+
+use build_cfg::{
+    BasicBlockIdx, Exit, FunctionCfg, block_id::BlockIdMap,
+    slotmap::SecondaryMap,
+};
+use dataflow::{
+    live_variables::compute_live_variables,
+    reaching_definitions::{DefSite, compute_reaching_definitions},
+};
+use serde_json::json;
+use snafu::{Whatever, whatever};
+
+use crate::{compute_dominator_tree, compute_dominators};
+
+const TEMPLATE: &str = include_str!("html.html");
+
+/// A dataflow analysis whose solution can be shown for each block.
+pub enum Fact {
+    LiveVariables,
+    ReachingDefinitions,
+}
+
+impl FromStr for Fact {
+    type Err = Whatever;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "live" => Self::LiveVariables,
+            "def" => Self::ReachingDefinitions,
+            _ => whatever!("Unknown dataflow fact '{}'", s),
+        })
+    }
+}
+
+impl Fact {
+    fn title(&self) -> &'static str {
+        match self {
+            Self::LiveVariables => "Live on entry",
+            Self::ReachingDefinitions => "Reaching definitions",
+        }
+    }
+
+    /// The solution of the analysis for each block, as sorted lines of text.
+    fn compute(
+        &self,
+        cfg: &FunctionCfg,
+        ids: &BlockIdMap,
+    ) -> SecondaryMap<BasicBlockIdx, Vec<String>> {
+        let mut facts = SecondaryMap::new();
+        match self {
+            Self::LiveVariables => {
+                for (block_idx, live) in compute_live_variables(cfg) {
+                    let live = live
+                        .into_iter()
+                        .map(|variable| cfg.symbols.name(variable).to_string())
+                        .collect();
+                    facts.insert(block_idx, live);
+                }
+            }
+            Self::ReachingDefinitions => {
+                for (block_idx, definitions) in
+                    compute_reaching_definitions(cfg)
+                {
+                    let definitions = definitions
+                        .into_iter()
+                        .map(|definition| {
+                            let name = cfg.symbols.name(definition.variable);
+                            match definition.site {
+                                DefSite::Argument => {
+                                    format!("{name}: argument")
+                                }
+                                DefSite::Instruction { block, index } => {
+                                    format!(
+                                        "{name}: {}[{index}]",
+                                        block_name(cfg, ids, block)
+                                    )
+                                }
+                            }
+                        })
+                        .collect();
+                    facts.insert(block_idx, definitions);
+                }
+            }
+        }
+        for (_, lines) in facts.iter_mut() {
+            lines.sort();
+        }
+        facts
+    }
+}
+
+/// The label of `block_idx`, or its number if it has none.
+fn block_name(
+    cfg: &FunctionCfg,
+    ids: &BlockIdMap,
+    block_idx: BasicBlockIdx,
+) -> String {
+    match &cfg.vertices[block_idx].label {
+        Some(label) => format!(".{}", label.name),
+        None => format!("#{}", ids.id(block_idx)),
+    }
+}
+
+/// A self-contained HTML page showing the CFG and dominator tree of `cfg`
+/// with the solution of each of `facts` at every block.
+pub fn export(cfg: &FunctionCfg, facts: &[Fact]) -> String {
+    let ids = BlockIdMap::new(cfg);
+    let dominators = compute_dominators(cfg);
+    let tree = compute_dominator_tree(&dominators);
+    let solutions = facts
+        .iter()
+        .map(|fact| (fact.title(), fact.compute(cfg, &ids)))
+        .collect::<Vec<_>>();
+
+    let blocks = ids
+        .blocks()
+        .iter()
+        .map(|&block_idx| {
+            let mut children = tree
+                .get(block_idx)
+                .into_iter()
+                .flatten()
+                .map(|child| ids.id(*child).index())
+                .collect::<Vec<_>>();
+            children.sort();
+            let mut block_dominators = dominators[block_idx]
+                .iter()
+                .map(|dominator| ids.id(*dominator).index())
+                .collect::<Vec<_>>();
+            block_dominators.sort();
+            let (successors, kinds) = match cfg.edges.get(block_idx) {
+                Some(Exit::Fallthrough(Some(next))) => {
+                    (vec![*next], vec!["fallthrough"])
+                }
+                Some(Exit::Unconditional(target)) => {
+                    (vec![*target], vec!["jump"])
+                }
+                Some(Exit::Conditional {
+                    if_true, if_false, ..
+                }) => (vec![*if_true, *if_false], vec!["true", "false"]),
+                _ => (vec![], vec![]),
+            };
+            let facts = solutions
+                .iter()
+                .map(|(title, solution)| {
+                    let lines =
+                        solution.get(block_idx).cloned().unwrap_or_default();
+                    (title.to_string(), json!(lines))
+                })
+                .collect::<serde_json::Map<_, _>>();
+            json!({
+                "id": ids.id(block_idx).index(),
+                "name": block_name(cfg, &ids, block_idx),
+                "instructions": cfg.vertices[block_idx]
+                    .instructions
+                    .iter()
+                    .map(|instruction| instruction.to_string())
+                    .collect::<Vec<_>>(),
+                "successors": successors
+                    .iter()
+                    .zip(kinds)
+                    .map(|(successor, kind)| json!({
+                        "id": ids.id(*successor).index(),
+                        "kind": kind,
+                    }))
+                    .collect::<Vec<_>>(),
+                "dominators": block_dominators,
+                "children": children,
+                "facts": facts,
+            })
+        })
+        .collect::<Vec<_>>();
+    let data = json!({
+        "function": cfg.signature.name,
+        "entry": ids.id(cfg.entry).index(),
+        "blocks": blocks,
+    });
+
+    // `</script>` in an instruction must not end the script embedding it
+    let data = data.to_string().replace("</", "<\\/");
+    TEMPLATE
+        .replace("{{title}}", &cfg.signature.name)
+        .replace("{{data}}", &data)
+}
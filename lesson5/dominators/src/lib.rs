@@ -1,28 +1,83 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 
-use build_cfg::{BasicBlockIdx, FunctionCfg, slotmap::SecondaryMap};
+use build_cfg::{
+    BasicBlockIdx, FunctionCfg,
+    pass::{Analysis, AnalysisCache},
+    slotmap::SecondaryMap,
+};
 use dataflow::construct_postorder;
 
+pub mod html;
+
 pub fn compute_dominators(
     cfg: &FunctionCfg,
-) -> SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>> {
+) -> SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>> {
     let mut reverse_postorder = construct_postorder(cfg);
     reverse_postorder.reverse();
     reverse_postorder.retain(|idx| *idx != cfg.entry);
 
-    let all_blocks = cfg.vertices.keys().collect::<HashSet<_>>();
+    let all_blocks = cfg.vertices.keys().collect::<BTreeSet<_>>();
     let mut dominators = SecondaryMap::new();
     for block_idx in cfg.vertices.keys() {
         dominators.insert(block_idx, all_blocks.clone());
     }
-    dominators[cfg.entry] = HashSet::from_iter([cfg.entry]);
+    dominators[cfg.entry] = BTreeSet::from_iter([cfg.entry]);
+
+    solve_dominators(cfg, &mut dominators, &reverse_postorder);
+    dominators
+}
+
+/// Updates `dominators` after edges of `cfg` were added, removed, or
+/// redirected and unreachable blocks were removed, where `changed` contains
+/// the old and new destinations of those edges. Only blocks reachable from
+/// `changed` can have different dominators, so only they are recomputed.
+pub fn update_dominators(
+    cfg: &FunctionCfg,
+    dominators: &mut SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    changed: impl IntoIterator<Item = BasicBlockIdx>,
+) {
+    dominators.retain(|block_idx, _| cfg.vertices.contains_key(block_idx));
+    for (_, block_dominators) in dominators.iter_mut() {
+        block_dominators
+            .retain(|block_idx| cfg.vertices.contains_key(*block_idx));
+    }
+
+    let mut affected = BTreeSet::new();
+    let mut worklist = changed
+        .into_iter()
+        .filter(|block_idx| cfg.vertices.contains_key(*block_idx))
+        .collect::<Vec<_>>();
+    while let Some(block_idx) = worklist.pop() {
+        if affected.insert(block_idx) {
+            worklist.extend(cfg.successors(block_idx));
+        }
+    }
+    affected.remove(&cfg.entry);
+
+    let all_blocks = cfg.vertices.keys().collect::<BTreeSet<_>>();
+    for block_idx in affected.iter().copied() {
+        dominators.insert(block_idx, all_blocks.clone());
+    }
+
+    let mut reverse_postorder = construct_postorder(cfg);
+    reverse_postorder.reverse();
+    reverse_postorder.retain(|idx| affected.contains(idx));
+    solve_dominators(cfg, dominators, &reverse_postorder);
+}
 
+/// Iterates the dominator equations over `order` until they stabilize,
+/// starting from `dominators`.
+fn solve_dominators(
+    cfg: &FunctionCfg,
+    dominators: &mut SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    order: &[BasicBlockIdx],
+) {
     let mut needs_update = true;
     while needs_update {
         needs_update = false;
-        for block_idx in reverse_postorder.iter().copied() {
+        for block_idx in order.iter().copied() {
             let previous = dominators[block_idx].clone();
-            let mut new = HashSet::new();
+            let mut new = BTreeSet::new();
             for (i, pred_idx) in
                 cfg.predecessors(block_idx).iter().copied().enumerate()
             {
@@ -42,14 +97,95 @@ pub fn compute_dominators(
             dominators[block_idx] = new;
         }
     }
+}
 
-    dominators
+/// The dominators of each block, as computed by [`compute_dominators`].
+pub struct Dominators;
+
+impl Analysis for Dominators {
+    type Output = SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>;
+
+    fn compute(cfg: &FunctionCfg, _cache: &mut AnalysisCache) -> Self::Output {
+        compute_dominators(cfg)
+    }
+}
+
+/// The dominator tree of a function, with the blocks each block immediately
+/// dominates as computed by [`compute_dominator_tree`], answering queries
+/// about where blocks are in the tree.
+///
+/// Only the blocks reachable from the entry are in the tree.
+pub struct DominatorTree {
+    dominators: SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    children: SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+}
+
+impl DominatorTree {
+    pub fn new(
+        dominators: SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    ) -> Self {
+        let children = compute_dominator_tree(&dominators);
+        Self {
+            dominators,
+            children,
+        }
+    }
+
+    /// The blocks `block` immediately dominates.
+    pub fn children(&self, block: BasicBlockIdx) -> &BTreeSet<BasicBlockIdx> {
+        static EMPTY: BTreeSet<BasicBlockIdx> = BTreeSet::new();
+        self.children.get(block).unwrap_or(&EMPTY)
+    }
+
+    /// The immediate dominator of `block`, unless it is the entry.
+    pub fn parent(&self, block: BasicBlockIdx) -> Option<BasicBlockIdx> {
+        let depth = self.depth(block).checked_sub(1)?;
+        self.dominators[block]
+            .iter()
+            .copied()
+            .find(|dominator| self.depth(*dominator) == depth)
+    }
+
+    /// The number of blocks strictly dominating `block`, i.e., its distance
+    /// from the entry in the tree.
+    pub fn depth(&self, block: BasicBlockIdx) -> usize {
+        self.dominators[block].len() - 1
+    }
+
+    /// Whether `block` is dominated by `other` and is not `other` itself.
+    pub fn is_strictly_dominated_by(
+        &self,
+        block: BasicBlockIdx,
+        other: BasicBlockIdx,
+    ) -> bool {
+        block != other && self.dominators[block].contains(&other)
+    }
+
+    /// The nearest common dominator of `a` and `b`: the deepest block that
+    /// dominates both, which is one of them if it dominates the other.
+    pub fn nca(&self, a: BasicBlockIdx, b: BasicBlockIdx) -> BasicBlockIdx {
+        // the common dominators form a path from the entry, so the deepest is
+        // the one with the most dominators
+        self.dominators[a]
+            .intersection(&self.dominators[b])
+            .copied()
+            .max_by_key(|common| self.depth(*common))
+            .expect("The entry dominates every block")
+    }
+}
+
+impl Analysis for DominatorTree {
+    type Output = DominatorTree;
+
+    fn compute(cfg: &FunctionCfg, cache: &mut AnalysisCache) -> Self::Output {
+        DominatorTree::new(SecondaryMap::clone(&cache.get::<Dominators>(cfg)))
+    }
 }
 
 pub fn compute_dominator_tree(
-    dominators: &SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>>,
-) -> SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>> {
-    let mut rev = SecondaryMap::<_, HashSet<_>>::new();
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>> {
+    let mut rev = SecondaryMap::<_, BTreeSet<_>>::new();
     for (idx, edge) in dominators.iter() {
         for dest_idx in edge {
             let entry = rev.entry(*dest_idx).unwrap().or_default();
@@ -59,7 +195,7 @@ pub fn compute_dominator_tree(
         }
     }
 
-    let mut tree = SecondaryMap::<_, HashSet<_>>::new();
+    let mut tree = SecondaryMap::<_, BTreeSet<_>>::new();
 
     for (idx, mut dominated) in rev.clone() {
         for (other_idx, other_dominated) in &rev {
@@ -77,9 +213,9 @@ pub fn compute_dominator_tree(
 
 pub fn compute_dominance_frontiers(
     cfg: &FunctionCfg,
-    dominators: SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>>,
-) -> SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>> {
-    let mut rev = SecondaryMap::<_, HashSet<_>>::new();
+    dominators: SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>> {
+    let mut rev = SecondaryMap::<_, BTreeSet<_>>::new();
     for (idx, edge) in dominators {
         for dest_idx in edge {
             let entry = rev.entry(dest_idx).unwrap().or_default();
@@ -89,9 +225,9 @@ pub fn compute_dominance_frontiers(
         }
     }
 
-    let mut frontiers = SecondaryMap::<_, HashSet<_>>::new();
+    let mut frontiers = SecondaryMap::<_, BTreeSet<_>>::new();
     for (idx, dominated) in rev {
-        let mut successors = HashSet::new();
+        let mut successors = BTreeSet::new();
 
         for dominated_idx in &dominated {
             successors.extend(cfg.successors(*dominated_idx));
@@ -108,3 +244,74 @@ pub fn compute_dominance_frontiers(
 
     frontiers
 }
+
+/// The merge set of each block in `tree`: the blocks where paths from it meet
+/// paths from the entry that avoid it, which is the iterated dominance
+/// frontier of the block alone. Unlike [`iterated_dominance_frontier`], this
+/// does not start from the dominance frontiers but from the join edges of
+/// `cfg`, those not from the immediate dominator of their destination, as in
+/// the formulation of SSA construction by merge sets.
+pub fn compute_merge_sets(
+    cfg: &FunctionCfg,
+    tree: &DominatorTree,
+) -> SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>> {
+    let mut merge_sets = SecondaryMap::<_, BTreeSet<_>>::new();
+    for block_idx in tree.dominators.keys() {
+        merge_sets.insert(block_idx, BTreeSet::new());
+    }
+
+    // a join edge into `target` makes `target` a merge point of every block
+    // from its source up to, but not including, the nearest block strictly
+    // dominating `target`
+    for source in tree.dominators.keys() {
+        for target in cfg.successors(source) {
+            let mut current = Some(source);
+            while let Some(block_idx) = current.filter(|block_idx| {
+                !tree.is_strictly_dominated_by(target, *block_idx)
+            }) {
+                merge_sets[block_idx].insert(target);
+                current = tree.parent(block_idx);
+            }
+        }
+    }
+
+    // control merging at `target` from `block_idx` continues on to where it
+    // merges from `target`
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block_idx in tree.dominators.keys() {
+            let reached = merge_sets[block_idx]
+                .iter()
+                .flat_map(|target| merge_sets[*target].iter().copied())
+                .collect::<Vec<_>>();
+            for target in reached {
+                changed |= merge_sets[block_idx].insert(target);
+            }
+        }
+    }
+    merge_sets
+}
+
+/// The iterated dominance frontier of `blocks`: the least set containing the
+/// dominance frontier of each of `blocks` and of each block in the set itself,
+/// which is where a variable defined in `blocks` needs a Phi node.
+pub fn iterated_dominance_frontier(
+    frontiers: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    blocks: impl IntoIterator<Item = BasicBlockIdx>,
+) -> HashSet<BasicBlockIdx> {
+    let mut worklist = blocks.into_iter().collect::<Vec<_>>();
+    let mut visited = worklist.iter().copied().collect::<HashSet<_>>();
+    let mut result = HashSet::new();
+    while let Some(block_idx) = worklist.pop() {
+        for frontier_idx in frontiers.get(block_idx).into_iter().flatten() {
+            result.insert(*frontier_idx);
+            // a frontier block acts as a new definition, whose own frontier
+            // also needs Phi nodes
+            if visited.insert(*frontier_idx) {
+                worklist.push(*frontier_idx);
+            }
+        }
+    }
+    result
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet},
     fs, io,
     path::PathBuf,
     str::FromStr,
@@ -7,9 +7,15 @@ use std::{
 
 use argh::FromArgs;
 use bril_rs::Program;
+use bril_util::{
+    filter::{FunctionFilter, FunctionPattern},
+    output::{DotGraph, OutputFormat, print_envelope},
+};
 use build_cfg::{BasicBlockIdx, FunctionCfg, slotmap::SecondaryMap};
 use dominators::{
-    compute_dominance_frontiers, compute_dominator_tree, compute_dominators,
+    DominatorTree, compute_dominance_frontiers, compute_dominator_tree,
+    compute_dominators, compute_merge_sets,
+    html::{Fact, export},
 };
 use serde_json::json;
 use snafu::{ResultExt, Whatever, whatever};
@@ -18,6 +24,7 @@ enum Algorithm {
     Dominators,
     DominatorTree,
     DominationFrontier,
+    MergeSets,
 }
 
 impl FromStr for Algorithm {
@@ -28,6 +35,7 @@ impl FromStr for Algorithm {
             "dom" => Self::Dominators,
             "tree" => Self::DominatorTree,
             "front" => Self::DominationFrontier,
+            "merge-sets" => Self::MergeSets,
             _ => whatever!("Unknown algorithm '{}'", s),
         })
     }
@@ -35,9 +43,29 @@ impl FromStr for Algorithm {
 /// computes dominators and related stuff
 #[derive(FromArgs)]
 struct Opts {
-    /// algorithm
+    /// algorithm: `dom`, `tree`, `front`, or `merge-sets`
+    #[argh(option)]
+    algo: Option<Algorithm>,
+
+    /// write a page exploring the CFG and dominator tree of each function to
+    /// `<function>.html` in the given directory instead
+    #[argh(option)]
+    html: Option<PathBuf>,
+
+    /// dataflow facts to show on the pages: `live` or `def`, repeatable
     #[argh(option)]
-    algo: Algorithm,
+    fact: Vec<Fact>,
+
+    /// print each function as `json`, `text` with a line per block, or a `dot`
+    /// graph with an edge from each block to each block in its set: omit for
+    /// a JSON object per function
+    #[argh(option)]
+    output: Option<OutputFormat>,
+
+    /// only analyze functions matching this name, like `@main` or `@test_*`:
+    /// may be repeated, and omit for every function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
 
     /// input Bril file: omit for stdin
     #[argh(positional)]
@@ -62,22 +90,75 @@ fn main() -> Result<(), Whatever> {
         )?
     };
 
+    let filter = FunctionFilter::new(opts.function);
+    if let Some(directory) = &opts.html {
+        fs::create_dir_all(directory).whatever_context(format!(
+            "Failed to create {}",
+            directory.to_string_lossy()
+        ))?;
+        for function in program.functions {
+            if !filter.selects(&function.name) {
+                continue;
+            }
+            let cfg = build_cfg::build_cfg(&function, true)
+                .whatever_context("Failed to build cfg")?;
+            let path = directory.join(format!("{}.html", function.name));
+            fs::write(&path, export(&cfg, &opts.fact)).whatever_context(
+                format!("Failed to write {}", path.to_string_lossy()),
+            )?;
+        }
+        return Ok(());
+    }
+    let Some(algo) = opts.algo else {
+        whatever!("Expected `--algo` or `--html`");
+    };
+
     for function in program.functions {
+        if !filter.selects(&function.name) {
+            continue;
+        }
         let cfg = build_cfg::build_cfg(&function, true)
             .whatever_context("Failed to build cfg")?;
         let dominators = compute_dominators(&cfg);
 
-        match &opts.algo {
-            Algorithm::Dominators => {
-                print_block_info_sorted(&cfg, dominators);
+        let blocks = match algo {
+            Algorithm::Dominators => dominators,
+            Algorithm::DominatorTree => compute_dominator_tree(&dominators),
+            Algorithm::DominationFrontier => {
+                compute_dominance_frontiers(&cfg, dominators)
             }
-            Algorithm::DominatorTree => {
-                let tree = compute_dominator_tree(&dominators);
-                print_block_info_sorted(&cfg, tree);
+            Algorithm::MergeSets => {
+                compute_merge_sets(&cfg, &DominatorTree::new(dominators))
             }
-            Algorithm::DominationFrontier => {
-                let frontiers = compute_dominance_frontiers(&cfg, dominators);
-                print_block_info_sorted(&cfg, frontiers);
+        };
+        let printout = block_info_sorted(&cfg, blocks);
+        match opts.output {
+            None => println!("{}", json!(printout)),
+            Some(OutputFormat::Json) => print_envelope(
+                env!("CARGO_PKG_NAME"),
+                Some(cfg.signature.name.as_str()),
+                json!(printout),
+            ),
+            Some(OutputFormat::Text) => {
+                println!("@{} {{", cfg.signature.name);
+                for (label, blocks) in &printout {
+                    let blocks = blocks
+                        .iter()
+                        .map(|block| format!(".{block}"))
+                        .collect::<Vec<_>>();
+                    println!("  .{}: {}", label, blocks.join(" "));
+                }
+                println!("}}");
+            }
+            Some(OutputFormat::Dot) => {
+                let mut graph = DotGraph::new(&cfg.signature.name);
+                for (label, blocks) in &printout {
+                    graph.node(*label, format!(".{label}"));
+                    for block in blocks {
+                        graph.edge(*label, *block);
+                    }
+                }
+                println!("{graph}");
             }
         }
     }
@@ -85,10 +166,11 @@ fn main() -> Result<(), Whatever> {
     Ok(())
 }
 
-fn print_block_info_sorted(
+/// The labels of the blocks in `blocks` for each labeled block, sorted.
+fn block_info_sorted(
     cfg: &FunctionCfg,
-    blocks: SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>>,
-) {
+    blocks: SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> BTreeMap<&str, Vec<&str>> {
     let mut printout = BTreeMap::new();
     for (block_idx, block_info) in blocks {
         if let Some(label) = cfg.vertices[block_idx]
@@ -109,5 +191,5 @@ fn print_block_info_sorted(
             printout.insert(label, dominators);
         }
     }
-    println!("{}", json!(printout));
+    printout
 }
@@ -0,0 +1,311 @@
+//! Folds branches whose condition is known, either because it is a constant
+//! or because an earlier branch on the same variable decided it, and threads
+//! edges through blocks that only jump or branch.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bril_rs::{Instruction, Literal};
+use bril_util::{InstructionExt, capabilities::Capabilities, error::ToolError};
+use build_cfg::{
+    BasicBlockIdx, Exit, FunctionCfg, LabeledExit,
+    pass::{AnalysisCache, Changed, FunctionPass},
+    slotmap::SecondaryMap,
+};
+use dominators::{Dominators, update_dominators};
+
+type DominatorSets = SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>;
+
+/// The variables assigned exactly once in `cfg`, by a boolean `const`, with
+/// their values. Parameters count as assignments.
+fn constant_conditions(cfg: &FunctionCfg) -> HashMap<String, bool> {
+    let mut assignments = HashMap::<_, Option<bool>>::new();
+    for argument in &cfg.signature.arguments {
+        assignments.insert(argument.name.clone(), None);
+    }
+    for instruction in
+        cfg.vertices.values().flat_map(|block| &block.instructions)
+    {
+        let Some(dest) = instruction.kill() else {
+            continue;
+        };
+        let value = match instruction {
+            Instruction::Constant {
+                value: Literal::Bool(value),
+                ..
+            } => Some(*value),
+            _ => None,
+        };
+        assignments
+            .entry(dest.clone())
+            .and_modify(|existing| *existing = None)
+            .or_insert(value);
+    }
+    assignments
+        .into_iter()
+        .filter_map(|(variable, value)| Some((variable, value?)))
+        .collect()
+}
+
+/// The value of `condition` when `block` exits, if it is known: from its last
+/// assignment in `block`, from `constants`, or from a dominating branch on
+/// `condition` whose outcome must have been taken to reach `block`.
+fn known_condition(
+    cfg: &FunctionCfg,
+    dominators: &DominatorSets,
+    constants: &HashMap<String, bool>,
+    block: BasicBlockIdx,
+    condition: &str,
+) -> Option<bool> {
+    let last_assignment =
+        cfg.vertices[block]
+            .instructions
+            .iter()
+            .rev()
+            .find(|instruction| {
+                instruction.kill().is_some_and(|dest| dest == condition)
+            });
+    match last_assignment {
+        Some(Instruction::Constant {
+            value: Literal::Bool(value),
+            ..
+        }) => return Some(*value),
+        Some(_) => return None,
+        None => {}
+    }
+    if let Some(value) = constants.get(condition) {
+        return Some(*value);
+    }
+
+    // if the only way into `dominator` is one edge of a branch on
+    // `condition`, every path to `block` takes that edge last among the
+    // branch's edges, then stays among the blocks `dominator` dominates
+    dominators[block].iter().copied().find_map(|dominator| {
+        let [branch] = cfg.predecessors(dominator) else {
+            return None;
+        };
+        let Exit::Conditional {
+            condition: branch_condition,
+            if_true,
+            if_false,
+        } = &cfg.edges[*branch]
+        else {
+            return None;
+        };
+        if branch_condition != condition || if_true == if_false {
+            return None;
+        }
+        let reassigned = cfg
+            .vertices
+            .iter()
+            .filter(|(block_idx, _)| {
+                dominators[*block_idx].contains(&dominator)
+            })
+            .flat_map(|(_, block)| &block.instructions)
+            .any(|instruction| {
+                instruction.kill().is_some_and(|dest| dest == condition)
+            });
+        (!reassigned).then_some(dominator == *if_true)
+    })
+}
+
+/// The value `condition` must have when control flows from `block` to
+/// `successor`, if it is known.
+fn condition_on_edge(
+    cfg: &FunctionCfg,
+    dominators: &DominatorSets,
+    constants: &HashMap<String, bool>,
+    block: BasicBlockIdx,
+    successor: BasicBlockIdx,
+    condition: &str,
+) -> Option<bool> {
+    match &cfg.edges[block] {
+        Exit::Conditional {
+            condition: branch_condition,
+            if_true,
+            if_false,
+        } if branch_condition == condition && if_true != if_false => {
+            Some(successor == *if_true)
+        }
+        _ => known_condition(cfg, dominators, constants, block, condition),
+    }
+}
+
+/// Replaces the branch ending `block` with a jump to `target`, returning the
+/// other destination, whose edge from `block` is removed.
+fn fold_branch(
+    cfg: &mut FunctionCfg,
+    block: BasicBlockIdx,
+    target: BasicBlockIdx,
+) -> Option<BasicBlockIdx> {
+    let removed = cfg
+        .successors(block)
+        .into_iter()
+        .find(|successor| *successor != target);
+    if let Some(removed) = removed {
+        cfg.rev_edges[removed].retain(|predecessor| *predecessor != block);
+    }
+    cfg.vertices[block].instructions.pop();
+    cfg.vertices[block].exit = LabeledExit::Fallthrough;
+    cfg.edges.remove(block);
+    cfg.set_unconditional_edge(block, target);
+    removed
+}
+
+/// Whether `block` does nothing but end in a jump or branch.
+fn is_trampoline(cfg: &FunctionCfg, block: BasicBlockIdx) -> bool {
+    matches!(
+        cfg.vertices[block].instructions.as_slice(),
+        [instruction] if instruction.is_jump() || instruction.is_branch()
+    )
+}
+
+/// One change made by [`thread_jumps`] along with the destinations of the
+/// edges it changed.
+enum Step {
+    Folded(Vec<BasicBlockIdx>),
+    Threaded(Vec<BasicBlockIdx>),
+}
+
+/// Finds and makes one change to `cfg`, if there is any left to make. Each
+/// edge in `threaded` has already been threaded once, so it is not threaded
+/// again, which would never stop if the edge leads into an empty loop.
+fn step(
+    cfg: &mut FunctionCfg,
+    dominators: &DominatorSets,
+    constants: &HashMap<String, bool>,
+    threaded: &mut HashSet<(BasicBlockIdx, BasicBlockIdx)>,
+) -> Option<Step> {
+    let blocks = cfg.vertices.keys().collect::<Vec<_>>();
+
+    // branches whose condition is known or whose destinations are the same
+    for block in blocks.iter().copied() {
+        let Exit::Conditional {
+            condition,
+            if_true,
+            if_false,
+        } = &cfg.edges[block]
+        else {
+            continue;
+        };
+        let (if_true, if_false) = (*if_true, *if_false);
+        let outcome = if if_true == if_false {
+            Some(true)
+        } else {
+            known_condition(cfg, dominators, constants, block, condition)
+        };
+        if let Some(outcome) = outcome {
+            let target = if outcome { if_true } else { if_false };
+            let removed = fold_branch(cfg, block, target);
+            return Some(Step::Folded(removed.into_iter().collect()));
+        }
+    }
+
+    // edges into blocks that only jump elsewhere or branch on a condition
+    // known along the edge
+    for block in blocks.iter().copied() {
+        if block == cfg.entry || !is_trampoline(cfg, block) {
+            continue;
+        }
+        for predecessor in cfg.predecessors(block).to_vec() {
+            if threaded.contains(&(predecessor, block)) {
+                continue;
+            }
+            let target = match &cfg.edges[block] {
+                Exit::Unconditional(target) => Some(*target),
+                Exit::Conditional {
+                    condition,
+                    if_true,
+                    if_false,
+                } => condition_on_edge(
+                    cfg,
+                    dominators,
+                    constants,
+                    predecessor,
+                    block,
+                    condition,
+                )
+                .map(|outcome| if outcome { *if_true } else { *if_false }),
+                _ => None,
+            };
+            match target {
+                Some(target) if target != block => {
+                    threaded.insert((predecessor, block));
+                    cfg.reorient_edge(predecessor, block, target);
+                    return Some(Step::Threaded(vec![block, target]));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    None
+}
+
+/// The changes made by [`thread_jumps`].
+#[derive(Default)]
+pub struct Threading {
+    pub folded: usize,
+    pub threaded: usize,
+    pub removed: usize,
+}
+
+/// Folds every branch in `cfg` whose condition is known and threads every
+/// edge through a block that only jumps, or only branches on a condition
+/// known along the edge, removing the blocks left unreachable. `dominators`
+/// must be the dominators of `cfg`; they are kept up to date incrementally as
+/// edges change.
+pub fn thread_jumps(
+    cfg: &mut FunctionCfg,
+    dominators: &mut DominatorSets,
+) -> Threading {
+    cfg.make_fallthroughs_explicit();
+    let constants = constant_conditions(cfg);
+
+    let mut threading = Threading::default();
+    let mut threaded = HashSet::new();
+    while let Some(step) = step(cfg, dominators, &constants, &mut threaded) {
+        let mut changed = match step {
+            Step::Folded(changed) => {
+                threading.folded += 1;
+                changed
+            }
+            Step::Threaded(changed) => {
+                threading.threaded += 1;
+                changed
+            }
+        };
+        let (removed, orphaned) = cfg.remove_unreachable_blocks();
+        threading.removed += removed;
+        changed.extend(orphaned);
+        update_dominators(cfg, dominators, changed);
+    }
+    threading
+}
+
+/// Runs [`thread_jumps`] as a pass.
+pub struct JumpThreadingPass;
+
+impl FunctionPass for JumpThreadingPass {
+    fn name(&self) -> &'static str {
+        "thread"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // removing unreachable blocks may remove the target of a `guard`
+        Capabilities::all() - Capabilities::SPECULATION
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        let mut dominators =
+            DominatorSets::clone(&cache.get::<Dominators>(cfg));
+        let threading = thread_jumps(cfg, &mut dominators);
+        cfg.simplify_unconditionals_to_fallthroughs();
+        Ok(Changed::from(
+            threading.folded > 0 || threading.threaded > 0,
+        ))
+    }
+}
@@ -0,0 +1,285 @@
+//! SSA form with block parameters instead of `get` and `set`, as in Cranelift
+//! or MLIR: each block takes the values its `get`s would read as parameters,
+//! and each edge into it passes the values the `set`s before the jump would
+//! write as arguments. Algorithms that work on the values flowing along each
+//! edge, like translating out of SSA or coalescing registers, can then look at
+//! one edge at a time instead of searching for matching `set`s.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter,
+};
+
+use bril_rs::{EffectOps, Instruction, Type, ValueOps};
+use bril_util::builder::{make_effect, make_value};
+use build_cfg::{BasicBlockIdx, Exit, FunctionCfg, slotmap::SecondaryMap};
+use snafu::{OptionExt, ensure};
+
+use crate::{
+    MisplacedGetSnafu, MissingGetSnafu, MissingSetSnafu, SetNotOnEdgeSnafu,
+    SsaError,
+};
+
+/// A value a block takes, defined where its `get` was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockParam {
+    pub name: String,
+    pub ty: Type,
+}
+
+/// The parameters of the blocks of a function and the arguments passed along
+/// each edge, which together replace its `get`s and `set`s.
+#[derive(Default)]
+pub struct BlockParams {
+    /// The parameters of each block that has any, in order.
+    pub params: SecondaryMap<BasicBlockIdx, Vec<BlockParam>>,
+    /// For each block, the arguments it passes to each of its successors that
+    /// take parameters, in the order of their parameters.
+    pub arguments:
+        SecondaryMap<BasicBlockIdx, BTreeMap<BasicBlockIdx, Vec<String>>>,
+}
+
+impl BlockParams {
+    pub fn params(&self, block: BasicBlockIdx) -> &[BlockParam] {
+        self.params.get(block).map_or(&[], Vec::as_slice)
+    }
+
+    /// The arguments the edge from `from` to `to` passes to `to`.
+    pub fn arguments(
+        &self,
+        from: BasicBlockIdx,
+        to: BasicBlockIdx,
+    ) -> &[String] {
+        self.arguments
+            .get(from)
+            .and_then(|successors| successors.get(&to))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Removes the `get`s and `set`s of `cfg`, which must be in the form
+/// [`into_ssa`](crate::into_ssa) produces, returning the block parameters and
+/// arguments they stood for: every `get` is at the start of its block, and
+/// every predecessor of a block with a `get` sets it exactly once.
+pub fn into_block_params(
+    cfg: &mut FunctionCfg,
+) -> Result<BlockParams, SsaError> {
+    let mut block_params = BlockParams::default();
+    // the block of each `get` and its position among the parameters
+    let mut gets = HashMap::new();
+    for (block_idx, block) in cfg.vertices.iter() {
+        let mut params = vec![];
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            if let Instruction::Value {
+                dest,
+                op: ValueOps::Get,
+                op_type,
+                ..
+            } = instruction
+            {
+                ensure!(
+                    index == params.len(),
+                    MisplacedGetSnafu {
+                        variable: dest.clone()
+                    }
+                );
+                gets.insert(dest.clone(), (block_idx, params.len()));
+                params.push(BlockParam {
+                    name: dest.clone(),
+                    ty: op_type.clone(),
+                });
+            }
+        }
+        if !params.is_empty() {
+            block_params.params.insert(block_idx, params);
+        }
+    }
+
+    for block_idx in cfg.vertices.keys().collect::<Vec<_>>() {
+        let successors = cfg.successors(block_idx);
+        let mut arguments = BTreeMap::<_, Vec<Option<String>>>::new();
+        for instruction in &cfg.vertices[block_idx].instructions {
+            let Instruction::Effect {
+                args,
+                op: EffectOps::Set,
+                ..
+            } = instruction
+            else {
+                continue;
+            };
+            let (target, position) =
+                gets.get(&args[0]).copied().context(MissingGetSnafu {
+                    variable: args[0].clone(),
+                })?;
+            ensure!(
+                successors.contains(&target),
+                SetNotOnEdgeSnafu {
+                    variable: args[0].clone()
+                }
+            );
+            arguments.entry(target).or_insert_with(|| {
+                vec![None; block_params.params(target).len()]
+            })[position] = Some(args[1].clone());
+        }
+
+        let mut complete = BTreeMap::new();
+        for successor in successors {
+            let params = block_params.params(successor);
+            if params.is_empty() {
+                continue;
+            }
+            let passed = arguments
+                .remove(&successor)
+                .unwrap_or_else(|| vec![None; params.len()]);
+            let passed = passed
+                .into_iter()
+                .zip(params)
+                .map(|(argument, param)| {
+                    argument.context(MissingSetSnafu {
+                        variable: param.name.clone(),
+                        block: block_name(cfg, block_idx),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            complete.insert(successor, passed);
+        }
+        if !complete.is_empty() {
+            block_params.arguments.insert(block_idx, complete);
+        }
+    }
+
+    for block in cfg.vertices.values_mut() {
+        block.instructions.retain(|instruction| {
+            !matches!(
+                instruction,
+                Instruction::Value {
+                    op: ValueOps::Get,
+                    ..
+                } | Instruction::Effect {
+                    op: EffectOps::Set,
+                    ..
+                }
+            )
+        });
+    }
+
+    Ok(block_params)
+}
+
+/// Turns `block_params` back into `get`s at the start of each block with
+/// parameters and `set`s before the exit of each of its predecessors.
+pub fn from_block_params(cfg: &mut FunctionCfg, block_params: &BlockParams) {
+    for (block_idx, params) in block_params.params.iter() {
+        let gets = params.iter().map(|param| {
+            make_value(
+                param.name.clone(),
+                param.ty.clone(),
+                ValueOps::Get,
+                vec![],
+            )
+        });
+        cfg.vertices[block_idx].instructions.insert_many(0, gets);
+    }
+
+    for (block_idx, successors) in block_params.arguments.iter() {
+        let sets = successors
+            .iter()
+            .flat_map(|(successor, arguments)| {
+                block_params.params(*successor).iter().zip(arguments)
+            })
+            .map(|(param, argument)| {
+                make_effect(
+                    EffectOps::Set,
+                    vec![param.name.clone(), argument.clone()],
+                )
+            })
+            .collect::<Vec<_>>();
+        let insertion_point = cfg.vertices[block_idx].index_before_exit();
+        cfg.vertices[block_idx]
+            .instructions
+            .insert_many(insertion_point, sets);
+    }
+}
+
+fn block_name(cfg: &FunctionCfg, block: BasicBlockIdx) -> String {
+    cfg.vertices[block]
+        .label
+        .as_ref()
+        .map(|label| format!(".{}", label.name))
+        .unwrap_or_else(|| "the entry block".into())
+}
+
+/// The lines of `cfg` with its blocks taking `block_params`, which is not Bril
+/// but reads like it: labels list the parameters of their blocks, like
+/// `.loop(i.2.1: int):`, and jumps and branches pass arguments, like
+/// `jmp .loop(i.3.1)`.
+pub fn block_params_to_lines(
+    cfg: &FunctionCfg,
+    block_params: &BlockParams,
+) -> Vec<String> {
+    let target = |from: BasicBlockIdx, to: BasicBlockIdx| {
+        let name = cfg.vertices[to]
+            .label
+            .as_ref()
+            .map(|label| label.name.clone())
+            .unwrap_or_default();
+        let arguments = block_params.arguments(from, to);
+        if arguments.is_empty() {
+            format!(".{name}")
+        } else {
+            format!(".{name}({})", arguments.join(", "))
+        }
+    };
+
+    let mut lines = vec![format!("@{} {{", cfg.signature.name)];
+    let blocks = iter::once(cfg.entry).chain(
+        cfg.vertices
+            .keys()
+            .filter(|block_idx| *block_idx != cfg.entry),
+    );
+    for block_idx in blocks {
+        let block = &cfg.vertices[block_idx];
+        if let Some(label) = &block.label {
+            let params = block_params.params(block_idx);
+            if params.is_empty() {
+                lines.push(format!(".{}:", label.name));
+            } else {
+                let params = params
+                    .iter()
+                    .map(|param| format!("{}: {}", param.name, param.ty))
+                    .collect::<Vec<_>>();
+                lines.push(format!(".{}({}):", label.name, params.join(", ")));
+            }
+        }
+        for instruction in &block.instructions[..block.index_before_exit()] {
+            lines.push(format!("  {}", instruction));
+        }
+        match &cfg.edges[block_idx] {
+            Exit::Fallthrough(Some(to))
+                if !block_params.arguments(block_idx, *to).is_empty() =>
+            {
+                lines.push(format!("  jmp {};", target(block_idx, *to)));
+            }
+            Exit::Fallthrough(_) => {}
+            Exit::Unconditional(to) => {
+                lines.push(format!("  jmp {};", target(block_idx, *to)));
+            }
+            Exit::Conditional {
+                condition,
+                if_true,
+                if_false,
+            } => lines.push(format!(
+                "  br {condition} {} {};",
+                target(block_idx, *if_true),
+                target(block_idx, *if_false)
+            )),
+            Exit::Return(_) => {
+                for exit in &block.instructions[block.index_before_exit()..] {
+                    lines.push(format!("  {}", exit));
+                }
+            }
+        }
+    }
+    lines.push("}".into());
+    lines
+}
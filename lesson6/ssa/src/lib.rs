@@ -1,12 +1,23 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use bril_rs::{EffectOps, Instruction, Type, ValueOps};
-use bril_util::InstructionExt;
+use bril_util::{
+    InstructionExt,
+    builder::{make_effect, make_id, make_value},
+    capabilities::Capabilities,
+    error::ToolError,
+};
 use build_cfg::{
-    BasicBlock, BasicBlockIdx, Exit, FunctionCfg, Label, LabeledExit,
+    BasicBlock, BasicBlockIdx, Exit, FunctionCfg, Instructions, Label,
+    LabeledExit,
+    block_id::{BlockId, BlockIdMap},
+    pass::{AnalysisCache, Changed, FunctionPass},
     slotmap::SecondaryMap,
 };
-use snafu::{OptionExt, Whatever, whatever};
+use dominators::iterated_dominance_frontier;
+use snafu::{OptionExt, Snafu, ensure};
+
+pub mod block_params;
 
 pub fn insert_new_empty_entry_block(cfg: &mut FunctionCfg) {
     cfg.vertices[cfg.entry].is_entry = false;
@@ -16,8 +27,9 @@ pub fn insert_new_empty_entry_block(cfg: &mut FunctionCfg) {
         label: Some(Label {
             name: "__SSA_ENTRY".into(),
         }),
-        instructions: vec![],
+        instructions: Instructions::new(),
         exit: LabeledExit::Fallthrough,
+        span: None,
     });
 
     cfg.edges
@@ -78,33 +90,18 @@ pub struct PhiInsertionPoints(
 /// for it.
 pub fn determine_phi_insertion_points(
     definition_sites: DefinitionSites,
-    dominance_frontiers: SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>>,
+    dominance_frontiers: SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
 ) -> PhiInsertionPoints {
-    let dominance_frontiers: SecondaryMap<_, BTreeSet<_>> = dominance_frontiers
-        .into_iter()
-        .map(|(idx, set)| (idx, set.into_iter().collect()))
-        .collect();
-
     let mut insertion_points = BTreeMap::new();
 
-    for (variable, (ty, mut definition_blocks)) in definition_sites.0 {
-        let mut current_definition_blocks = definition_blocks.clone();
-        while let Some(block_idx) = current_definition_blocks.pop_first() {
-            for frontier_idx in &dominance_frontiers[block_idx] {
-                let ty = ty.clone();
-                insertion_points
-                    .entry(variable.clone())
-                    .or_insert_with(move || {
-                        (ty, BTreeSet::<BasicBlockIdx>::default())
-                    })
-                    .1
-                    .insert(*frontier_idx);
-
-                if !definition_blocks.contains(frontier_idx) {
-                    current_definition_blocks.insert(*frontier_idx);
-                    definition_blocks.insert(*frontier_idx);
-                }
-            }
+    for (variable, (ty, definition_blocks)) in definition_sites.0 {
+        let frontier = iterated_dominance_frontier(
+            &dominance_frontiers,
+            definition_blocks,
+        );
+        if !frontier.is_empty() {
+            insertion_points
+                .insert(variable, (ty, frontier.into_iter().collect()));
         }
     }
 
@@ -122,38 +119,29 @@ pub fn insert_phis(
                 .entry(place_to_insert)
                 .unwrap()
                 .or_insert_with(Vec::default)
-                .push(Instruction::Value {
-                    args: vec![],
-                    dest: variable.clone(),
-                    funcs: vec![],
-                    labels: vec![],
-                    op: ValueOps::Get,
-                    pos: None,
-                    op_type: ty.clone(),
-                });
+                .push(make_value(
+                    variable.clone(),
+                    ty.clone(),
+                    ValueOps::Get,
+                    vec![],
+                ));
         }
     }
     for (block_idx, phis) in phis_to_insert {
-        cfg.vertices[block_idx].instructions.splice(0..0, phis);
+        cfg.vertices[block_idx].instructions.insert_many(0, phis);
     }
 }
 
 pub fn simulate_parameters_as_locals(cfg: &mut FunctionCfg) {
-    cfg.vertices[cfg.entry].instructions.splice(
-        0..0,
-        cfg.signature
-            .arguments
-            .iter()
-            .map(|argument| Instruction::Value {
-                args: vec![argument.name.clone()],
-                dest: argument.name.clone(),
-                funcs: vec![],
-                labels: vec![],
-                op: ValueOps::Id,
-                pos: None,
-                op_type: argument.arg_type.clone(),
-            }),
-    );
+    let copies = cfg
+        .argument_definitions()
+        .into_iter()
+        .map(|argument| {
+            let name = cfg.symbols.name(argument.variable).to_string();
+            make_id(name.clone(), argument.ty, name)
+        })
+        .collect::<Vec<_>>();
+    cfg.vertices[cfg.entry].instructions.insert_many(0, copies);
 }
 
 #[derive(Default)]
@@ -205,22 +193,25 @@ pub struct LocalRenamer {
     current_idx: BasicBlockIdx,
     is_entry: bool,
     parameters: HashSet<String>,
-    current_id: u64,
+    current_id: BlockId,
     numbering: HashMap<String, usize>,
 }
 
 impl LocalRenamer {
-    pub fn new(cfg: &FunctionCfg, current_idx: BasicBlockIdx) -> Self {
+    pub fn new(
+        cfg: &FunctionCfg,
+        ids: &BlockIdMap,
+        current_idx: BasicBlockIdx,
+    ) -> Self {
         Self {
             current_idx,
             is_entry: cfg.vertices[current_idx].is_entry,
             parameters: cfg
-                .signature
-                .arguments
-                .iter()
-                .map(|argument| argument.name.clone())
+                .argument_definitions()
+                .into_iter()
+                .map(|argument| cfg.symbols.name(argument.variable).to_string())
                 .collect(),
-            current_id: current_idx.as_index_for_slotmap_version_1_0_7_only(),
+            current_id: ids.id(current_idx),
             numbering: HashMap::default(),
         }
     }
@@ -233,6 +224,7 @@ impl LocalRenamer {
 
     pub fn rewrite_argument(
         &self,
+        ids: &BlockIdMap,
         dominating_definitions_stacks: &DominatingDefinitionsStacks,
         name: &str,
     ) -> Option<String> {
@@ -243,7 +235,7 @@ impl LocalRenamer {
         {
             Some(format!(
                 "{name}.{}.{previous_number}",
-                defining_dominator.as_index_for_slotmap_version_1_0_7_only()
+                ids.id(defining_dominator)
             ))
         } else if self.is_entry && self.parameters.contains(name) {
             Some(name.to_owned())
@@ -257,22 +249,6 @@ impl LocalRenamer {
         }
     }
 
-    pub fn rewrite_arguments(
-        &mut self,
-        dominating_definitions_stacks: &DominatingDefinitionsStacks,
-        names: impl IntoIterator<Item = String>,
-    ) -> Vec<String> {
-        names
-            .into_iter()
-            .map(|name| {
-                self.rewrite_argument(dominating_definitions_stacks, &name)
-                    .expect(
-                        "Definitions of arguments did not dominate their uses",
-                    )
-            })
-            .collect()
-    }
-
     /// This function is very cheap.
     pub fn latest_definitions(
         &self,
@@ -295,62 +271,33 @@ impl LocalRenamer {
 
 pub fn rename_and_insert_upsilons(
     cfg: &mut FunctionCfg,
+    ids: &BlockIdMap,
     block_idx: BasicBlockIdx,
-    dominance_tree: &SecondaryMap<BasicBlockIdx, HashSet<BasicBlockIdx>>,
+    dominance_tree: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
     dominating_definitions_stacks: &mut DominatingDefinitionsStacks,
     undefined_names: &mut BTreeMap<String, Type>,
 ) {
-    let mut local_renamer = LocalRenamer::new(cfg, block_idx);
+    let mut local_renamer = LocalRenamer::new(cfg, ids, block_idx);
 
     for instruction in &mut cfg.vertices[block_idx].instructions {
-        *instruction = match instruction.clone() {
-            Instruction::Constant {
-                dest,
-                op,
-                pos,
-                const_type,
-                value,
-            } => Instruction::Constant {
-                dest: local_renamer.rewrite_destination(dest),
-                op,
-                pos,
-                const_type,
-                value,
-            },
-            Instruction::Value {
-                args,
-                dest,
-                funcs,
-                labels,
-                op,
-                pos,
-                op_type,
-            } => Instruction::Value {
-                args: local_renamer
-                    .rewrite_arguments(dominating_definitions_stacks, args),
-                dest: local_renamer.rewrite_destination(dest),
-                funcs,
-                labels,
-                op,
-                pos,
-                op_type,
-            },
+        // `set`s are inserted with their arguments already renamed
+        if matches!(
+            instruction,
             Instruction::Effect {
-                args,
-                funcs,
-                labels,
-                op,
-                pos,
-            } if !matches!(op, EffectOps::Set) => Instruction::Effect {
-                args: local_renamer
-                    .rewrite_arguments(dominating_definitions_stacks, args),
-                funcs,
-                labels,
-                op,
-                pos,
-            },
-            other => other,
-        };
+                op: EffectOps::Set,
+                ..
+            }
+        ) {
+            continue;
+        }
+        instruction.map_args(&mut |arg| {
+            *arg = local_renamer
+                .rewrite_argument(ids, dominating_definitions_stacks, arg)
+                .expect("Definitions of arguments did not dominate their uses");
+        });
+        instruction.map_dest(|dest| {
+            *dest = local_renamer.rewrite_destination(dest.clone());
+        });
     }
 
     let mut locally_required_sets = BTreeMap::new();
@@ -382,10 +329,8 @@ pub fn rename_and_insert_upsilons(
                 .split_once(".")
                 .map(|(first, _)| first)
                 .unwrap_or(phi_node.0);
-            let phi_name = format!(
-                "{original_name}.{}.1",
-                successor_idx.as_index_for_slotmap_version_1_0_7_only()
-            );
+            let phi_name =
+                format!("{original_name}.{}.1", ids.id(successor_idx));
             locally_required_sets.insert(
                 phi_name,
                 (original_name.to_string(), phi_node.1.to_owned()),
@@ -393,12 +338,13 @@ pub fn rename_and_insert_upsilons(
         }
     }
     let set_insertion_point = cfg.vertices[block_idx].index_before_exit();
-    cfg.vertices[block_idx].instructions.splice(
-        set_insertion_point..set_insertion_point,
+    cfg.vertices[block_idx].instructions.insert_many(
+        set_insertion_point,
         locally_required_sets.into_iter().map(
             |(phi_name, (original_name, phi_type))| {
                 let current_name = local_renamer
                     .rewrite_argument(
+                        ids,
                         dominating_definitions_stacks,
                         &original_name,
                     )
@@ -408,13 +354,7 @@ pub fn rename_and_insert_upsilons(
                             .insert(undefined_name.clone(), phi_type);
                         undefined_name
                     });
-                Instruction::Effect {
-                    args: vec![phi_name, current_name],
-                    funcs: vec![],
-                    labels: vec![],
-                    op: EffectOps::Set,
-                    pos: None,
-                }
+                make_effect(EffectOps::Set, vec![phi_name, current_name])
             },
         ),
     );
@@ -425,6 +365,7 @@ pub fn rename_and_insert_upsilons(
             for imm_idx in &dominance_tree[block_idx] {
                 rename_and_insert_upsilons(
                     cfg,
+                    ids,
                     *imm_idx,
                     dominance_tree,
                     dominating_definitions_stacks,
@@ -449,18 +390,9 @@ pub fn insert_undefined_names_at_entry(
         }
     }
     for (other, ty) in undefined_names {
-        cfg.vertices[cfg.entry].instructions.insert(
-            0,
-            Instruction::Value {
-                args: vec![],
-                dest: other,
-                funcs: vec![],
-                labels: vec![],
-                op: ValueOps::Undef,
-                pos: None,
-                op_type: ty,
-            },
-        );
+        cfg.vertices[cfg.entry]
+            .instructions
+            .insert(0, make_value(other, ty, ValueOps::Undef, vec![]));
     }
 }
 
@@ -506,10 +438,54 @@ impl FunctionNameGenerator {
     }
 }
 
-pub fn from_ssa(cfg: &mut FunctionCfg) -> Result<(), Whatever> {
-    if !is_ssa(cfg) {
-        whatever!("Input was not in SSA already");
+/// Why a function could not be translated into or out of SSA form.
+#[derive(Debug, Snafu)]
+pub enum SsaError {
+    #[snafu(display("Input was not in SSA already"))]
+    NotSsa,
+
+    #[snafu(display(
+        "The `get` instruction corresponding to `set {variable}` does not exist or did not specify a type"
+    ))]
+    MissingGet { variable: String },
+
+    #[snafu(display("Result of SSA transformation was not SSA"))]
+    NotSsaAfterTranslation,
+
+    #[snafu(display(
+        "`get {variable}` is not at the start of its block, so it cannot become a block parameter"
+    ))]
+    MisplacedGet { variable: String },
+
+    #[snafu(display(
+        "`set {variable}` is not in a predecessor of the block of its `get`"
+    ))]
+    SetNotOnEdge { variable: String },
+
+    #[snafu(display("{block} jumps to `get {variable}` without setting it"))]
+    MissingSet { variable: String, block: String },
+}
+
+impl From<SsaError> for ToolError {
+    fn from(error: SsaError) -> Self {
+        match error {
+            SsaError::NotSsa
+            | SsaError::MissingGet { .. }
+            | SsaError::MisplacedGet { .. }
+            | SsaError::SetNotOnEdge { .. }
+            | SsaError::MissingSet { .. } => ToolError::MalformedInput {
+                message: error.to_string(),
+                span: None,
+            },
+            SsaError::NotSsaAfterTranslation => ToolError::InvariantViolated {
+                message: error.to_string(),
+            },
+        }
     }
+}
+
+pub fn from_ssa(cfg: &mut FunctionCfg) -> Result<(), SsaError> {
+    ensure!(is_ssa(cfg), NotSsaSnafu);
 
     let mut set_operation_types = HashMap::new();
     for block in cfg.vertices.values() {
@@ -550,15 +526,7 @@ pub fn from_ssa(cfg: &mut FunctionCfg) -> Result<(), Whatever> {
                 let shadow_variable =
                     name_generator.new_prefixed(format!("shadow.{}", dest));
                 shadow_env.insert(dest.clone(), shadow_variable.clone());
-                Some(Instruction::Value {
-                    args: vec![shadow_variable],
-                    dest: dest.clone(),
-                    funcs: vec![],
-                    labels: vec![],
-                    op: ValueOps::Id,
-                    pos: None,
-                    op_type: op_type.clone(),
-                })
+                Some(make_id(dest.clone(), op_type.clone(), shadow_variable))
             } else {
                 None
             } {
@@ -578,15 +546,20 @@ pub fn from_ssa(cfg: &mut FunctionCfg) -> Result<(), Whatever> {
                     args.len() == 2,
                     "EffectOps::Set should have two arguments"
                 );
-                Some(Instruction::Value {
-                args: vec![args[1].clone()],
-                dest: shadow_env.get(&args[0]).cloned().expect("No corresponding `get` instruction"),
-                funcs: vec![],
-                labels: vec![],
-                op: ValueOps::Id,
-                pos: None,
-                op_type: set_operation_types.get(&args[0]).whatever_context("The corresponding `get` instruction does not exist or did not specify a type")?.clone(),
-            })
+                let missing_get = || MissingGetSnafu {
+                    variable: args[0].clone(),
+                };
+                Some(make_id(
+                    shadow_env
+                        .get(&args[0])
+                        .cloned()
+                        .with_context(missing_get)?,
+                    set_operation_types
+                        .get(&args[0])
+                        .with_context(missing_get)?
+                        .clone(),
+                    args[1].clone(),
+                ))
             } else {
                 None
             } {
@@ -597,3 +570,94 @@ pub fn from_ssa(cfg: &mut FunctionCfg) -> Result<(), Whatever> {
 
     Ok(())
 }
+
+/// Translates `cfg` into SSA form, or only inserts phis if
+/// `skip_post_phi_insertion` is set.
+pub fn into_ssa(
+    cfg: &mut FunctionCfg,
+    skip_post_phi_insertion: bool,
+) -> Result<(), SsaError> {
+    insert_new_empty_entry_block(cfg);
+
+    let dominators = dominators::compute_dominators(cfg);
+    let dominance_tree = dominators::compute_dominator_tree(&dominators);
+    let dominance_frontiers =
+        dominators::compute_dominance_frontiers(cfg, dominators);
+
+    // 1: Insert phi nodes
+
+    let definition_sites = compute_definition_sites(cfg);
+    let phi_insertion_points =
+        determine_phi_insertion_points(definition_sites, dominance_frontiers);
+    insert_phis(cfg, phi_insertion_points);
+
+    if !skip_post_phi_insertion {
+        // 2: Rename variables and insert upsilon nodes
+
+        simulate_parameters_as_locals(cfg);
+
+        let entry = cfg.entry;
+        let ids = BlockIdMap::new(cfg);
+        let mut dominating_definitiions_stacks =
+            DominatingDefinitionsStacks::default();
+        let mut undefined_names = BTreeMap::new();
+        rename_and_insert_upsilons(
+            cfg,
+            &ids,
+            entry,
+            &dominance_tree,
+            &mut dominating_definitiions_stacks,
+            &mut undefined_names,
+        );
+
+        insert_undefined_names_at_entry(cfg, undefined_names);
+
+        ensure!(is_ssa(cfg), NotSsaAfterTranslationSnafu);
+    }
+    Ok(())
+}
+
+/// Translates functions into SSA form.
+pub struct IntoSsaPass;
+
+impl FunctionPass for IntoSsaPass {
+    fn name(&self) -> &'static str {
+        "ssa"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // shadow variables already in the input would clash with new ones
+        Capabilities::all() - Capabilities::SSA - Capabilities::SPECULATION
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        _cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        into_ssa(cfg, false)?;
+        Ok(Changed::Yes)
+    }
+}
+
+/// Translates functions out of SSA form.
+pub struct FromSsaPass;
+
+impl FunctionPass for FromSsaPass {
+    fn name(&self) -> &'static str {
+        "from-ssa"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all() - Capabilities::SPECULATION
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        _cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        from_ssa(cfg)?;
+        Ok(Changed::Yes)
+    }
+}
@@ -1,9 +1,11 @@
-use std::{collections::BTreeMap, fs, io, path::PathBuf};
+use std::{fs, io, path::PathBuf};
 
 use argh::FromArgs;
 use bril_rs::Program;
+use bril_util::filter::{FunctionFilter, FunctionPattern};
 use build_cfg::print;
 use snafu::{ResultExt, Whatever, whatever};
+use ssa::block_params::{block_params_to_lines, into_block_params};
 
 /// Transforms Bril into and out of SSA
 #[derive(FromArgs)]
@@ -21,6 +23,18 @@ struct Opts {
     #[argh(switch)]
     skip_post_phi_insertion: bool,
 
+    /// with --into-ssa, print blocks taking parameters and jumps passing them
+    /// arguments instead of `get` and `set`, which is for reading and not
+    /// valid Bril
+    #[argh(switch)]
+    block_params: bool,
+
+    /// only translate functions matching this name, like `@main` or `@test_*`,
+    /// printing the rest unchanged: may be repeated, and omit for every
+    /// function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
     /// input Bril file: omit for stdin
     #[argh(positional)]
     input: Option<PathBuf>,
@@ -44,58 +58,37 @@ fn main() -> Result<(), Whatever> {
         )?
     };
 
+    if opts.block_params && (!opts.into_ssa || opts.skip_post_phi_insertion) {
+        whatever!(
+            "--block-params needs --into-ssa without --skip-post-phi-insertion"
+        );
+    }
+
+    let filter = FunctionFilter::new(opts.function);
     for function in program.functions {
+        if !filter.selects(&function.name) {
+            println!("{}", function);
+            continue;
+        }
         match (opts.into_ssa, opts.from_ssa) {
             (true, false) => {
                 let mut cfg = build_cfg::build_cfg(&function, true)
                     .whatever_context("Failed to build cfg")?;
 
-                ssa::insert_new_empty_entry_block(&mut cfg);
-
-                let dominators = dominators::compute_dominators(&cfg);
-                let dominance_tree =
-                    dominators::compute_dominator_tree(&dominators);
-                let dominance_frontiers =
-                    dominators::compute_dominance_frontiers(&cfg, dominators);
-
-                // 1: Insert phi nodes
-
-                let definition_sites = ssa::compute_definition_sites(&cfg);
-                let phi_insertion_points = ssa::determine_phi_insertion_points(
-                    definition_sites,
-                    dominance_frontiers,
-                );
-                ssa::insert_phis(&mut cfg, phi_insertion_points);
-
-                if !opts.skip_post_phi_insertion {
-                    // 2: Rename variables and insert upsilon nodes
-
-                    ssa::simulate_parameters_as_locals(&mut cfg);
-
-                    let entry = cfg.entry;
-                    let mut dominating_definitiions_stacks =
-                        ssa::DominatingDefinitionsStacks::default();
-                    let mut undefined_names = BTreeMap::new();
-                    ssa::rename_and_insert_upsilons(
-                        &mut cfg,
-                        entry,
-                        &dominance_tree,
-                        &mut dominating_definitiions_stacks,
-                        &mut undefined_names,
-                    );
-
-                    ssa::insert_undefined_names_at_entry(
-                        &mut cfg,
-                        undefined_names,
-                    );
-
-                    assert!(
-                        ssa::is_ssa(&cfg),
-                        "Result of SSA transformation was not SSA"
-                    );
+                ssa::into_ssa(&mut cfg, opts.skip_post_phi_insertion)
+                    .whatever_context("Failed to convert into SSA form")?;
+
+                if opts.block_params {
+                    let block_params = into_block_params(&mut cfg)
+                        .whatever_context(
+                            "Failed to convert into block parameters",
+                        )?;
+                    for line in block_params_to_lines(&cfg, &block_params) {
+                        println!("{line}");
+                    }
+                } else {
+                    print::print_cfg_as_bril_text(cfg);
                 }
-
-                print::print_cfg_as_bril_text(cfg);
             }
             (false, true) => {
                 let mut cfg = build_cfg::build_cfg(&function, true)
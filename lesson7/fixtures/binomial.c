@@ -0,0 +1,27 @@
+#include "cycles.h"
+
+#define N 22
+
+__attribute__((annotate("memoize"))) int binomial(int n, int k) {
+    __builtin_assume(n >= 0);
+    __builtin_assume(n <= N);
+    __builtin_assume(k >= 0);
+    __builtin_assume(k <= N);
+    if (k == 0 || k == n) {
+        return 1;
+    } else {
+        return binomial(n - 1, k - 1) + binomial(n - 1, k);
+    }
+}
+
+int main(void) {
+    uint64_t start = read_cycles();
+    int total = 0;
+    for (int k = 0; k <= N; k++) {
+        total += binomial(N, k);
+    }
+    uint64_t end = read_cycles();
+
+    printf("%d\n", total);
+    report_cycles(end - start);
+}
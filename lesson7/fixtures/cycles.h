@@ -0,0 +1,27 @@
+// Times the fixtures with the cycle counter: the time-stamp counter on x86
+// and the virtual counter on ARM, which ticks at a fixed rate instead.
+
+#include <stdint.h>
+#include <stdio.h>
+
+#if defined(__x86_64__) || defined(__i386__)
+    #include <x86intrin.h>
+#endif
+
+static inline uint64_t read_cycles(void) {
+#if defined(__x86_64__) || defined(__i386__)
+    return __rdtsc();
+#elif defined(__aarch64__)
+    uint64_t counter;
+    __asm__ volatile("isb; mrs %0, cntvct_el0" : "=r"(counter));
+    return counter;
+#else
+    #error "No cycle counter for this architecture"
+#endif
+}
+
+// Reported on standard error so that standard output can be compared between
+// builds with and without the pass.
+static inline void report_cycles(uint64_t cycles) {
+    fprintf(stderr, "cycles: %llu\n", (unsigned long long)cycles);
+}
@@ -0,0 +1,22 @@
+#include "cycles.h"
+
+#define N 32
+
+__attribute__((annotate("memoize"))) int fib(int n) {
+    __builtin_assume(n >= 0);
+    __builtin_assume(n <= N);
+    if (n < 2) {
+        return n;
+    } else {
+        return fib(n - 1) + fib(n - 2);
+    }
+}
+
+int main(void) {
+    uint64_t start = read_cycles();
+    int result = fib(N);
+    uint64_t end = read_cycles();
+
+    printf("%d\n", result);
+    report_cycles(end - start);
+}
@@ -0,0 +1,26 @@
+#include "cycles.h"
+
+#define N 30
+
+// The cached range of `n` ends at 128, which does not fit in its `i8`, so the
+// bounds check must compare it as a wider integer.
+__attribute__((annotate("memoize"))) int tribonacci(signed char n) {
+    __builtin_assume(n >= 0);
+    __builtin_assume(n <= 127);
+    if (n < 2) {
+        return 0;
+    } else if (n == 2) {
+        return 1;
+    } else {
+        return tribonacci(n - 1) + tribonacci(n - 2) + tribonacci(n - 3);
+    }
+}
+
+int main(void) {
+    uint64_t start = read_cycles();
+    int result = tribonacci(N);
+    uint64_t end = read_cycles();
+
+    printf("%d\n", result);
+    report_cycles(end - start);
+}
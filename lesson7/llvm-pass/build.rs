@@ -0,0 +1,31 @@
+use std::{env, path::PathBuf};
+
+/// Tells the tests in `tests/` where to find their inputs and the built
+/// plugin.
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    for (variable, directory) in [
+        ("LIT_TESTS_DIR", "tests"),
+        ("RUNTIME_FIXTURES_DIR", "fixtures"),
+    ] {
+        let directory = manifest_dir.parent().unwrap().join(directory);
+        println!("cargo:rerun-if-changed={}", directory.display());
+        println!("cargo:rustc-env={variable}={}", directory.display());
+    }
+
+    // `OUT_DIR` is `target/<profile>/build/llvm-pass-<hash>/out`, and the
+    // plugin is built to `target/<profile>`
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let profile_dir = out_dir.ancestors().nth(3).unwrap();
+    let extension = match env::var("CARGO_CFG_TARGET_OS").unwrap().as_str() {
+        "macos" => "dylib",
+        "windows" => "dll",
+        _ => "so",
+    };
+    println!(
+        "cargo:rustc-env=LLVM_PASS_PLUGIN={}",
+        profile_dir
+            .join(format!("libllvm_pass.{extension}"))
+            .display()
+    );
+}
@@ -0,0 +1,269 @@
+use llvm_plugin::{
+    LlvmModulePass, ModuleAnalysisManager, PreservedAnalyses,
+    inkwell::{
+        IntPredicate,
+        builder::Builder,
+        context::ContextRef,
+        module::{Linkage, Module},
+        types::ArrayType,
+        values::{BasicValue, FunctionValue, GlobalValue, InstructionOpcode},
+    },
+};
+
+use crate::{declare_atexit, declare_dprintf, verify_in_debug_builds};
+
+/// Counts how many instructions the program executes, like `brili -p` does
+/// for Bril: every basic block increments its own counter on entry, and a
+/// report run at exit weighs each count by the size of its block and prints
+/// `total_dyn_inst: N` to standard error. The report is registered with
+/// `atexit` at the start of `main`, so the program must be compiled as a
+/// single module.
+pub struct InstrCountPass;
+
+impl InstrCountPass {
+    pub fn parse(name: &str) -> Option<Self> {
+        (name == "instr-count").then_some(Self)
+    }
+
+    /// Inserts an increment of `counts[index]` at the start of each block of
+    /// `function` after its phis, and returns the number of instructions in
+    /// each block beforehand.
+    fn instrument_function<'a>(
+        &self,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        function: FunctionValue<'a>,
+        counts_type: ArrayType<'a>,
+        counts: GlobalValue<'a>,
+        first_index: u64,
+    ) -> Vec<u64> {
+        let i32_type = context.i32_type();
+        let i64_type = context.i64_type();
+
+        let mut sizes = vec![];
+        for (i, basic_block) in function.get_basic_block_iter().enumerate() {
+            sizes.push(basic_block.get_instructions().count() as u64);
+
+            let insertion_point = basic_block
+                .get_instructions()
+                .find(|instruction| {
+                    !matches!(
+                        instruction.get_opcode(),
+                        InstructionOpcode::Phi | InstructionOpcode::LandingPad
+                    )
+                })
+                .expect("basic block has no terminator");
+            builder.position_before(&insertion_point);
+
+            let count_pointer = unsafe {
+                builder.build_gep(
+                    counts_type,
+                    counts.as_pointer_value(),
+                    &[
+                        i32_type.const_zero(),
+                        i64_type.const_int(first_index + i as u64, false),
+                    ],
+                    "count_pointer",
+                )
+            }
+            .unwrap();
+            let count = builder
+                .build_load(i64_type, count_pointer, "count")
+                .unwrap()
+                .into_int_value();
+            let count = builder
+                .build_int_add(count, i64_type.const_int(1, false), "count")
+                .unwrap();
+            builder.build_store(count_pointer, count).unwrap();
+        }
+        sizes
+    }
+
+    /// Builds `void report()`, which prints the total number of instructions
+    /// executed.
+    fn build_report<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        counts_type: ArrayType<'a>,
+        counts: GlobalValue<'a>,
+        sizes: &[u64],
+    ) -> FunctionValue<'a> {
+        let i32_type = context.i32_type();
+        let i64_type = context.i64_type();
+
+        let sizes_type = i64_type.array_type(sizes.len() as u32);
+        let sizes_global =
+            module.add_global(sizes_type, None, "instr_count.block_sizes");
+        sizes_global.set_linkage(Linkage::Internal);
+        sizes_global.set_constant(true);
+        sizes_global.set_initializer(
+            &i64_type.const_array(
+                &sizes
+                    .iter()
+                    .map(|size| i64_type.const_int(*size, false))
+                    .collect::<Vec<_>>(),
+            ),
+        );
+
+        let report = module.add_function(
+            "instr_count.report",
+            context.void_type().fn_type(&[], false),
+            Some(Linkage::Internal),
+        );
+        let entry_block = context.append_basic_block(report, "entry");
+        let loop_block = context.append_basic_block(report, "loop");
+        let done_block = context.append_basic_block(report, "done");
+
+        builder.position_at_end(entry_block);
+        let _ = builder.build_unconditional_branch(loop_block).unwrap();
+
+        // total += counts[i] * sizes[i] for every block i
+        builder.position_at_end(loop_block);
+        let index_phi = builder.build_phi(i64_type, "index").unwrap();
+        let total_phi = builder.build_phi(i64_type, "total").unwrap();
+        let index = index_phi.as_basic_value().into_int_value();
+        let total = total_phi.as_basic_value().into_int_value();
+
+        let load_element =
+            |array_type: ArrayType<'a>, array: GlobalValue<'a>, name: &str| {
+                let pointer = unsafe {
+                    builder.build_gep(
+                        array_type,
+                        array.as_pointer_value(),
+                        &[i32_type.const_zero(), index],
+                        name,
+                    )
+                }
+                .unwrap();
+                builder
+                    .build_load(i64_type, pointer, name)
+                    .unwrap()
+                    .into_int_value()
+            };
+        let count = load_element(counts_type, counts, "count");
+        let size = load_element(sizes_type, sizes_global, "size");
+        let executed = builder.build_int_mul(count, size, "executed").unwrap();
+        let next_total =
+            builder.build_int_add(total, executed, "total").unwrap();
+        let next_index = builder
+            .build_int_add(index, i64_type.const_int(1, false), "index")
+            .unwrap();
+        let is_done = builder
+            .build_int_compare(
+                IntPredicate::EQ,
+                next_index,
+                i64_type.const_int(sizes.len() as u64, false),
+                "is_done",
+            )
+            .unwrap();
+        let _ = builder
+            .build_conditional_branch(is_done, done_block, loop_block)
+            .unwrap();
+
+        index_phi.add_incoming(&[
+            (&i64_type.const_zero() as &dyn BasicValue, entry_block),
+            (&next_index, loop_block),
+        ]);
+        total_phi.add_incoming(&[
+            (&i64_type.const_zero() as &dyn BasicValue, entry_block),
+            (&next_total, loop_block),
+        ]);
+
+        builder.position_at_end(done_block);
+        let format = builder
+            .build_global_string_ptr(
+                "total_dyn_inst: %llu\n",
+                "instr_count.report_format",
+            )
+            .unwrap();
+        builder
+            .build_call(
+                declare_dprintf(module, context),
+                &[
+                    i32_type.const_int(2, false).into(),
+                    format.as_pointer_value().into(),
+                    next_total.into(),
+                ],
+                "",
+            )
+            .unwrap();
+        builder.build_return(None).unwrap();
+
+        report
+    }
+}
+
+impl LlvmModulePass for InstrCountPass {
+    fn run_pass(
+        &self,
+        module: &mut Module,
+        _manager: &ModuleAnalysisManager,
+    ) -> PreservedAnalyses {
+        let context = module.get_context();
+        let builder = context.create_builder();
+
+        let functions = module
+            .get_functions()
+            .filter(|function| function.count_basic_blocks() > 0)
+            .collect::<Vec<_>>();
+        let Some(main) = module
+            .get_function("main")
+            .filter(|main| main.count_basic_blocks() > 0)
+        else {
+            tracing::warn!(
+                target: "instr-count",
+                "Skipping module without `main`"
+            );
+            return PreservedAnalyses::All;
+        };
+        let total_blocks = functions
+            .iter()
+            .map(|function| function.count_basic_blocks())
+            .sum::<u32>();
+
+        let counts_type = context.i64_type().array_type(total_blocks);
+        let counts =
+            module.add_global(counts_type, None, "instr_count.block_counts");
+        counts.set_linkage(Linkage::Internal);
+        counts.set_initializer(&counts_type.const_zero());
+
+        let mut sizes = vec![];
+        for function in functions {
+            let first_index = sizes.len() as u64;
+            sizes.extend(self.instrument_function(
+                context,
+                &builder,
+                function,
+                counts_type,
+                counts,
+                first_index,
+            ));
+        }
+
+        let report = self.build_report(
+            module,
+            context,
+            &builder,
+            counts_type,
+            counts,
+            &sizes,
+        );
+
+        // registered after instrumenting so that the call is not counted
+        let main_entry = main.get_first_basic_block().unwrap();
+        builder.position_before(&main_entry.get_first_instruction().unwrap());
+        builder
+            .build_call(
+                declare_atexit(module, context),
+                &[report.as_global_value().as_pointer_value().into()],
+                "",
+            )
+            .unwrap();
+
+        verify_in_debug_builds(module, "instr-count");
+
+        PreservedAnalyses::None
+    }
+}
@@ -7,47 +7,224 @@ use llvm_plugin::{
     LlvmModulePass, ModuleAnalysisManager, PassBuilder, PipelineParsing,
     PreservedAnalyses,
     inkwell::{
-        IntPredicate,
+        AddressSpace, FloatPredicate, IntPredicate,
+        attributes::{Attribute, AttributeLoc},
         basic_block::BasicBlock,
         builder::Builder,
         context::ContextRef,
         module::{Linkage, Module},
-        types::{ArrayType, BasicType, BasicTypeEnum},
+        types::{ArrayType, BasicType, BasicTypeEnum, FunctionType},
         values::{
-            ArrayValue, BasicValue, BasicValueEnum, FunctionValue, GlobalValue,
-            InstructionOpcode, InstructionValue, IntValue, PointerValue,
+            AnyValue, AnyValueEnum, BasicMetadataValueEnum, BasicValue,
+            BasicValueEnum, CallSiteValue, FunctionValue, GlobalValue,
+            InstructionOpcode, InstructionValue, IntValue, PhiValue,
+            PointerValue,
         },
     },
 };
 use slotmap::{SecondaryMap, SlotMap, new_key_type};
 
+use crate::{instr_count::InstrCountPass, licm::SimpleLicmPass};
+
+mod instr_count;
+mod licm;
+
 #[llvm_plugin::plugin(name = "CustomPass", version = "0.1")]
 fn plugin_registrar(builder: &mut PassBuilder) {
     builder.add_module_pipeline_parsing_callback(|name, manager| {
-        if name == "auto-memoize" {
-            manager.add_pass(AutoMemoizePass { verbose: false });
+        if let Some(pass) = AutoMemoizePass::parse(name) {
+            manager.add_pass(pass);
             PipelineParsing::Parsed
-        } else if name == "auto-memoize:verbose" {
-            manager.add_pass(AutoMemoizePass { verbose: true });
+        } else if let Some(pass) = InstrCountPass::parse(name) {
+            manager.add_pass(pass);
+            PipelineParsing::Parsed
+        } else if let Some(pass) = SimpleLicmPass::parse(name) {
+            manager.add_pass(pass);
             PipelineParsing::Parsed
         } else {
             PipelineParsing::NotParsed
         }
     });
+
+    // `clang -fpass-plugin` only runs the default pipeline, so memoize the
+    // annotated functions at its start, before other passes rewrite them
+    builder.add_pipeline_start_ep_callback(|manager, _| {
+        manager.add_pass(AutoMemoizePass::default());
+    });
 }
 
 const LLVM_BUILTIN_ASSUME: &str = "llvm.assume";
+const MEMOIZE_ANNOTATION: &str = "memoize";
+const NO_MEMOIZE_ANNOTATION: &str = "nomemoize";
+
+/// Gets the function `name` in `module`, declaring it with type `ty` if it is
+/// not already.
+fn get_or_declare_function<'a>(
+    module: &Module<'a>,
+    name: &str,
+    ty: FunctionType<'a>,
+) -> FunctionValue<'a> {
+    module
+        .get_function(name)
+        .unwrap_or_else(|| module.add_function(name, ty, None))
+}
+
+/// Declares `int atexit(void (*)(void))`.
+fn declare_atexit<'a>(
+    module: &Module<'a>,
+    context: ContextRef<'a>,
+) -> FunctionValue<'a> {
+    let ptr_type = context.ptr_type(AddressSpace::default());
+    get_or_declare_function(
+        module,
+        "atexit",
+        context.i32_type().fn_type(&[ptr_type.into()], false),
+    )
+}
 
+/// Declares `int dprintf(int, const char*, ...)`, which is used to print to
+/// standard error since `stderr` is named differently across C libraries.
+fn declare_dprintf<'a>(
+    module: &Module<'a>,
+    context: ContextRef<'a>,
+) -> FunctionValue<'a> {
+    let i32_type = context.i32_type();
+    let ptr_type = context.ptr_type(AddressSpace::default());
+    get_or_declare_function(
+        module,
+        "dprintf",
+        i32_type.fn_type(&[i32_type.into(), ptr_type.into()], true),
+    )
+}
+
+/// Checks in debug builds that `pass` left `module` valid, so that a bad
+/// rewrite fails where it happens rather than in whatever runs next.
+fn verify_in_debug_builds(module: &Module, pass: &str) {
+    if cfg!(debug_assertions) {
+        module.verify().unwrap_or_else(|error| {
+            panic!("[{pass}] Produced invalid IR:\n{}", error.to_string_lossy())
+        });
+    }
+}
+
+/// The name of the callee of the call `instruction`, which is its last operand.
+/// For an indirect call, this is the name of the function pointer instead,
+/// which will not name a function in the module.
 fn get_callee_of_known_call(instruction: InstructionValue) -> Option<String> {
-    instruction.get_operand(1).and_then(|o| o.left()).map(|o| {
-        o.into_pointer_value()
-            .get_name()
-            .to_string_lossy()
-            .to_string()
-    })
+    let callee_index = instruction.get_num_operands().checked_sub(1)?;
+    instruction
+        .get_operand(callee_index)
+        .and_then(|o| o.left())
+        .filter(|o| o.is_pointer_value())
+        .map(|o| {
+            o.into_pointer_value()
+                .get_name()
+                .to_string_lossy()
+                .to_string()
+        })
+}
+
+/// The annotations on `function`, either from
+/// `__attribute__((annotate("...")))` or from being listed in named metadata,
+/// e.g., `!memoize = !{!0}` with `!0 = !{ptr @function}`.
+fn get_annotations<'a>(
+    module: &Module<'a>,
+    function: FunctionValue<'a>,
+) -> HashSet<String> {
+    let function_pointer = function.as_global_value().as_pointer_value();
+    let mut annotations = HashSet::new();
+
+    // clang emits each annotation as a use of the function in a struct
+    // `{ ptr @function, ptr @.str, ptr @.str.1, i32 line, ptr null }` in
+    // `@llvm.global.annotations`, where `@.str` holds the annotation
+    let mut current_use = function_pointer.get_first_use();
+    while let Some(function_use) = current_use {
+        if let AnyValueEnum::StructValue(annotation) = function_use.get_user() {
+            let annotation_string = annotation
+                .get_field_at_index(1)
+                .filter(|field| field.is_pointer_value())
+                .and_then(|field| {
+                    module.get_global(
+                        &field
+                            .into_pointer_value()
+                            .get_name()
+                            .to_string_lossy(),
+                    )
+                })
+                .and_then(|global| global.get_initializer())
+                .filter(|initializer| initializer.is_array_value())
+                .and_then(|initializer| {
+                    initializer
+                        .into_array_value()
+                        .get_string_constant()
+                        .map(|string| string.to_string_lossy().to_string())
+                });
+            if let Some(annotation_string) = annotation_string {
+                annotations.insert(annotation_string);
+            }
+        }
+        current_use = function_use.get_next_use();
+    }
+
+    for name in [MEMOIZE_ANNOTATION, NO_MEMOIZE_ANNOTATION] {
+        let is_listed = module
+            .get_global_metadata(name)
+            .into_iter()
+            .flat_map(|node| node.get_node_values())
+            .any(|value| {
+                matches!(
+                    value,
+                    BasicMetadataValueEnum::PointerValue(pointer)
+                        if pointer == function_pointer
+                )
+            });
+        if is_listed {
+            annotations.insert(name.to_string());
+        }
+    }
+
+    annotations
+}
+
+/// Whether `pointer`, looking through address computations, points into an
+/// `alloca` of the function using it or into a `constant` global, so that
+/// loading from it reads memory no other function can change.
+fn points_to_local_or_constant(
+    module: &Module,
+    pointer: BasicValueEnum,
+) -> bool {
+    match pointer.as_instruction_value() {
+        Some(instruction) => match instruction.get_opcode() {
+            InstructionOpcode::Alloca => true,
+            InstructionOpcode::AddrSpaceCast
+            | InstructionOpcode::BitCast
+            | InstructionOpcode::GetElementPtr => instruction
+                .get_operand(0)
+                .and_then(|operand| operand.left())
+                .is_some_and(|base| points_to_local_or_constant(module, base)),
+            _ => false,
+        },
+        None => {
+            pointer.is_pointer_value()
+                && module
+                    .get_global(
+                        &pointer
+                            .into_pointer_value()
+                            .get_name()
+                            .to_string_lossy(),
+                    )
+                    .is_some_and(|global| global.is_constant())
+        }
+    }
 }
 
-fn is_conservatively_pure(function: FunctionValue) -> bool {
+/// Whether `function` is pure assuming that the functions it calls, which are
+/// added to `callees`, are pure.
+fn is_locally_pure(
+    module: &Module,
+    function: FunctionValue,
+    callees: &mut HashSet<String>,
+) -> bool {
     let mut local_allocations = HashSet::new();
     for basic_block in function.get_basic_block_iter() {
         for instruction in basic_block.get_instructions() {
@@ -75,7 +252,6 @@ fn is_conservatively_pure(function: FunctionValue) -> bool {
                 | InstructionOpcode::ICmp
                 | InstructionOpcode::IndirectBr
                 | InstructionOpcode::IntToPtr
-                | InstructionOpcode::Load
                 | InstructionOpcode::LShr
                 | InstructionOpcode::Mul
                 | InstructionOpcode::Or
@@ -101,18 +277,27 @@ fn is_conservatively_pure(function: FunctionValue) -> bool {
                     local_allocations.insert(instruction);
                     true
                 }
+                // a load from a pointer argument or a mutable global may see
+                // a store made between two calls with the same arguments
+                InstructionOpcode::Load => instruction
+                    .get_operand(0)
+                    .and_then(|operand| operand.left())
+                    .is_some_and(|pointer| {
+                        points_to_local_or_constant(module, pointer)
+                    }),
                 InstructionOpcode::Store => {
                     let pointer = instruction.get_operand(1).and_then(|either| either.expect_left("expected value, not block, as argument to store").as_basic_value_enum().as_instruction_value()).expect("could not get pointer argument for store");
                     local_allocations.contains(&pointer)
                 }
                 InstructionOpcode::Call => {
-                    if get_callee_of_known_call(instruction).as_deref()
-                        == Some(LLVM_BUILTIN_ASSUME)
-                    {
-                        return true;
+                    match get_callee_of_known_call(instruction) {
+                        Some(callee) if callee == LLVM_BUILTIN_ASSUME => true,
+                        Some(callee) => {
+                            callees.insert(callee);
+                            true
+                        }
+                        None => false,
                     }
-                    // TODO
-                    false
                 }
                 _ => false,
             } {
@@ -124,16 +309,155 @@ fn is_conservatively_pure(function: FunctionValue) -> bool {
     true
 }
 
+/// Whether `function` is declared without a body but is known not to access
+/// memory, e.g., most math intrinsics.
+fn is_readnone_declaration(function: FunctionValue) -> bool {
+    function.count_basic_blocks() == 0
+        && function
+            .get_enum_attribute(
+                AttributeLoc::Function,
+                Attribute::get_named_enum_kind_id("memory"),
+            )
+            .is_some_and(|memory| memory.get_enum_value() == 0)
+}
+
+type CallGraph = HashMap<String, Option<HashSet<String>>>;
+
+/// Tarjan's algorithm on a [`CallGraph`], which finds strongly connected
+/// components in reverse topological order, that is, callees before callers.
+struct StronglyConnectedComponents<'a> {
+    call_graph: &'a CallGraph,
+    index: HashMap<&'a str, usize>,
+    low_link: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: HashSet<&'a str>,
+    components: Vec<Vec<String>>,
+}
+
+impl<'a> StronglyConnectedComponents<'a> {
+    fn find(call_graph: &'a CallGraph) -> Vec<Vec<String>> {
+        let mut search = Self {
+            call_graph,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: vec![],
+            on_stack: HashSet::new(),
+            components: vec![],
+        };
+        for function in call_graph.keys() {
+            if !search.index.contains_key(function.as_str()) {
+                search.visit(function);
+            }
+        }
+        search.components
+    }
+
+    fn visit(&mut self, function: &'a str) {
+        let index = self.index.len();
+        self.index.insert(function, index);
+        self.low_link.insert(function, index);
+        self.stack.push(function);
+        self.on_stack.insert(function);
+
+        let call_graph = self.call_graph;
+        for callee in call_graph[function].iter().flatten() {
+            let callee = callee.as_str();
+            if !call_graph.contains_key(callee) {
+                continue;
+            }
+            let callee_low_link = if !self.index.contains_key(callee) {
+                self.visit(callee);
+                self.low_link[callee]
+            } else if self.on_stack.contains(callee) {
+                self.index[callee]
+            } else {
+                continue;
+            };
+            let low_link = self.low_link.get_mut(function).unwrap();
+            *low_link = (*low_link).min(callee_low_link);
+        }
+
+        if self.low_link[function] == self.index[function] {
+            let mut component = vec![];
+            loop {
+                let member = self.stack.pop().unwrap();
+                self.on_stack.remove(member);
+                component.push(member.to_string());
+                if member == function {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+/// Determines which functions defined in `module` are pure by summarizing the
+/// call graph bottom-up. A function is pure if it is locally pure and all its
+/// callees are pure, are in the same strongly connected component (so that
+/// recursive functions can be pure), or are [`is_readnone_declaration`]s.
+fn compute_pure_functions(module: &Module) -> HashSet<String> {
+    let mut call_graph = CallGraph::new();
+    for function in module.get_functions() {
+        if function.count_basic_blocks() > 0 {
+            let mut callees = HashSet::new();
+            let is_pure = is_locally_pure(module, function, &mut callees);
+            call_graph.insert(
+                function.get_name().to_string_lossy().to_string(),
+                is_pure.then_some(callees),
+            );
+        }
+    }
+
+    let mut pure_functions = HashSet::new();
+    for component in StronglyConnectedComponents::find(&call_graph) {
+        let is_pure = component.iter().all(|function| {
+            call_graph[function].as_ref().is_some_and(|callees| {
+                callees.iter().all(|callee| {
+                    component.contains(callee)
+                        || pure_functions.contains(callee)
+                        || module
+                            .get_function(callee)
+                            .is_some_and(is_readnone_declaration)
+                })
+            })
+        });
+        if is_pure {
+            pure_functions.extend(component);
+        }
+    }
+    pure_functions
+}
+
 struct AutoMemoizePass {
-    verbose: bool,
+    /// Whether to consider every function not annotated `nomemoize` instead of
+    /// only those annotated `memoize`.
+    memoize_all: bool,
+    /// Whether to count how often the memoized functions hit their caches and
+    /// report it when the program exits.
+    profile: bool,
+    /// The most entries a memoization table can have. Parameters whose
+    /// combined ranges would exceed this are cached in a hash table instead.
+    max_entries: u32,
+    /// The exclusive upper bound cached for parameters without an assumed one.
+    /// If there is none, such parameters are cached in a hash table instead.
+    default_range: Option<u32>,
+    /// The alignment of the memoization tables, which must be a power of two.
+    alignment: u32,
+    eviction: Eviction,
 }
 
-macro_rules! local_log {
-    ($self:ident, $($format:tt)*) => {
-        if $self.verbose {
-            eprintln!($($format)*);
+impl Default for AutoMemoizePass {
+    fn default() -> Self {
+        Self {
+            memoize_all: false,
+            profile: false,
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            default_range: None,
+            alignment: Self::TYPICAL_PAGE_SIZE,
+            eviction: Eviction::Never,
         }
-    };
+    }
 }
 
 struct RelevantBlocks<'a> {
@@ -143,11 +467,84 @@ struct RelevantBlocks<'a> {
     fast_path_block: BasicBlock<'a>,
     cache_and_return_block: BasicBlock<'a>,
     always_return_block: BasicBlock<'a>,
+    /// Every return of the original function branches here instead.
+    return_block: BasicBlock<'a>,
+}
+
+/// What the lowering of a memoization table needs to hook into the rest of
+/// the memoized function.
+struct MemoizedFunction<'a> {
+    blocks: RelevantBlocks<'a>,
+    return_type: BasicTypeEnum<'a>,
+    /// Joins the return values of the original function in `return_block`,
+    /// which dominates `cache_and_return_block` and `always_return_block`.
+    return_value: PhiValue<'a>,
+    /// Where the lookup stores whether the return value should be cached.
+    should_cache_indirect: PointerValue<'a>,
+}
+
+/// Arrays caching return values. A struct return value is cached in one
+/// array per field.
+struct ValueArrays<'a> {
+    return_type: BasicTypeEnum<'a>,
+    fields: Vec<(ArrayType<'a>, GlobalValue<'a>)>,
+}
+
+/// What a hash table does when every slot a parameter tuple can be cached in
+/// is taken by another tuple.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Eviction {
+    /// Do not cache the result.
+    Never,
+    /// Each tuple has exactly one slot, which is overwritten.
+    DirectMapped,
+    /// Each tuple has a set of two slots, and the one not recently used is
+    /// overwritten.
+    TwoWay,
+    /// Each tuple can be in any slot of its probe window, which is swept from
+    /// the start giving recently used slots a second chance.
+    Clock,
+}
+
+impl Eviction {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::Never),
+            "direct-mapped" => Some(Self::DirectMapped),
+            "2-way" => Some(Self::TwoWay),
+            "clock" => Some(Self::Clock),
+            _ => None,
+        }
+    }
+
+    /// The number of slots a tuple can be cached in.
+    fn window_size(self, max_probes: u64) -> u64 {
+        match self {
+            Self::Never | Self::Clock => max_probes,
+            Self::DirectMapped => 1,
+            Self::TwoWay => 2,
+        }
+    }
+
+    /// Whether slots track whether they were recently used.
+    fn uses_reference_bits(self) -> bool {
+        matches!(self, Self::TwoWay | Self::Clock)
+    }
+}
+
+/// Runtime support for `auto-memoize:profile`.
+struct StatisticsRuntime<'a> {
+    /// `void count(ptr counter)` increments a counter, registering `report`
+    /// with `atexit` the first time it is called.
+    count_function: FunctionValue<'a>,
+    /// `void report()` prints the statistics of every memoized function.
+    report_function: FunctionValue<'a>,
+    /// The name of each memoized function and its array of counters.
+    statistics: Vec<(String, GlobalValue<'a>)>,
 }
 
 struct MemoizationGlobals<'a> {
-    value_array_type: ArrayType<'a>,
-    value_array: GlobalValue<'a>,
+    value_arrays: ValueArrays<'a>,
     ready_array_type: ArrayType<'a>,
     ready_array: GlobalValue<'a>,
 }
@@ -159,13 +556,349 @@ new_key_type! {
 /// The subset of the parameter domain that is memoized.
 struct MemoizationBounds<'a> {
     parameters: SlotMap<ParameterKey, IntValue<'a>>,
+    /// The range cached for each parameter with an upper bound, either derived
+    /// from an assumption or the default range.
     cached_ranges: SecondaryMap<ParameterKey, Range<u32>>,
 }
 
+impl MemoizationBounds<'_> {
+    /// The number of bits the bounded parameter `key` takes up in the
+    /// flattened index, which is enough for its offset from the start of its
+    /// cached range. Packing each parameter into its own bits makes the index
+    /// a few shifts, and a boolean or a small enum only costs a bit or two.
+    fn index_bits(&self, key: ParameterKey) -> u32 {
+        let range = &self.cached_ranges[key];
+        range
+            .end
+            .saturating_sub(range.start)
+            .checked_next_power_of_two()
+            .map_or(u32::BITS, u32::trailing_zeros)
+    }
+
+    /// The number of entries in arrays indexed directly by the parameters, if
+    /// every parameter is bounded and the number fits in a `u32`.
+    fn flattened_array_length(&self) -> Option<u32> {
+        if self.cached_ranges.len() != self.parameters.len() {
+            return None;
+        }
+        let total_bits = self
+            .parameters
+            .keys()
+            .map(|key| self.index_bits(key))
+            .sum::<u32>();
+        1u32.checked_shl(total_bits)
+    }
+}
+
 #[derive(Debug)]
 enum AssumedInequality<'a> {
-    LowerInclusive(InstructionValue<'a>, u32),
-    UpperExclusive(InstructionValue<'a>, u32),
+    LowerInclusive(IntValue<'a>, i64),
+    UpperExclusive(IntValue<'a>, i64),
+}
+
+/// Finds the parameter that `value` is or is an extension of, looking through
+/// `zext`, `sext`, and loads of the most recent store to the same pointer in
+/// the same block, which is how clang spills parameters without optimizations.
+fn trace_to_parameter<'a>(
+    input_parameters: &[IntValue<'a>],
+    value: IntValue<'a>,
+) -> Option<IntValue<'a>> {
+    if input_parameters.contains(&value) {
+        return Some(value);
+    }
+
+    let instruction = value.as_instruction_value()?;
+    let source = match instruction.get_opcode() {
+        InstructionOpcode::ZExt | InstructionOpcode::SExt => {
+            instruction.get_operand(0)?.left()?
+        }
+        InstructionOpcode::Load => {
+            let pointer = instruction.get_operand(0)?.left()?;
+            let mut previous = instruction.get_previous_instruction();
+            loop {
+                let candidate = previous?;
+                if candidate.get_opcode() == InstructionOpcode::Store
+                    && candidate.get_operand(1)?.left()? == pointer
+                {
+                    break candidate.get_operand(0)?.left()?;
+                }
+                previous = candidate.get_previous_instruction();
+            }
+        }
+        _ => return None,
+    };
+    if !source.is_int_value() {
+        return None;
+    }
+    trace_to_parameter(input_parameters, source.into_int_value())
+}
+
+/// The predicate `rhs predicate lhs` equivalent to `lhs predicate rhs`.
+fn swap_predicate(predicate: IntPredicate) -> IntPredicate {
+    match predicate {
+        IntPredicate::SGE => IntPredicate::SLE,
+        IntPredicate::SGT => IntPredicate::SLT,
+        IntPredicate::SLE => IntPredicate::SGE,
+        IntPredicate::SLT => IntPredicate::SGT,
+        IntPredicate::UGE => IntPredicate::ULE,
+        IntPredicate::UGT => IntPredicate::ULT,
+        IntPredicate::ULE => IntPredicate::UGE,
+        IntPredicate::ULT => IntPredicate::UGT,
+        IntPredicate::EQ | IntPredicate::NE => predicate,
+    }
+}
+
+/// The bounds on parameters implied by assuming the comparison `assumption`
+/// holds, when it compares a parameter (see [`trace_to_parameter`]) against a
+/// constant in either order.
+fn parse_assumption<'a>(
+    input_parameters: &[IntValue<'a>],
+    assumption: InstructionValue<'a>,
+) -> Vec<AssumedInequality<'a>> {
+    if assumption.get_opcode() != InstructionOpcode::ICmp {
+        return vec![];
+    }
+    let (Some(predicate), Some(Some(lhs)), Some(Some(rhs))) = (
+        assumption.get_icmp_predicate(),
+        assumption.get_operand(0).map(|operand| operand.left()),
+        assumption.get_operand(1).map(|operand| operand.left()),
+    ) else {
+        return vec![];
+    };
+    if !lhs.is_int_value() || !rhs.is_int_value() {
+        return vec![];
+    }
+    let (lhs, rhs) = (lhs.into_int_value(), rhs.into_int_value());
+
+    let (parameter, constant, predicate) = if rhs.is_const() {
+        let Some(parameter) = trace_to_parameter(input_parameters, lhs) else {
+            return vec![];
+        };
+        (parameter, rhs, predicate)
+    } else if lhs.is_const() {
+        let Some(parameter) = trace_to_parameter(input_parameters, rhs) else {
+            return vec![];
+        };
+        (parameter, lhs, swap_predicate(predicate))
+    } else {
+        return vec![];
+    };
+
+    let constant = match predicate {
+        IntPredicate::UGE
+        | IntPredicate::UGT
+        | IntPredicate::ULE
+        | IntPredicate::ULT => constant
+            .get_zero_extended_constant()
+            .map(|constant| constant.min(i64::MAX as u64) as i64),
+        _ => constant.get_sign_extended_constant(),
+    };
+    let Some(constant) = constant else {
+        return vec![];
+    };
+
+    match predicate {
+        IntPredicate::SGE | IntPredicate::UGE => {
+            vec![AssumedInequality::LowerInclusive(parameter, constant)]
+        }
+        IntPredicate::SGT | IntPredicate::UGT => {
+            vec![AssumedInequality::LowerInclusive(
+                parameter,
+                constant.saturating_add(1),
+            )]
+        }
+        IntPredicate::SLT | IntPredicate::ULT => {
+            vec![AssumedInequality::UpperExclusive(parameter, constant)]
+        }
+        IntPredicate::SLE | IntPredicate::ULE => {
+            vec![AssumedInequality::UpperExclusive(
+                parameter,
+                constant.saturating_add(1),
+            )]
+        }
+        IntPredicate::EQ => vec![
+            AssumedInequality::LowerInclusive(parameter, constant),
+            AssumedInequality::UpperExclusive(
+                parameter,
+                constant.saturating_add(1),
+            ),
+        ],
+        IntPredicate::NE => vec![],
+    }
+}
+
+/// The bounds on a parameter (see [`trace_to_parameter`]) implied by
+/// `switch` on it when its default destination is unreachable, as for a
+/// `switch` over every value of an enum, since then the parameter must equal
+/// one of the cases.
+fn parse_exhaustive_switch<'a>(
+    input_parameters: &[IntValue<'a>],
+    switch: InstructionValue<'a>,
+) -> Vec<AssumedInequality<'a>> {
+    if switch.get_opcode() != InstructionOpcode::Switch {
+        return vec![];
+    }
+    let default_is_unreachable = switch
+        .get_operand(1)
+        .and_then(|operand| operand.right())
+        .and_then(|default| default.get_first_instruction())
+        .is_some_and(|instruction| {
+            instruction.get_opcode() == InstructionOpcode::Unreachable
+        });
+    let parameter = switch
+        .get_operand(0)
+        .and_then(|operand| operand.left())
+        .filter(|condition| condition.is_int_value())
+        .and_then(|condition| {
+            trace_to_parameter(input_parameters, condition.into_int_value())
+        });
+    let Some(parameter) = parameter.filter(|_| default_is_unreachable) else {
+        return vec![];
+    };
+
+    // the operands after the condition and default are pairs of a case and
+    // its destination
+    let cases = (2..switch.get_num_operands())
+        .step_by(2)
+        .filter_map(|index| {
+            switch
+                .get_operand(index)?
+                .left()?
+                .into_int_value()
+                .get_sign_extended_constant()
+        })
+        .collect::<Vec<_>>();
+    let (Some(smallest), Some(largest)) =
+        (cases.iter().min(), cases.iter().max())
+    else {
+        return vec![];
+    };
+    vec![
+        AssumedInequality::LowerInclusive(parameter, *smallest),
+        AssumedInequality::UpperExclusive(parameter, largest.saturating_add(1)),
+    ]
+}
+
+/// Whether `value` is a constant, e.g., a literal, `null`, or a global.
+fn is_constant(value: BasicValueEnum) -> bool {
+    match value {
+        BasicValueEnum::IntValue(int_value) => int_value.is_const(),
+        BasicValueEnum::FloatValue(float_value) => float_value.is_const(),
+        BasicValueEnum::PointerValue(pointer_value) => pointer_value.is_const(),
+        _ => false,
+    }
+}
+
+/// The comparisons for equality against a constant that are the only uses of
+/// `parameter` in `function`, each with the index of the operand that is
+/// `parameter`. Like [`trace_to_parameter`], this looks through clang's spill
+/// of the parameter to the stack, provided the stack slot is only stored to
+/// once and is otherwise only loaded from. Returns `None` if `parameter` is
+/// used any other way, so the result of `function` may depend on more than
+/// the outcomes of these comparisons.
+fn find_equality_comparisons<'a>(
+    function: FunctionValue<'a>,
+    parameter: BasicValueEnum<'a>,
+) -> Option<Vec<(InstructionValue<'a>, u32)>> {
+    let instructions = function
+        .get_basic_block_iter()
+        .flat_map(|basic_block| basic_block.get_instructions())
+        .collect::<Vec<_>>();
+    let operands = |instruction: InstructionValue<'a>| {
+        (0..instruction.get_num_operands()).filter_map(move |index| {
+            Some((index, instruction.get_operand(index)?.left()?))
+        })
+    };
+
+    let stack_slots = instructions
+        .iter()
+        .filter(|instruction| {
+            instruction.get_opcode() == InstructionOpcode::Store
+                && instruction
+                    .get_operand(0)
+                    .and_then(|operand| operand.left())
+                    == Some(parameter)
+        })
+        .filter_map(|store| store.get_operand(1)?.left())
+        .filter(|pointer| {
+            pointer.as_instruction_value().is_some_and(|allocation| {
+                allocation.get_opcode() == InstructionOpcode::Alloca
+            })
+        })
+        .collect::<HashSet<_>>();
+    let spill_count = instructions
+        .iter()
+        .filter(|instruction| {
+            instruction.get_opcode() == InstructionOpcode::Store
+                && instruction
+                    .get_operand(1)
+                    .and_then(|operand| operand.left())
+                    .is_some_and(|pointer| stack_slots.contains(&pointer))
+        })
+        .count();
+    if spill_count != stack_slots.len() {
+        return None;
+    }
+
+    let mut copies = HashSet::from([parameter]);
+    for instruction in &instructions {
+        for (index, operand) in operands(*instruction) {
+            if !stack_slots.contains(&operand) {
+                continue;
+            }
+            match instruction.get_opcode() {
+                InstructionOpcode::Load => {
+                    copies.insert(
+                        instruction.as_any_value_enum().try_into().ok()?,
+                    );
+                }
+                InstructionOpcode::Store if index == 1 => {}
+                _ => return None,
+            }
+        }
+    }
+
+    let mut comparisons = vec![];
+    for instruction in &instructions {
+        for (index, operand) in operands(*instruction) {
+            if !copies.contains(&operand) {
+                continue;
+            }
+            match instruction.get_opcode() {
+                InstructionOpcode::Store if index == 0 => {
+                    let pointer = instruction.get_operand(1)?.left()?;
+                    if !stack_slots.contains(&pointer) {
+                        return None;
+                    }
+                }
+                InstructionOpcode::ICmp | InstructionOpcode::FCmp => {
+                    let is_equality = matches!(
+                        instruction.get_icmp_predicate(),
+                        Some(IntPredicate::EQ | IntPredicate::NE)
+                    ) || matches!(
+                        instruction.get_fcmp_predicate(),
+                        Some(
+                            FloatPredicate::OEQ
+                                | FloatPredicate::ONE
+                                | FloatPredicate::UEQ
+                                | FloatPredicate::UNE
+                        )
+                    );
+                    let other_operand =
+                        instruction.get_operand(1 - index)?.left()?;
+                    if !is_equality
+                        || !is_constant(other_operand)
+                        || !instruction.get_type().is_int_type()
+                    {
+                        return None;
+                    }
+                    comparisons.push((*instruction, index));
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(comparisons)
 }
 
 // Annoyingly, these are member functions because it is more convenient to store
@@ -173,7 +906,95 @@ enum AssumedInequality<'a> {
 // style, I'm making other helper functions take `&self` even though I'd prefer
 // them to be plain functions.
 impl AutoMemoizePass {
+    /// Parses `auto-memoize`, optionally with parameters separated by
+    /// semicolons as in `auto-memoize<all;max-entries=4096;default-range=256>`,
+    /// and optionally followed by the modes `:verbose` and `:profile`. See the
+    /// fields of [`AutoMemoizePass`] and [`Eviction::parse`] for the
+    /// parameters.
+    fn parse(name: &str) -> Option<Self> {
+        let mut modes = name.split(':');
+        let parameters = modes.next()?.strip_prefix("auto-memoize")?;
+
+        let mut pass = Self::default();
+        for mode in modes {
+            match mode {
+                "verbose" => {
+                    // another pass may have installed it already
+                    let _ =
+                        pass_trace::init(&["auto-memoize=debug".to_string()]);
+                }
+                "profile" => pass.profile = true,
+                _ => return None,
+            }
+        }
+        if !parameters.is_empty() {
+            let parameters = parameters.strip_prefix('<')?.strip_suffix('>')?;
+            for parameter in parameters.split(';') {
+                match parameter.split_once('=') {
+                    None if parameter == "all" => pass.memoize_all = true,
+                    Some(("max-entries", value)) => {
+                        pass.max_entries =
+                            value.parse().ok().filter(|value| *value > 0)?;
+                    }
+                    Some(("default-range", value)) => {
+                        pass.default_range = Some(value.parse().ok()?);
+                    }
+                    Some(("eviction", value)) => {
+                        pass.eviction = Eviction::parse(value)?;
+                    }
+                    Some(("alignment", value)) => {
+                        pass.alignment = value
+                            .parse()
+                            .ok()
+                            .filter(|value: &u32| value.is_power_of_two())?;
+                    }
+                    _ => return None,
+                }
+            }
+        }
+        Some(pass)
+    }
+
+    /// Whether `function` was selected for memoization by its annotations.
+    fn is_selected<'a>(
+        &self,
+        module: &Module<'a>,
+        function: FunctionValue<'a>,
+    ) -> bool {
+        let annotations = get_annotations(module, function);
+        if annotations.contains(NO_MEMOIZE_ANNOTATION) {
+            tracing::info!(
+                target: "auto-memoize",
+                "Skipping memoization for {:?} because it is annotated `{NO_MEMOIZE_ANNOTATION}`",
+                function.get_name()
+            );
+            false
+        } else if !self.memoize_all && !annotations.contains(MEMOIZE_ANNOTATION)
+        {
+            tracing::info!(
+                target: "auto-memoize",
+                "Skipping memoization for {:?} because it is not annotated `{MEMOIZE_ANNOTATION}`",
+                function.get_name()
+            );
+            false
+        } else {
+            true
+        }
+    }
+
     const TYPICAL_PAGE_SIZE: u32 = 4096;
+    const DEFAULT_MAX_ENTRIES: u32 = 4096;
+    const MEMOIZABLE_PARAMETER_WIDTHS: [u32; 4] = [8, 16, 32, 64];
+    const MAX_STRUCT_RETURN_FIELDS: u32 = 4;
+    /// Calls answered from the cache.
+    const HITS_COUNTER: u64 = 0;
+    /// Calls whose result was computed and cached.
+    const MISSES_COUNTER: u64 = 1;
+    /// Calls whose result could not be cached, because the parameters were
+    /// out of bounds or the hash table probe gave up.
+    const OUT_OF_BOUNDS_COUNTER: u64 = 2;
+    const STATISTICS_COUNTERS: u32 = 3;
+    const HASH_TABLE_MAX_PROBES: u64 = 8;
 
     fn construct_memoization_bounds<'a>(
         &self,
@@ -183,189 +1004,83 @@ impl AutoMemoizePass {
     ) -> MemoizationBounds<'a> {
         let bool_type = context.bool_type();
 
-        // LLVM generates code like this for a __builtin_assume with a parameter
-        // in a constant comparison right at the start of the function:
-        //
-        // %2 = alloca i32, align 4
-        // store i32 %0, ptr %2, align 4
-        // %3 = load i32, ptr %2, align 4
-        // %4 = icmp sge i32 %3, 0
-        // call void @llvm.assume(i1 %4)
-        //
-        // Thus we will try to infer the bounds on the variable by pattern
-        // matching for this kind of code. This is likely unsustainable for
-        // future LLVM versions.
-
-        let assumed_inqualities = old_entry_block
+        // Each `__builtin_assume` becomes a call to `llvm.assume` on a
+        // comparison, so we look for comparisons between a constant and
+        // something that traces back to a parameter (see
+        // `trace_to_parameter`). The entry block always runs, so a `switch`
+        // ending it that covers every possible value bounds its condition too.
+
+        let mut lower_bounds = HashMap::<IntValue, i64>::new();
+        let mut upper_bounds = HashMap::<IntValue, i64>::new();
+
+        let assumptions = old_entry_block
             .get_instructions()
-            .filter_map(|instruction| {
-                if instruction.get_opcode() == InstructionOpcode::Call
-                    && get_callee_of_known_call(instruction).as_deref()
+            .filter(|instruction| {
+                instruction.get_opcode() == InstructionOpcode::Call
+                    && get_callee_of_known_call(*instruction).as_deref()
                         == Some(LLVM_BUILTIN_ASSUME)
-                {
-                    let assumption = instruction
-                        .get_operand(0)
-                        .unwrap()
-                        .unwrap_left()
-                        .into_int_value();
-                    Some(assumption)
-                } else {
-                    None
-                }
             })
-            .filter(|assumption| assumption.get_type() == bool_type)
-            .filter_map(|assumption| assumption.as_instruction_value())
-            .filter(|assumption| {
-                assumption.get_opcode() == InstructionOpcode::ICmp
+            .filter_map(|call| call.get_operand(0)?.left())
+            .filter(|condition| {
+                condition.is_int_value()
+                    && condition.into_int_value().get_type() == bool_type
             })
-            .filter_map(|assumption| {
-                let lhs = assumption
-                    .get_operand(0)
-                    .unwrap()
-                    .unwrap_left()
-                    .into_int_value()
-                    .as_instruction_value()?;
-                let const_bound = assumption
-                    .get_operand(1)
-                    .unwrap()
-                    .unwrap_left()
-                    .into_int_value()
-                    .get_zero_extended_constant()?
-                    as u32;
-                assumption.get_icmp_predicate().and_then(|predicate| {
-                    match predicate {
-                        IntPredicate::SGE => Some(
-                            AssumedInequality::LowerInclusive(lhs, const_bound),
-                        ),
-                        IntPredicate::SLT => Some(
-                            AssumedInequality::UpperExclusive(lhs, const_bound),
-                        ),
-                        _ => None,
-                    }
-                })
+            .filter_map(|condition| condition.as_instruction_value());
+
+        let inequalities = assumptions
+            .flat_map(|assumption| {
+                parse_assumption(&input_parameters, assumption)
             })
-            .inspect(|inequality| match &inequality {
-                AssumedInequality::LowerInclusive(lhs, const_bound) => {
-                    local_log!(
-                        self,
-                        "  [auto-memoize] Derived potentially useful inequality ({lhs}) >= {const_bound}",
+            .chain(
+                old_entry_block
+                    .get_terminator()
+                    .map(|terminator| {
+                        parse_exhaustive_switch(&input_parameters, terminator)
+                    })
+                    .unwrap_or_default(),
+            );
+
+        for inequality in inequalities {
+            match inequality {
+                AssumedInequality::LowerInclusive(parameter, bound) => {
+                    tracing::debug!(
+                        target: "auto-memoize",
+                        "Derived parameter bound ({parameter}) >= {bound}"
                     );
+                    let current_lower_bound =
+                        lower_bounds.entry(parameter).or_insert(bound);
+                    *current_lower_bound = (*current_lower_bound).max(bound);
                 }
-                AssumedInequality::UpperExclusive(lhs, const_bound) => {
-                    local_log!(
-                        self,
-                        "  [auto-memoize] Derived potentially useful inequality ({lhs}) < {const_bound}",
+                AssumedInequality::UpperExclusive(parameter, bound) => {
+                    tracing::debug!(
+                        target: "auto-memoize",
+                        "Derived parameter bound ({parameter}) < {bound}"
                     );
-                }
-            });
-
-        /// Hacky way to try to determine the parameter from something directly
-        /// and not far off from it (`close_enough`).
-        fn determine_parameter_source<'a>(
-            input_parameters: &[IntValue<'a>],
-            parameter_source_cache: &mut HashMap<
-                InstructionValue<'a>,
-                IntValue<'a>,
-            >,
-            close_enough: InstructionValue<'a>,
-        ) -> Option<IntValue<'a>> {
-            if close_enough.get_opcode() == InstructionOpcode::Load {
-                let read_from = close_enough
-                    .get_operand(0)
-                    .unwrap()
-                    .unwrap_left()
-                    .as_instruction_value()?;
-
-                if let Some(cached_parameter) =
-                    parameter_source_cache.get(&read_from)
-                {
-                    return Some(*cached_parameter);
-                }
-
-                if let Some(pre_load) = close_enough.get_previous_instruction()
-                {
-                    if pre_load.get_opcode() == InstructionOpcode::Store
-                        && pre_load
-                            .get_operand(1)
-                            .unwrap()
-                            .unwrap_left()
-                            .as_instruction_value()?
-                            == read_from
-                    {
-                        let potential_parameter = pre_load
-                            .get_operand(0)
-                            .unwrap()
-                            .unwrap_left()
-                            .into_int_value();
-                        if input_parameters.contains(&potential_parameter) {
-                            parameter_source_cache
-                                .insert(read_from, potential_parameter);
-                            return Some(potential_parameter);
-                        }
-                    }
+                    let current_upper_bound =
+                        upper_bounds.entry(parameter).or_insert(bound);
+                    *current_upper_bound = (*current_upper_bound).min(bound);
                 }
             }
-
-            None
         }
 
-        let mut lower_bounds = HashMap::new();
-        let mut upper_bounds = HashMap::new();
-        let mut parameter_source_cache = HashMap::new();
-
-        for inequality in assumed_inqualities {
-            match inequality {
-                AssumedInequality::LowerInclusive(lhs, const_bound) => {
-                    if let Some(parameter) = determine_parameter_source(
-                        &input_parameters,
-                        &mut parameter_source_cache,
-                        lhs,
-                    ) {
-                        local_log!(
-                            self,
-                            "  [auto-memoize] Confirmed parameter bound ({parameter}) >= {const_bound}"
-                        );
-                        let current_lower_bound =
-                            lower_bounds.entry(parameter).or_insert(0);
-                        if const_bound > *current_lower_bound {
-                            *current_lower_bound = const_bound;
-                        }
-                    }
-                }
-                AssumedInequality::UpperExclusive(lhs, const_bound) => {
-                    if let Some(parameter) = determine_parameter_source(
-                        &input_parameters,
-                        &mut parameter_source_cache,
-                        lhs,
-                    ) {
-                        local_log!(
-                            self,
-                            "  [auto-memoize] Confirmed parameter bound ({parameter}) < {const_bound}"
-                        );
-                        let current_upper_bound =
-                            upper_bounds.entry(parameter).or_insert(u32::MAX);
-                        if const_bound < *current_upper_bound {
-                            *current_upper_bound = const_bound;
-                        }
-                    }
-                }
-            }
-        }
+        // cached ranges are nonnegative, and values outside them are checked
+        // for at runtime, so clamping is always sound
+        let clamp = |bound: i64| bound.clamp(0, u32::MAX as i64) as u32;
 
         let mut parameters = SlotMap::<ParameterKey, _>::with_key();
         let mut cached_ranges = SecondaryMap::new();
         for input_parameter in input_parameters {
             let parameter_key = parameters.insert(input_parameter);
             let lower_bound =
-                lower_bounds.get(&input_parameter).copied().unwrap_or(0);
+                lower_bounds.get(&input_parameter).copied().map_or(0, clamp);
             let upper_bound = upper_bounds
                 .get(&input_parameter)
                 .copied()
-                .and_then(
-                    |value| if value == u32::MAX { None } else { Some(value) },
-                )
-                .unwrap_or(64);
-            cached_ranges.insert(parameter_key, lower_bound..upper_bound);
+                .map(clamp)
+                .or(self.default_range);
+            if let Some(upper_bound) = upper_bound {
+                cached_ranges.insert(parameter_key, lower_bound..upper_bound);
+            }
         }
         MemoizationBounds {
             parameters,
@@ -397,53 +1112,180 @@ impl AutoMemoizePass {
         global
     }
 
-    fn create_memoization_globals<'a>(
+    /// Whether values of `return_type` can be cached: scalars, or small structs
+    /// of scalars.
+    fn is_memoizable_return_type(return_type: BasicTypeEnum) -> bool {
+        fn is_scalar(ty: BasicTypeEnum) -> bool {
+            matches!(
+                ty,
+                BasicTypeEnum::IntType(_)
+                    | BasicTypeEnum::FloatType(_)
+                    | BasicTypeEnum::PointerType(_)
+            )
+        }
+
+        match return_type {
+            BasicTypeEnum::StructType(struct_type) => {
+                struct_type.count_fields() <= Self::MAX_STRUCT_RETURN_FIELDS
+                    && struct_type.get_field_types().into_iter().all(is_scalar)
+            }
+            _ => is_scalar(return_type),
+        }
+    }
+
+    fn create_value_arrays<'a>(
         &self,
         module: &Module<'a>,
-        context: ContextRef<'a>,
         function: FunctionValue<'a>,
         return_type: BasicTypeEnum<'a>,
-        flattened_array_length: u32,
-    ) -> MemoizationGlobals<'a> {
-        let value_array_type = return_type.array_type(flattened_array_length);
-
-        let value_array = self.add_static(
-            module,
-            function,
-            value_array_type,
-            "memo_value_array",
-            Self::TYPICAL_PAGE_SIZE,
-        );
-        // safety: elements of values are same type as return type
-        let zero_initialized_const_array = unsafe {
-            ArrayValue::new_const_array(
-                &return_type,
-                &vec![
-                    return_type.const_zero();
-                    flattened_array_length as usize
-                ],
-            )
+        length: u32,
+    ) -> ValueArrays<'a> {
+        let fields = match return_type {
+            BasicTypeEnum::StructType(struct_type) => struct_type
+                .get_field_types()
+                .into_iter()
+                .enumerate()
+                .map(|(i, field_type)| {
+                    (field_type, format!("memo_value_array.{i}"))
+                })
+                .collect(),
+            _ => vec![(return_type, "memo_value_array".to_string())],
         };
-        value_array.set_initializer(&zero_initialized_const_array);
 
-        let bool_type = context.bool_type();
-        let ready_array_type = bool_type.array_type(flattened_array_length);
+        let fields = fields
+            .into_iter()
+            .map(|(field_type, name)| {
+                let value_array_type = field_type.array_type(length);
+                let value_array = self.add_static(
+                    module,
+                    function,
+                    value_array_type,
+                    name,
+                    self.alignment,
+                );
+                value_array.set_initializer(&value_array_type.const_zero());
+                (value_array_type, value_array)
+            })
+            .collect();
 
-        let ready_array = self.add_static(
-            module,
-            function,
-            ready_array_type,
-            "memo_ready_array",
-            Self::TYPICAL_PAGE_SIZE,
-        );
-        ready_array.set_initializer(&bool_type.const_array(&vec![
+        ValueArrays {
+            return_type,
+            fields,
+        }
+    }
+
+    /// Loads the cached return value at `index` in `value_arrays`.
+    fn build_load_cached_value<'a>(
+        &self,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        value_arrays: &ValueArrays<'a>,
+        index: IntValue<'a>,
+    ) -> BasicValueEnum<'a> {
+        let fields = value_arrays
+            .fields
+            .iter()
+            .map(|(value_array_type, value_array)| {
+                let value_pointer = self.build_pointer_for_array_index(
+                    context,
+                    builder,
+                    *value_array_type,
+                    *value_array,
+                    index,
+                    "value_pointer",
+                );
+                builder
+                    .build_load(
+                        value_array_type.get_element_type(),
+                        value_pointer,
+                        "memo_value",
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        match value_arrays.return_type {
+            BasicTypeEnum::StructType(struct_type) => {
+                let mut cached_value = struct_type.get_undef();
+                for (i, field) in fields.into_iter().enumerate() {
+                    cached_value = builder
+                        .build_insert_value(
+                            cached_value,
+                            field,
+                            i as u32,
+                            "memo_value",
+                        )
+                        .unwrap()
+                        .into_struct_value();
+                }
+                cached_value.as_basic_value_enum()
+            }
+            _ => fields[0],
+        }
+    }
+
+    /// Stores `value` to be cached at `index` in `value_arrays`.
+    fn build_store_cached_value<'a>(
+        &self,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        value_arrays: &ValueArrays<'a>,
+        index: IntValue<'a>,
+        value: BasicValueEnum<'a>,
+    ) {
+        for (i, (value_array_type, value_array)) in
+            value_arrays.fields.iter().enumerate()
+        {
+            let value_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                *value_array_type,
+                *value_array,
+                index,
+                "value_pointer",
+            );
+            let field = match value {
+                BasicValueEnum::StructValue(struct_value) => builder
+                    .build_extract_value(struct_value, i as u32, "field")
+                    .unwrap(),
+                _ => value,
+            };
+            builder.build_store(value_pointer, field).unwrap();
+        }
+    }
+
+    fn create_memoization_globals<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        function: FunctionValue<'a>,
+        return_type: BasicTypeEnum<'a>,
+        flattened_array_length: u32,
+    ) -> MemoizationGlobals<'a> {
+        let value_arrays = self.create_value_arrays(
+            module,
+            function,
+            return_type,
+            flattened_array_length,
+        );
+
+        let bool_type = context.bool_type();
+        let ready_array_type = bool_type.array_type(flattened_array_length);
+
+        let ready_array = self.add_static(
+            module,
+            function,
+            ready_array_type,
+            "memo_ready_array",
+            self.alignment,
+        );
+        ready_array.set_initializer(&bool_type.const_array(&vec![
                 bool_type.const_int(0, false);
                 flattened_array_length as usize
             ]));
 
         MemoizationGlobals {
-            value_array_type,
-            value_array,
+            value_arrays,
             ready_array_type,
             ready_array,
         }
@@ -476,6 +1318,8 @@ impl AutoMemoizePass {
             context.append_basic_block(function, "memo_always_return");
         always_return_block.move_before(old_entry_block).unwrap();
 
+        let return_block = context.append_basic_block(function, "memo_return");
+
         RelevantBlocks {
             old_entry_block,
             header_block,
@@ -483,34 +1327,49 @@ impl AutoMemoizePass {
             fast_path_block,
             cache_and_return_block,
             always_return_block,
+            return_block,
         }
     }
 
+    /// Computes the index of the parameters into the memoization arrays by
+    /// packing the offset of each into its own bits (see
+    /// [`MemoizationBounds::index_bits`]). Parameters may have different
+    /// widths, so each is sign-extended to `i64` before being combined.
     fn build_flattened_index_from_parameters<'a>(
         &self,
         context: ContextRef<'a>,
         builder: &Builder<'a>,
         bounds: &MemoizationBounds<'a>,
     ) -> IntValue<'a> {
-        let i32_type = context.i32_type();
-
-        let mut flattened_index = i32_type.const_int(0, false);
+        let i64_type = context.i64_type();
 
-        let mut cached_ranges_iter = bounds.cached_ranges.values();
-        for (i, parameter) in bounds.parameters.values().copied().enumerate() {
-            if i > 0 {
-                let width = i32_type.const_int(
-                    cached_ranges_iter.next().unwrap().end as u64,
-                    false,
-                );
+        let mut flattened_index = i64_type.const_int(0, false);
 
-                flattened_index = builder
-                    .build_int_mul(flattened_index, width, "flattened_index")
-                    .unwrap();
-            }
+        for (key, parameter) in &bounds.parameters {
+            let bits = i64_type.const_int(bounds.index_bits(key) as u64, false);
+            flattened_index = builder
+                .build_left_shift(flattened_index, bits, "flattened_index")
+                .unwrap();
 
+            let extended_parameter = builder
+                .build_int_s_extend_or_bit_cast(
+                    *parameter,
+                    i64_type,
+                    "extended_parameter",
+                )
+                .unwrap();
+            let offset = builder
+                .build_int_sub(
+                    extended_parameter,
+                    i64_type.const_int(
+                        bounds.cached_ranges[key].start as u64,
+                        false,
+                    ),
+                    "offset",
+                )
+                .unwrap();
             flattened_index = builder
-                .build_int_add(flattened_index, parameter, "flattened_index")
+                .build_or(flattened_index, offset, "flattened_index")
                 .unwrap();
         }
 
@@ -537,22 +1396,31 @@ impl AutoMemoizePass {
         .unwrap()
     }
 
+    /// Checks that each parameter lies within its cached range. The bounds may
+    /// not fit in a narrow parameter type, like `i8`, so each parameter is
+    /// sign-extended to `i64` and compared there.
     fn build_checks_for_within_memoization_bounds<'a>(
         &self,
         context: ContextRef<'a>,
         builder: &Builder<'a>,
         bounds: &MemoizationBounds<'a>,
     ) -> impl Iterator<Item = IntValue<'a>> {
-        let i32_type = context.i32_type();
-
+        let i64_type = context.i64_type();
         bounds.parameters.iter().map(move |(key, parameter)| {
+            let extended_parameter = builder
+                .build_int_s_extend_or_bit_cast(
+                    *parameter,
+                    i64_type,
+                    "extended_parameter",
+                )
+                .unwrap();
             // TODO: figure out how to make this work without fixing
             // "signed"
             let lower_bound_check = builder
                 .build_int_compare(
                     IntPredicate::SGE,
-                    *parameter,
-                    i32_type.const_int(
+                    extended_parameter,
+                    i64_type.const_int(
                         bounds.cached_ranges[key].start as u64,
                         false,
                     ),
@@ -562,8 +1430,8 @@ impl AutoMemoizePass {
             let upper_bound_check = builder
                 .build_int_compare(
                     IntPredicate::SLT,
-                    *parameter,
-                    i32_type
+                    extended_parameter,
+                    i64_type
                         .const_int(bounds.cached_ranges[key].end as u64, false),
                     "",
                 )
@@ -574,82 +1442,33 @@ impl AutoMemoizePass {
         })
     }
 
-    fn maybe_memoize<'a>(
+    /// Caches results in arrays indexed directly by the parameters, which must
+    /// lie within `bounds` for the result to be cached. Stores whether to cache
+    /// the result to `should_cache_indirect` and fills in the lookup blocks and
+    /// the block that caches the return value.
+    fn build_array_lookup<'a>(
         &self,
         module: &Module<'a>,
         context: ContextRef<'a>,
         builder: &Builder<'a>,
         function: FunctionValue<'a>,
+        bounds: &MemoizationBounds<'a>,
+        memoized: &MemoizedFunction<'a>,
     ) {
-        let i32_type = context.i32_type();
+        let &MemoizedFunction {
+            ref blocks,
+            return_type,
+            return_value,
+            should_cache_indirect,
+        } = memoized;
         let bool_type = context.bool_type();
 
-        let Some(return_type) = function.get_type().get_return_type() else {
-            local_log!(
-                self,
-                "[auto-memoize] Skipping memoization for {:?} because it does not have a return type, so it's a pure function without a return...",
-                function.get_name()
-            );
-            return;
-        };
-
-        let Some(int_parameters) = function
-            .get_params()
-            .into_iter()
-            .map(|parameter| match parameter {
-                BasicValueEnum::IntValue(int_value) => {
-                    if int_value.get_type() == i32_type {
-                        Some(int_value)
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
-            })
-            .collect::<Option<Vec<_>>>()
-        else {
-            local_log!(
-                self,
-                "[auto-memoize] Skipping memoization for {:?} because it does not only have integer (that is, LLVM i32) parameters",
-                function.get_name()
-            );
-            return;
-        };
-        if int_parameters.len() > 3 {
-            local_log!(
-                self,
-                "[auto-memoize] Skipping memoization for {:?} because it has more than 3 integer parameters",
-                function.get_name()
-            );
-            return;
-        }
-
-        local_log!(self, "[auto-memoize] Memoizing {:?}", function.get_name());
-
-        let RelevantBlocks {
-            old_entry_block,
-            header_block,
-            check_if_ready_block,
-            fast_path_block,
-            cache_and_return_block,
-            always_return_block,
-        } = self.insert_memoization_basic_blocks(context, function);
-
-        let bounds = self.construct_memoization_bounds(
-            context,
-            int_parameters,
-            old_entry_block,
-        );
-
-        let flattened_array_length: u32 = bounds
-            .cached_ranges
-            .values()
-            .map(|range| range.end)
-            .product();
+        let flattened_array_length = bounds
+            .flattened_array_length()
+            .expect("array lookup requires every parameter to be bounded");
 
         let MemoizationGlobals {
-            value_array_type,
-            value_array,
+            value_arrays,
             ready_array_type,
             ready_array,
         } = self.create_memoization_globals(
@@ -660,14 +1479,14 @@ impl AutoMemoizePass {
             flattened_array_length,
         );
 
-        builder.position_at_end(header_block);
+        builder.position_at_end(blocks.header_block);
 
         let flattened_index = self
-            .build_flattened_index_from_parameters(context, builder, &bounds);
+            .build_flattened_index_from_parameters(context, builder, bounds);
 
         let memoization_bounds_checks = self
             .build_checks_for_within_memoization_bounds(
-                context, builder, &bounds,
+                context, builder, bounds,
             );
 
         let ready_pointer = self.build_pointer_for_array_index(
@@ -678,18 +1497,6 @@ impl AutoMemoizePass {
             flattened_index,
             "ready_pointer",
         );
-        let value_pointer = self.build_pointer_for_array_index(
-            context,
-            builder,
-            value_array_type,
-            value_array,
-            flattened_index,
-            "value_pointer",
-        );
-
-        let return_value_indirect = builder
-            .build_alloca(return_type, "return_value_indirect")
-            .unwrap();
 
         let mut parameters_in_bounds = bool_type.const_int(1, false);
         for condition in memoization_bounds_checks {
@@ -701,16 +1508,19 @@ impl AutoMemoizePass {
                 )
                 .unwrap();
         }
+        builder
+            .build_store(should_cache_indirect, parameters_in_bounds)
+            .unwrap();
 
         let _ = builder
             .build_conditional_branch(
                 parameters_in_bounds,
-                check_if_ready_block,
-                old_entry_block,
+                blocks.check_if_ready_block,
+                blocks.old_entry_block,
             )
             .unwrap();
 
-        builder.position_at_end(check_if_ready_block);
+        builder.position_at_end(blocks.check_if_ready_block);
 
         let is_ready = builder
             .build_load(context.bool_type(), ready_pointer, "is_ready")
@@ -723,86 +1533,965 @@ impl AutoMemoizePass {
         let _ = builder
             .build_conditional_branch(
                 can_memoize,
-                fast_path_block,
-                old_entry_block,
+                blocks.fast_path_block,
+                blocks.old_entry_block,
             )
             .unwrap();
 
-        builder.position_at_end(fast_path_block);
+        builder.position_at_end(blocks.fast_path_block);
 
-        let cached_value = builder
-            .build_load(i32_type, value_pointer, "memo_value")
-            .unwrap();
+        let cached_value = self.build_load_cached_value(
+            context,
+            builder,
+            &value_arrays,
+            flattened_index,
+        );
 
         builder.build_return(Some(&cached_value)).unwrap();
 
-        builder.position_at_end(cache_and_return_block);
+        builder.position_at_end(blocks.cache_and_return_block);
 
-        let loaded_return_value = builder
-            .build_load(
-                return_type,
-                return_value_indirect,
-                "loaded_return_value",
+        let return_value = return_value.as_basic_value();
+        self.build_store_cached_value(
+            context,
+            builder,
+            &value_arrays,
+            flattened_index,
+            return_value,
+        );
+        let _ = builder
+            .build_store(ready_pointer, bool_type.const_int(1, false))
+            .unwrap();
+
+        let _ = builder.build_return(Some(&return_value)).unwrap();
+    }
+
+    /// The number of slots in a hash table, which is the largest power of two
+    /// that is at most the maximum number of entries, so that slots can be
+    /// computed by masking.
+    fn hash_table_size(&self) -> u64 {
+        1 << self.max_entries.ilog2()
+    }
+
+    /// Hashes the full tuple of parameters into a slot of the hash table.
+    fn build_hash_from_parameters<'a>(
+        &self,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        bounds: &MemoizationBounds<'a>,
+    ) -> IntValue<'a> {
+        let i64_type = context.i64_type();
+
+        // FNV-1a over the parameters, each widened to 64 bits
+        let mut hash = i64_type.const_int(0xcbf29ce484222325, false);
+        for parameter in bounds.parameters.values() {
+            let extended_parameter = builder
+                .build_int_s_extend_or_bit_cast(
+                    *parameter,
+                    i64_type,
+                    "extended_parameter",
+                )
+                .unwrap();
+            hash = builder.build_xor(hash, extended_parameter, "hash").unwrap();
+            hash = builder
+                .build_int_mul(
+                    hash,
+                    i64_type.const_int(0x100000001b3, false),
+                    "hash",
+                )
+                .unwrap();
+        }
+
+        // the low bits of the product depend only on the low bits of the
+        // parameters, so fold the high bits in before masking
+        let high_bits = builder
+            .build_right_shift(
+                hash,
+                i64_type.const_int(32, false),
+                false,
+                "high_bits",
+            )
+            .unwrap();
+        hash = builder.build_xor(hash, high_bits, "hash").unwrap();
+        builder
+            .build_and(
+                hash,
+                i64_type.const_int(self.hash_table_size() - 1, false),
+                "hash_slot",
+            )
+            .unwrap()
+    }
+
+    /// Caches results in an open-addressing hash table keyed on all the
+    /// parameters, for when there are no bounds to index arrays by. A lookup
+    /// probes linearly through the window of slots the tuple can be cached in
+    /// (see [`Eviction::window_size`]), and if they are all taken, either
+    /// evicts one or gives up on caching according to [`Self::eviction`].
+    /// Stores whether to cache the result to `should_cache_indirect` and fills
+    /// in the lookup blocks and the block that caches the return value.
+    fn build_hash_table_lookup<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        function: FunctionValue<'a>,
+        bounds: &MemoizationBounds<'a>,
+        memoized: &MemoizedFunction<'a>,
+    ) {
+        let &MemoizedFunction {
+            ref blocks,
+            return_type,
+            return_value,
+            should_cache_indirect,
+        } = memoized;
+        let i64_type = context.i64_type();
+        let bool_type = context.bool_type();
+        let table_size = self.hash_table_size() as u32;
+
+        let key_arrays = bounds
+            .parameters
+            .iter()
+            .enumerate()
+            .map(|(i, (key, parameter))| {
+                let key_array_type =
+                    parameter.get_type().array_type(table_size);
+                let key_array = self.add_static(
+                    module,
+                    function,
+                    key_array_type,
+                    format!("memo_key_array.{i}"),
+                    self.alignment,
+                );
+                key_array.set_initializer(&key_array_type.const_zero());
+                (key, (key_array_type, key_array))
+            })
+            .collect::<SecondaryMap<_, _>>();
+
+        let value_arrays =
+            self.create_value_arrays(module, function, return_type, table_size);
+
+        let occupied_array_type = bool_type.array_type(table_size);
+        let occupied_array = self.add_static(
+            module,
+            function,
+            occupied_array_type,
+            "memo_occupied_array",
+            self.alignment,
+        );
+        occupied_array.set_initializer(&occupied_array_type.const_zero());
+
+        let reference_array_type = bool_type.array_type(table_size);
+        let reference_array = self.eviction.uses_reference_bits().then(|| {
+            let reference_array = self.add_static(
+                module,
+                function,
+                reference_array_type,
+                "memo_reference_array",
+                self.alignment,
+            );
+            reference_array.set_initializer(&reference_array_type.const_zero());
+            reference_array
+        });
+        let window_size = self
+            .eviction
+            .window_size(Self::HASH_TABLE_MAX_PROBES)
+            .min(self.hash_table_size());
+
+        let compare_keys_block =
+            context.append_basic_block(function, "memo_compare_keys");
+        compare_keys_block
+            .move_before(blocks.old_entry_block)
+            .unwrap();
+        let probe_next_block =
+            context.append_basic_block(function, "memo_probe_next");
+        probe_next_block
+            .move_before(blocks.old_entry_block)
+            .unwrap();
+        let claim_slot_block =
+            context.append_basic_block(function, "memo_claim_slot");
+        claim_slot_block
+            .move_before(blocks.old_entry_block)
+            .unwrap();
+        let evict_block = (self.eviction != Eviction::Never).then(|| {
+            let evict_block =
+                context.append_basic_block(function, "memo_evict");
+            evict_block.move_before(blocks.old_entry_block).unwrap();
+            evict_block
+        });
+
+        builder.position_at_end(blocks.header_block);
+
+        let slot_indirect =
+            builder.build_alloca(i64_type, "slot_indirect").unwrap();
+        builder
+            .build_store(should_cache_indirect, bool_type.const_int(0, false))
+            .unwrap();
+        let hash_slot =
+            self.build_hash_from_parameters(context, builder, bounds);
+        let window_start = if self.eviction == Eviction::TwoWay {
+            builder
+                .build_and(
+                    hash_slot,
+                    i64_type
+                        .const_int(!1 & (self.hash_table_size() - 1), false),
+                    "window_start",
+                )
+                .unwrap()
+        } else {
+            hash_slot
+        };
+        let _ = builder
+            .build_unconditional_branch(blocks.check_if_ready_block)
+            .unwrap();
+
+        // the probe loop: `check_if_ready_block` is the loop header
+        builder.position_at_end(blocks.check_if_ready_block);
+
+        let slot_phi = builder.build_phi(i64_type, "slot").unwrap();
+        let probes_phi = builder.build_phi(i64_type, "probes").unwrap();
+        let slot = slot_phi.as_basic_value().into_int_value();
+        let probes = probes_phi.as_basic_value().into_int_value();
+
+        let occupied_pointer = self.build_pointer_for_array_index(
+            context,
+            builder,
+            occupied_array_type,
+            occupied_array,
+            slot,
+            "occupied_pointer",
+        );
+        let is_occupied = builder
+            .build_load(bool_type, occupied_pointer, "is_occupied")
+            .unwrap()
+            .into_int_value();
+        let _ = builder
+            .build_conditional_branch(
+                is_occupied,
+                compare_keys_block,
+                claim_slot_block,
             )
             .unwrap();
+
+        builder.position_at_end(compare_keys_block);
+
+        let mut keys_match = bool_type.const_int(1, false);
+        for (key, parameter) in &bounds.parameters {
+            let (key_array_type, key_array) = key_arrays[key];
+            let key_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                key_array_type,
+                key_array,
+                slot,
+                "key_pointer",
+            );
+            let cached_key = builder
+                .build_load(parameter.get_type(), key_pointer, "cached_key")
+                .unwrap()
+                .into_int_value();
+            let key_matches = builder
+                .build_int_compare(
+                    IntPredicate::EQ,
+                    cached_key,
+                    *parameter,
+                    "key_matches",
+                )
+                .unwrap();
+            keys_match = builder
+                .build_and(keys_match, key_matches, "keys_match")
+                .unwrap();
+        }
         let _ = builder
-            .build_store(value_pointer, loaded_return_value)
+            .build_conditional_branch(
+                keys_match,
+                blocks.fast_path_block,
+                probe_next_block,
+            )
+            .unwrap();
+
+        builder.position_at_end(probe_next_block);
+
+        let next_slot = builder
+            .build_int_add(slot, i64_type.const_int(1, false), "next_slot")
+            .unwrap();
+        let next_slot = builder
+            .build_and(
+                next_slot,
+                i64_type.const_int(self.hash_table_size() - 1, false),
+                "next_slot",
+            )
+            .unwrap();
+        let next_probes = builder
+            .build_int_add(probes, i64_type.const_int(1, false), "next_probes")
+            .unwrap();
+        let can_probe = builder
+            .build_int_compare(
+                IntPredicate::ULT,
+                next_probes,
+                i64_type.const_int(window_size, false),
+                "can_probe",
+            )
             .unwrap();
         let _ = builder
-            .build_store(ready_pointer, bool_type.const_int(1, false))
+            .build_conditional_branch(
+                can_probe,
+                blocks.check_if_ready_block,
+                evict_block.unwrap_or(blocks.old_entry_block),
+            )
             .unwrap();
 
-        let _ = builder.build_return(Some(&loaded_return_value)).unwrap();
+        slot_phi.add_incoming(&[
+            (&window_start as &dyn BasicValue, blocks.header_block),
+            (&next_slot, probe_next_block),
+        ]);
+        probes_phi.add_incoming(&[
+            (
+                &i64_type.const_int(0, false) as &dyn BasicValue,
+                blocks.header_block,
+            ),
+            (&next_probes, probe_next_block),
+        ]);
 
-        builder.position_at_end(always_return_block);
+        builder.position_at_end(claim_slot_block);
 
-        let loaded_return_value = builder
+        builder.build_store(slot_indirect, slot).unwrap();
+        builder
+            .build_store(should_cache_indirect, bool_type.const_int(1, false))
+            .unwrap();
+        let _ = builder
+            .build_unconditional_branch(blocks.old_entry_block)
+            .unwrap();
+
+        if let Some(evict_block) = evict_block {
+            builder.position_at_end(evict_block);
+
+            let victim = match reference_array {
+                Some(reference_array) => self.build_clock_sweep(
+                    context,
+                    builder,
+                    reference_array_type,
+                    reference_array,
+                    window_start,
+                    window_size,
+                ),
+                None => window_start,
+            };
+            builder.build_store(slot_indirect, victim).unwrap();
+            builder
+                .build_store(
+                    should_cache_indirect,
+                    bool_type.const_int(1, false),
+                )
+                .unwrap();
+            let _ = builder
+                .build_unconditional_branch(blocks.old_entry_block)
+                .unwrap();
+        }
+
+        builder.position_at_end(blocks.fast_path_block);
+
+        if let Some(reference_array) = reference_array {
+            let reference_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                reference_array_type,
+                reference_array,
+                slot,
+                "reference_pointer",
+            );
+            builder
+                .build_store(reference_pointer, bool_type.const_int(1, false))
+                .unwrap();
+        }
+        let cached_value =
+            self.build_load_cached_value(context, builder, &value_arrays, slot);
+
+        builder.build_return(Some(&cached_value)).unwrap();
+
+        builder.position_at_end(blocks.cache_and_return_block);
+
+        let claimed_slot = builder
+            .build_load(i64_type, slot_indirect, "claimed_slot")
+            .unwrap()
+            .into_int_value();
+        let return_value = return_value.as_basic_value();
+        for (key, parameter) in &bounds.parameters {
+            let (key_array_type, key_array) = key_arrays[key];
+            let key_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                key_array_type,
+                key_array,
+                claimed_slot,
+                "key_pointer",
+            );
+            builder.build_store(key_pointer, *parameter).unwrap();
+        }
+        self.build_store_cached_value(
+            context,
+            builder,
+            &value_arrays,
+            claimed_slot,
+            return_value,
+        );
+        let occupied_pointer = self.build_pointer_for_array_index(
+            context,
+            builder,
+            occupied_array_type,
+            occupied_array,
+            claimed_slot,
+            "occupied_pointer",
+        );
+        builder
+            .build_store(occupied_pointer, bool_type.const_int(1, false))
+            .unwrap();
+        if let Some(reference_array) = reference_array {
+            let reference_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                reference_array_type,
+                reference_array,
+                claimed_slot,
+                "reference_pointer",
+            );
+            builder
+                .build_store(reference_pointer, bool_type.const_int(1, false))
+                .unwrap();
+        }
+
+        let _ = builder.build_return(Some(&return_value)).unwrap();
+    }
+
+    /// Picks the first slot in the window of `window_size` slots from
+    /// `window_start` whose reference bit is clear, clearing the reference bits
+    /// of the slots passed over. If every slot was referenced, they are all
+    /// cleared and the first slot is picked. The sweep is unrolled since the
+    /// window is small.
+    fn build_clock_sweep<'a>(
+        &self,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        reference_array_type: ArrayType<'a>,
+        reference_array: GlobalValue<'a>,
+        window_start: IntValue<'a>,
+        window_size: u64,
+    ) -> IntValue<'a> {
+        let i64_type = context.i64_type();
+        let bool_type = context.bool_type();
+
+        let mut victim = window_start;
+        let mut found = bool_type.const_int(0, false);
+        for i in 0..window_size {
+            let slot = builder
+                .build_int_add(
+                    window_start,
+                    i64_type.const_int(i, false),
+                    "slot",
+                )
+                .unwrap();
+            let slot = builder
+                .build_and(
+                    slot,
+                    i64_type.const_int(self.hash_table_size() - 1, false),
+                    "slot",
+                )
+                .unwrap();
+            let reference_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                reference_array_type,
+                reference_array,
+                slot,
+                "reference_pointer",
+            );
+            let is_referenced = builder
+                .build_load(bool_type, reference_pointer, "is_referenced")
+                .unwrap()
+                .into_int_value();
+
+            let is_unreferenced =
+                builder.build_not(is_referenced, "is_unreferenced").unwrap();
+            let not_found = builder.build_not(found, "not_found").unwrap();
+            let is_victim = builder
+                .build_and(not_found, is_unreferenced, "is_victim")
+                .unwrap();
+            victim = builder
+                .build_select(is_victim, slot, victim, "victim")
+                .unwrap()
+                .into_int_value();
+
+            // slots before the victim lose their second chance
+            let kept_reference = builder
+                .build_and(found, is_referenced, "kept_reference")
+                .unwrap();
+            builder
+                .build_store(reference_pointer, kept_reference)
+                .unwrap();
+            found = builder.build_or(found, is_victim, "found").unwrap();
+        }
+        victim
+    }
+
+    fn create_statistics_runtime<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+    ) -> StatisticsRuntime<'a> {
+        let void_type = context.void_type();
+        let bool_type = context.bool_type();
+        let i64_type = context.i64_type();
+        let ptr_type = context.ptr_type(AddressSpace::default());
+
+        let report_function = module.add_function(
+            "auto_memoize.report",
+            void_type.fn_type(&[], false),
+            Some(Linkage::Internal),
+        );
+        let count_function = module.add_function(
+            "auto_memoize.count",
+            void_type.fn_type(&[ptr_type.into()], false),
+            Some(Linkage::Internal),
+        );
+        let atexit = declare_atexit(module, context);
+
+        let is_registered = module.add_global(
+            bool_type,
+            None,
+            "auto_memoize.is_report_registered",
+        );
+        is_registered.set_linkage(Linkage::Internal);
+        is_registered.set_initializer(&bool_type.const_zero());
+
+        let entry_block = context.append_basic_block(count_function, "entry");
+        let register_block =
+            context.append_basic_block(count_function, "register");
+        let increment_block =
+            context.append_basic_block(count_function, "increment");
+
+        builder.position_at_end(entry_block);
+        let was_registered = builder
             .build_load(
-                return_type,
-                return_value_indirect,
-                "loaded_return_value",
+                bool_type,
+                is_registered.as_pointer_value(),
+                "was_registered",
             )
+            .unwrap()
+            .into_int_value();
+        let _ = builder
+            .build_conditional_branch(
+                was_registered,
+                increment_block,
+                register_block,
+            )
+            .unwrap();
+
+        builder.position_at_end(register_block);
+        builder
+            .build_store(
+                is_registered.as_pointer_value(),
+                bool_type.const_int(1, false),
+            )
+            .unwrap();
+        builder
+            .build_call(
+                atexit,
+                &[report_function.as_global_value().as_pointer_value().into()],
+                "",
+            )
+            .unwrap();
+        let _ = builder.build_unconditional_branch(increment_block).unwrap();
+
+        builder.position_at_end(increment_block);
+        let counter_pointer = count_function
+            .get_first_param()
+            .unwrap()
+            .into_pointer_value();
+        let count = builder
+            .build_load(i64_type, counter_pointer, "count")
+            .unwrap()
+            .into_int_value();
+        let count = builder
+            .build_int_add(count, i64_type.const_int(1, false), "count")
             .unwrap();
+        builder.build_store(counter_pointer, count).unwrap();
+        builder.build_return(None).unwrap();
+
+        StatisticsRuntime {
+            count_function,
+            report_function,
+            statistics: vec![],
+        }
+    }
+
+    /// Counts the hits, misses, and out-of-bounds calls of the memoized
+    /// `function`.
+    fn instrument_statistics<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        function: FunctionValue<'a>,
+        blocks: &RelevantBlocks<'a>,
+        runtime: &mut StatisticsRuntime<'a>,
+    ) {
+        let i64_type = context.i64_type();
+
+        let statistics_type = i64_type.array_type(Self::STATISTICS_COUNTERS);
+        let statistics = self.add_static(
+            module,
+            function,
+            statistics_type,
+            "memo_statistics",
+            8,
+        );
+        statistics.set_initializer(&statistics_type.const_zero());
+
+        // every call that does not hit the cache returns through either
+        // `cache_and_return_block` or `always_return_block`
+        for (block, counter) in [
+            (blocks.fast_path_block, Self::HITS_COUNTER),
+            (blocks.cache_and_return_block, Self::MISSES_COUNTER),
+            (blocks.always_return_block, Self::OUT_OF_BOUNDS_COUNTER),
+        ] {
+            builder.position_before(&block.get_first_instruction().unwrap());
+            let counter_pointer = self.build_pointer_for_array_index(
+                context,
+                builder,
+                statistics_type,
+                statistics,
+                i64_type.const_int(counter, false),
+                "counter_pointer",
+            );
+            builder
+                .build_call(
+                    runtime.count_function,
+                    &[counter_pointer.into()],
+                    "",
+                )
+                .unwrap();
+        }
+
+        runtime.statistics.push((
+            function.get_name().to_string_lossy().to_string(),
+            statistics,
+        ));
+    }
+
+    fn build_statistics_report<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        runtime: &StatisticsRuntime<'a>,
+    ) {
+        let i32_type = context.i32_type();
+        let i64_type = context.i64_type();
+        let statistics_type = i64_type.array_type(Self::STATISTICS_COUNTERS);
 
-        let _ = builder.build_return(Some(&loaded_return_value)).unwrap();
+        let dprintf = declare_dprintf(module, context);
 
-        for basic_block in function.get_basic_block_iter() {
-            if ![
-                header_block,
-                check_if_ready_block,
-                fast_path_block,
-                cache_and_return_block,
-                always_return_block,
+        let entry_block =
+            context.append_basic_block(runtime.report_function, "entry");
+        builder.position_at_end(entry_block);
+
+        let format = builder
+            .build_global_string_ptr(
+                "[auto-memoize] %s: %llu hits, %llu misses, %llu out of bounds\n",
+                "auto_memoize.report_format",
+            )
+            .unwrap();
+        for (name, statistics) in &runtime.statistics {
+            let name = builder
+                .build_global_string_ptr(name, "auto_memoize.function_name")
+                .unwrap();
+            let [hits, misses, out_of_bounds] = [
+                Self::HITS_COUNTER,
+                Self::MISSES_COUNTER,
+                Self::OUT_OF_BOUNDS_COUNTER,
             ]
-            .contains(&basic_block)
-            {
-                let instructions: Vec<_> =
-                    basic_block.get_instructions().collect();
-                for instruction in instructions {
-                    if instruction.get_opcode() == InstructionOpcode::Return {
-                        if let Some(return_value) = instruction.get_operand(0) {
-                            let return_value = return_value.unwrap_left();
-                            builder.position_at_end(basic_block);
-                            builder
-                                .build_store(
-                                    return_value_indirect,
-                                    return_value,
-                                )
-                                .unwrap();
-                        }
-                        builder.position_at_end(basic_block);
-                        let _ = builder
-                            .build_conditional_branch(
-                                parameters_in_bounds,
-                                cache_and_return_block,
-                                always_return_block,
-                            )
-                            .unwrap();
-                        instruction.erase_from_basic_block();
-                    }
+            .map(|counter| {
+                let counter_pointer = self.build_pointer_for_array_index(
+                    context,
+                    builder,
+                    statistics_type,
+                    *statistics,
+                    i64_type.const_int(counter, false),
+                    "counter_pointer",
+                );
+                builder
+                    .build_load(i64_type, counter_pointer, "count")
+                    .unwrap()
+            });
+            builder
+                .build_call(
+                    dprintf,
+                    &[
+                        i32_type.const_int(2, false).into(),
+                        format.as_pointer_value().into(),
+                        name.as_pointer_value().into(),
+                        hits.into(),
+                        misses.into(),
+                        out_of_bounds.into(),
+                    ],
+                    "",
+                )
+                .unwrap();
+        }
+
+        builder.build_return(None).unwrap();
+    }
+
+    fn find_self_calls<'a>(
+        &self,
+        function: FunctionValue<'a>,
+    ) -> Vec<InstructionValue<'a>> {
+        let name = function.get_name().to_string_lossy();
+        function
+            .get_basic_block_iter()
+            .flat_map(|basic_block| basic_block.get_instructions())
+            .filter(|instruction| {
+                instruction.get_opcode() == InstructionOpcode::Call
+                    && get_callee_of_known_call(*instruction).as_deref()
+                        == Some(name.as_ref())
+            })
+            .collect()
+    }
+
+    fn maybe_memoize<'a>(
+        &self,
+        module: &Module<'a>,
+        context: ContextRef<'a>,
+        builder: &Builder<'a>,
+        function: FunctionValue<'a>,
+    ) -> Option<RelevantBlocks<'a>> {
+        let bool_type = context.bool_type();
+
+        let Some(return_type) = function.get_type().get_return_type() else {
+            tracing::info!(
+                target: "auto-memoize",
+                "Skipping memoization for {:?} because it does not have a return type, so it's a pure function without a return...",
+                function.get_name()
+            );
+            return None;
+        };
+        if !Self::is_memoizable_return_type(return_type) {
+            tracing::info!(
+                target: "auto-memoize",
+                "Skipping memoization for {:?} because its return type is not a scalar or a struct of at most {} scalars",
+                function.get_name(),
+                Self::MAX_STRUCT_RETURN_FIELDS
+            );
+            return None;
+        }
+
+        // Other parameters are fine as long as the result can only depend on
+        // them through comparisons for equality against constants, in which
+        // case the outcomes of those comparisons are memoized on instead
+        let mut int_parameters = vec![];
+        let mut boolean_parameters = vec![];
+        let mut equality_comparisons = vec![];
+        for (index, parameter) in function.get_params().into_iter().enumerate()
+        {
+            match parameter {
+                BasicValueEnum::IntValue(int_value)
+                    if Self::MEMOIZABLE_PARAMETER_WIDTHS
+                        .contains(&int_value.get_type().get_bit_width()) =>
+                {
+                    int_parameters.push(int_value);
+                    continue;
                 }
+                BasicValueEnum::IntValue(int_value)
+                    if int_value.get_type().get_bit_width() == 1 =>
+                {
+                    boolean_parameters.push(int_value);
+                    continue;
+                }
+                _ => {}
+            }
+            let Some(comparisons) =
+                find_equality_comparisons(function, parameter)
+            else {
+                tracing::info!(
+                    target: "auto-memoize",
+                    "Skipping memoization for {:?} because parameter {index} is not an integer (that is, LLVM i1, i8, i16, i32, or i64) and is used other than in comparisons for equality against constants",
+                    function.get_name()
+                );
+                return None;
+            };
+            if comparisons.is_empty() {
+                tracing::debug!(
+                    target: "auto-memoize",
+                    "Ignoring parameter {index} of {:?} because it is unused",
+                    function.get_name()
+                );
+            } else {
+                tracing::debug!(
+                    target: "auto-memoize",
+                    "Memoizing on the outcomes of {} comparisons of parameter {index} of {:?} against constants",
+                    comparisons.len(),
+                    function.get_name()
+                );
+            }
+            equality_comparisons.extend(
+                comparisons.into_iter().map(|(comparison, operand)| {
+                    (comparison, operand, parameter)
+                }),
+            );
+        }
+        // Booleans and comparison outcomes only take one bit of the index (see
+        // `MemoizationBounds::index_bits`), so they do not count
+        if int_parameters.len() > 3 {
+            tracing::info!(
+                target: "auto-memoize",
+                "Skipping memoization for {:?} because it has more than 3 integer parameters",
+                function.get_name()
+            );
+            return None;
+        }
+
+        tracing::info!(target: "auto-memoize", "Memoizing {:?}", function.get_name());
+
+        // The memoization is lowered in place, so recursive calls, which still
+        // call `function`, enter through the memo table like any other call.
+        // This is what makes memoizing recursive functions pay off: each
+        // subproblem is computed once. Since the recursive calls are no longer
+        // in tail position once returns are rewritten to cache their value,
+        // they cannot be guaranteed tail calls.
+        let self_calls = self.find_self_calls(function);
+        if !self_calls.is_empty() {
+            tracing::info!(
+                target: "auto-memoize",
+                "The {} recursive calls in {:?} will go through the memo table",
+                self_calls.len(),
+                function.get_name()
+            );
+        }
+        for self_call in self_calls {
+            if let Ok(self_call) = CallSiteValue::try_from(self_call) {
+                self_call.set_tail_call(false);
+            }
+        }
+
+        let original_blocks =
+            function.get_basic_block_iter().collect::<Vec<_>>();
+        let blocks = self.insert_memoization_basic_blocks(context, function);
+
+        let mut bounds = self.construct_memoization_bounds(
+            context,
+            int_parameters,
+            blocks.old_entry_block,
+        );
+
+        builder.position_at_end(blocks.header_block);
+
+        let should_cache_indirect = builder
+            .build_alloca(bool_type, "should_cache_indirect")
+            .unwrap();
+
+        // Booleans are zero-extended so that `true` is 1 rather than -1, and
+        // each comparison is redone on the parameter itself in the header; both
+        // are then memoized on like parameters in `0..2`
+        for parameter in boolean_parameters {
+            let extended_boolean = builder
+                .build_int_z_extend(
+                    parameter,
+                    context.i8_type(),
+                    "extended_boolean",
+                )
+                .unwrap();
+            let parameter_key = bounds.parameters.insert(extended_boolean);
+            bounds.cached_ranges.insert(parameter_key, 0..2);
+        }
+        for (comparison, operand, parameter) in equality_comparisons {
+            let outcome = comparison.explicit_clone();
+            outcome.set_operand(operand, parameter);
+            builder.insert_instruction(&outcome, Some("equality_outcome"));
+            let outcome = builder
+                .build_int_z_extend(
+                    IntValue::try_from(outcome).unwrap(),
+                    context.i8_type(),
+                    "equality_key",
+                )
+                .unwrap();
+            let parameter_key = bounds.parameters.insert(outcome);
+            bounds.cached_ranges.insert(parameter_key, 0..2);
+        }
+
+        // Returns join here rather than communicating through memory, and
+        // whether to cache is decided once for all of them
+        builder.position_at_end(blocks.return_block);
+        let return_value =
+            builder.build_phi(return_type, "return_value").unwrap();
+        let should_cache = builder
+            .build_load(bool_type, should_cache_indirect, "should_cache")
+            .unwrap()
+            .into_int_value();
+        let _ = builder
+            .build_conditional_branch(
+                should_cache,
+                blocks.cache_and_return_block,
+                blocks.always_return_block,
+            )
+            .unwrap();
+
+        let memoized = MemoizedFunction {
+            blocks,
+            return_type,
+            return_value,
+            should_cache_indirect,
+        };
+
+        let fits_in_array = bounds
+            .flattened_array_length()
+            .is_some_and(|length| length <= self.max_entries);
+        if fits_in_array {
+            self.build_array_lookup(
+                module, context, builder, function, &bounds, &memoized,
+            );
+        } else {
+            tracing::info!(
+                target: "auto-memoize",
+                "Using a hash table for {:?} because not every parameter has an upper bound or caching every combination would take more than {} entries",
+                function.get_name(),
+                self.max_entries
+            );
+            self.build_hash_table_lookup(
+                module, context, builder, function, &bounds, &memoized,
+            );
+        }
+
+        let MemoizedFunction {
+            blocks,
+            return_value,
+            ..
+        } = memoized;
+        builder.position_at_end(blocks.always_return_block);
+        let _ = builder
+            .build_return(Some(&return_value.as_basic_value()))
+            .unwrap();
+
+        // The return type is not void, so each return has a value
+        for basic_block in original_blocks {
+            let Some(terminator) = basic_block.get_terminator() else {
+                continue;
+            };
+            if terminator.get_opcode() != InstructionOpcode::Return {
+                continue;
             }
+            let returned_value = terminator
+                .get_operand(0)
+                .and_then(|operand| operand.left())
+                .expect("non-void return has no value");
+            return_value.add_incoming(&[(&returned_value, basic_block)]);
+            builder.position_before(&terminator);
+            let _ = builder
+                .build_unconditional_branch(blocks.return_block)
+                .unwrap();
+            terminator.erase_from_basic_block();
         }
+
+        Some(blocks)
     }
 }
 
@@ -817,26 +2506,50 @@ impl LlvmModulePass for AutoMemoizePass {
         let context = module.get_context();
         let builder = context.create_builder();
 
+        let pure_functions = compute_pure_functions(module);
+        let mut statistics_runtime = self
+            .profile
+            .then(|| self.create_statistics_runtime(module, context, &builder));
+
         for function in module.get_functions() {
-            local_log!(
-                self,
-                "[auto-memoize] Visiting function {:?}",
+            tracing::info!(
+                target: "auto-memoize",
+                "Visiting function {:?}",
                 function.get_name()
             );
 
             let scuffed_is_defined = function.count_basic_blocks() > 0;
-            if scuffed_is_defined && is_conservatively_pure(function) {
-                local_log!(
-                    self,
-                    "[auto-memoize] Function {:?} is pure",
+            if scuffed_is_defined
+                && self.is_selected(module, function)
+                && pure_functions
+                    .contains(function.get_name().to_string_lossy().as_ref())
+            {
+                tracing::info!(
+                    target: "auto-memoize",
+                    "Function {:?} is pure",
                     function.get_name()
                 );
-                self.maybe_memoize(module, context, &builder, function);
+                let memoized_blocks =
+                    self.maybe_memoize(module, context, &builder, function);
+                if let (Some(blocks), Some(runtime)) =
+                    (memoized_blocks, &mut statistics_runtime)
+                {
+                    self.instrument_statistics(
+                        module, context, &builder, function, &blocks, runtime,
+                    );
+                }
 
                 preserved_analyses = PreservedAnalyses::None;
             }
         }
 
+        if let Some(runtime) = &statistics_runtime {
+            self.build_statistics_report(module, context, &builder, runtime);
+            preserved_analyses = PreservedAnalyses::None;
+        }
+
+        verify_in_debug_builds(module, "auto-memoize");
+
         preserved_analyses
     }
 }
@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+
+use llvm_plugin::{
+    LlvmModulePass, ModuleAnalysisManager, PreservedAnalyses,
+    inkwell::{
+        basic_block::BasicBlock,
+        builder::Builder,
+        module::Module,
+        values::{FunctionValue, InstructionOpcode, InstructionValue},
+    },
+};
+
+use crate::verify_in_debug_builds;
+
+/// Hoists loop-invariant instructions that are safe to speculate out of loops
+/// and into their preheaders, like the Bril LICM in lesson 8 but at the LLVM
+/// level. Since SSA values are defined exactly once, an instruction is loop
+/// invariant when none of its operands are defined in the loop, so unoptimized
+/// code, which keeps every variable in memory, should go through `mem2reg`
+/// first. llvm-plugin does not expose LLVM's `LoopAnalysis`, so natural loops
+/// are found from dominators and back edges here instead.
+pub struct SimpleLicmPass;
+
+/// The blocks that can reach a back edge to `header` without going through
+/// `header`, in the order they appear in the function. Back edges to the same
+/// header are combined into one loop.
+struct NaturalLoop<'a> {
+    header: BasicBlock<'a>,
+    blocks: Vec<BasicBlock<'a>>,
+}
+
+fn successors(basic_block: BasicBlock) -> Vec<BasicBlock> {
+    basic_block
+        .get_terminator()
+        .map(|terminator| {
+            (0..terminator.get_num_operands())
+                .filter_map(|index| terminator.get_operand(index)?.right())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether `instruction` has no side effects and cannot trap, so it can be
+/// executed even when the loop it is in would not have run. Instructions that
+/// may produce poison, like `getelementptr inbounds` or shifts, still count.
+fn is_speculatable(instruction: InstructionValue) -> bool {
+    matches!(
+        instruction.get_opcode(),
+        InstructionOpcode::Add
+            | InstructionOpcode::AddrSpaceCast
+            | InstructionOpcode::And
+            | InstructionOpcode::AShr
+            | InstructionOpcode::BitCast
+            | InstructionOpcode::ExtractElement
+            | InstructionOpcode::ExtractValue
+            | InstructionOpcode::FAdd
+            | InstructionOpcode::FCmp
+            | InstructionOpcode::FDiv
+            | InstructionOpcode::FMul
+            | InstructionOpcode::FNeg
+            | InstructionOpcode::FPExt
+            | InstructionOpcode::FPToSI
+            | InstructionOpcode::FPToUI
+            | InstructionOpcode::FPTrunc
+            | InstructionOpcode::FRem
+            | InstructionOpcode::Freeze
+            | InstructionOpcode::FSub
+            | InstructionOpcode::GetElementPtr
+            | InstructionOpcode::ICmp
+            | InstructionOpcode::InsertElement
+            | InstructionOpcode::InsertValue
+            | InstructionOpcode::IntToPtr
+            | InstructionOpcode::LShr
+            | InstructionOpcode::Mul
+            | InstructionOpcode::Or
+            | InstructionOpcode::PtrToInt
+            | InstructionOpcode::Select
+            | InstructionOpcode::SExt
+            | InstructionOpcode::Shl
+            | InstructionOpcode::ShuffleVector
+            | InstructionOpcode::SIToFP
+            | InstructionOpcode::Sub
+            | InstructionOpcode::Trunc
+            | InstructionOpcode::UIToFP
+            | InstructionOpcode::Xor
+            | InstructionOpcode::ZExt
+    )
+}
+
+impl SimpleLicmPass {
+    pub fn parse(name: &str) -> Option<Self> {
+        (name == "simple-licm").then_some(Self)
+    }
+
+    /// The dominators of each block in `function`, computed iteratively.
+    fn compute_dominators<'a>(
+        &self,
+        function: FunctionValue<'a>,
+        predecessors: &HashMap<BasicBlock<'a>, Vec<BasicBlock<'a>>>,
+    ) -> HashMap<BasicBlock<'a>, HashSet<BasicBlock<'a>>> {
+        let basic_blocks = function.get_basic_blocks();
+        let every_block = basic_blocks.iter().copied().collect::<HashSet<_>>();
+
+        let mut dominators = basic_blocks
+            .iter()
+            .enumerate()
+            .map(|(i, basic_block)| {
+                if i == 0 {
+                    (*basic_block, HashSet::from([*basic_block]))
+                } else {
+                    (*basic_block, every_block.clone())
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for basic_block in basic_blocks.iter().skip(1) {
+                let mut new_dominators = predecessors[basic_block]
+                    .iter()
+                    .map(|predecessor| dominators[predecessor].clone())
+                    .reduce(|first, second| &first & &second)
+                    .unwrap_or_default();
+                new_dominators.insert(*basic_block);
+                if new_dominators != dominators[basic_block] {
+                    dominators.insert(*basic_block, new_dominators);
+                    changed = true;
+                }
+            }
+        }
+        dominators
+    }
+
+    /// The natural loops of `function`, innermost first.
+    fn find_loops<'a>(
+        &self,
+        function: FunctionValue<'a>,
+        predecessors: &HashMap<BasicBlock<'a>, Vec<BasicBlock<'a>>>,
+    ) -> Vec<NaturalLoop<'a>> {
+        let dominators = self.compute_dominators(function, predecessors);
+
+        let mut loop_blocks = HashMap::<BasicBlock, HashSet<BasicBlock>>::new();
+        for latch in function.get_basic_block_iter() {
+            for header in successors(latch) {
+                if !dominators[&latch].contains(&header) {
+                    continue;
+                }
+                let blocks = loop_blocks
+                    .entry(header)
+                    .or_insert_with(|| HashSet::from([header]));
+                let mut worklist = vec![latch];
+                while let Some(basic_block) = worklist.pop() {
+                    if blocks.insert(basic_block) {
+                        worklist.extend(&predecessors[&basic_block]);
+                    }
+                }
+            }
+        }
+
+        let mut loops = function
+            .get_basic_block_iter()
+            .filter_map(|header| {
+                let blocks = loop_blocks.remove(&header)?;
+                Some(NaturalLoop {
+                    header,
+                    blocks: function
+                        .get_basic_block_iter()
+                        .filter(|basic_block| blocks.contains(basic_block))
+                        .collect(),
+                })
+            })
+            .collect::<Vec<_>>();
+        loops.sort_by_key(|natural_loop| natural_loop.blocks.len());
+        loops
+    }
+
+    /// Moves every invariant, speculatable instruction in `natural_loop` to
+    /// the end of `preheader`, including those that only become invariant
+    /// once others are hoisted. Returns whether any instruction was hoisted.
+    fn hoist_invariants<'a>(
+        &self,
+        builder: &Builder<'a>,
+        natural_loop: &NaturalLoop<'a>,
+        preheader: BasicBlock<'a>,
+    ) -> bool {
+        let is_invariant = |instruction: InstructionValue<'a>| {
+            (0..instruction.get_num_operands()).all(|index| {
+                match instruction.get_operand(index) {
+                    Some(operand) => operand
+                        .left()
+                        .and_then(|value| value.as_instruction_value())
+                        .and_then(|definition| definition.get_parent())
+                        .is_none_or(|parent| {
+                            !natural_loop.blocks.contains(&parent)
+                        }),
+                    None => true,
+                }
+            })
+        };
+        let terminator = preheader
+            .get_terminator()
+            .expect("preheader has no terminator");
+
+        let mut hoisted_any = false;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for basic_block in &natural_loop.blocks {
+                let instructions =
+                    basic_block.get_instructions().collect::<Vec<_>>();
+                for instruction in instructions {
+                    if !is_speculatable(instruction)
+                        || !is_invariant(instruction)
+                    {
+                        continue;
+                    }
+                    let name = instruction
+                        .get_name()
+                        .map(|name| name.to_string_lossy().to_string());
+                    instruction.remove_from_basic_block();
+                    builder.position_before(&terminator);
+                    builder.insert_instruction(&instruction, name.as_deref());
+                    changed = true;
+                    hoisted_any = true;
+                }
+            }
+        }
+        hoisted_any
+    }
+
+    fn optimize_function(
+        &self,
+        builder: &Builder,
+        function: FunctionValue,
+    ) -> bool {
+        let mut predecessors = function
+            .get_basic_block_iter()
+            .map(|basic_block| (basic_block, vec![]))
+            .collect::<HashMap<_, _>>();
+        for basic_block in function.get_basic_block_iter() {
+            for successor in successors(basic_block) {
+                predecessors.get_mut(&successor).unwrap().push(basic_block);
+            }
+        }
+
+        let mut changed = false;
+        for natural_loop in self.find_loops(function, &predecessors) {
+            // the preheader is the only way into the loop and only goes to
+            // the loop, so anything hoisted there runs exactly once before
+            let entries = predecessors[&natural_loop.header]
+                .iter()
+                .filter(|predecessor| {
+                    !natural_loop.blocks.contains(predecessor)
+                })
+                .collect::<Vec<_>>();
+            let [preheader] = entries[..] else {
+                continue;
+            };
+            if successors(*preheader) != [natural_loop.header] {
+                continue;
+            }
+            changed |=
+                self.hoist_invariants(builder, &natural_loop, *preheader);
+        }
+        changed
+    }
+}
+
+impl LlvmModulePass for SimpleLicmPass {
+    fn run_pass(
+        &self,
+        module: &mut Module,
+        _manager: &ModuleAnalysisManager,
+    ) -> PreservedAnalyses {
+        let builder = module.get_context().create_builder();
+
+        let mut changed = false;
+        for function in module.get_functions() {
+            if function.count_basic_blocks() > 0 {
+                changed |= self.optimize_function(&builder, function);
+            }
+        }
+
+        verify_in_debug_builds(module, "simple-licm");
+
+        if changed {
+            PreservedAnalyses::None
+        } else {
+            PreservedAnalyses::All
+        }
+    }
+}
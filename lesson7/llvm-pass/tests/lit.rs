@@ -0,0 +1,14 @@
+use std::process::Command;
+
+#[test]
+fn lit() {
+    let status = Command::new("lit")
+        .arg("-v")
+        .arg(env!("LIT_TESTS_DIR"))
+        .env("LLVM_PASS_PLUGIN", env!("LLVM_PASS_PLUGIN"))
+        .status()
+        .expect(
+            "Failed to run lit, which can be installed with `pipx install lit`",
+        );
+    assert!(status.success(), "lit tests failed");
+}
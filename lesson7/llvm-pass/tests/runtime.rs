@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Each binary is run this many times and the fastest run is kept, since it
+/// is the least affected by whatever else the machine is doing.
+const RUNS: usize = 3;
+
+fn compile(fixture: &Path, binary: &Path, with_plugin: bool) {
+    let mut clang = Command::new("clang");
+    clang.arg("-O0").arg(fixture).arg("-o").arg(binary);
+    if with_plugin {
+        clang.arg(format!("-fpass-plugin={}", env!("LLVM_PASS_PLUGIN")));
+    }
+    let status = clang.status().expect("Failed to run clang");
+    assert!(status.success(), "Failed to compile {}", fixture.display());
+}
+
+/// Runs `binary`, returning its standard output and the cycles it reports.
+fn run(binary: &Path) -> (String, u64) {
+    let output = Command::new(binary)
+        .output()
+        .unwrap_or_else(|_| panic!("Failed to run {}", binary.display()));
+    assert!(output.status.success(), "{} failed", binary.display());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let cycles = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("cycles: "))
+        .and_then(|cycles| cycles.parse().ok())
+        .unwrap_or_else(|| {
+            panic!("{} did not report its cycles", binary.display())
+        });
+    (String::from_utf8_lossy(&output.stdout).to_string(), cycles)
+}
+
+/// Compiles every fixture without and with the plugin into binaries named
+/// after the fixture and `test`, so that tests running at the same time do not
+/// overwrite each other's binaries. Returns the name of each fixture with the
+/// two binaries.
+fn compile_fixtures(test: &str) -> Vec<(String, PathBuf, PathBuf)> {
+    let mut fixtures = fs::read_dir(env!("RUNTIME_FIXTURES_DIR"))
+        .expect("Failed to read the fixtures")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension().is_some_and(|extension| extension == "c")
+        })
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "No fixtures found");
+
+    fixtures
+        .into_iter()
+        .map(|fixture| {
+            let name = fixture.file_stem().unwrap().to_string_lossy();
+            let binary = |suffix: &str| -> PathBuf {
+                Path::new(env!("CARGO_TARGET_TMPDIR"))
+                    .join(format!("{name}.{test}.{suffix}"))
+            };
+            let (baseline, memoized) = (binary("baseline"), binary("memoized"));
+            compile(&fixture, &baseline, false);
+            compile(&fixture, &memoized, true);
+            (name.to_string(), baseline, memoized)
+        })
+        .collect()
+}
+
+/// Memoization must not change what any fixture prints.
+#[test]
+fn fixtures_preserve_output() {
+    for (name, baseline, memoized) in compile_fixtures("output") {
+        assert_eq!(
+            run(&baseline).0,
+            run(&memoized).0,
+            "Memoization changed the output of {name}"
+        );
+    }
+}
+
+/// Memoization must make every fixture take at most 90% of the cycles. The
+/// cycle counts are too noisy on shared machines to check on every run, so
+/// run this with `cargo test -- --ignored`.
+#[test]
+#[ignore = "timing depends on the load on the machine"]
+fn fixtures_speed_up() {
+    for (name, baseline, memoized) in compile_fixtures("speed") {
+        let mut baseline_cycles = run(&baseline).1;
+        let mut memoized_cycles = run(&memoized).1;
+        for _ in 1..RUNS {
+            baseline_cycles = baseline_cycles.min(run(&baseline).1);
+            memoized_cycles = memoized_cycles.min(run(&memoized).1);
+        }
+        assert!(
+            memoized_cycles * 10 <= baseline_cycles * 9,
+            "Memoization did not speed up {name}: {baseline_cycles} cycles without it, {memoized_cycles} with it"
+        );
+    }
+}
@@ -4,7 +4,7 @@
 
 #define N 10000
 
-int add1(int a) {
+__attribute__((annotate("memoize"))) int add1(int a) {
     __builtin_assume(a >= 0);
     __builtin_assume(a < N);
     return a + 1;
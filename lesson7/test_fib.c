@@ -4,7 +4,7 @@
 
 #define N 30
 
-int fib(int a) {
+__attribute__((annotate("memoize"))) int fib(int a) {
     __builtin_assume(a >= 0);
     __builtin_assume(a < N);
     if (a == 0) {
@@ -4,7 +4,7 @@
 
 #define N 30
 
-int sum(int x, int y) {
+__attribute__((annotate("memoize"))) int sum(int x, int y) {
     __builtin_assume(x >= 0);
     __builtin_assume(x < N);
     __builtin_assume(y >= 0);
@@ -0,0 +1,39 @@
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all>' %s -S -o %t.ll
+; RUN: FileCheck %s < %t.ll
+; RUN: FileCheck %s --check-prefix=RETURNS < %t.ll
+
+; The memoization blocks come first, and every return of the original function
+; joins in a single block deciding whether to cache the return value.
+
+; CHECK-LABEL: define i32 @abs_plus_one(i32 %x)
+; CHECK-NEXT: memo_header:
+; CHECK: %should_cache_indirect = alloca i1
+; CHECK: br i1 %parameters_in_bounds{{[0-9]*}}, label %memo_check_if_ready, label %entry
+; CHECK-DAG: memo_fast_path:
+; CHECK-DAG: memo_check_if_ready:
+; CHECK-DAG: memo_always_return:
+; CHECK-DAG: memo_cache_and_return:
+; CHECK: memo_return:
+; CHECK-NEXT: %return_value = phi i32 [ %negated, %negative ], [ %x, %positive ]
+; CHECK-NEXT: %should_cache = load i1, ptr %should_cache_indirect
+
+; RETURNS-NOT: ret i32 %x
+; RETURNS-NOT: ret i32 %negated
+define i32 @abs_plus_one(i32 %x) {
+entry:
+  %lower = icmp sge i32 %x, 0
+  call void @llvm.assume(i1 %lower)
+  %upper = icmp slt i32 %x, 8
+  call void @llvm.assume(i1 %upper)
+  %is_negative = icmp slt i32 %x, 0
+  br i1 %is_negative, label %negative, label %positive
+
+negative:
+  %negated = sub i32 0, %x
+  ret i32 %negated
+
+positive:
+  ret i32 %x
+}
+
+declare void @llvm.assume(i1 noundef)
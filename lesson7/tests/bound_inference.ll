@@ -0,0 +1,78 @@
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all>:verbose' %s -S -o %t.ll 2> %t.log
+; RUN: FileCheck %s < %t.ll
+; RUN: FileCheck %s --check-prefix=LOG < %t.log
+
+; Assumptions on arguments directly, with the constant on either side, through
+; extensions, and with strict and non-strict predicates.
+
+; LOG-LABEL: Visiting function "direct"
+; LOG: Derived parameter bound ({{.*}}%x) >= 0
+; LOG: Derived parameter bound ({{.*}}%x) < 16
+; CHECK-DAG: @direct.memo_value_array = internal global [16 x i32] zeroinitializer
+; CHECK-DAG: @direct.memo_ready_array = internal global [16 x i1] zeroinitializer
+define i32 @direct(i32 %x) {
+entry:
+  %lower = icmp sgt i32 %x, -1
+  call void @llvm.assume(i1 %lower)
+  %upper = icmp slt i32 %x, 16
+  call void @llvm.assume(i1 %upper)
+  %result = mul i32 %x, %x
+  ret i32 %result
+}
+
+; LOG-LABEL: Visiting function "swapped"
+; LOG: Derived parameter bound ({{.*}}%x) < 9
+; CHECK-DAG: @swapped.memo_value_array = internal global [16 x i64] zeroinitializer
+define i64 @swapped(i64 %x) {
+entry:
+  %lower = icmp sle i64 0, %x
+  call void @llvm.assume(i1 %lower)
+  %upper = icmp sge i64 8, %x
+  call void @llvm.assume(i1 %upper)
+  ret i64 %x
+}
+
+; LOG-LABEL: Visiting function "extended"
+; LOG: Derived parameter bound ({{.*}}%x) < 5
+; CHECK-DAG: @extended.memo_value_array = internal global [8 x i32] zeroinitializer
+define i32 @extended(i8 %x) {
+entry:
+  %wide = zext i8 %x to i32
+  %upper = icmp ult i32 %wide, 5
+  call void @llvm.assume(i1 %upper)
+  ret i32 %wide
+}
+
+; Parameters spilled to the stack as clang does without optimizations.
+
+; LOG-LABEL: Visiting function "spilled"
+; LOG: Derived parameter bound ({{.*}}%x) >= 0
+; LOG: Derived parameter bound ({{.*}}%x) < 30
+; LOG: Derived parameter bound ({{.*}}%y) >= 0
+; LOG: Derived parameter bound ({{.*}}%y) < 4
+; CHECK-DAG: @spilled.memo_value_array = internal global [128 x i32] zeroinitializer
+define i32 @spilled(i32 %x, i32 %y) {
+entry:
+  %x.addr = alloca i32, align 4
+  %y.addr = alloca i32, align 4
+  store i32 %x, ptr %x.addr, align 4
+  store i32 %y, ptr %y.addr, align 4
+  %0 = load i32, ptr %x.addr, align 4
+  %1 = icmp sge i32 %0, 0
+  call void @llvm.assume(i1 %1)
+  %2 = load i32, ptr %x.addr, align 4
+  %3 = icmp slt i32 %2, 30
+  call void @llvm.assume(i1 %3)
+  %4 = load i32, ptr %y.addr, align 4
+  %5 = icmp sge i32 %4, 0
+  call void @llvm.assume(i1 %5)
+  %6 = load i32, ptr %y.addr, align 4
+  %7 = icmp slt i32 %6, 4
+  call void @llvm.assume(i1 %7)
+  %8 = load i32, ptr %x.addr, align 4
+  %9 = load i32, ptr %y.addr, align 4
+  %10 = add i32 %8, %9
+  ret i32 %10
+}
+
+declare void @llvm.assume(i1 noundef)
@@ -0,0 +1,34 @@
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all>:verbose' %s -S -o %t.ll 2> %t.log
+; RUN: FileCheck %s < %t.ll
+; RUN: FileCheck %s --check-prefix=LOG < %t.log
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all;max-entries=100>' %s -S -o - | FileCheck %s --check-prefix=SMALL
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all;default-range=10>' %s -S -o - | FileCheck %s --check-prefix=RANGE
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all;eviction=clock>' %s -S -o - | FileCheck %s --check-prefix=CLOCK
+
+; Without bounds, results are cached in a hash table keyed on every parameter.
+
+; LOG: Using a hash table for "unbounded"
+; CHECK-DAG: @unbounded.memo_key_array.0 = internal global [4096 x i32] zeroinitializer
+; CHECK-DAG: @unbounded.memo_key_array.1 = internal global [4096 x i64] zeroinitializer
+; CHECK-DAG: @unbounded.memo_occupied_array = internal global [4096 x i1] zeroinitializer
+; CHECK-NOT: memo_reference_array
+; CHECK-LABEL: define i32 @unbounded(i32 %x, i64 %y)
+; CHECK: memo_compare_keys:
+; CHECK: memo_probe_next:
+; CHECK: memo_claim_slot:
+; CHECK-NOT: memo_evict:
+
+; The table is the largest power of two that fits.
+; SMALL-DAG: @unbounded.memo_occupied_array = internal global [64 x i1] zeroinitializer
+
+; With a default range, arrays indexed by the parameters are used instead.
+; RANGE-DAG: @unbounded.memo_value_array = internal global [256 x i32] zeroinitializer
+
+; CLOCK-DAG: @unbounded.memo_reference_array = internal global [4096 x i1] zeroinitializer
+; CLOCK: memo_evict:
+define i32 @unbounded(i32 %x, i64 %y) {
+entry:
+  %narrow = trunc i64 %y to i32
+  %result = xor i32 %x, %narrow
+  ret i32 %result
+}
@@ -0,0 +1,19 @@
+// RUN: clang -S -emit-llvm %s -o %t.ll
+// RUN: opt --load-pass-plugin=%plugin --passes=instr-count %t.ll -S -o %t.counted.ll
+// RUN: lli %t.counted.ll 2> %t.count | FileCheck %s --check-prefix=OUTPUT
+// RUN: FileCheck %s < %t.count
+
+// The instrumentation must not change what the program prints.
+// OUTPUT: 55
+
+// CHECK: total_dyn_inst: {{[1-9][0-9]*}}
+
+#include <stdio.h>
+
+int main() {
+    int sum = 0;
+    for (int i = 1; i <= 10; i++) {
+        sum += i;
+    }
+    printf("%d\n", sum);
+}
@@ -0,0 +1,73 @@
+; RUN: opt --load-pass-plugin=%plugin --passes=simple-licm %s -S -o - | FileCheck %s
+
+; Invariant arithmetic moves to the preheader, including instructions that are
+; only invariant once their operands are hoisted and those in inner loops.
+; Division may trap, so it stays put.
+
+; CHECK-LABEL: define i32 @sum(i32 %n, i32 %a, i32 %b)
+; CHECK: entry:
+; CHECK-NEXT: %product = mul i32 %a, %b
+; CHECK-NEXT: %offset = add i32 %product, 1
+; CHECK-NEXT: %scaled = shl i32 %a, 2
+; CHECK-NEXT: br label %outer
+; CHECK: outer:
+; CHECK-NOT: %product =
+; CHECK-NOT: %scaled =
+; CHECK: inner:
+; CHECK: %quotient = sdiv i32 %a, %b
+define i32 @sum(i32 %n, i32 %a, i32 %b) {
+entry:
+  br label %outer
+
+outer:
+  %i = phi i32 [ 0, %entry ], [ %i.next, %outer.latch ]
+  %total = phi i32 [ 0, %entry ], [ %total.next, %outer.latch ]
+  %product = mul i32 %a, %b
+  %offset = add i32 %product, 1
+  br label %inner
+
+inner:
+  %j = phi i32 [ 0, %outer ], [ %j.next, %inner ]
+  %partial = phi i32 [ %total, %outer ], [ %partial.next, %inner ]
+  %scaled = shl i32 %a, 2
+  %quotient = sdiv i32 %a, %b
+  %term = add i32 %scaled, %quotient
+  %with_offset = add i32 %term, %offset
+  %partial.next = add i32 %partial, %with_offset
+  %j.next = add i32 %j, 1
+  %inner.done = icmp eq i32 %j.next, %n
+  br i1 %inner.done, label %outer.latch, label %inner
+
+outer.latch:
+  %total.next = phi i32 [ %partial.next, %inner ]
+  %i.next = add i32 %i, 1
+  %outer.done = icmp eq i32 %i.next, %n
+  br i1 %outer.done, label %exit, label %outer
+
+exit:
+  ret i32 %total.next
+}
+
+; Without a unique preheader, nothing is hoisted.
+
+; CHECK-LABEL: define i32 @no_preheader(i1 %c, i32 %a)
+; CHECK: header:
+; CHECK-NEXT: %x = phi
+; CHECK-NEXT: %invariant = mul i32 %a, %a
+define i32 @no_preheader(i1 %c, i32 %a) {
+entry:
+  br i1 %c, label %header, label %other
+
+other:
+  br label %header
+
+header:
+  %x = phi i32 [ 0, %entry ], [ 1, %other ], [ %x.next, %header ]
+  %invariant = mul i32 %a, %a
+  %x.next = add i32 %x, %invariant
+  %done = icmp sgt i32 %x.next, 100
+  br i1 %done, label %exit, label %header
+
+exit:
+  ret i32 %x.next
+}
@@ -0,0 +1,13 @@
+# Run with `just test`, which runs these through `cargo test` with
+# `LLVM_PASS_PLUGIN` pointing at the built plugin.
+
+import os
+
+import lit.formats
+
+config.name = "llvm-pass"
+config.test_format = lit.formats.ShTest(True)
+config.suffixes = [".c", ".ll"]
+config.test_source_root = os.path.dirname(__file__)
+config.environment["PATH"] = os.environ["PATH"]
+config.substitutions.append(("%plugin", os.environ["LLVM_PASS_PLUGIN"]))
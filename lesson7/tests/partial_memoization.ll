@@ -0,0 +1,43 @@
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all>:verbose' %s -S -o %t.ll 2> %t.log
+; RUN: FileCheck %s < %t.ll
+; RUN: FileCheck %s --check-prefix=LOG < %t.log
+
+; Parameters that are not integers are ignored when unused and otherwise
+; memoized on through the outcomes of comparing them for equality.
+
+; LOG: Ignoring parameter 1 of "scale" because it is unused
+; LOG: Memoizing on the outcomes of 1 comparisons of parameter 2 of "scale" against constants
+; LOG: Memoizing "scale"
+; CHECK-DAG: @scale.memo_value_array = internal global [32 x i32] zeroinitializer
+; CHECK-LABEL: define i32 @scale(i32 %x, ptr %unused, double %mode)
+; CHECK-NEXT: memo_header:
+; CHECK: %equality_outcome = fcmp oeq double %mode, 0.000000e+00
+; CHECK-NEXT: %equality_key = zext i1 %equality_outcome to i8
+define i32 @scale(i32 %x, ptr %unused, double %mode) {
+entry:
+  %mode.addr = alloca double, align 8
+  store double %mode, ptr %mode.addr, align 8
+  %lower = icmp sge i32 %x, 0
+  call void @llvm.assume(i1 %lower)
+  %upper = icmp slt i32 %x, 16
+  call void @llvm.assume(i1 %upper)
+  %0 = load double, ptr %mode.addr, align 8
+  %is_zero = fcmp oeq double %0, 0.000000e+00
+  %doubled = shl i32 %x, 1
+  %result = select i1 %is_zero, i32 %x, i32 %doubled
+  ret i32 %result
+}
+
+; LOG: Skipping memoization for "null_check" because parameter 0 is not an integer
+; CHECK-LABEL: define i32 @null_check(ptr %p)
+; CHECK-NOT: memo_
+; CHECK: ret i32
+define i32 @null_check(ptr %p) {
+entry:
+  %is_null = icmp eq ptr %p, null
+  %offset = ptrtoint ptr %p to i32
+  %result = select i1 %is_null, i32 0, i32 %offset
+  ret i32 %result
+}
+
+declare void @llvm.assume(i1 noundef)
@@ -0,0 +1,30 @@
+// RUN: clang -S -emit-llvm %s -o %t.ll
+// RUN: lli %t.ll | FileCheck %s --check-prefix=OUTPUT
+// RUN: opt --load-pass-plugin=%plugin --passes=auto-memoize:profile %t.ll -S -o %t.memo.ll
+// RUN: lli %t.memo.ll 2> %t.calls | FileCheck %s --check-prefix=OUTPUT
+// RUN: FileCheck %s --check-prefix=CALLS < %t.calls
+
+// Without memoization, fib(25) makes 2 * fib(26) - 1 = 242785 calls. The
+// recursive calls go through the memo table too, so with it, each of fib(0),
+// ..., fib(25) misses once, and fib(n - 2) hits for n = 3, ..., 25 because
+// fib(n - 1) just computed it, for 49 calls in total.
+
+// OUTPUT: 75025
+// CALLS: [auto-memoize] fib: 23 hits, 26 misses, 0 out of bounds
+
+#include <stdio.h>
+
+#define N 30
+
+__attribute__((annotate("memoize"))) int fib(int n) {
+    __builtin_assume(n >= 0);
+    __builtin_assume(n < N);
+    if (n < 2) {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+
+int main() {
+    printf("%d\n", fib(25));
+}
@@ -0,0 +1,89 @@
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all>:verbose' %s -S -o %t.ll 2> %t.log
+; RUN: FileCheck %s < %t.ll
+; RUN: FileCheck %s --check-prefix=LOG < %t.log
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize:verbose' %s -S -o /dev/null 2>&1 | FileCheck %s --check-prefix=ANNOTATED
+; RUN: not opt --load-pass-plugin=%plugin --passes='auto-memoize<bogus>' %s -S -o /dev/null 2>&1 | FileCheck %s --check-prefix=BAD
+
+; BAD: auto-memoize<bogus>
+
+; ANNOTATED: Skipping memoization for "pure_but_unannotated" because it is not annotated `memoize`
+
+; LOG: Skipping memoization for "with_float" because parameter 0 is not an integer
+; CHECK-LABEL: define i32 @with_float(double %x)
+; CHECK-NOT: memo_
+; CHECK: ret i32
+define i32 @with_float(double %x) {
+entry:
+  %result = fptosi double %x to i32
+  ret i32 %result
+}
+
+; LOG: Skipping memoization for "too_many" because it has more than 3 integer parameters
+; CHECK-LABEL: define i32 @too_many(i32 %a, i32 %b, i32 %c, i32 %d)
+; CHECK-NOT: memo_
+; CHECK: ret i32
+define i32 @too_many(i32 %a, i32 %b, i32 %c, i32 %d) {
+entry:
+  %ab = add i32 %a, %b
+  %cd = add i32 %c, %d
+  %result = add i32 %ab, %cd
+  ret i32 %result
+}
+
+; LOG: Skipping memoization for "returns_nothing" because it does not have a return type
+define void @returns_nothing(i32 %x) {
+entry:
+  ret void
+}
+
+@counter = global i32 0
+
+; LOG-NOT: Function "impure" is pure
+; CHECK-LABEL: define i32 @impure(i32 %x)
+; CHECK-NOT: memo_
+; CHECK: ret i32
+define i32 @impure(i32 %x) {
+entry:
+  store i32 %x, ptr @counter
+  ret i32 %x
+}
+
+; LOG-NOT: Function "reads_counter" is pure
+; CHECK-LABEL: define i32 @reads_counter(i32 %x)
+; CHECK-NOT: memo_
+; CHECK: ret i32
+define i32 @reads_counter(i32 %x) {
+entry:
+  %count = load i32, ptr @counter
+  %result = add i32 %x, %count
+  ret i32 %result
+}
+
+@squares = constant [4 x i32] [i32 0, i32 1, i32 4, i32 9]
+
+; LOG: Function "reads_constant" is pure
+; CHECK-LABEL: define i32 @reads_constant(i32 %x)
+define i32 @reads_constant(i32 %x) {
+entry:
+  %index = and i32 %x, 3
+  %element = getelementptr inbounds [4 x i32], ptr @squares, i32 0, i32 %index
+  %result = load i32, ptr %element
+  ret i32 %result
+}
+
+; LOG: Skipping memoization for "excluded" because it is annotated `nomemoize`
+; CHECK-LABEL: define i32 @excluded(i32 %x)
+; CHECK-NOT: memo_
+; CHECK: ret i32
+define i32 @excluded(i32 %x) {
+entry:
+  ret i32 %x
+}
+
+define i32 @pure_but_unannotated(i32 %x) {
+entry:
+  ret i32 %x
+}
+
+!nomemoize = !{!0}
+!0 = !{ptr @excluded}
@@ -0,0 +1,56 @@
+; RUN: opt --load-pass-plugin=%plugin --passes='auto-memoize<all>:verbose' %s -S -o %t.ll 2> %t.log
+; RUN: FileCheck %s < %t.ll
+; RUN: FileCheck %s --check-prefix=LOG < %t.log
+
+; Booleans and parameters bounded by an exhaustive switch each take only as
+; many bits of the index as their range needs, offset by its start.
+
+; LOG-LABEL: Visiting function "classify"
+; LOG: Derived parameter bound ({{.*}}%kind) >= 3
+; LOG: Derived parameter bound ({{.*}}%kind) < 6
+; LOG: Memoizing "classify"
+; CHECK-DAG: @classify.memo_value_array = internal global [8 x i32] zeroinitializer
+; CHECK-LABEL: define i32 @classify(i32 %kind, i1 %flag)
+; CHECK-NEXT: memo_header:
+; CHECK: %extended_boolean = zext i1 %flag to i8
+; CHECK: %offset = sub i64 %extended_parameter, 3
+define i32 @classify(i32 %kind, i1 %flag) {
+entry:
+  switch i32 %kind, label %default [
+    i32 3, label %three
+    i32 4, label %four
+    i32 5, label %five
+  ]
+
+default:
+  unreachable
+
+three:
+  %first = select i1 %flag, i32 10, i32 11
+  ret i32 %first
+
+four:
+  ret i32 20
+
+five:
+  %second = select i1 %flag, i32 30, i32 31
+  ret i32 %second
+}
+
+; A switch with a reachable default says nothing about the range.
+
+; LOG-LABEL: Visiting function "partial"
+; LOG-NOT: Derived parameter bound
+; LOG: Using a hash table for "partial"
+define i32 @partial(i32 %kind) {
+entry:
+  switch i32 %kind, label %other [
+    i32 0, label %zero
+  ]
+
+zero:
+  ret i32 1
+
+other:
+  ret i32 2
+}
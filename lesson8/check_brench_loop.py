@@ -18,33 +18,35 @@ def check_did_optimize(baseline, new, name):
         print(f"> \x1b[33m{name} NOP ({name}: {new}, baseline: {baseline})\x1b[m")
 
 
-for i in range(1, len(rows), 3):
+RUNS = [
+    "insert-preheader",
+    "licm",
+    "fusion",
+    "distribution",
+    "dead-loop-deletion",
+    "scalar-promotion",
+    "address-precomputation",
+]
+
+for i in range(1, len(rows), len(RUNS) + 1):
     baseline = rows[i]
-    insert_preheader = rows[i + 1]
-    licm = rows[i + 2]
+    optimized = rows[i + 1 : i + 1 + len(RUNS)]
 
-    if insert_preheader[2] == "incorrect" or licm[2] == "incorrect":
+    if any(row[2] == "incorrect" for row in optimized):
         print(f"\x1b[31m{baseline[0]} INCORRECT\x1b[m")
         sys.exit(1)
-    elif insert_preheader[2] == "timeout" or licm[2] == "timeout":
+    elif any(row[2] == "timeout" for row in optimized):
         print(f"\x1b[31m{baseline[0]} TIMED OUT\x1b[m")
         sys.exit(1)
-    elif insert_preheader[2] == "missing" or insert_preheader[2] == "missing":
+    elif any(row[2] == "missing" for row in optimized):
         print(f"\x1b[31m{baseline[0]} MISSING\x1b[m")
         sys.exit(1)
 
     baseline_time = int(baseline[2])
-    insert_preheader_time = int(insert_preheader[2])
-    licm_time = int(licm[2])
+    times = [int(row[2]) for row in optimized]
 
     print(f"{baseline[0]}")
-    check_did_optimize(baseline_time, insert_preheader_time, "insert-preheader")
-    check_did_optimize(baseline_time, licm_time, "licm")
-    times_scored = sorted(
-        [
-            (baseline_time, "baseline"),
-            (insert_preheader_time, "insert-preheader"),
-            (licm_time, "licm"),
-        ]
-    )
+    for name, time in zip(RUNS, times):
+        check_did_optimize(baseline_time, time, name)
+    times_scored = sorted([(baseline_time, "baseline"), *zip(times, RUNS)])
     print(f"  (times in order: {times_scored})")
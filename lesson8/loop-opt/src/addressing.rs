@@ -0,0 +1,192 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bril_rs::{Instruction, ValueOps};
+use bril_util::{
+    InstructionExt,
+    builder::{make_value, with_pos},
+};
+use build_cfg::{BasicBlockIdx, FunctionCfg, slotmap::SecondaryMap};
+
+use crate::loops::{LoopForest, NaturalLoop, fresh_variable};
+
+fn is_defined_in_loop(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+    name: &str,
+) -> bool {
+    natural_loop.body.iter().any(|block| {
+        cfg.vertices[*block].instructions.iter().any(|instruction| {
+            instruction.kill().map(String::as_str) == Some(name)
+        })
+    })
+}
+
+/// Whether `name` is an argument or is defined in some block dominating
+/// `block`, so that it is certainly defined at the end of `block`.
+fn is_available(
+    cfg: &FunctionCfg,
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    block: BasicBlockIdx,
+    name: &str,
+) -> bool {
+    cfg.signature
+        .arguments
+        .iter()
+        .any(|argument| argument.name == name)
+        || dominators[block].iter().any(|dominator| {
+            cfg.vertices[*dominator]
+                .instructions
+                .iter()
+                .any(|instruction| {
+                    instruction.kill().map(String::as_str) == Some(name)
+                })
+        })
+}
+
+/// If `pointer` is computed earlier in `block` than `index` by `pointer =
+/// ptradd base offset` and `offset` is not reassigned in between, returns the
+/// index of that definition, `base`, and `offset`.
+fn find_address_chain(
+    cfg: &FunctionCfg,
+    block: BasicBlockIdx,
+    index: usize,
+    pointer: &str,
+) -> Option<(usize, String, String)> {
+    let instructions = &cfg.vertices[block].instructions[..index];
+    let definition_index = instructions.iter().rposition(|instruction| {
+        instruction.kill().map(String::as_str) == Some(pointer)
+    })?;
+    let Instruction::Value {
+        args,
+        op: ValueOps::PtrAdd,
+        ..
+    } = &instructions[definition_index]
+    else {
+        return None;
+    };
+    let [base, offset] = args.as_slice() else {
+        return None;
+    };
+    if instructions[definition_index + 1..]
+        .iter()
+        .any(|instruction| instruction.kill() == Some(offset))
+    {
+        return None;
+    }
+    Some((definition_index, base.clone(), offset.clone()))
+}
+
+/// Precomputes `ptradd base offset` in the preheader of each loop for a
+/// loop-invariant `base` and `offset` and rewrites the address expressions in
+/// the loop to use the precomputed pointers. Chains `ptradd (ptradd base
+/// variant) invariant` are reassociated to `ptradd (ptradd base invariant)
+/// variant` so that the inner address can be precomputed too.
+///
+/// Requires: `forest` has been normalized.
+pub fn precompute_addresses(cfg: &mut FunctionCfg, forest: &LoopForest) {
+    let dominators = dominators::compute_dominators(cfg);
+    for natural_loop in forest.innermost_first() {
+        let preheader = natural_loop.preheader();
+        let mut precomputed = HashMap::<(String, String), String>::new();
+
+        for &block in &natural_loop.body {
+            let mut index = 0;
+            while index < cfg.vertices[block].instructions.len() {
+                let Instruction::Value {
+                    args,
+                    dest,
+                    op: ValueOps::PtrAdd,
+                    op_type,
+                    pos,
+                    ..
+                } = &cfg.vertices[block].instructions[index]
+                else {
+                    index += 1;
+                    continue;
+                };
+                let [pointer, offset] = args.as_slice() else {
+                    index += 1;
+                    continue;
+                };
+                let (dest, op_type, pos) =
+                    (dest.clone(), op_type.clone(), pos.clone());
+                let (pointer, offset) = (pointer.clone(), offset.clone());
+
+                let is_invariant = |name: &str| {
+                    !is_defined_in_loop(cfg, natural_loop, name)
+                        && is_available(cfg, &dominators, preheader, name)
+                };
+                if !is_invariant(&offset) {
+                    index += 1;
+                    continue;
+                }
+                let (base, chain) = if is_invariant(&pointer) {
+                    (pointer.clone(), None)
+                } else {
+                    match find_address_chain(cfg, block, index, &pointer) {
+                        Some((definition_index, base, variant_offset))
+                            if is_invariant(&base) =>
+                        {
+                            (base, Some((definition_index, variant_offset)))
+                        }
+                        _ => {
+                            index += 1;
+                            continue;
+                        }
+                    }
+                };
+
+                let key = (base.clone(), offset.clone());
+                let precomputed_pointer = match precomputed.get(&key) {
+                    Some(precomputed_pointer) => precomputed_pointer.clone(),
+                    None => {
+                        let precomputed_pointer =
+                            fresh_variable(cfg, format!("{base}.{offset}"));
+                        let insertion_point =
+                            cfg.vertices[preheader].index_before_exit();
+                        cfg.vertices[preheader].instructions.insert(
+                            insertion_point,
+                            make_value(
+                                precomputed_pointer.clone(),
+                                op_type.clone(),
+                                ValueOps::PtrAdd,
+                                vec![base, offset],
+                            ),
+                        );
+                        precomputed.insert(key, precomputed_pointer.clone());
+                        precomputed_pointer
+                    }
+                };
+
+                let (op, args) = match &chain {
+                    Some((_, variant_offset)) => (
+                        ValueOps::PtrAdd,
+                        vec![precomputed_pointer, variant_offset.clone()],
+                    ),
+                    None => (ValueOps::Id, vec![precomputed_pointer]),
+                };
+                cfg.vertices[block].instructions[index] =
+                    with_pos(make_value(dest, op_type, op, args), pos);
+
+                // the intermediate address of a reassociated chain is often
+                // used nowhere else
+                if let Some((definition_index, _)) = chain {
+                    let is_used = cfg
+                        .vertices
+                        .values()
+                        .flat_map(|block| &block.instructions)
+                        .any(|instruction| {
+                            instruction.gen_set().contains(&pointer)
+                        });
+                    if !is_used {
+                        cfg.vertices[block]
+                            .instructions
+                            .remove(definition_index);
+                        index -= 1;
+                    }
+                }
+                index += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bril_rs::{Instruction, Type, ValueOps};
+use build_cfg::{
+    BasicBlockIdx, FunctionCfg,
+    pass::{Analysis, AnalysisCache, PersistentAnalysis},
+};
+use serde_json::{Value, json};
+
+/// An abstract memory location a pointer may refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemoryLocation {
+    /// Somewhere inside the allocation made by the `alloc` at the given
+    /// instruction.
+    Allocation(BasicBlockIdx, usize),
+    /// Anywhere, e.g., for pointers passed as arguments or loaded from memory.
+    Unknown,
+}
+
+/// A flow-insensitive, allocation-site-based may-alias analysis: two pointers
+/// may alias unless they provably point into different allocations made within
+/// the function.
+pub struct AliasAnalysis {
+    points_to: HashMap<String, BTreeSet<MemoryLocation>>,
+}
+
+impl AliasAnalysis {
+    pub fn new(cfg: &FunctionCfg) -> Self {
+        let mut points_to = HashMap::<String, BTreeSet<MemoryLocation>>::new();
+        for argument in &cfg.signature.arguments {
+            if matches!(argument.arg_type, Type::Pointer(_)) {
+                points_to
+                    .entry(argument.name.clone())
+                    .or_default()
+                    .insert(MemoryLocation::Unknown);
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (block_idx, block) in &cfg.vertices {
+                for (i, instruction) in block.instructions.iter().enumerate() {
+                    let Instruction::Value {
+                        args,
+                        dest,
+                        op,
+                        op_type: Type::Pointer(_),
+                        ..
+                    } = instruction
+                    else {
+                        continue;
+                    };
+
+                    let new_locations =
+                        match op {
+                            ValueOps::Alloc => BTreeSet::from_iter([
+                                MemoryLocation::Allocation(block_idx, i),
+                            ]),
+                            ValueOps::Id | ValueOps::PtrAdd => args
+                                .first()
+                                .and_then(|pointer| points_to.get(pointer))
+                                .cloned()
+                                .unwrap_or_default(),
+                            _ => BTreeSet::from_iter([MemoryLocation::Unknown]),
+                        };
+
+                    let locations = points_to.entry(dest.clone()).or_default();
+                    for location in new_locations {
+                        changed |= locations.insert(location);
+                    }
+                }
+            }
+        }
+
+        Self { points_to }
+    }
+
+    /// The locations `pointer` may point to.
+    pub fn locations(&self, pointer: &str) -> BTreeSet<MemoryLocation> {
+        self.points_to
+            .get(pointer)
+            .cloned()
+            .unwrap_or_else(|| BTreeSet::from_iter([MemoryLocation::Unknown]))
+    }
+
+    pub fn may_alias(&self, first: &str, second: &str) -> bool {
+        let first = self.locations(first);
+        let second = self.locations(second);
+        first.contains(&MemoryLocation::Unknown)
+            || second.contains(&MemoryLocation::Unknown)
+            || !first.is_disjoint(&second)
+    }
+}
+
+/// The [`AliasAnalysis`] of a CFG, which can be saved between runs.
+pub struct PointsTo;
+
+impl Analysis for PointsTo {
+    type Output = AliasAnalysis;
+
+    fn compute(cfg: &FunctionCfg, _cache: &mut AnalysisCache) -> Self::Output {
+        AliasAnalysis::new(cfg)
+    }
+}
+
+impl PersistentAnalysis for PointsTo {
+    const KEY: &'static str = "points-to-v1";
+
+    /// Allocations are saved as the position of their block in
+    /// [`FunctionCfg::vertices`] and their index in it, and unknown locations
+    /// as `null`.
+    fn save(output: &Self::Output, cfg: &FunctionCfg) -> Value {
+        let positions = cfg
+            .vertices
+            .keys()
+            .enumerate()
+            .map(|(position, block_idx)| (block_idx, position))
+            .collect::<HashMap<_, _>>();
+        output
+            .points_to
+            .iter()
+            .map(|(pointer, locations)| {
+                let locations = locations
+                    .iter()
+                    .map(|location| match location {
+                        MemoryLocation::Allocation(block_idx, i) => {
+                            json!([positions[block_idx], i])
+                        }
+                        MemoryLocation::Unknown => Value::Null,
+                    })
+                    .collect::<Vec<_>>();
+                (pointer.clone(), Value::Array(locations))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    fn load(value: Value, cfg: &FunctionCfg) -> Option<Self::Output> {
+        let blocks = cfg.vertices.keys().collect::<Vec<_>>();
+        let Value::Object(pointers) = value else {
+            return None;
+        };
+        let mut points_to = HashMap::new();
+        for (pointer, locations) in pointers {
+            let locations = locations
+                .as_array()?
+                .iter()
+                .map(|location| match location {
+                    Value::Null => Some(MemoryLocation::Unknown),
+                    _ => {
+                        let position = location.get(0)?.as_u64()? as usize;
+                        let i = location.get(1)?.as_u64()? as usize;
+                        Some(MemoryLocation::Allocation(
+                            *blocks.get(position)?,
+                            i,
+                        ))
+                    }
+                })
+                .collect::<Option<BTreeSet<_>>>()?;
+            points_to.insert(pointer, locations);
+        }
+        Some(AliasAnalysis { points_to })
+    }
+}
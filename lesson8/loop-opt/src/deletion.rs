@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use bril_util::InstructionExt;
+use build_cfg::{
+    BasicBlockIdx, Exit, FunctionCfg, slotmap::SecondaryMap, symbol::Symbol,
+};
+use dataflow::live_variables::compute_live_variables;
+use snafu::{Whatever, whatever};
+
+use crate::{
+    induction::analyze_simple_loop,
+    licm::{Speculation, speculation_safety},
+    loops::{LoopForest, NaturalLoop, block_name, remove_blocks},
+    profile::{LoopPolicy, apply_policy},
+};
+
+/// Repeatedly deletes loops that have no observable effects and define nothing
+/// used after them, so that control goes straight from the preheader to the
+/// exit. Unless `assume_termination` is set, a loop is only deleted if it
+/// provably terminates, since deleting an infinite loop would change the
+/// behavior of the program. Only loops `policy` selects are deleted, in order
+/// of priority. Returns the reasons any otherwise-dead loops were kept.
+pub fn delete_dead_loops(
+    cfg: &mut FunctionCfg,
+    policy: &dyn LoopPolicy,
+    assume_termination: bool,
+) -> Vec<String> {
+    'delete: loop {
+        let dominators = dominators::compute_dominators(cfg);
+        let mut forest = LoopForest::find(cfg, &dominators);
+        forest.normalize(cfg);
+        apply_policy(cfg, &mut forest, policy);
+        let live_variables = compute_live_variables(cfg);
+
+        let mut failures = vec![];
+        for natural_loop in &forest.loops {
+            match check_deletion(
+                cfg,
+                &live_variables,
+                natural_loop,
+                assume_termination,
+            ) {
+                Ok(Some(exit)) => {
+                    cfg.reorient_edge(
+                        natural_loop.preheader(),
+                        natural_loop.header,
+                        exit,
+                    );
+                    remove_blocks(cfg, &natural_loop.body);
+                    continue 'delete;
+                }
+                Ok(None) => {}
+                Err(error) => failures.push(format!(
+                    "not deleting dead loop at `{}`: {error}",
+                    block_name(cfg, natural_loop.header)
+                )),
+            }
+        }
+        return failures;
+    }
+}
+
+/// Returns the block to jump to instead of the loop if it is dead, or `None`
+/// if it is not.
+fn check_deletion(
+    cfg: &FunctionCfg,
+    live_variables: &SecondaryMap<BasicBlockIdx, HashSet<Symbol>>,
+    natural_loop: &NaturalLoop,
+    assume_termination: bool,
+) -> Result<Option<BasicBlockIdx>, Whatever> {
+    for block in &natural_loop.body {
+        if matches!(cfg.edges[*block], Exit::Return(_)) {
+            return Ok(None);
+        }
+        let block = &cfg.vertices[*block];
+        if block.instructions[..block.index_before_exit()].iter().any(
+            |instruction| speculation_safety(instruction) != Speculation::Safe,
+        ) {
+            return Ok(None);
+        }
+    }
+
+    let exit_blocks = natural_loop.exit_blocks(cfg);
+    let mut exit_blocks = exit_blocks.iter();
+    let (Some(exit), None) = (exit_blocks.next(), exit_blocks.next()) else {
+        return Ok(None);
+    };
+    let is_used_after_loop = natural_loop
+        .body
+        .iter()
+        .flat_map(|block| &cfg.vertices[*block].instructions)
+        .filter_map(|instruction| instruction.kill())
+        .any(|variable| {
+            live_variables[*exit].contains(&cfg.symbols.intern(variable))
+        });
+    if is_used_after_loop {
+        return Ok(None);
+    }
+
+    if !assume_termination {
+        let simple_loop = match analyze_simple_loop(cfg, natural_loop) {
+            Ok(simple_loop) => simple_loop,
+            Err(error) => whatever!(
+                "cannot prove that it terminates ({error}); pass --assume-termination to delete it anyway"
+            ),
+        };
+        if let Err(error) = simple_loop.check_termination(cfg) {
+            whatever!(
+                "cannot prove that it terminates ({error}); pass --assume-termination to delete it anyway"
+            );
+        }
+    }
+
+    Ok(Some(*exit))
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeSet;
+
+use bril_rs::{EffectOps, Instruction, ValueOps};
+use bril_util::InstructionExt;
+
+use crate::alias::AliasAnalysis;
+
+/// The variables and memory read and written by a sequence of instructions.
+#[derive(Debug, Default)]
+pub struct Accesses {
+    pub reads: BTreeSet<String>,
+    pub writes: BTreeSet<String>,
+    pub loaded_pointers: BTreeSet<String>,
+    pub stored_pointers: BTreeSet<String>,
+    /// Whether any instruction has effects whose relative order is observable,
+    /// such as printing or calling a function.
+    pub has_ordered_effects: bool,
+}
+
+impl Accesses {
+    pub fn of<'a>(
+        instructions: impl IntoIterator<Item = &'a Instruction>,
+    ) -> Self {
+        let mut accesses = Self::default();
+        for instruction in instructions {
+            accesses.reads.extend(instruction.gen_set().iter().cloned());
+            if let Some(kill) = instruction.kill() {
+                accesses.writes.insert(kill.clone());
+            }
+            match instruction {
+                Instruction::Value {
+                    args,
+                    op: ValueOps::Load,
+                    ..
+                } => {
+                    accesses.loaded_pointers.extend(args.first().cloned());
+                }
+                // `get` reads the shadow variable that a `set` writes
+                Instruction::Value {
+                    op: ValueOps::Call | ValueOps::Alloc | ValueOps::Get,
+                    ..
+                } => {
+                    accesses.has_ordered_effects = true;
+                }
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Store,
+                    ..
+                } => {
+                    accesses.stored_pointers.extend(args.first().cloned());
+                }
+                Instruction::Effect { .. } => {
+                    accesses.has_ordered_effects = true;
+                }
+                _ => {}
+            }
+        }
+        accesses
+    }
+
+    /// Explains why the instructions summarized by `self` cannot be reordered
+    /// with respect to those summarized by `other`, if they cannot be.
+    pub fn conflict_with(
+        &self,
+        other: &Accesses,
+        aliases: &AliasAnalysis,
+    ) -> Option<String> {
+        if self.has_ordered_effects && other.has_ordered_effects {
+            return Some("both have observable side effects".into());
+        }
+        if let Some(variable) = self.writes.intersection(&other.reads).next() {
+            return Some(format!("`{variable}` is written and then read"));
+        }
+        if let Some(variable) = other.writes.intersection(&self.reads).next() {
+            return Some(format!("`{variable}` is read and then written"));
+        }
+        if let Some(variable) = self.writes.intersection(&other.writes).next() {
+            return Some(format!("`{variable}` is written by both"));
+        }
+        for (stored_pointers, accessed_pointers) in [
+            (&self.stored_pointers, &other.accessed_pointers()),
+            (&other.stored_pointers, &self.accessed_pointers()),
+        ] {
+            for stored_pointer in stored_pointers {
+                for accessed_pointer in accessed_pointers {
+                    if aliases.may_alias(stored_pointer, accessed_pointer) {
+                        return Some(format!(
+                            "store through `{stored_pointer}` may alias access through `{accessed_pointer}`"
+                        ));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn accessed_pointers(&self) -> BTreeSet<String> {
+        self.loaded_pointers
+            .union(&self.stored_pointers)
+            .cloned()
+            .collect()
+    }
+}
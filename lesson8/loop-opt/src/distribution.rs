@@ -0,0 +1,230 @@
+use bril_rs::Instruction;
+use build_cfg::{
+    BasicBlock, FunctionCfg, pass::AnalysisCache, smallvec::smallvec,
+};
+use snafu::{OptionExt, Whatever, whatever};
+
+use crate::{
+    alias::{AliasAnalysis, PointsTo},
+    dependence::Accesses,
+    induction::{
+        SimpleLoop, analyze_simple_loop, find_constant_initialization,
+    },
+    loops::{LoopForest, NaturalLoop, block_name, fresh_label},
+    profile::{LoopPolicy, apply_policy},
+};
+
+/// A legal distribution of a loop into one loop per group of statements.
+struct Distribution {
+    simple_loop: SimpleLoop,
+    initialization: Instruction,
+    /// The statements of the loop body, excluding the induction variable
+    /// update, partitioned into groups in their original order.
+    groups: Vec<Vec<Instruction>>,
+}
+
+/// Splits every loop `policy` selects whose body consists of independent
+/// groups of statements into one loop per group, in order of priority,
+/// returning the reasons any loops could not be split.
+pub fn distribute_loops(
+    cfg: &mut FunctionCfg,
+    cache: &mut AnalysisCache,
+    policy: &dyn LoopPolicy,
+) -> Vec<String> {
+    'distribute: loop {
+        let dominators = dominators::compute_dominators(cfg);
+        let mut forest = LoopForest::find(cfg, &dominators);
+        forest.normalize(cfg);
+        cache.invalidate();
+        apply_policy(cfg, &mut forest, policy);
+        let aliases = cache.get_persistent::<PointsTo>(cfg);
+
+        let mut failures = vec![];
+        for natural_loop in &forest.loops {
+            match check_distribution(cfg, &aliases, natural_loop) {
+                Ok(Some(distribution)) => {
+                    distribute(cfg, distribution);
+                    continue 'distribute;
+                }
+                Ok(None) => {}
+                Err(error) => failures.push(format!(
+                    "cannot distribute loop at `{}`: {error}",
+                    block_name(cfg, natural_loop.header)
+                )),
+            }
+        }
+        return failures;
+    }
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    if parents[i] != i {
+        parents[i] = find(parents, parents[i]);
+    }
+    parents[i]
+}
+
+/// Returns `None` if the loop body cannot be split into more than one group.
+fn check_distribution(
+    cfg: &FunctionCfg,
+    aliases: &AliasAnalysis,
+    natural_loop: &NaturalLoop,
+) -> Result<Option<Distribution>, Whatever> {
+    let simple_loop = match analyze_simple_loop(cfg, natural_loop) {
+        Ok(simple_loop) => simple_loop,
+        Err(error) => whatever!("loop is not simple: {error}"),
+    };
+    let induction_variable = &simple_loop.induction_variable;
+
+    let latch_instructions = &cfg.vertices[simple_loop.latch].instructions;
+    let statements = &latch_instructions[..simple_loop.update_index(cfg)];
+    let statement_accesses = statements
+        .iter()
+        .map(|statement| Accesses::of([statement]))
+        .collect::<Vec<_>>();
+
+    // statements that cannot be reordered with respect to each other must end
+    // up in the same loop
+    let mut parents = (0..statements.len()).collect::<Vec<_>>();
+    for i in 0..statements.len() {
+        for j in (i + 1)..statements.len() {
+            if statement_accesses[i]
+                .conflict_with(&statement_accesses[j], aliases)
+                .is_some()
+            {
+                let (root_i, root_j) =
+                    (find(&mut parents, i), find(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+    let mut roots = vec![];
+    let mut groups = Vec::<Vec<Instruction>>::new();
+    for (i, statement) in statements.iter().enumerate() {
+        let root = find(&mut parents, i);
+        let group = match roots.iter().position(|other| *other == root) {
+            Some(group) => group,
+            None => {
+                roots.push(root);
+                groups.push(vec![]);
+                groups.len() - 1
+            }
+        };
+        groups[group].push(statement.clone());
+    }
+    if groups.len() < 2 {
+        return Ok(None);
+    }
+
+    // the header is duplicated into every new loop, so it must compute the
+    // same thing each time
+    let header_block = &cfg.vertices[simple_loop.header];
+    let header_accesses = Accesses::of(
+        &header_block.instructions[..header_block.index_before_exit()],
+    );
+    let body_accesses = Accesses::of(statements);
+    if header_accesses.has_ordered_effects {
+        whatever!("loop header has side effects");
+    }
+    if !header_accesses.loaded_pointers.is_empty() {
+        whatever!("loop header reads memory");
+    }
+    if let Some(variable) = header_accesses
+        .reads
+        .union(&header_accesses.writes)
+        .find(|variable| body_accesses.writes.contains(*variable))
+    {
+        whatever!("loop header uses `{variable}`, which the body assigns");
+    }
+
+    let (init_block, init_index) = find_constant_initialization(
+        cfg,
+        simple_loop.preheader,
+        &induction_variable.name,
+    )
+    .with_whatever_context(|| {
+        format!(
+            "initial value of `{}` is not a known constant",
+            induction_variable.name
+        )
+    })?;
+    let initialization =
+        cfg.vertices[init_block].instructions[init_index].clone();
+
+    Ok(Some(Distribution {
+        simple_loop,
+        initialization,
+        groups,
+    }))
+}
+
+/// Keeps the first group in the original loop and places every other group in
+/// a copy of the loop, which reinitializes the induction variable and runs
+/// after the previous one.
+fn distribute(cfg: &mut FunctionCfg, distribution: Distribution) {
+    let Distribution {
+        simple_loop,
+        initialization,
+        mut groups,
+    } = distribution;
+    let header_name = block_name(cfg, simple_loop.header);
+
+    let header_block = &cfg.vertices[simple_loop.header];
+    let header_instructions =
+        header_block.instructions[..header_block.index_before_exit()].to_vec();
+    let update = cfg.vertices[simple_loop.latch].instructions
+        [simple_loop.update_index(cfg)]
+    .clone();
+
+    let rest = groups.split_off(1);
+    let first_group = groups.pop().expect("there are at least two groups");
+    let update_index = simple_loop.update_index(cfg);
+    let latch_instructions = &mut cfg.vertices[simple_loop.latch].instructions;
+    latch_instructions.drain(..update_index);
+    latch_instructions.insert_many(0, first_group);
+
+    let mut copies = vec![];
+    for group in rest {
+        let label = fresh_label(cfg, format!("{header_name}_preheader"));
+        let preheader = cfg.add_block(BasicBlock {
+            label: Some(label),
+            instructions: smallvec![initialization.clone()],
+            ..Default::default()
+        });
+        let label = fresh_label(cfg, header_name.clone());
+        let header = cfg.add_block(BasicBlock {
+            label: Some(label),
+            instructions: header_instructions.clone().into(),
+            ..Default::default()
+        });
+        let label = fresh_label(cfg, format!("{header_name}_latch"));
+        let mut instructions = group;
+        instructions.push(update.clone());
+        let latch = cfg.add_block(BasicBlock {
+            label: Some(label),
+            instructions: instructions.into(),
+            ..Default::default()
+        });
+        copies.push((preheader, header, latch));
+    }
+
+    cfg.reorient_edge(simple_loop.header, simple_loop.exit, copies[0].0);
+    for (i, &(preheader, header, latch)) in copies.iter().enumerate() {
+        let exit = copies
+            .get(i + 1)
+            .map_or(simple_loop.exit, |(next_preheader, _, _)| *next_preheader);
+        let (if_true, if_false) = if simple_loop.stays_if_true {
+            (latch, exit)
+        } else {
+            (exit, latch)
+        };
+        cfg.set_unconditional_edge(preheader, header);
+        cfg.set_conditional_edge(
+            header,
+            simple_loop.induction_variable.condition.clone(),
+            if_true,
+            if_false,
+        );
+        cfg.set_unconditional_edge(latch, header);
+    }
+}
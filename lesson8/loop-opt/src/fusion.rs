@@ -0,0 +1,258 @@
+use std::collections::BTreeSet;
+
+use bril_rs::Instruction;
+use bril_util::InstructionExt;
+use build_cfg::{BasicBlockIdx, FunctionCfg, pass::AnalysisCache};
+use snafu::{OptionExt, Whatever, whatever};
+
+use crate::{
+    alias::{AliasAnalysis, PointsTo},
+    dependence::Accesses,
+    induction::{
+        SimpleLoop, analyze_simple_loop, find_constant_initialization,
+    },
+    loops::{LoopForest, NaturalLoop, block_name, remove_blocks},
+    profile::{LoopPolicy, apply_policy},
+};
+
+/// A legal fusion of two adjacent loops.
+struct Fusion {
+    first: SimpleLoop,
+    second: SimpleLoop,
+    /// The block between the loops: the exit of the first and the preheader
+    /// of the second.
+    between: BasicBlockIdx,
+    /// The index in `between` of the reinitialization of an induction
+    /// variable shared by both loops, which becomes redundant after fusion.
+    redundant_initialization: Option<usize>,
+}
+
+/// Repeatedly fuses pairs of adjacent loops iterating over the same range with
+/// no dependencies between them, trying the loops `policy` prioritizes first,
+/// returning the reasons any remaining adjacent pairs could not be fused.
+pub fn fuse_loops(
+    cfg: &mut FunctionCfg,
+    cache: &mut AnalysisCache,
+    policy: &dyn LoopPolicy,
+) -> Vec<String> {
+    'fuse: loop {
+        let dominators = dominators::compute_dominators(cfg);
+        let mut forest = LoopForest::find(cfg, &dominators);
+        forest.normalize(cfg);
+        cache.invalidate();
+        apply_policy(cfg, &mut forest, policy);
+        let aliases = cache.get_persistent::<PointsTo>(cfg);
+
+        let mut failures = vec![];
+        for first in &forest.loops {
+            for second in &forest.loops {
+                if first.header == second.header
+                    || first.exit_blocks(cfg)
+                        != BTreeSet::from_iter([second.preheader()])
+                {
+                    continue;
+                }
+                match check_fusion(cfg, &aliases, first, second) {
+                    Ok(fusion) => {
+                        fuse(cfg, fusion);
+                        continue 'fuse;
+                    }
+                    Err(error) => failures.push(format!(
+                        "cannot fuse loops at `{}` and `{}`: {error}",
+                        block_name(cfg, first.header),
+                        block_name(cfg, second.header)
+                    )),
+                }
+            }
+        }
+        return failures;
+    }
+}
+
+/// The instructions of a simple loop other than its jumps and branches.
+fn loop_instructions(
+    cfg: &FunctionCfg,
+    header: BasicBlockIdx,
+    latch: BasicBlockIdx,
+) -> impl Iterator<Item = &Instruction> {
+    [header, latch].into_iter().flat_map(move |block| {
+        let block = &cfg.vertices[block];
+        &block.instructions[..block.index_before_exit()]
+    })
+}
+
+fn check_fusion(
+    cfg: &FunctionCfg,
+    aliases: &AliasAnalysis,
+    first: &NaturalLoop,
+    second: &NaturalLoop,
+) -> Result<Fusion, Whatever> {
+    let first = match analyze_simple_loop(cfg, first) {
+        Ok(first) => first,
+        Err(error) => whatever!("first loop is not simple: {error}"),
+    };
+    let second = match analyze_simple_loop(cfg, second) {
+        Ok(second) => second,
+        Err(error) => whatever!("second loop is not simple: {error}"),
+    };
+    let between = second.preheader;
+    if cfg.predecessors(between) != [first.header] {
+        whatever!("the code between the loops is reachable from elsewhere");
+    }
+
+    let first_variable = &first.induction_variable;
+    let second_variable = &second.induction_variable;
+    if first_variable.comparison != second_variable.comparison
+        || first_variable.is_swapped != second_variable.is_swapped
+        || first.stays_if_true != second.stays_if_true
+        || first_variable.bound != second_variable.bound
+    {
+        whatever!("the loops do not have the same exit condition");
+    }
+    if first_variable.step != second_variable.step
+        && (first_variable.step_value.is_none()
+            || first_variable.step_value != second_variable.step_value)
+    {
+        whatever!(
+            "induction variables `{}` and `{}` do not have the same step",
+            first_variable.name,
+            second_variable.name
+        );
+    }
+
+    let (first_init_block, first_init_index) = find_constant_initialization(
+        cfg,
+        first.preheader,
+        &first_variable.name,
+    )
+    .with_whatever_context(|| {
+        format!(
+            "initial value of `{}` is not a known constant",
+            first_variable.name
+        )
+    })?;
+    let (second_init_block, second_init_index) =
+        find_constant_initialization(cfg, between, &second_variable.name)
+            .with_whatever_context(|| {
+                format!(
+                    "initial value of `{}` is not a known constant",
+                    second_variable.name
+                )
+            })?;
+    if cfg.vertices[first_init_block].instructions[first_init_index].value()
+        != cfg.vertices[second_init_block].instructions[second_init_index]
+            .value()
+    {
+        whatever!(
+            "induction variables `{}` and `{}` do not start at the same value",
+            first_variable.name,
+            second_variable.name
+        );
+    }
+    let redundant_initialization = (first_variable.name
+        == second_variable.name
+        && second_init_block == between)
+        .then_some(second_init_index);
+
+    let mut first_accesses =
+        Accesses::of(loop_instructions(cfg, first.header, first.latch));
+    let mut second_accesses =
+        Accesses::of(loop_instructions(cfg, second.header, second.latch));
+
+    // the code between the loops is moved before the first loop
+    let between_block = &cfg.vertices[between];
+    let between_accesses = Accesses::of(
+        between_block.instructions[..between_block.index_before_exit()]
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != redundant_initialization)
+            .map(|(_, instruction)| instruction),
+    );
+    if between_accesses.has_ordered_effects {
+        whatever!("the code between the loops has side effects");
+    }
+    if let Some(reason) =
+        first_accesses.conflict_with(&between_accesses, aliases)
+    {
+        whatever!(
+            "the code between the loops depends on the first loop: {reason}"
+        );
+    }
+
+    // a shared induction variable or condition takes the same value in both
+    // loops on every iteration, so it does not create a dependency
+    for shared in [
+        (&first_variable.name, &second_variable.name),
+        (&first_variable.condition, &second_variable.condition),
+    ]
+    .into_iter()
+    .filter_map(|(a, b)| (a == b).then_some(a))
+    {
+        first_accesses.writes.remove(shared);
+        second_accesses.writes.remove(shared);
+    }
+    if let Some(reason) =
+        first_accesses.conflict_with(&second_accesses, aliases)
+    {
+        whatever!("the loops depend on each other: {reason}");
+    }
+
+    Ok(Fusion {
+        first,
+        second,
+        between,
+        redundant_initialization,
+    })
+}
+
+/// Moves the code between the loops before the first loop and the header and
+/// latch of the second loop into those of the first, which then exits to where
+/// the second loop did.
+fn fuse(cfg: &mut FunctionCfg, fusion: Fusion) {
+    let Fusion {
+        first,
+        second,
+        between,
+        redundant_initialization,
+    } = fusion;
+
+    let between_end = cfg.vertices[between].index_before_exit();
+    let mut moved = cfg.vertices[between].instructions[..between_end].to_vec();
+    if let Some(redundant_initialization) = redundant_initialization {
+        moved.remove(redundant_initialization);
+    }
+    let insertion_point = cfg.vertices[first.preheader].index_before_exit();
+    cfg.vertices[first.preheader]
+        .instructions
+        .insert_many(insertion_point, moved);
+
+    let second_header_end = cfg.vertices[second.header].index_before_exit();
+    let second_header_instructions =
+        cfg.vertices[second.header].instructions[..second_header_end].to_vec();
+    let insertion_point = cfg.vertices[first.header].index_before_exit();
+    cfg.vertices[first.header]
+        .instructions
+        .insert_many(insertion_point, second_header_instructions);
+
+    // the first latch becomes its body, then the second body, then the
+    // induction variable updates, then the jump back to the header
+    let second_update_index = second.update_index(cfg);
+    let second_body =
+        cfg.vertices[second.latch].instructions[..second_update_index].to_vec();
+    let second_update =
+        cfg.vertices[second.latch].instructions[second_update_index].clone();
+    let first_update_index = first.update_index(cfg);
+    let first_latch = &mut cfg.vertices[first.latch].instructions;
+    let mut tail = first_latch.drain(first_update_index..).collect::<Vec<_>>();
+    first_latch.extend(second_body);
+    if first.induction_variable.name != second.induction_variable.name {
+        tail.insert(1, second_update);
+    }
+    first_latch.extend(tail);
+
+    cfg.reorient_edge(first.header, between, second.exit);
+    remove_blocks(
+        cfg,
+        &BTreeSet::from_iter([between, second.header, second.latch]),
+    );
+}
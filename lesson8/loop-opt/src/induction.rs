@@ -0,0 +1,328 @@
+use bril_rs::{Instruction, Literal, ValueOps};
+use bril_util::InstructionExt;
+use build_cfg::{BasicBlockIdx, Exit, FunctionCfg};
+use snafu::{OptionExt, Whatever, whatever};
+
+use crate::loops::NaturalLoop;
+
+/// A basic induction variable `name` updated once per iteration by `name =
+/// add name step` and compared against a loop-invariant `bound` to decide
+/// whether to stay in the loop.
+#[derive(Debug, Clone)]
+pub struct InductionVariable {
+    pub name: String,
+    pub step: String,
+    /// The value of `step`, if it is defined by a unique constant.
+    pub step_value: Option<i64>,
+    /// The comparison `condition = comparison name bound` (or with the
+    /// operands swapped when `is_swapped`) performed in the header.
+    pub comparison: ValueOps,
+    pub is_swapped: bool,
+    pub condition: String,
+    pub bound: String,
+}
+
+/// A normalized loop consisting of just a header, which decides whether to
+/// exit, and a latch, which contains the rest of the body and ends by updating
+/// the induction variable and jumping back to the header.
+#[derive(Debug, Clone)]
+pub struct SimpleLoop {
+    pub preheader: BasicBlockIdx,
+    pub header: BasicBlockIdx,
+    pub latch: BasicBlockIdx,
+    pub exit: BasicBlockIdx,
+    /// Whether the header branches to the latch when the condition is true.
+    pub stays_if_true: bool,
+    pub induction_variable: InductionVariable,
+}
+
+impl SimpleLoop {
+    /// The index of the induction variable update in the latch.
+    pub fn update_index(&self, cfg: &FunctionCfg) -> usize {
+        cfg.vertices[self.latch].index_before_exit() - 1
+    }
+
+    /// Whether the loop terminates regardless of the initial value of the
+    /// induction variable, explaining why not otherwise. Since integers wrap
+    /// around, this only holds when the induction variable moves toward the
+    /// bound by one each iteration and the bound is reachable.
+    pub fn check_termination(&self, cfg: &FunctionCfg) -> Result<(), Whatever> {
+        let induction_variable = &self.induction_variable;
+        let name = &induction_variable.name;
+
+        // normalize the condition to stay in the loop to `name op bound`
+        let mut op = induction_variable.comparison;
+        if induction_variable.is_swapped {
+            op = match op {
+                ValueOps::Lt => ValueOps::Gt,
+                ValueOps::Le => ValueOps::Ge,
+                ValueOps::Gt => ValueOps::Lt,
+                ValueOps::Ge => ValueOps::Le,
+                _ => op,
+            };
+        }
+        if !self.stays_if_true {
+            op = match op {
+                ValueOps::Lt => ValueOps::Ge,
+                ValueOps::Le => ValueOps::Gt,
+                ValueOps::Gt => ValueOps::Le,
+                ValueOps::Ge => ValueOps::Lt,
+                _ => op,
+            };
+        }
+
+        let (required_step, unreachable_bound) = match op {
+            ValueOps::Lt => (1, None),
+            ValueOps::Le => (1, Some(i64::MAX)),
+            ValueOps::Gt => (-1, None),
+            ValueOps::Ge => (-1, Some(i64::MIN)),
+            _ => whatever!("loop condition is not an integer comparison"),
+        };
+        if induction_variable.step_value != Some(required_step) {
+            whatever!(
+                "induction variable `{name}` does not move toward the bound by {required_step} each iteration"
+            );
+        }
+        if let Some(unreachable_bound) = unreachable_bound {
+            match unique_constant(cfg, &induction_variable.bound) {
+                Some(Literal::Int(bound)) if bound != unreachable_bound => {}
+                _ => whatever!(
+                    "bound `{}` of induction variable `{name}` may never be exceeded",
+                    induction_variable.bound
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_defined_in_loop(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+    name: &str,
+) -> bool {
+    natural_loop.body.iter().any(|block| {
+        cfg.vertices[*block].instructions.iter().any(|instruction| {
+            instruction.kill().map(String::as_str) == Some(name)
+        })
+    })
+}
+
+/// The value of the unique constant definition of `name` in `cfg`, if any.
+pub fn unique_constant(cfg: &FunctionCfg, name: &str) -> Option<Literal> {
+    let mut definitions = cfg
+        .vertices
+        .values()
+        .flat_map(|block| &block.instructions)
+        .filter(|instruction| {
+            instruction.kill().map(String::as_str) == Some(name)
+        });
+    match (definitions.next(), definitions.next()) {
+        (Some(Instruction::Constant { value, .. }), None) => {
+            Some(value.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Recognizes `natural_loop` as a [`SimpleLoop`], explaining why not
+/// otherwise.
+///
+/// Requires: `natural_loop` has been normalized.
+pub fn analyze_simple_loop(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+) -> Result<SimpleLoop, Whatever> {
+    let header = natural_loop.header;
+    let latch = natural_loop.latch();
+    if header == latch || natural_loop.body.len() != 2 {
+        whatever!("loop body is not just a header and a latch");
+    }
+
+    let Exit::Conditional {
+        condition,
+        if_true,
+        if_false,
+    } = &cfg.edges[header]
+    else {
+        whatever!("loop header does not end in a branch");
+    };
+    let (stays_if_true, exit) = if *if_true == latch {
+        (true, *if_false)
+    } else if *if_false == latch {
+        (false, *if_true)
+    } else {
+        whatever!("loop header does not branch to the latch");
+    };
+    if natural_loop.body.contains(&exit) {
+        whatever!("loop header does not branch out of the loop");
+    }
+    if !matches!(cfg.edges[latch], Exit::Unconditional(target) if target == header)
+    {
+        whatever!("loop latch does not jump straight to the header");
+    }
+
+    let induction_variable =
+        analyze_induction_variable(cfg, natural_loop, condition)?;
+
+    Ok(SimpleLoop {
+        preheader: natural_loop.preheader(),
+        header,
+        latch,
+        exit,
+        stays_if_true,
+        induction_variable,
+    })
+}
+
+/// Recognizes the induction variable of `natural_loop` that `condition`, which
+/// decides whether to stay in the loop, compares against a bound in the
+/// header, explaining why there is none otherwise. It must be updated at the
+/// end of the latch.
+///
+/// Requires: `natural_loop` has been normalized.
+pub fn analyze_induction_variable(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+    condition: &str,
+) -> Result<InductionVariable, Whatever> {
+    let Some(Instruction::Value {
+        args: comparison_args,
+        op: comparison,
+        ..
+    }) = cfg.vertices[natural_loop.header]
+        .instructions
+        .iter()
+        .rev()
+        .find(|instruction| {
+            instruction.kill().map(String::as_str) == Some(condition)
+        })
+    else {
+        whatever!("loop condition `{condition}` is not computed in the header");
+    };
+    if !matches!(
+        comparison,
+        ValueOps::Lt | ValueOps::Le | ValueOps::Gt | ValueOps::Ge
+    ) {
+        whatever!("loop condition `{condition}` is not an integer comparison");
+    }
+    let [lhs, rhs] = comparison_args.as_slice() else {
+        whatever!("loop condition `{condition}` is malformed");
+    };
+
+    let (name, bound, is_swapped) = match (
+        is_defined_in_loop(cfg, natural_loop, lhs),
+        is_defined_in_loop(cfg, natural_loop, rhs),
+    ) {
+        (true, false) => (lhs, rhs, false),
+        (false, true) => (rhs, lhs, true),
+        _ => whatever!(
+            "loop condition `{condition}` does not compare a loop variable against an invariant bound"
+        ),
+    };
+
+    let latch = natural_loop.latch();
+    let latch_instructions = &cfg.vertices[latch].instructions;
+    let update_index = cfg.vertices[latch]
+        .index_before_exit()
+        .checked_sub(1)
+        .whatever_context("loop latch is empty")?;
+    let Instruction::Value {
+        args: update_args,
+        dest,
+        op: ValueOps::Add,
+        ..
+    } = &latch_instructions[update_index]
+    else {
+        whatever!(
+            "induction variable `{name}` is not updated by an `add` at the end of the latch"
+        );
+    };
+    if dest != name {
+        whatever!(
+            "induction variable `{name}` is not updated at the end of the latch"
+        );
+    }
+    let step = match update_args.as_slice() {
+        [a, b] if a == name => b,
+        [a, b] if b == name => a,
+        _ => whatever!(
+            "induction variable `{name}` is not updated by adding a step to itself"
+        ),
+    };
+    if is_defined_in_loop(cfg, natural_loop, step) {
+        whatever!(
+            "step `{step}` of induction variable `{name}` is not invariant"
+        );
+    }
+    let definitions_of_name = natural_loop
+        .body
+        .iter()
+        .flat_map(|block| &cfg.vertices[*block].instructions)
+        .filter(|instruction| instruction.kill() == Some(name))
+        .count();
+    if definitions_of_name != 1 {
+        whatever!(
+            "induction variable `{name}` is assigned more than once in the loop"
+        );
+    }
+
+    let step_value = match unique_constant(cfg, step) {
+        Some(Literal::Int(value)) => Some(value),
+        _ => None,
+    };
+
+    Ok(InductionVariable {
+        name: name.clone(),
+        step: step.clone(),
+        step_value,
+        comparison: *comparison,
+        is_swapped,
+        condition: condition.to_string(),
+        bound: bound.clone(),
+    })
+}
+
+/// Finds the last definition of `name` before control reaches the end of
+/// `block`, following unique predecessors backward.
+pub fn find_reaching_definition(
+    cfg: &FunctionCfg,
+    mut block: BasicBlockIdx,
+    name: &str,
+) -> Option<(BasicBlockIdx, usize)> {
+    let mut visited = vec![];
+    loop {
+        if let Some(index) =
+            cfg.vertices[block]
+                .instructions
+                .iter()
+                .rposition(|instruction| {
+                    instruction.kill().map(String::as_str) == Some(name)
+                })
+        {
+            return Some((block, index));
+        }
+        visited.push(block);
+        match cfg.predecessors(block) {
+            [predecessor] if !visited.contains(predecessor) => {
+                block = *predecessor;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Like [`find_reaching_definition`], but only if the definition is a
+/// constant.
+pub fn find_constant_initialization(
+    cfg: &FunctionCfg,
+    block: BasicBlockIdx,
+    name: &str,
+) -> Option<(BasicBlockIdx, usize)> {
+    let (definition_block, index) = find_reaching_definition(cfg, block, name)?;
+    matches!(
+        cfg.vertices[definition_block].instructions[index],
+        Instruction::Constant { .. }
+    )
+    .then_some((definition_block, index))
+}
@@ -0,0 +1,722 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use bril_rs::{EffectOps, Instruction, Literal, ValueOps};
+use bril_util::InstructionExt;
+use build_cfg::{BasicBlockIdx, Exit, FunctionCfg, pass::AnalysisCache};
+use snafu::{OptionExt, Whatever, whatever};
+
+use crate::{
+    alias::{AliasAnalysis, PointsTo},
+    dependence::Accesses,
+    induction::{
+        InductionVariable, SimpleLoop, analyze_induction_variable,
+        analyze_simple_loop, find_constant_initialization, unique_constant,
+    },
+    loops::{LoopForest, NaturalLoop, block_name},
+};
+
+/// An integer polynomial over variables, as the coefficient of each monomial,
+/// which is a sorted list of variables. Monomials with a zero coefficient are
+/// left out, so that equal polynomials compare equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Polynomial(BTreeMap<Vec<String>, i64>);
+
+impl Polynomial {
+    fn constant(value: i64) -> Self {
+        let mut polynomial = Self::default();
+        polynomial.add_term(vec![], value);
+        polynomial
+    }
+
+    fn variable(name: &str) -> Self {
+        let mut polynomial = Self::default();
+        polynomial.add_term(vec![name.to_string()], 1);
+        polynomial
+    }
+
+    fn add_term(&mut self, monomial: Vec<String>, coefficient: i64) {
+        let sum = self
+            .0
+            .get(&monomial)
+            .copied()
+            .unwrap_or(0)
+            .wrapping_add(coefficient);
+        if sum == 0 {
+            self.0.remove(&monomial);
+        } else {
+            self.0.insert(monomial, sum);
+        }
+    }
+
+    /// `self + sign * other`.
+    fn add(&self, other: &Self, sign: i64) -> Self {
+        let mut sum = self.clone();
+        for (monomial, coefficient) in &other.0 {
+            sum.add_term(monomial.clone(), coefficient.wrapping_mul(sign));
+        }
+        sum
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut product = Self::default();
+        for (lhs, lhs_coefficient) in &self.0 {
+            for (rhs, rhs_coefficient) in &other.0 {
+                let mut monomial =
+                    lhs.iter().chain(rhs).cloned().collect::<Vec<_>>();
+                monomial.sort();
+                product.add_term(
+                    monomial,
+                    lhs_coefficient.wrapping_mul(*rhs_coefficient),
+                );
+            }
+        }
+        product
+    }
+
+    fn as_constant(&self) -> Option<i64> {
+        match self.0.iter().next() {
+            None => Some(0),
+            Some((monomial, coefficient))
+                if self.0.len() == 1 && monomial.is_empty() =>
+            {
+                Some(*coefficient)
+            }
+            _ => None,
+        }
+    }
+
+    fn mentions(&self, variable: &str) -> bool {
+        self.0
+            .keys()
+            .any(|monomial| monomial.iter().any(|name| name == variable))
+    }
+
+    /// `self` as `coefficient * variable + rest`, where neither mentions
+    /// `variable`, if it is linear in `variable`.
+    fn split(&self, variable: &str) -> Option<(Self, Self)> {
+        let mut coefficient = Self::default();
+        let mut rest = Self::default();
+        for (monomial, value) in &self.0 {
+            match monomial.iter().position(|name| name == variable) {
+                Some(position) => {
+                    let mut remaining = monomial.clone();
+                    remaining.remove(position);
+                    if remaining.iter().any(|name| name == variable) {
+                        return None;
+                    }
+                    coefficient.add_term(remaining, *value);
+                }
+                None => rest.add_term(monomial.clone(), *value),
+            }
+        }
+        Some((coefficient, rest))
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return "0".fmt(f);
+        }
+        let terms = self
+            .0
+            .iter()
+            .map(|(monomial, coefficient)| {
+                match (monomial.is_empty(), coefficient) {
+                    (true, _) => coefficient.to_string(),
+                    (false, 1) => monomial.join(" * "),
+                    (false, _) => {
+                        format!("{coefficient} * {}", monomial.join(" * "))
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        terms.join(" + ").fmt(f)
+    }
+}
+
+/// A load or store in the inner loop through `ptradd base index`.
+struct MemoryAccess {
+    base: String,
+    index: Polynomial,
+    is_store: bool,
+}
+
+/// A perfectly nested pair of loops: the outer header decides whether to run
+/// the inner loop, whose preheader only resets its induction variable and
+/// whose exit is the outer latch, which only updates the outer induction
+/// variable.
+struct Nest<'a> {
+    outer: &'a NaturalLoop,
+    inner: SimpleLoop,
+    outer_variable: InductionVariable,
+    /// Where the outer induction variable is initialized before the nest.
+    outer_initialization: (BasicBlockIdx, usize),
+    /// The index in the inner preheader where the inner induction variable
+    /// is reset.
+    inner_initialization: usize,
+}
+
+impl Nest<'_> {
+    /// The value of `name` just before `index` in the inner latch, following
+    /// its definitions in the latch back to the induction variables and
+    /// variables the outer loop does not define. [`None`] if it is not a
+    /// polynomial in those.
+    fn evaluate(
+        &self,
+        cfg: &FunctionCfg,
+        index: usize,
+        name: &str,
+    ) -> Option<Polynomial> {
+        let instructions = &cfg.vertices[self.inner.latch].instructions;
+        let Some(definition) =
+            instructions[..index].iter().rposition(|instruction| {
+                instruction.kill().map(String::as_str) == Some(name)
+            })
+        else {
+            if let Some(Literal::Int(value)) = unique_constant(cfg, name) {
+                return Some(Polynomial::constant(value));
+            }
+            let is_induction_variable = name == self.outer_variable.name
+                || name == self.inner.induction_variable.name;
+            return (is_induction_variable
+                || !is_defined_in_loop(cfg, self.outer, name))
+            .then(|| Polynomial::variable(name));
+        };
+        match &instructions[definition] {
+            Instruction::Constant {
+                value: Literal::Int(value),
+                ..
+            } => Some(Polynomial::constant(*value)),
+            Instruction::Value { args, op, .. } => {
+                match (op, args.as_slice()) {
+                    (ValueOps::Id, [arg]) => {
+                        self.evaluate(cfg, definition, arg)
+                    }
+                    (ValueOps::Add | ValueOps::Sub, [lhs, rhs]) => {
+                        let lhs = self.evaluate(cfg, definition, lhs)?;
+                        let rhs = self.evaluate(cfg, definition, rhs)?;
+                        let sign = if *op == ValueOps::Add { 1 } else { -1 };
+                        Some(lhs.add(&rhs, sign))
+                    }
+                    (ValueOps::Mul, [lhs, rhs]) => {
+                        let lhs = self.evaluate(cfg, definition, lhs)?;
+                        let rhs = self.evaluate(cfg, definition, rhs)?;
+                        Some(lhs.mul(&rhs))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The loads and stores in the inner loop, explaining why their addresses
+    /// cannot be analyzed otherwise.
+    fn memory_accesses(
+        &self,
+        cfg: &FunctionCfg,
+    ) -> Result<Vec<MemoryAccess>, Whatever> {
+        let instructions = &cfg.vertices[self.inner.latch].instructions;
+        let mut accesses = vec![];
+        for (index, instruction) in instructions.iter().enumerate() {
+            let (pointer, is_store) = match instruction {
+                Instruction::Value {
+                    args,
+                    op: ValueOps::Load,
+                    ..
+                } => (&args[0], false),
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Store,
+                    ..
+                } => (&args[0], true),
+                _ => continue,
+            };
+            let definition = instructions[..index]
+                .iter()
+                .rposition(|instruction| instruction.kill() == Some(pointer));
+            let (base, index) = match definition
+                .map(|definition| (definition, &instructions[definition]))
+            {
+                None if !is_defined_in_loop(cfg, self.outer, pointer) => {
+                    (pointer.clone(), Polynomial::default())
+                }
+                Some((
+                    definition,
+                    Instruction::Value {
+                        args,
+                        op: ValueOps::PtrAdd,
+                        ..
+                    },
+                )) if !is_defined_in_loop(cfg, self.outer, &args[0]) => {
+                    let index = self
+                        .evaluate(cfg, definition, &args[1])
+                        .with_whatever_context(|| {
+                            format!(
+                                "the offset `{}` of `{pointer}` is not a polynomial in the induction variables",
+                                args[1]
+                            )
+                        })?;
+                    (args[0].clone(), index)
+                }
+                _ => whatever!(
+                    "`{pointer}` is not an offset from a pointer computed outside the loops"
+                ),
+            };
+            accesses.push(MemoryAccess {
+                base,
+                index,
+                is_store,
+            });
+        }
+        Ok(accesses)
+    }
+
+    /// Whether the induction variable of the inner loop, if `inner`, and
+    /// otherwise that of the outer loop, takes the values `0, 1, ..., bound -
+    /// 1`, where `bound` is returned.
+    fn counts_from_zero(
+        &self,
+        cfg: &FunctionCfg,
+        inner: bool,
+    ) -> Option<Polynomial> {
+        let (variable, initialization) = if inner {
+            (
+                &self.inner.induction_variable,
+                (self.inner.preheader, self.inner_initialization),
+            )
+        } else {
+            (&self.outer_variable, self.outer_initialization)
+        };
+        let stays_below_bound = matches!(
+            (
+                variable.comparison,
+                variable.is_swapped,
+                self.inner.stays_if_true
+            ),
+            (ValueOps::Lt, false, true)
+                | (ValueOps::Gt, true, true)
+                | (ValueOps::Ge, false, false)
+                | (ValueOps::Le, true, false)
+        );
+        let (block, index) = initialization;
+        let starts_at_zero = matches!(
+            &cfg.vertices[block].instructions[index],
+            Instruction::Constant {
+                value: Literal::Int(0),
+                ..
+            }
+        );
+        if !stays_below_bound
+            || !starts_at_zero
+            || variable.step_value != Some(1)
+        {
+            return None;
+        }
+        Some(match unique_constant(cfg, &variable.bound) {
+            Some(Literal::Int(bound)) => Polynomial::constant(bound),
+            _ => Polynomial::variable(&variable.bound),
+        })
+    }
+
+    /// Explains why iterations of the nest might access the same element at
+    /// `index` in an order that interchanging the loops would reverse, if
+    /// they might. Each iteration must access a different element, or all
+    /// iterations of one of the loops the same one.
+    fn check_index(
+        &self,
+        cfg: &FunctionCfg,
+        index: &Polynomial,
+    ) -> Result<(), Whatever> {
+        let outer_name = &self.outer_variable.name;
+        let inner_name = &self.inner.induction_variable.name;
+        let not_affine = || {
+            format!(
+                "the index `{index}` is not affine in `{outer_name}` and `{inner_name}`"
+            )
+        };
+        let (outer_coefficient, rest) =
+            index.split(outer_name).with_whatever_context(not_affine)?;
+        let (inner_coefficient, _) =
+            rest.split(inner_name).with_whatever_context(not_affine)?;
+        if outer_coefficient.mentions(inner_name) {
+            whatever!("{}", not_affine());
+        }
+
+        let is_nonzero_constant = |polynomial: &Polynomial| {
+            polynomial.as_constant().unwrap_or(0) != 0
+        };
+        let is_one =
+            |polynomial: &Polynomial| polynomial.as_constant() == Some(1);
+        let distinct_rows = inner_coefficient.as_constant() == Some(0)
+            && is_nonzero_constant(&outer_coefficient);
+        let distinct_columns = outer_coefficient.as_constant() == Some(0)
+            && is_nonzero_constant(&inner_coefficient);
+        // `outer * bound + inner` with `0 <= inner < bound` is different on
+        // every iteration, and likewise with the loops swapped
+        let row_major = is_one(&inner_coefficient)
+            && self.counts_from_zero(cfg, true)
+                == Some(outer_coefficient.clone());
+        let column_major = is_one(&outer_coefficient)
+            && self.counts_from_zero(cfg, false) == Some(inner_coefficient);
+        if !(distinct_rows || distinct_columns || row_major || column_major) {
+            whatever!(
+                "cannot tell which iterations access the same element at index `{index}`"
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_defined_in_loop(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+    name: &str,
+) -> bool {
+    natural_loop.body.iter().any(|block| {
+        cfg.vertices[*block].instructions.iter().any(|instruction| {
+            instruction.kill().map(String::as_str) == Some(name)
+        })
+    })
+}
+
+/// Whether the coefficient of `variable` in `index` is a constant of at most
+/// one in magnitude, so that consecutive iterations access adjacent elements.
+fn has_unit_stride(index: &Polynomial, variable: &str) -> bool {
+    index.split(variable).is_some_and(|(coefficient, _)| {
+        coefficient
+            .as_constant()
+            .is_some_and(|coefficient| coefficient.abs() <= 1)
+    })
+}
+
+/// Recognizes `outer` and `inner` as a perfectly nested pair of loops,
+/// explaining why not otherwise.
+fn analyze_nest<'a>(
+    cfg: &FunctionCfg,
+    outer: &'a NaturalLoop,
+    inner: &NaturalLoop,
+) -> Result<Nest<'a>, Whatever> {
+    let inner = match analyze_simple_loop(cfg, inner) {
+        Ok(inner) => inner,
+        Err(error) => whatever!("inner loop is not simple: {error}"),
+    };
+
+    let Exit::Conditional {
+        condition,
+        if_true,
+        if_false,
+    } = &cfg.edges[outer.header]
+    else {
+        whatever!("outer loop header does not end in a branch");
+    };
+    let stays_if_true = if *if_true == inner.preheader {
+        true
+    } else if *if_false == inner.preheader {
+        false
+    } else {
+        whatever!("outer loop header does not branch to the inner loop");
+    };
+    let nest_blocks = BTreeSet::from_iter([
+        outer.header,
+        inner.preheader,
+        inner.header,
+        inner.latch,
+        inner.exit,
+    ]);
+    if outer.body != nest_blocks
+        || outer.latch() != inner.exit
+        || cfg.predecessors(inner.preheader) != [outer.header]
+    {
+        whatever!("the loops are not perfectly nested");
+    }
+    if stays_if_true != inner.stays_if_true {
+        whatever!("the loops do not stay in on the same branch");
+    }
+    let outer_variable = match analyze_induction_variable(cfg, outer, condition)
+    {
+        Ok(outer_variable) => outer_variable,
+        Err(error) => whatever!("outer loop is not counted: {error}"),
+    };
+
+    let inner_variable = &inner.induction_variable;
+    for name in [&inner_variable.bound, &inner_variable.step] {
+        if is_defined_in_loop(cfg, outer, name) {
+            whatever!("`{name}` of the inner loop varies in the outer loop");
+        }
+    }
+    let inner_initialization = match find_constant_initialization(
+        cfg,
+        inner.preheader,
+        &inner_variable.name,
+    ) {
+        Some((block, index)) if block == inner.preheader => index,
+        _ => whatever!(
+            "`{}` is not reset to a constant before the inner loop",
+            inner_variable.name
+        ),
+    };
+    let outer_initialization = find_constant_initialization(
+        cfg,
+        outer.preheader(),
+        &outer_variable.name,
+    )
+    .with_whatever_context(|| {
+        format!(
+            "initial value of `{}` is not a known constant",
+            outer_variable.name
+        )
+    })?;
+
+    // everything but the inner body only controls the loops
+    let control = [
+        (outer.header, None),
+        (inner.preheader, Some(inner_initialization)),
+        (inner.header, None),
+        (
+            inner.exit,
+            Some(cfg.vertices[inner.exit].index_before_exit() - 1),
+        ),
+    ];
+    for (block, allowed) in control {
+        let instructions = &cfg.vertices[block].instructions
+            [..cfg.vertices[block].index_before_exit()];
+        for (index, instruction) in instructions.iter().enumerate() {
+            let controls_loop = Some(index) == allowed
+                || instruction.kill() == Some(&outer_variable.condition)
+                || instruction.kill() == Some(&inner_variable.condition);
+            let is_unrelated_constant =
+                matches!(instruction, Instruction::Constant { .. })
+                    && instruction.kill() != Some(&outer_variable.name)
+                    && instruction.kill() != Some(&inner_variable.name);
+            if !controls_loop && !is_unrelated_constant {
+                whatever!(
+                    "the loops are not perfectly nested: `.{}` does more than control them",
+                    block_name(cfg, block)
+                );
+            }
+        }
+    }
+
+    Ok(Nest {
+        outer,
+        inner,
+        outer_variable,
+        outer_initialization,
+        inner_initialization,
+    })
+}
+
+/// Checks that running the iterations of `nest` with the loops swapped
+/// computes the same result, explaining why not otherwise.
+fn check_legality(
+    cfg: &FunctionCfg,
+    aliases: &AliasAnalysis,
+    nest: &Nest,
+) -> Result<Vec<MemoryAccess>, Whatever> {
+    let outer_name = &nest.outer_variable.name;
+    let inner_name = &nest.inner.induction_variable.name;
+    for (block_idx, block) in cfg.vertices.iter() {
+        if nest.outer.body.contains(&block_idx) {
+            continue;
+        }
+        for name in [outer_name, inner_name] {
+            if block
+                .instructions
+                .iter()
+                .any(|instruction| instruction.gen_set().contains(name))
+            {
+                whatever!("`{name}` is used after the loops");
+            }
+        }
+    }
+
+    let latch = &cfg.vertices[nest.inner.latch].instructions;
+    let body = &latch[..nest.inner.update_index(cfg)];
+    let accesses = Accesses::of(body);
+    if accesses.has_ordered_effects {
+        whatever!("the inner loop has side effects whose order would change");
+    }
+    let mut written = BTreeSet::new();
+    for instruction in body {
+        if let Some(variable) = instruction.gen_set().iter().find(|arg| {
+            accesses.writes.contains(*arg) && !written.contains(*arg)
+        }) {
+            whatever!("`{variable}` carries a value between iterations");
+        }
+        written.extend(instruction.kill().cloned());
+    }
+    for (block_idx, block) in cfg.vertices.iter() {
+        if block_idx == nest.inner.latch {
+            continue;
+        }
+        if let Some(variable) = block
+            .instructions
+            .iter()
+            .flat_map(|instruction| instruction.gen_set())
+            .find(|arg| accesses.writes.contains(*arg))
+        {
+            whatever!("`{variable}` is used outside the inner loop");
+        }
+    }
+
+    let memory_accesses = nest.memory_accesses(cfg)?;
+    for (i, first) in memory_accesses.iter().enumerate() {
+        for second in &memory_accesses[i..] {
+            if !first.is_store && !second.is_store {
+                continue;
+            }
+            if first.base != second.base {
+                if aliases.may_alias(&first.base, &second.base) {
+                    whatever!(
+                        "`{}` and `{}` may point into the same allocation",
+                        first.base,
+                        second.base
+                    );
+                }
+                continue;
+            }
+            if first.index != second.index {
+                whatever!(
+                    "accesses through `{}` at different indices may depend on each other",
+                    first.base
+                );
+            }
+            nest.check_index(cfg, &first.index)?;
+        }
+    }
+    Ok(memory_accesses)
+}
+
+/// Swaps the loops of `nest`: the induction variables trade their
+/// initializations, comparisons, and updates, so that the body runs over the
+/// same iterations in the other order.
+fn interchange(cfg: &mut FunctionCfg, nest: &Nest) {
+    let swap =
+        |cfg: &mut FunctionCfg,
+         (first_block, first_index): (BasicBlockIdx, usize),
+         (second_block, second_index): (BasicBlockIdx, usize)| {
+            let first =
+                cfg.vertices[first_block].instructions[first_index].clone();
+            let second =
+                cfg.vertices[second_block].instructions[second_index].clone();
+            cfg.vertices[first_block].instructions[first_index] = second;
+            cfg.vertices[second_block].instructions[second_index] = first;
+        };
+    let inner = &nest.inner;
+    let outer_latch = nest.outer.latch();
+
+    swap(
+        cfg,
+        nest.outer_initialization,
+        (inner.preheader, nest.inner_initialization),
+    );
+    let outer_update = cfg.vertices[outer_latch].index_before_exit() - 1;
+    let inner_update = inner.update_index(cfg);
+    swap(
+        cfg,
+        (outer_latch, outer_update),
+        (inner.latch, inner_update),
+    );
+
+    // the conditions keep their names, which the branches refer to
+    let comparison_index = |cfg: &FunctionCfg, block, condition: &str| {
+        cfg.vertices[block]
+            .instructions
+            .iter()
+            .rposition(|instruction| {
+                instruction.kill().map(String::as_str) == Some(condition)
+            })
+            .expect("the induction variables were analyzed")
+    };
+    let outer_comparison = (
+        nest.outer.header,
+        comparison_index(
+            cfg,
+            nest.outer.header,
+            &nest.outer_variable.condition,
+        ),
+    );
+    let inner_comparison = (
+        inner.header,
+        comparison_index(
+            cfg,
+            inner.header,
+            &inner.induction_variable.condition,
+        ),
+    );
+    swap(cfg, outer_comparison, inner_comparison);
+    for ((block, index), condition) in [
+        (outer_comparison, &nest.outer_variable.condition),
+        (inner_comparison, &inner.induction_variable.condition),
+    ] {
+        cfg.vertices[block].instructions[index]
+            .map_dest(|dest| *dest = condition.clone());
+    }
+}
+
+/// Interchanges each perfectly nested pair of loops in `forest` over a
+/// rectangular iteration space when no dependence between iterations forbids
+/// it and more of the loads and stores in the body then walk memory with unit
+/// stride, returning why the other nests were not interchanged. Runs before
+/// other loop optimizations, since hoisting code into the inner preheader
+/// breaks the perfect nesting.
+///
+/// Requires: `forest` has been normalized.
+pub fn interchange_loops(
+    cfg: &mut FunctionCfg,
+    forest: &LoopForest,
+    cache: &mut AnalysisCache,
+) -> Vec<String> {
+    let aliases = cache.get_persistent::<PointsTo>(cfg);
+    let is_nested_in = |inner: &NaturalLoop, outer: &NaturalLoop| {
+        inner.header != outer.header && outer.body.is_superset(&inner.body)
+    };
+
+    let mut failures = vec![];
+    for outer in &forest.loops {
+        for inner in &forest.loops {
+            // only loops nested directly in each other
+            if !is_nested_in(inner, outer)
+                || forest.loops.iter().any(|between| {
+                    is_nested_in(inner, between) && is_nested_in(between, outer)
+                })
+            {
+                continue;
+            }
+            let result = analyze_nest(cfg, outer, inner).and_then(|nest| {
+                let memory_accesses = check_legality(cfg, &aliases, &nest)?;
+                let strided = |variable: &str| {
+                    memory_accesses
+                        .iter()
+                        .filter(|access| !has_unit_stride(&access.index, variable))
+                        .count()
+                };
+                if strided(&nest.inner.induction_variable.name)
+                    <= strided(&nest.outer_variable.name)
+                {
+                    whatever!(
+                        "the inner loop would not access memory with smaller strides"
+                    );
+                }
+                Ok(nest)
+            });
+            match result {
+                Ok(nest) => {
+                    interchange(cfg, &nest);
+                    cache.invalidate();
+                }
+                Err(error) => failures.push(format!(
+                    "cannot interchange loops at `{}` and `{}`: {error}",
+                    block_name(cfg, outer.header),
+                    block_name(cfg, inner.header)
+                )),
+            }
+        }
+    }
+    failures
+}
@@ -0,0 +1,14 @@
+pub mod addressing;
+pub mod alias;
+pub mod deletion;
+pub mod dependence;
+pub mod distribution;
+pub mod fusion;
+pub mod induction;
+pub mod interchange;
+pub mod licm;
+pub mod loops;
+pub mod measure;
+pub mod profile;
+pub mod promotion;
+pub mod schedule;
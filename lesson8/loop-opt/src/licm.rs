@@ -0,0 +1,745 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+use bril_rs::{ConstOps, Instruction, ValueOps};
+use bril_util::{
+    EffectKind, InstructionExt, capabilities::Capabilities, error::ToolError,
+};
+use build_cfg::{
+    BasicBlockIdx, Exit, FunctionCfg, Instructions,
+    pass::{AnalysisCache, Changed, FunctionPass},
+    slotmap::SecondaryMap,
+};
+use dataflow::{
+    construct_postorder,
+    reaching_definitions::{
+        DefSite, Definition, compute_reaching_definitions,
+        reaching_definitions_in_block,
+    },
+};
+use purity::{Purity, PuritySummaries};
+
+use crate::loops::{
+    IrreducibleEdges, LoopForest, Loops, NaturalLoop, block_name,
+    fresh_variable,
+};
+
+/// How safe it is to execute an instruction on a path where it originally
+/// would not have been executed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Speculation {
+    /// The instruction cannot trap and has no side effects.
+    Safe,
+    /// The instruction has no side effects but may trap at runtime, e.g.,
+    /// integer division by zero.
+    MayTrap,
+    /// The instruction has side effects or depends on mutable state, so it
+    /// can never be treated as loop invariant.
+    Never,
+}
+
+pub fn speculation_safety(instruction: &Instruction) -> Speculation {
+    match (instruction.effect_kind(), instruction) {
+        (
+            EffectKind::Pure,
+            Instruction::Value {
+                op: ValueOps::Div, ..
+            },
+        ) => Speculation::MayTrap,
+        (EffectKind::Pure, _) => Speculation::Safe,
+        // a `load` depends on the `store`s in the loop, and calls that may
+        // store, just like a `get` depends on the `set`s
+        _ => Speculation::Never,
+    }
+}
+
+/// Like [`speculation_safety`], except that calls to functions `summaries`
+/// shows to be total are safe, and calls to other pure functions are treated
+/// like instructions that may trap, since they could still trap or never
+/// return.
+fn hoisting_safety(
+    instruction: &Instruction,
+    summaries: &PuritySummaries,
+) -> Speculation {
+    match instruction {
+        Instruction::Value {
+            funcs,
+            op: ValueOps::Call,
+            ..
+        } => match summaries.purity(&funcs[0]) {
+            Purity::PureTotal => Speculation::Safe,
+            Purity::Pure => Speculation::MayTrap,
+            Purity::ReadOnly | Purity::Effectful => Speculation::Never,
+        },
+        _ => speculation_safety(instruction),
+    }
+}
+
+/// A condition that prevented LICM from hoisting an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoistFailure {
+    /// See [`Speculation::Never`].
+    HasSideEffects,
+    /// Some argument is defined inside the loop by an instruction that is not
+    /// itself loop invariant.
+    NotLoopInvariant,
+    /// The destination is assigned more than once in the loop.
+    NotUniqueDefinition,
+    /// Some use of the destination in the loop is not dominated by the
+    /// instruction.
+    DoesNotDominateUses,
+    /// Some exit of the loop is not dominated by the instruction.
+    DoesNotDominateExits,
+    /// See [`Speculation::MayTrap`]; the instruction is also not guaranteed to
+    /// execute.
+    MayTrap,
+    /// See [`Speculation::MayTrap`]; an instruction in the loop that is
+    /// observable, or may itself trap or never return, can run before it, so
+    /// trapping in the preheader would skip that instruction.
+    FollowsSideEffects,
+    /// Some argument is defined inside the loop by a loop-invariant
+    /// instruction that was not hoisted and is not a copy or constant that
+    /// can be recomputed in the preheader.
+    OperandNotHoisted,
+}
+
+impl fmt::Display for HoistFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HasSideEffects => "has side effects",
+            Self::NotLoopInvariant => "not loop invariant",
+            Self::NotUniqueDefinition => "not unique definition",
+            Self::DoesNotDominateUses => "doesn't dominate uses",
+            Self::DoesNotDominateExits => "doesn't dominate exits",
+            Self::MayTrap => "may trap and isn't guaranteed to execute",
+            Self::FollowsSideEffects => "may trap after a side effect",
+            Self::OperandNotHoisted => {
+                "depends on an instruction left in the loop"
+            }
+        }
+        .fmt(f)
+    }
+}
+
+/// Why an instruction in a loop was not hoisted.
+pub struct Explanation {
+    pub loop_header: String,
+    pub instruction: Instruction,
+    pub failures: Vec<HoistFailure>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            ".{}: did not hoist `{}`: {}",
+            self.loop_header,
+            self.instruction,
+            self.failures
+                .iter()
+                .map(|failure| failure.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// The pure instruction computing `condition` for the branch ending `block`,
+/// if it is computed in `block` and none of its arguments are reassigned
+/// before the branch.
+fn condition_definition<'a>(
+    cfg: &'a FunctionCfg,
+    block: BasicBlockIdx,
+    condition: &str,
+) -> Option<&'a Instruction> {
+    let instructions = &cfg.vertices[block].instructions;
+    let definition_index = instructions.iter().rposition(|instruction| {
+        instruction.kill().map(String::as_str) == Some(condition)
+    })?;
+    let definition = &instructions[definition_index];
+    // the definition itself counts, since `c = not c` reads the old `c`
+    let reassigned =
+        instructions[definition_index..].iter().any(|instruction| {
+            instruction
+                .kill()
+                .is_some_and(|dest| definition.gen_set().contains(dest))
+        });
+    (definition.effect_kind() == EffectKind::Pure && !reassigned)
+        .then_some(definition)
+}
+
+/// Whether the loop is only entered once its condition is known to hold, as
+/// in a rotated `while` loop guarded by a copy of its test: the preheader is
+/// only entered from a branch, on the side that stays in the loop, whose
+/// condition is computed by the same instruction as the condition of every
+/// edge leaving the loop. A guarded loop never runs zero times once its
+/// preheader is reached.
+pub fn is_guarded_by_loop_condition(
+    natural_loop: &NaturalLoop,
+    cfg: &FunctionCfg,
+) -> bool {
+    let [guard] = cfg.predecessors(natural_loop.preheader()) else {
+        return false;
+    };
+    let Exit::Conditional {
+        condition: guard_condition,
+        if_true,
+        if_false,
+    } = &cfg.edges[*guard]
+    else {
+        return false;
+    };
+    let enters_if_true = *if_true == natural_loop.preheader();
+    if enters_if_true == (*if_false == natural_loop.preheader()) {
+        return false;
+    }
+    let Some(guard_test) = condition_definition(cfg, *guard, guard_condition)
+    else {
+        return false;
+    };
+
+    let exit_edges = natural_loop.exit_edges(cfg);
+    !exit_edges.is_empty()
+        && exit_edges.iter().all(|&(exiting_block, exit)| {
+            let Exit::Conditional {
+                condition, if_true, ..
+            } = &cfg.edges[exiting_block]
+            else {
+                return false;
+            };
+            (*if_true != exit) == enters_if_true
+                && condition_definition(cfg, exiting_block, condition)
+                    .is_some_and(|test| test.value() == guard_test.value())
+        })
+}
+
+/// Whether `block` executes whenever the preheader does, so that an
+/// instruction in it that traps in the preheader would have trapped anyway.
+/// This is the case for the header, and, if the loop
+/// [is guarded by its condition](is_guarded_by_loop_condition), for a block
+/// dominating every block that can leave the loop.
+pub fn is_guaranteed_to_execute(
+    block: BasicBlockIdx,
+    natural_loop: &NaturalLoop,
+    cfg: &FunctionCfg,
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> bool {
+    block == natural_loop.header
+        || (is_guarded_by_loop_condition(natural_loop, cfg)
+            && dominates_exits(
+                block,
+                &natural_loop.exit_edges(cfg),
+                dominators,
+            ))
+}
+
+/// What [`loop_invariant_code_motion`] did.
+#[derive(Default)]
+pub struct Hoisting {
+    /// The number of instructions moved out of loops or copied into their
+    /// preheaders.
+    pub hoisted: usize,
+    /// Why each instruction that stayed in its loop was not hoisted.
+    pub explanations: Vec<Explanation>,
+}
+
+/// Hoists loop-invariant instructions into the preheaders of their loops.
+/// Loops are processed innermost first so that instructions hoisted out of an
+/// inner loop can then be hoisted out of the enclosing loops, and otherwise in
+/// the order of `forest`.
+///
+/// Requires: `forest` has been normalized.
+pub fn loop_invariant_code_motion(
+    cfg: &mut FunctionCfg,
+    forest: &LoopForest,
+    summaries: &PuritySummaries,
+) -> Hoisting {
+    let dominators = dominators::compute_dominators(cfg);
+    let mut hoisting = Hoisting::default();
+    for natural_loop in forest.innermost_first() {
+        hoisting.hoisted += hoist_loop_invariant_instructions(
+            cfg,
+            natural_loop,
+            &dominators,
+            summaries,
+            &mut hoisting.explanations,
+        );
+    }
+    hoisting
+}
+
+/// The loop-invariant instructions of a loop, by block and index.
+struct LoopInvariance {
+    invariant: SecondaryMap<BasicBlockIdx, BTreeSet<usize>>,
+    /// The definitions reaching each instruction of each block in the loop.
+    reaching: SecondaryMap<BasicBlockIdx, Vec<HashSet<Definition>>>,
+}
+
+impl LoopInvariance {
+    fn is_invariant(&self, block: BasicBlockIdx, index: usize) -> bool {
+        self.invariant
+            .get(block)
+            .is_some_and(|invariant| invariant.contains(&index))
+    }
+}
+
+fn is_outside(definition: &Definition, body: &BTreeSet<BasicBlockIdx>) -> bool {
+    // arguments are defined outside every loop, even one headed by the entry
+    // block
+    match definition.site {
+        DefSite::Argument => true,
+        DefSite::Instruction { block, .. } => !body.contains(&block),
+    }
+}
+
+/// The definitions of `variable` among `reaching`.
+fn definitions_of<'a>(
+    cfg: &FunctionCfg,
+    reaching: &'a HashSet<Definition>,
+    variable: &str,
+) -> Vec<&'a Definition> {
+    let variable = cfg.symbols.intern(variable);
+    reaching
+        .iter()
+        .filter(|definition| definition.variable == variable)
+        .collect()
+}
+
+fn find_loop_invariant_instructions(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+    summaries: &PuritySummaries,
+) -> LoopInvariance {
+    let body = &natural_loop.body;
+
+    let solution = compute_reaching_definitions(cfg);
+    let mut invariance = LoopInvariance {
+        invariant: SecondaryMap::new(),
+        reaching: SecondaryMap::new(),
+    };
+    for block in body {
+        invariance.reaching.insert(
+            *block,
+            reaching_definitions_in_block(cfg, &solution, *block),
+        );
+    }
+
+    // an argument is invariant if every definition of it reaching the
+    // instruction is outside the loop, or if only one does and it is itself
+    // invariant, like the `id` in `x = id c; y = add x one`
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in body {
+            for (i, instruction) in
+                cfg.vertices[*block].instructions.iter().enumerate()
+            {
+                let is_invariant = match instruction {
+                    Instruction::Value { args, .. }
+                        if hoisting_safety(instruction, summaries)
+                            != Speculation::Never =>
+                    {
+                        args.iter().all(|arg| {
+                            let definitions = definitions_of(
+                                cfg,
+                                &invariance.reaching[*block][i],
+                                arg,
+                            );
+                            definitions
+                                .iter()
+                                .all(|definition| is_outside(definition, body))
+                                || match definitions.as_slice() {
+                                    [definition] => match definition.site {
+                                        DefSite::Argument => true,
+                                        DefSite::Instruction {
+                                            block,
+                                            index,
+                                        } => invariance
+                                            .is_invariant(block, index),
+                                    },
+                                    _ => false,
+                                }
+                        })
+                    }
+                    Instruction::Constant { .. } => true,
+                    _ => false,
+                };
+                if is_invariant {
+                    changed |= invariance
+                        .invariant
+                        .entry(*block)
+                        .unwrap()
+                        .or_default()
+                        .insert(i);
+                }
+            }
+        }
+    }
+
+    invariance
+}
+
+fn is_unique_definition(
+    definition: (BasicBlockIdx, usize),
+    loop_body: &BTreeSet<BasicBlockIdx>,
+    cfg: &FunctionCfg,
+) -> bool {
+    let definition_name = cfg.vertices[definition.0].instructions[definition.1]
+        .kill()
+        .expect("should be a Value or Constant instruction");
+
+    loop_body.iter().fold(0, |acc, block| {
+        acc + cfg.vertices[*block]
+            .instructions
+            .iter()
+            .filter(|instruction| instruction.kill() == Some(definition_name))
+            .count()
+    }) == 1
+}
+
+fn dominates_uses(
+    definition_block: BasicBlockIdx,
+    use_blocks: &[BasicBlockIdx],
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> bool {
+    use_blocks
+        .iter()
+        .all(|&use_block| dominators[use_block].contains(&definition_block))
+}
+
+/// Whether `definition_block` dominates every block an edge leaves the loop
+/// from, so that the definition has run whenever the loop is left.
+fn dominates_exits(
+    definition_block: BasicBlockIdx,
+    exit_edges: &[(BasicBlockIdx, BasicBlockIdx)],
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> bool {
+    exit_edges.iter().all(|&(exiting_block, _)| {
+        dominators[exiting_block].contains(&definition_block)
+    })
+}
+
+/// The values of variables defined in a loop, as seen from its preheader,
+/// with instructions by their block and their index before any were hoisted.
+struct PreheaderValues {
+    preheader: BasicBlockIdx,
+    body: BTreeSet<BasicBlockIdx>,
+    invariance: LoopInvariance,
+    /// The instructions of each block in the loop before hoisting.
+    original: SecondaryMap<BasicBlockIdx, Instructions>,
+    hoisted: HashSet<(BasicBlockIdx, usize)>,
+    /// The fresh variable each constant was copied to in the preheader.
+    rematerialized: HashMap<(BasicBlockIdx, usize), String>,
+}
+
+impl PreheaderValues {
+    fn new(
+        cfg: &FunctionCfg,
+        natural_loop: &NaturalLoop,
+        invariance: LoopInvariance,
+    ) -> Self {
+        let mut original = SecondaryMap::new();
+        for block in &natural_loop.body {
+            original.insert(*block, cfg.vertices[*block].instructions.clone());
+        }
+        Self {
+            preheader: natural_loop.preheader(),
+            body: natural_loop.body.clone(),
+            invariance,
+            original,
+            hoisted: HashSet::new(),
+            rematerialized: HashMap::new(),
+        }
+    }
+
+    /// Whether the instruction at `index` in `block` runs before every
+    /// instruction in the loop, other than those already hoisted, that is
+    /// observable or may itself trap or never return, so that it may trap in
+    /// the preheader instead without skipping any of them.
+    fn precedes_side_effects(
+        &self,
+        block: BasicBlockIdx,
+        index: usize,
+        dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+        summaries: &PuritySummaries,
+    ) -> bool {
+        self.body.iter().all(|&other_block| {
+            self.original[other_block].iter().enumerate().all(
+                |(other_index, instruction)| {
+                    // a block dominated by `block` is only reached after it,
+                    // even on the first iteration
+                    (other_block, other_index) == (block, index)
+                        || self.hoisted.contains(&(other_block, other_index))
+                        || instruction.effect_kind() == EffectKind::Control
+                        || hoisting_safety(instruction, summaries)
+                            == Speculation::Safe
+                        || if other_block == block {
+                            other_index > index
+                        } else {
+                            dominators[other_block].contains(&block)
+                        }
+                },
+            )
+        })
+    }
+
+    /// A variable holding, in the preheader, the value `variable` has where
+    /// `reaching` are the definitions reaching it: itself if it is defined
+    /// outside the loop or by a hoisted instruction, the source of a chain of
+    /// loop-invariant `id`s, or a copy of a loop-invariant constant put in the
+    /// preheader. [`None`] if its value is only computed inside the loop.
+    fn resolve(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        variable: &str,
+        reaching: &HashSet<Definition>,
+    ) -> Option<String> {
+        let definitions = definitions_of(cfg, reaching, variable);
+        if definitions
+            .iter()
+            .all(|definition| is_outside(definition, &self.body))
+        {
+            return Some(variable.to_string());
+        }
+        let [definition] = definitions.as_slice() else {
+            return None;
+        };
+        let DefSite::Instruction { block, index } = definition.site else {
+            return Some(variable.to_string());
+        };
+
+        if self.hoisted.contains(&(block, index)) {
+            return Some(variable.to_string());
+        }
+        if let Some(copy) = self.rematerialized.get(&(block, index)) {
+            return Some(copy.clone());
+        }
+        if !self.invariance.is_invariant(block, index) {
+            return None;
+        }
+        match self.original[block][index].clone() {
+            Instruction::Value {
+                op: ValueOps::Id,
+                args,
+                ..
+            } => {
+                let reaching = self.invariance.reaching[block][index].clone();
+                self.resolve(cfg, &args[0], &reaching)
+            }
+            Instruction::Constant {
+                const_type,
+                value,
+                pos,
+                ..
+            } => {
+                let copy = fresh_variable(cfg, format!("{variable}.hoisted"));
+                let preheader = &mut cfg.vertices[self.preheader];
+                let insertion_point = preheader.index_before_exit();
+                preheader.instructions.insert(
+                    insertion_point,
+                    Instruction::Constant {
+                        dest: copy.clone(),
+                        op: ConstOps::Const,
+                        const_type,
+                        value,
+                        pos,
+                    },
+                );
+                self.rematerialized.insert((block, index), copy.clone());
+                Some(copy)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn hoist_loop_invariant_instructions(
+    cfg: &mut FunctionCfg,
+    natural_loop: &NaturalLoop,
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    summaries: &PuritySummaries,
+    explanations: &mut Vec<Explanation>,
+) -> usize {
+    let body = &natural_loop.body;
+    let preheader = natural_loop.preheader();
+    let loop_header = block_name(cfg, natural_loop.header);
+
+    let invariance =
+        find_loop_invariant_instructions(cfg, natural_loop, summaries);
+    let exit_edges = natural_loop.exit_edges(cfg);
+    let mut values = PreheaderValues::new(cfg, natural_loop, invariance);
+
+    // hoisted instructions are appended to the preheader in reverse postorder
+    // so that definitions are still placed before their uses
+    let mut reverse_postorder = construct_postorder(cfg);
+    reverse_postorder.reverse();
+
+    let mut hoisted = vec![];
+    for block in reverse_postorder {
+        if block == cfg.entry || !body.contains(&block) {
+            continue;
+        }
+        let mut to_move = vec![];
+        let block_instructions = values.original[block].clone();
+        for (instruction_idx, instruction) in
+            block_instructions.iter().enumerate()
+        {
+            let Some(kill) = instruction.kill() else {
+                continue;
+            };
+
+            let mut failures = vec![];
+            if !values.invariance.is_invariant(block, instruction_idx) {
+                failures.push(
+                    if hoisting_safety(instruction, summaries)
+                        == Speculation::Never
+                    {
+                        HoistFailure::HasSideEffects
+                    } else {
+                        HoistFailure::NotLoopInvariant
+                    },
+                );
+            } else {
+                let mut use_blocks = vec![];
+                for other_block in body {
+                    for other_instruction in
+                        &cfg.vertices[*other_block].instructions
+                    {
+                        if other_instruction.gen_set().contains(kill) {
+                            use_blocks.push(*other_block);
+                            break;
+                        }
+                    }
+                }
+
+                if !is_unique_definition((block, instruction_idx), body, cfg) {
+                    failures.push(HoistFailure::NotUniqueDefinition);
+                }
+                if !dominates_uses(block, &use_blocks, dominators) {
+                    failures.push(HoistFailure::DoesNotDominateUses);
+                }
+                if !dominates_exits(block, &exit_edges, dominators) {
+                    failures.push(HoistFailure::DoesNotDominateExits);
+                }
+                // potentially-trapping instructions may only be hoisted if
+                // they would have executed anyway, since otherwise a loop
+                // running zero times could trap in the preheader, and before
+                // anything observable, which a trap in the preheader would
+                // otherwise skip
+                if hoisting_safety(instruction, summaries)
+                    == Speculation::MayTrap
+                {
+                    if !is_guaranteed_to_execute(
+                        block,
+                        natural_loop,
+                        cfg,
+                        dominators,
+                    ) {
+                        failures.push(HoistFailure::MayTrap);
+                    }
+                    if !values.precedes_side_effects(
+                        block,
+                        instruction_idx,
+                        dominators,
+                        summaries,
+                    ) {
+                        failures.push(HoistFailure::FollowsSideEffects);
+                    }
+                }
+            }
+
+            // the arguments are read in the preheader, so those computed in
+            // the loop by copies or constants are read from their sources, and
+            // those computed otherwise must have been hoisted already
+            let mut hoisted_instruction = instruction.clone();
+            if failures.is_empty() {
+                if let Instruction::Value { args, .. } =
+                    &mut hoisted_instruction
+                {
+                    let reaching = values.invariance.reaching[block]
+                        [instruction_idx]
+                        .clone();
+                    for arg in args {
+                        match values.resolve(cfg, arg, &reaching) {
+                            Some(value) => *arg = value,
+                            None => {
+                                failures.push(HoistFailure::OperandNotHoisted);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if failures.is_empty() {
+                values.hoisted.insert((block, instruction_idx));
+                to_move.push((instruction_idx, hoisted_instruction));
+            } else {
+                let explanation = Explanation {
+                    loop_header: loop_header.clone(),
+                    instruction: instruction.clone(),
+                    failures,
+                };
+                tracing::debug!(target: "licm", "{explanation}");
+                explanations.push(explanation);
+            }
+        }
+
+        for (instruction_idx, _) in to_move.iter().rev() {
+            cfg.vertices[block].instructions.remove(*instruction_idx);
+        }
+        hoisted.extend(to_move.into_iter().map(|(_, instruction)| instruction));
+    }
+
+    let insertion_point = cfg.vertices[preheader].index_before_exit();
+    cfg.vertices[preheader]
+        .instructions
+        .insert_many(insertion_point, hoisted);
+    values.hoisted.len() + values.rematerialized.len()
+}
+
+/// Runs loop-invariant code motion on reducible functions, leaving functions
+/// with irreducible control flow unchanged.
+#[derive(Default)]
+pub struct LicmPass {
+    pub summaries: Arc<PuritySummaries>,
+}
+
+impl FunctionPass for LicmPass {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all() - Capabilities::SPECULATION
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        if !cache.get::<IrreducibleEdges>(cfg).is_empty() {
+            return Ok(Changed::No);
+        }
+
+        let mut loop_forest = LoopForest::clone(&cache.get::<Loops>(cfg));
+        let blocks = cfg.vertices.len();
+        loop_forest.normalize(cfg);
+        let normalized = cfg.vertices.len() > blocks;
+        if normalized {
+            cache.invalidate();
+        }
+        let hoisting =
+            loop_invariant_code_motion(cfg, &loop_forest, &self.summaries);
+
+        let merged = cfg.merge_straightline_blocks();
+        cfg.simplify_unconditionals_to_fallthroughs();
+        Ok(Changed::from(
+            normalized || hoisting.hoisted > 0 || merged > 0,
+        ))
+    }
+}
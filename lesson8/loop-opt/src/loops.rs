@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use bril_util::InstructionExt;
+use build_cfg::{
+    BasicBlock, BasicBlockIdx, FunctionCfg, Label,
+    pass::{Analysis, AnalysisCache},
+    slotmap::SecondaryMap,
+};
+use dominators::Dominators;
+
+/// A natural loop, formed from all the back edges to its header.
+#[derive(Clone)]
+pub struct NaturalLoop {
+    pub header: BasicBlockIdx,
+    /// The sources of the back edges to the header. After
+    /// [`LoopForest::normalize`], there is exactly one latch.
+    pub latches: Vec<BasicBlockIdx>,
+    /// The unique block outside the loop that enters the header. Only present
+    /// after [`LoopForest::normalize`].
+    pub preheader: Option<BasicBlockIdx>,
+    pub body: BTreeSet<BasicBlockIdx>,
+}
+
+impl NaturalLoop {
+    /// Requires: the loop has been normalized.
+    pub fn latch(&self) -> BasicBlockIdx {
+        let [latch] = self.latches.as_slice() else {
+            panic!("Call LoopForest::normalize to create a unique latch");
+        };
+        *latch
+    }
+
+    /// Requires: the loop has been normalized.
+    pub fn preheader(&self) -> BasicBlockIdx {
+        self.preheader
+            .expect("Call LoopForest::normalize to create a preheader")
+    }
+
+    /// The edges leaving the loop, from a block in its body to one outside it.
+    pub fn exit_edges(
+        &self,
+        cfg: &FunctionCfg,
+    ) -> Vec<(BasicBlockIdx, BasicBlockIdx)> {
+        self.body
+            .iter()
+            .flat_map(|&block_idx| {
+                cfg.successors(block_idx)
+                    .into_iter()
+                    .map(move |successor| (block_idx, successor))
+            })
+            .filter(|(_, successor)| !self.body.contains(successor))
+            .collect()
+    }
+
+    /// The blocks outside the loop that are targets of edges leaving it.
+    pub fn exit_blocks(&self, cfg: &FunctionCfg) -> BTreeSet<BasicBlockIdx> {
+        self.exit_edges(cfg)
+            .into_iter()
+            .map(|(_, exit)| exit)
+            .collect()
+    }
+
+    /// Makes every exit block of the loop dedicated, i.e., only entered from
+    /// inside the loop, by routing the edges from the loop into an exit that
+    /// is also entered from outside it through a new block. Returns each new
+    /// block with the exit it jumps to. Prefer [`LoopForest::dedicate_exits`],
+    /// which also keeps the other loops up to date.
+    ///
+    /// Requires: the loop has been normalized.
+    pub fn dedicated_exits(
+        &self,
+        cfg: &mut FunctionCfg,
+    ) -> Vec<(BasicBlockIdx, BasicBlockIdx)> {
+        let mut dedicated = vec![];
+        for exit in self.exit_blocks(cfg) {
+            let (inside, outside): (Vec<_>, Vec<_>) = cfg
+                .predecessors(exit)
+                .iter()
+                .copied()
+                .partition(|predecessor| self.body.contains(predecessor));
+            if outside.is_empty() {
+                continue;
+            }
+
+            let label =
+                fresh_label(cfg, format!("{}_exit", block_name(cfg, exit)));
+            let block = cfg.add_block(BasicBlock {
+                label: Some(label),
+                ..Default::default()
+            });
+            for predecessor in inside {
+                // a branch may leave to the exit both ways
+                while cfg.successors(predecessor).contains(&exit) {
+                    cfg.reorient_edge(predecessor, exit, block);
+                }
+            }
+            cfg.set_unconditional_edge(block, exit);
+            dedicated.push((block, exit));
+        }
+        dedicated
+    }
+}
+
+/// All the natural loops in a function.
+#[derive(Default, Clone)]
+pub struct LoopForest {
+    pub loops: Vec<NaturalLoop>,
+}
+
+impl LoopForest {
+    /// Finds the natural loops of `cfg`, merging those that share a header.
+    pub fn find(
+        cfg: &FunctionCfg,
+        dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+    ) -> Self {
+        let mut back_edges = BTreeMap::<_, Vec<_>>::new();
+        for start in cfg.vertices.keys() {
+            for end in cfg.successors(start) {
+                if dominators[start].contains(&end) {
+                    back_edges.entry(end).or_default().push(start);
+                }
+            }
+        }
+
+        let loops = back_edges
+            .into_iter()
+            .map(|(header, latches)| {
+                let mut body = BTreeSet::from_iter([header]);
+                let mut stack = latches.clone();
+                while let Some(next) = stack.pop() {
+                    if body.insert(next) {
+                        stack.extend(cfg.predecessors(next));
+                    }
+                }
+                NaturalLoop {
+                    header,
+                    latches,
+                    preheader: None,
+                    body,
+                }
+            })
+            .collect();
+
+        Self { loops }
+    }
+
+    /// Gives every loop a unique latch and a unique preheader, reusing an
+    /// existing block as the preheader when it is the only way into the loop
+    /// and jumps straight to the header. New blocks are added to the bodies of
+    /// the loops enclosing them.
+    pub fn normalize(&mut self, cfg: &mut FunctionCfg) {
+        cfg.make_fallthroughs_explicit();
+
+        for i in 0..self.loops.len() {
+            let header = self.loops[i].header;
+            // only the entry has no label
+            let header_name = match block_name(cfg, header) {
+                name if name.is_empty() => "entry".to_string(),
+                name => name,
+            };
+
+            if self.loops[i].latches.len() > 1 {
+                let label = fresh_label(cfg, format!("{header_name}_latch"));
+                let latch = cfg.add_block(BasicBlock {
+                    label: Some(label),
+                    ..Default::default()
+                });
+                for old_latch in self.loops[i].latches.clone() {
+                    // a branch may go to the header both ways
+                    while cfg.successors(old_latch).contains(&header) {
+                        cfg.reorient_edge(old_latch, header, latch);
+                    }
+                }
+                cfg.set_unconditional_edge(latch, header);
+
+                self.loops[i].latches = vec![latch];
+                self.loops[i].body.insert(latch);
+                self.add_to_enclosing_loops(i, latch);
+            }
+
+            let outside_predecessors = cfg
+                .predecessors(header)
+                .iter()
+                .copied()
+                .filter(|predecessor| !self.loops[i].body.contains(predecessor))
+                .collect::<Vec<_>>();
+
+            let preheader = match outside_predecessors.as_slice() {
+                [only] if cfg.successors(*only) == [header] => *only,
+                _ => {
+                    let label =
+                        fresh_label(cfg, format!("{header_name}_preheader"));
+                    let preheader = cfg.add_block(BasicBlock {
+                        label: Some(label),
+                        ..Default::default()
+                    });
+                    for predecessor in outside_predecessors {
+                        while cfg.successors(predecessor).contains(&header) {
+                            cfg.reorient_edge(predecessor, header, preheader);
+                        }
+                    }
+                    cfg.set_unconditional_edge(preheader, header);
+
+                    // every edge into the entry is a back edge, so the
+                    // preheader of a loop headed by the entry must become the
+                    // new entry
+                    if header == cfg.entry {
+                        cfg.vertices[header].is_entry = false;
+                        cfg.vertices[preheader].is_entry = true;
+                        cfg.entry = preheader;
+                    }
+
+                    self.add_to_enclosing_loops(i, preheader);
+                    preheader
+                }
+            };
+            self.loops[i].preheader = Some(preheader);
+        }
+
+        if cfg!(debug_assertions) {
+            if let Err(error) = cfg.verify() {
+                panic!("Normalizing loops corrupted the CFG: {error}");
+            }
+        }
+    }
+
+    /// Gives the loop at index `i` dedicated exits with
+    /// [`NaturalLoop::dedicated_exits`], adding the new blocks to the loops
+    /// enclosing them. A new block in front of the header of another loop
+    /// takes over as its preheader or latch. Returns the new blocks.
+    ///
+    /// Requires: the forest has been normalized.
+    pub fn dedicate_exits(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        i: usize,
+    ) -> Vec<BasicBlockIdx> {
+        let dedicated = self.loops[i].dedicated_exits(cfg);
+        for &(block, exit) in &dedicated {
+            let sources = cfg.predecessors(block).to_vec();
+            for other in &mut self.loops {
+                if exit == other.header {
+                    for latch in &mut other.latches {
+                        if sources.contains(latch) {
+                            *latch = block;
+                            other.body.insert(block);
+                        }
+                    }
+                    if other
+                        .preheader
+                        .is_some_and(|preheader| sources.contains(&preheader))
+                    {
+                        other.preheader = Some(block);
+                    }
+                } else if other.body.contains(&exit) {
+                    other.body.insert(block);
+                }
+            }
+        }
+        dedicated.into_iter().map(|(block, _)| block).collect()
+    }
+
+    /// An order of the blocks of `cfg` for [`FunctionCfg::reorder_blocks`]
+    /// that keeps the `original` blocks in order, puts each preheader not
+    /// among them right before its header, and puts any other new blocks
+    /// last, so that the output reads like the input.
+    pub fn layout(
+        &self,
+        cfg: &FunctionCfg,
+        original: &[BasicBlockIdx],
+    ) -> Vec<BasicBlockIdx> {
+        let original_blocks = original.iter().copied().collect::<HashSet<_>>();
+        // blocks may have been removed since the loops were normalized
+        let preheaders = self
+            .loops
+            .iter()
+            .filter_map(|natural_loop| {
+                Some((natural_loop.header, natural_loop.preheader?))
+            })
+            .filter(|(header, preheader)| {
+                cfg.vertices.contains_key(*header)
+                    && cfg.vertices.contains_key(*preheader)
+                    && !original_blocks.contains(preheader)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut order = vec![];
+        let mut placed = HashSet::new();
+        for &block in original {
+            if !cfg.vertices.contains_key(block) {
+                continue;
+            }
+            if let Some(&preheader) = preheaders.get(&block) {
+                if placed.insert(preheader) {
+                    order.push(preheader);
+                }
+            }
+            if placed.insert(block) {
+                order.push(block);
+            }
+        }
+        for block in cfg.vertices.keys() {
+            if placed.insert(block) {
+                order.push(block);
+            }
+        }
+        order
+    }
+
+    /// The loops ordered so that every loop comes before the loops enclosing
+    /// it, and otherwise in the order of `loops`, e.g., as a
+    /// [`LoopPolicy`](crate::profile::LoopPolicy) prioritized them.
+    pub fn innermost_first(&self) -> Vec<&NaturalLoop> {
+        let mut order = Vec::<&NaturalLoop>::new();
+        for natural_loop in &self.loops {
+            // the nest includes the loop itself, and a loop nested in another
+            // has a strictly smaller body
+            let mut nest = self
+                .loops
+                .iter()
+                .filter(|inner| natural_loop.body.contains(&inner.header))
+                .collect::<Vec<_>>();
+            nest.sort_by_key(|inner| inner.body.len());
+            for inner in nest {
+                if !order.iter().any(|placed| placed.header == inner.header) {
+                    order.push(inner);
+                }
+            }
+        }
+        order
+    }
+
+    fn add_to_enclosing_loops(&mut self, i: usize, block: BasicBlockIdx) {
+        let header = self.loops[i].header;
+        for (j, other) in self.loops.iter_mut().enumerate() {
+            if j != i && other.body.contains(&header) {
+                other.body.insert(block);
+            }
+        }
+    }
+}
+
+/// The name of the label of `block`, or the empty string if it has none.
+pub fn block_name(cfg: &FunctionCfg, block: BasicBlockIdx) -> String {
+    cfg.vertices[block]
+        .label
+        .as_ref()
+        .map(|label| label.name.clone())
+        .unwrap_or_default()
+}
+
+/// The retreating edges of a depth-first traversal of `cfg` from its entry that
+/// are not back edges, i.e., edges into a cycle through a block that does not
+/// dominate the rest of it. `cfg` is reducible exactly when there are none, in
+/// which case every cycle is part of a natural loop.
+pub fn find_irreducible_edges(
+    cfg: &FunctionCfg,
+    dominators: &SecondaryMap<BasicBlockIdx, BTreeSet<BasicBlockIdx>>,
+) -> Vec<(BasicBlockIdx, BasicBlockIdx)> {
+    let mut irreducible_edges = vec![];
+    let mut visited = HashSet::from([cfg.entry]);
+    let mut on_stack = HashSet::from([cfg.entry]);
+    let mut stack = vec![(cfg.entry, cfg.successors(cfg.entry))];
+    while let Some((block, successors)) = stack.last_mut() {
+        let block = *block;
+        let Some(successor) = successors.pop() else {
+            on_stack.remove(&block);
+            stack.pop();
+            continue;
+        };
+        if on_stack.contains(&successor) {
+            if !dominators[block].contains(&successor) {
+                irreducible_edges.push((block, successor));
+            }
+        } else if visited.insert(successor) {
+            on_stack.insert(successor);
+            stack.push((successor, cfg.successors(successor)));
+        }
+    }
+    irreducible_edges
+}
+
+/// The natural loops of a function before [`LoopForest::normalize`], as found
+/// by [`LoopForest::find`].
+pub struct Loops;
+
+impl Analysis for Loops {
+    type Output = LoopForest;
+
+    fn compute(cfg: &FunctionCfg, cache: &mut AnalysisCache) -> Self::Output {
+        LoopForest::find(cfg, &cache.get::<Dominators>(cfg))
+    }
+}
+
+/// The edges that make `cfg` irreducible, as found by
+/// [`find_irreducible_edges`].
+pub struct IrreducibleEdges;
+
+impl Analysis for IrreducibleEdges {
+    type Output = Vec<(BasicBlockIdx, BasicBlockIdx)>;
+
+    fn compute(cfg: &FunctionCfg, cache: &mut AnalysisCache) -> Self::Output {
+        find_irreducible_edges(cfg, &cache.get::<Dominators>(cfg))
+    }
+}
+
+/// A label based on `name` not used by any block in `cfg`.
+pub fn fresh_label(cfg: &FunctionCfg, name: String) -> Label {
+    let existing = cfg
+        .vertices
+        .values()
+        .filter_map(|block| block.label.as_ref())
+        .map(|label| label.name.as_str())
+        .collect::<HashSet<_>>();
+
+    if !existing.contains(name.as_str()) {
+        return Label { name };
+    }
+    let name = (1..)
+        .map(|i| format!("{name}.{i}"))
+        .find(|candidate| !existing.contains(candidate.as_str()))
+        .expect("There are infinitely many candidate labels");
+    Label { name }
+}
+
+/// A variable name based on `name` not used anywhere in `cfg`.
+pub fn fresh_variable(cfg: &FunctionCfg, name: String) -> String {
+    let existing = cfg
+        .signature
+        .arguments
+        .iter()
+        .map(|argument| argument.name.as_str())
+        .chain(
+            cfg.vertices
+                .values()
+                .flat_map(|block| &block.instructions)
+                .flat_map(|instruction| {
+                    instruction.kill().into_iter().chain(instruction.gen_set())
+                })
+                .map(String::as_str),
+        )
+        .collect::<HashSet<_>>();
+
+    if !existing.contains(name.as_str()) {
+        return name;
+    }
+    (1..)
+        .map(|i| format!("{name}.{i}"))
+        .find(|candidate| !existing.contains(candidate.as_str()))
+        .expect("There are infinitely many candidate names")
+}
+
+/// Removes `blocks` from `cfg` along with every edge into or out of them.
+pub fn remove_blocks(cfg: &mut FunctionCfg, blocks: &BTreeSet<BasicBlockIdx>) {
+    for block in blocks {
+        cfg.vertices.remove(*block);
+        cfg.edges.remove(*block);
+        cfg.rev_edges.remove(*block);
+    }
+    for (_, predecessors) in cfg.rev_edges.iter_mut() {
+        predecessors.retain(|predecessor| !blocks.contains(predecessor));
+    }
+}
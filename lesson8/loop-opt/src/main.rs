@@ -1,24 +1,43 @@
-use std::{
-    collections::{BTreeSet, HashMap, HashSet},
-    fs, io,
-    path::PathBuf,
-};
+use std::{env, fs, io, path::PathBuf, sync::Arc};
 
 use argh::FromArgs;
-use bril_rs::{Instruction, Program};
-use bril_util::InstructionExt;
+use bril_rs::Program;
+use bril_util::{
+    filter::{FunctionFilter, FunctionPattern},
+    output::{DotGraph, OutputFormat, print_envelope},
+};
 use build_cfg::{
-    BasicBlock, BasicBlockIdx, FunctionCfg, Label, print, slotmap::SecondaryMap,
+    BasicBlockIdx, FunctionCfg, disk_cache::DiskCache, pass::AnalysisCache,
+    print,
 };
-use dataflow::reaching_definitions::{
-    self, Definition, compute_reaching_definitions,
+use loop_opt::{
+    addressing::precompute_addresses,
+    deletion::delete_dead_loops,
+    distribution::distribute_loops,
+    fusion::fuse_loops,
+    interchange::interchange_loops,
+    licm::loop_invariant_code_motion,
+    loops::{IrreducibleEdges, LoopForest, Loops, block_name},
+    measure::{instrument_block_profile, instrument_dynamic_instruction_count},
+    profile::{
+        LoopPolicy, OptimizeAll, SkipColdLoops, apply_policy, read_profile,
+    },
+    promotion::promote_scalars,
 };
-use snafu::{ResultExt, Whatever};
+use purity::PuritySummaries;
+use serde_json::json;
+use snafu::{ResultExt, Whatever, whatever};
 
 #[repr(u32)]
 enum Stage {
     InsertPreheader,
     LoopInvariantCodeMotion,
+    LoopFusion,
+    LoopDistribution,
+    DeadLoopDeletion,
+    ScalarPromotion,
+    AddressPrecomputation,
+    LoopInterchange,
 }
 
 /// Performs loop optimization.
@@ -28,30 +47,233 @@ struct Opts {
     #[argh(positional)]
     input: Option<PathBuf>,
 
-    /// stage: 0 = insert preheader
+    /// stage: 0 = insert preheader, 1 = loop-invariant code motion, 2 = loop
+    /// fusion, 3 = loop distribution, 4 = dead loop deletion, 5 = scalar
+    /// promotion, 6 = address precomputation, 7 = loop interchange
     #[argh(option, default = "0")]
     stage: u32,
+
+    /// skip all optimizations, e.g., to measure the original program
+    #[argh(switch)]
+    baseline: bool,
+
+    /// only optimize functions matching this name, like `@main` or `@test_*`,
+    /// leaving the rest unoptimized: may be repeated, and omit for every
+    /// function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
+    /// print the natural loops of each function as `json`, `text`, or a `dot`
+    /// graph of its blocks labeled with their loops instead of optimizing
+    #[argh(option)]
+    output: Option<OutputFormat>,
+
+    /// delete dead loops even if they cannot be proven to terminate
+    #[argh(switch)]
+    assume_termination: bool,
+
+    /// print why loop-invariant code motion did not hoist each instruction,
+    /// like `--trace licm=debug`
+    #[argh(switch)]
+    explain: bool,
+
+    /// show the events of a pass at a level, e.g., `licm=debug`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+
+    /// instrument the output to print its dynamic instruction count on exit
+    #[argh(switch)]
+    measure: bool,
+
+    /// instrument the output to print how many times each block executes on
+    /// exit, writing the block for each count to the given file
+    #[argh(option)]
+    instrument_profile: Option<PathBuf>,
+
+    /// profile from `profile.py` or `bril-interp --profile-blocks` used to
+    /// skip cold loops
+    #[argh(option)]
+    profile: Option<PathBuf>,
+
+    /// minimum number of times a loop header must have executed in the
+    /// profile for the loop to be optimized
+    #[argh(option, default = "1")]
+    cold_threshold: u64,
+
+    /// directory in which to save expensive analyses, like points-to sets,
+    /// so later runs on unchanged functions reuse them: defaults to
+    /// `$BRIL_ANALYSIS_CACHE`, if set
+    #[argh(option)]
+    analysis_cache: Option<PathBuf>,
+}
+
+fn optimize(
+    cfg: &mut FunctionCfg,
+    cache: &mut AnalysisCache,
+    opts: &Opts,
+    policy: &dyn LoopPolicy,
+    summaries: &PuritySummaries,
+) {
+    let stage = opts.stage;
+    let original = cfg.vertices.keys().collect::<Vec<_>>();
+
+    let mut loop_forest = LoopForest::clone(&cache.get::<Loops>(cfg));
+    loop_forest.normalize(cfg);
+    cache.invalidate();
+    apply_policy(cfg, &mut loop_forest, policy);
+
+    if stage != Stage::InsertPreheader as u32 {
+        optimize_loops(cfg, cache, &mut loop_forest, opts, policy, summaries);
+    }
+
+    cfg.reorder_blocks(&loop_forest.layout(cfg, &original));
+    if stage != Stage::InsertPreheader as u32 {
+        // preheaders and other blocks the passes added often just jump on
+        cfg.merge_straightline_blocks();
+        cfg.simplify_unconditionals_to_fallthroughs();
+    }
 }
 
-struct NaturalLoop {
-    header: BasicBlockIdx,
-    backedge_start: BasicBlockIdx,
-    body: BTreeSet<BasicBlockIdx>,
+/// Runs the passes of the stage after preheaders are inserted. Passes that
+/// find the loops again after changing the CFG apply `policy` to them too.
+fn optimize_loops(
+    cfg: &mut FunctionCfg,
+    cache: &mut AnalysisCache,
+    loop_forest: &mut LoopForest,
+    opts: &Opts,
+    policy: &dyn LoopPolicy,
+    summaries: &PuritySummaries,
+) {
+    let stage = opts.stage;
+
+    // interchange needs the loops perfectly nested, so it runs before LICM
+    // hoists anything into the inner preheader
+    if stage == Stage::LoopInterchange as u32 {
+        for failure in interchange_loops(cfg, loop_forest, cache) {
+            tracing::warn!(target: "loop-interchange", "{failure}");
+        }
+    }
+
+    loop_invariant_code_motion(cfg, loop_forest, summaries);
+    cache.invalidate();
+
+    // fusion and distribution undo each other, so at most one of them runs
+    if stage == Stage::LoopFusion as u32 {
+        for failure in fuse_loops(cfg, cache, policy) {
+            tracing::warn!(target: "loop-fusion", "{failure}");
+        }
+    } else if stage == Stage::LoopDistribution as u32 {
+        for failure in distribute_loops(cfg, cache, policy) {
+            tracing::warn!(target: "loop-distribution", "{failure}");
+        }
+    } else if stage == Stage::DeadLoopDeletion as u32 {
+        for failure in delete_dead_loops(cfg, policy, opts.assume_termination) {
+            tracing::warn!(target: "dead-loop-deletion", "{failure}");
+        }
+    } else if stage == Stage::ScalarPromotion as u32 {
+        for failure in promote_scalars(cfg, loop_forest, cache) {
+            tracing::warn!(target: "scalar-promotion", "{failure}");
+        }
+    } else if stage == Stage::AddressPrecomputation as u32 {
+        precompute_addresses(cfg, loop_forest);
+    }
 }
 
-struct NaturalLoopWithPreheader {
-    preheader: BasicBlockIdx,
-    header: BasicBlockIdx,
-    backedge_start: BasicBlockIdx,
-    body: BTreeSet<BasicBlockIdx>,
+fn block_names<'a>(
+    cfg: &FunctionCfg,
+    blocks: impl IntoIterator<Item = &'a BasicBlockIdx>,
+) -> Vec<String> {
+    blocks
+        .into_iter()
+        .map(|block| block_name(cfg, *block))
+        .collect()
+}
+
+/// Prints the natural loops of `cfg` in `output`, with blocks by their names.
+fn print_loops(cfg: &FunctionCfg, forest: &LoopForest, output: OutputFormat) {
+    match output {
+        OutputFormat::Json => {
+            let loops = forest
+                .loops
+                .iter()
+                .map(|natural_loop| {
+                    let exits = natural_loop.exit_blocks(cfg);
+                    json!({
+                        "header": block_name(cfg, natural_loop.header),
+                        "latches": block_names(cfg, &natural_loop.latches),
+                        "body": block_names(cfg, &natural_loop.body),
+                        "exits": block_names(cfg, &exits),
+                    })
+                })
+                .collect::<Vec<_>>();
+            print_envelope(
+                env!("CARGO_PKG_NAME"),
+                Some(cfg.signature.name.as_str()),
+                json!({ "loops": loops }),
+            );
+        }
+        OutputFormat::Text => {
+            let dotted = |names: Vec<String>| {
+                names
+                    .iter()
+                    .map(|name| format!(".{name}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            println!("@{} {{", cfg.signature.name);
+            for natural_loop in &forest.loops {
+                println!("  .{}", block_name(cfg, natural_loop.header));
+                println!(
+                    "    latches: {}",
+                    dotted(block_names(cfg, &natural_loop.latches))
+                );
+                println!(
+                    "    body: {}",
+                    dotted(block_names(cfg, &natural_loop.body))
+                );
+                println!(
+                    "    exits: {}",
+                    dotted(block_names(cfg, &natural_loop.exit_blocks(cfg)))
+                );
+            }
+            println!("}}");
+        }
+        OutputFormat::Dot => {
+            let id = |block: BasicBlockIdx| format!("{block:?}");
+            let mut graph = DotGraph::new(&cfg.signature.name);
+            for block in cfg.vertices.keys() {
+                let mut lines = vec![format!(".{}", block_name(cfg, block))];
+                for natural_loop in &forest.loops {
+                    if natural_loop.body.contains(&block) {
+                        lines.push(format!(
+                            "in loop .{}",
+                            block_name(cfg, natural_loop.header)
+                        ));
+                    }
+                }
+                graph.node(id(block), lines.join("\n"));
+                for successor in cfg.successors(block) {
+                    graph.edge(id(block), id(successor));
+                }
+            }
+            println!("{graph}");
+        }
+    }
 }
 
 #[snafu::report]
 fn main() -> Result<(), Whatever> {
     let opts = argh::from_env::<Opts>();
 
-    let program: Program = if let Some(path) = opts.input {
-        let contents = fs::read_to_string(&path).whatever_context(format!(
+    let mut directives = opts.trace.clone();
+    if opts.explain {
+        directives.push("licm=debug".to_string());
+    }
+    pass_trace::init(&directives)?;
+
+    let program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
             "Failed to read the contents of {}",
             path.to_string_lossy()
         ))?;
@@ -64,298 +286,105 @@ fn main() -> Result<(), Whatever> {
         )?
     };
 
-    for function in program.functions {
-        let mut cfg = build_cfg::build_cfg(&function, true)
-            .whatever_context("Failed to build cfg")?;
-
-        cfg.make_fallthroughs_explicit();
-
-        let dominators = dominators::compute_dominators(&cfg);
-        let dominance_tree = dominators::compute_dominator_tree(&dominators);
-
-        let mut back_edges = vec![];
-        for start in cfg.vertices.keys() {
-            for end in cfg.successors(start) {
-                if dominance_tree[end].contains(&start) {
-                    back_edges.push((start, end));
-                }
+    if let Some(output) = opts.output {
+        let filter = FunctionFilter::new(opts.function.clone());
+        for function in &program.functions {
+            if !filter.selects(&function.name) {
+                continue;
             }
+            let cfg = build_cfg::build_cfg(function, true)
+                .whatever_context("Failed to build cfg")?;
+            let forest = AnalysisCache::default().get::<Loops>(&cfg);
+            print_loops(&cfg, &forest, output);
         }
+        return Ok(());
+    }
 
-        let mut natural_loops = vec![];
-        for (start, end) in back_edges {
-            let mut natural_loop = BTreeSet::from_iter([end]);
-            let mut stack = vec![start];
-            while let Some(next) = stack.pop() {
-                if !natural_loop.contains(&next) {
-                    natural_loop.insert(next);
-                    stack.extend(cfg.predecessors(next));
-                }
-            }
-
-            // println!("new loop containing:");
-            // println!(
-            //     "* backedge {:?} -> {:?}",
-            //     cfg.vertices[start].label, cfg.vertices[end].label
-            // );
-            // print!("contents:");
-            // for block in &natural_loop {
-            //     print!(" {:?}", cfg.vertices[*block].label);
-            // }
-            // println!();
-
-            natural_loops.push(NaturalLoop {
-                header: end,
-                backedge_start: start,
-                body: natural_loop,
-            });
-        }
+    if opts.measure && opts.instrument_profile.is_some() {
+        whatever!("--measure and --instrument-profile cannot be combined");
+    }
 
-        let mut natural_loops_with_preheaders = vec![];
-        for NaturalLoop {
-            header,
-            backedge_start,
-            body,
-        } in natural_loops
-        {
-            let preheader = cfg.add_block(BasicBlock {
-                label: Some(Label {
-                    name: format!(
-                        "{}_preheader",
-                        cfg.vertices[header]
-                            .label
-                            .as_ref()
-                            .map(|label| label.name.clone())
-                            .unwrap_or_default()
-                    ),
-                }),
-                ..Default::default()
-            });
-            for header_predecessor in cfg.predecessors(header).to_vec() {
-                cfg.reorient_edge(header_predecessor, header, preheader);
-            }
-            cfg.set_unconditional_edge(preheader, header);
-
-            natural_loops_with_preheaders.push(NaturalLoopWithPreheader {
-                preheader,
-                header,
-                backedge_start,
-                body,
-            });
-        }
+    let profile = opts.profile.as_deref().map(read_profile).transpose()?;
+    let policy: Box<dyn LoopPolicy> = match &profile {
+        Some(profile) => Box::new(SkipColdLoops {
+            profile,
+            threshold: opts.cold_threshold,
+        }),
+        None => Box::new(OptimizeAll),
+    };
 
-        if opts.stage == Stage::InsertPreheader as u32 {
-            print::print_cfg_as_bril_text(cfg);
-            continue;
+    let analysis_cache = opts
+        .analysis_cache
+        .clone()
+        .or_else(|| env::var_os("BRIL_ANALYSIS_CACHE").map(PathBuf::from))
+        .map(DiskCache::new)
+        .transpose()?
+        .map(Arc::new);
+    let summaries = match &analysis_cache {
+        Some(analysis_cache) => {
+            PuritySummaries::cached(&program, analysis_cache)
         }
+        None => PuritySummaries::new(&program),
+    };
 
-        for NaturalLoopWithPreheader {
-            preheader,
-            header,
-            backedge_start,
-            body,
-        } in natural_loops_with_preheaders
-        {
-            eprintln!("==== PART 1 ====");
-            let reaching_definitions = compute_reaching_definitions(&cfg);
-            let mut loop_invariant =
-                SecondaryMap::<BasicBlockIdx, BTreeSet<usize>>::new();
-
-            let mut changed = true;
-            while changed {
-                changed = false;
-                for block in &body {
-                    for (i, instruction) in
-                        cfg.vertices[*block].instructions.iter().enumerate()
-                    {
-                        match instruction {
-                            Instruction::Value { dest, args, .. } => {
-                                if args.iter().all(|arg| {
-                                    let reaching_definitions_of_arg =
-                                        reaching_definitions[*block]
-                                            .iter()
-                                            .filter(|definition| {
-                                                definition.0.as_str()
-                                                    == arg.as_str()
-                                            })
-                                            .collect::<Vec<_>>();
-
-                                    reaching_definitions_of_arg.iter().all(
-                                        |definition| {
-                                            !body.contains(&definition.2)
-                                        },
-                                    ) || (reaching_definitions_of_arg.len()
-                                        == 1
-                                        && {
-                                            let definition =
-                                                reaching_definitions_of_arg[0];
-                                            loop_invariant
-                                                .entry(definition.2)
-                                                .unwrap()
-                                                .or_default()
-                                                .contains(
-                                                    &(definition.3 as usize),
-                                                )
-                                        })
-                                }) {
-                                    eprintln!(
-                                        "{instruction:?} is loop invariant"
-                                    );
-                                    changed |= loop_invariant
-                                        .entry(*block)
-                                        .unwrap()
-                                        .or_default()
-                                        .insert(i);
-                                }
-                            }
-                            Instruction::Constant { .. } => {
-                                eprintln!("{instruction:?} is loop invariant");
-                                changed |= loop_invariant
-                                    .entry(*block)
-                                    .unwrap()
-                                    .or_default()
-                                    .insert(i);
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-            }
+    let filter = FunctionFilter::new(opts.function.clone());
+    let mut cfgs = vec![];
+    for function in &program.functions {
+        let mut cfg = build_cfg::build_cfg(function, true)
+            .whatever_context("Failed to build cfg")?;
 
-            fn is_unique_definition(
-                definition: (BasicBlockIdx, usize),
-                loop_body: &BTreeSet<BasicBlockIdx>,
-                cfg: &FunctionCfg,
-            ) -> bool {
-                let definition_name = cfg.vertices[definition.0].instructions
-                    [definition.1]
-                    .kill()
-                    .expect("should be a Value or Constant instruction");
-
-                loop_body.iter().fold(0, |acc, block| {
-                    acc + cfg.vertices[*block]
-                        .instructions
-                        .iter()
-                        .filter(|instruction| {
-                            instruction.kill() == Some(&definition_name)
-                        })
-                        .count()
-                }) == 1
-            }
+        cfg.make_fallthroughs_explicit();
 
-            let exit_blocks: BTreeSet<BasicBlockIdx> = body
-                .iter()
-                .flat_map(|&block_idx| cfg.successors(block_idx))
-                .filter(|successor| !body.contains(successor))
-                .collect();
-
-            fn dominates_uses(
-                definition_block: BasicBlockIdx,
-                use_blocks: &[BasicBlockIdx],
-                dominators: &SecondaryMap<
-                    BasicBlockIdx,
-                    HashSet<BasicBlockIdx>,
-                >,
-            ) -> bool {
-                use_blocks.iter().all(|&use_block| {
-                    dominators[use_block].contains(&definition_block)
-                })
+        let mut cache = match &analysis_cache {
+            Some(analysis_cache) => {
+                AnalysisCache::with_disk_cache(analysis_cache.clone())
             }
+            None => AnalysisCache::default(),
+        };
+        let irreducible_edges = cache.get::<IrreducibleEdges>(&cfg);
+        if let Some((start, end)) = irreducible_edges.first() {
+            tracing::warn!(
+                target: "loop-opt",
+                "skipping @{}: irreducible control flow (.{} jumps back to .{}, which does not dominate it)",
+                cfg.signature.name,
+                block_name(&cfg, *start),
+                block_name(&cfg, *end)
+            );
+        } else if !opts.baseline && filter.selects(&cfg.signature.name) {
+            optimize(&mut cfg, &mut cache, &opts, policy.as_ref(), &summaries);
+        }
+        if opts.measure {
+            instrument_dynamic_instruction_count(&mut cfg);
+        }
+        cfgs.push(cfg);
+    }
 
-            fn dominates_exits(
-                definition_block: BasicBlockIdx,
-                exit_blocks: &BTreeSet<BasicBlockIdx>,
-                dominators: &SecondaryMap<
-                    BasicBlockIdx,
-                    HashSet<BasicBlockIdx>,
-                >,
-            ) -> bool {
-                exit_blocks.iter().all(|&exit_block| {
-                    dominators
-                        .get(exit_block)
-                        .map(|exit_block| {
-                            exit_block.contains(&definition_block)
-                        })
-                        .unwrap_or(true)
-                })
-            }
-
-            eprintln!("==== PART 2 ====");
-
-            for (block, instructions) in loop_invariant {
-                if block == cfg.entry {
-                    continue;
-                }
-
-                let mut to_move = vec![];
-                for instruction_idx in instructions {
-                    let mut use_blocks = vec![];
-
-                    let kill = cfg.vertices[block].instructions
-                        [instruction_idx]
-                        .kill()
-                        .unwrap();
-                    for other_block in &body {
-                        for other_instruction in
-                            &cfg.vertices[*other_block].instructions
-                        {
-                            if other_instruction.gen_set().contains(&kill) {
-                                use_blocks.push(*other_block);
-                                break;
-                            }
-                        }
-                    }
-
-                    // eprintln!(
-                    //     "{:?} is a unique def?: {}",
-                    //     cfg.vertices[block].instructions[instruction_idx],
-                    //     is_unique_definition(
-                    //         (block, instruction_idx),
-                    //         &body,
-                    //         &cfg
-                    //     )
-                    // );
-                    // eprintln!(
-                    //     "dominates uses?: {}",
-                    //     dominates_uses(block, &use_blocks, &dominators)
-                    // );
-                    // eprintln!(
-                    //     "dominates exits?: {}",
-                    //     dominates_exits(block, &exit_blocks, &dominators)
-                    // );
-                    if is_unique_definition(
-                        (block, instruction_idx),
-                        &body,
-                        &cfg,
-                    ) && dominates_uses(block, &use_blocks, &dominators)
-                        && dominates_exits(block, &exit_blocks, &dominators)
-                    {
-                        eprintln!(
-                            "moving {:?}",
-                            cfg.vertices[block].instructions[instruction_idx],
-                        );
-                        to_move.push(instruction_idx);
-                    }
-                }
-
-                while let Some(to_move) = to_move.pop() {
-                    let instruction =
-                        cfg.vertices[block].instructions.remove(to_move);
-                    cfg.vertices[preheader].instructions.insert(0, instruction);
-                }
+    if let Some(layout_path) = &opts.instrument_profile {
+        let total_counters =
+            cfgs.iter().map(|cfg| cfg.vertices.len()).sum::<usize>();
+        let mut layout = String::new();
+        let mut first_counter = 0;
+        for cfg in &mut cfgs {
+            let labels =
+                instrument_block_profile(cfg, first_counter, total_counters);
+            for (i, label) in labels.iter().enumerate() {
+                layout.push_str(&format!(
+                    "{} {} {}\n",
+                    first_counter + i,
+                    cfg.signature.name,
+                    label
+                ));
             }
-
-            // Finally, since the back edge has been reoriented, we bring it
-            // back to the original header
-            cfg.reorient_edge(backedge_start, preheader, header);
+            first_counter += labels.len();
         }
+        fs::write(layout_path, layout).whatever_context(format!(
+            "Failed to write the profile layout to {}",
+            layout_path.to_string_lossy()
+        ))?;
+    }
 
-        cfg.simplify_unconditionals_to_fallthroughs();
-
-        if opts.stage == Stage::LoopInvariantCodeMotion as u32 {
-            print::print_cfg_as_bril_text(cfg);
-            continue;
-        }
+    for cfg in cfgs {
+        print::print_cfg_as_bril_text(cfg);
     }
 
     Ok(())
@@ -0,0 +1,242 @@
+use bril_rs::{Argument, EffectOps, Instruction, Type, ValueOps};
+use bril_util::builder::{make_const_int, make_effect, make_value};
+use build_cfg::{Exit, FunctionCfg};
+
+const COUNTER: &str = "__dynamic_instructions";
+const COUNTER_VALUE: &str = "__dynamic_instructions.value";
+const COUNTER_STEP: &str = "__dynamic_instructions.step";
+const PROFILE: &str = "__block_counts";
+const PROFILE_INDEX: &str = "__block_counts.index";
+const PROFILE_POINTER: &str = "__block_counts.pointer";
+const PROFILE_VALUE: &str = "__block_counts.value";
+const PROFILE_STEP: &str = "__block_counts.step";
+
+fn counter_type() -> Type {
+    Type::Pointer(Box::new(Type::Int))
+}
+
+/// Passes the pointer `name` along to every call in `cfg` and, unless `cfg` is
+/// `main`, which must create it, takes it as an extra argument.
+fn thread_pointer_argument(cfg: &mut FunctionCfg, name: &str) {
+    for block in cfg.vertices.values_mut() {
+        for instruction in &mut block.instructions {
+            if let Instruction::Value {
+                args,
+                op: ValueOps::Call,
+                ..
+            }
+            | Instruction::Effect {
+                args,
+                op: EffectOps::Call,
+                ..
+            } = instruction
+            {
+                args.push(name.into());
+            }
+        }
+    }
+
+    if cfg.signature.name != "main" {
+        cfg.signature.arguments.push(Argument {
+            name: name.into(),
+            arg_type: counter_type(),
+        });
+    }
+}
+
+/// Requires: there are no fallthroughs to the end of the function.
+fn insert_before_returns(cfg: &mut FunctionCfg, instructions: &[Instruction]) {
+    let returning_blocks = cfg
+        .vertices
+        .keys()
+        .filter(|block| matches!(cfg.edges[*block], Exit::Return(_)))
+        .collect::<Vec<_>>();
+    for block in returning_blocks {
+        let block_instructions = &mut cfg.vertices[block].instructions;
+        let insertion_point = block_instructions.len() - 1;
+        block_instructions
+            .insert_many(insertion_point, instructions.iter().cloned());
+    }
+}
+
+/// Instruments `cfg` to count the instructions it executes in a counter
+/// threaded through every function as an extra pointer argument. The `main`
+/// function allocates the counter and prints its final value on return, so
+/// the last line of output is the dynamic instruction count of the program as
+/// it was before instrumentation.
+///
+/// Every function in the program must be instrumented, since calls pass the
+/// counter along.
+///
+/// Requires: there are no fallthroughs to the end of the function.
+pub fn instrument_dynamic_instruction_count(cfg: &mut FunctionCfg) {
+    for block in cfg.vertices.values_mut() {
+        let count = block.instructions.len() as i64;
+        block.instructions.insert_many(
+            0,
+            [
+                make_value(
+                    COUNTER_VALUE,
+                    Type::Int,
+                    ValueOps::Load,
+                    vec![COUNTER.into()],
+                ),
+                make_const_int(COUNTER_STEP, count),
+                make_value(
+                    COUNTER_VALUE,
+                    Type::Int,
+                    ValueOps::Add,
+                    vec![COUNTER_VALUE.into(), COUNTER_STEP.into()],
+                ),
+                make_effect(
+                    EffectOps::Store,
+                    vec![COUNTER.into(), COUNTER_VALUE.into()],
+                ),
+            ],
+        );
+    }
+    thread_pointer_argument(cfg, COUNTER);
+
+    if cfg.signature.name != "main" {
+        return;
+    }
+
+    cfg.vertices[cfg.entry].instructions.insert_many(
+        0,
+        [
+            make_const_int(COUNTER_STEP, 1),
+            make_value(
+                COUNTER,
+                counter_type(),
+                ValueOps::Alloc,
+                vec![COUNTER_STEP.into()],
+            ),
+            make_const_int(COUNTER_VALUE, 0),
+            make_effect(
+                EffectOps::Store,
+                vec![COUNTER.into(), COUNTER_VALUE.into()],
+            ),
+        ],
+    );
+    insert_before_returns(
+        cfg,
+        &[
+            make_value(
+                COUNTER_VALUE,
+                Type::Int,
+                ValueOps::Load,
+                vec![COUNTER.into()],
+            ),
+            make_effect(EffectOps::Print, vec![COUNTER_VALUE.into()]),
+            make_effect(EffectOps::Free, vec![COUNTER.into()]),
+        ],
+    );
+}
+
+/// Instruments `cfg` to count how many times each of its blocks executes,
+/// using the counters starting at `first_counter` in an array of
+/// `total_counters` counters threaded through every function as an extra
+/// pointer argument. The `main` function allocates the array and prints a
+/// line `counter count` for every counter on return. Returns the label of the
+/// block using each counter, in order, or `-` for an unlabeled entry.
+///
+/// Every function in the program must be instrumented, since calls pass the
+/// array along.
+///
+/// Requires: there are no fallthroughs to the end of the function.
+pub fn instrument_block_profile(
+    cfg: &mut FunctionCfg,
+    first_counter: usize,
+    total_counters: usize,
+) -> Vec<String> {
+    let mut labels = vec![];
+    for (i, block) in cfg.vertices.values_mut().enumerate() {
+        labels.push(
+            block
+                .label
+                .as_ref()
+                .map_or_else(|| "-".into(), |label| label.name.clone()),
+        );
+        block.instructions.insert_many(
+            0,
+            [
+                make_const_int(PROFILE_INDEX, (first_counter + i) as i64),
+                make_value(
+                    PROFILE_POINTER,
+                    counter_type(),
+                    ValueOps::PtrAdd,
+                    vec![PROFILE.into(), PROFILE_INDEX.into()],
+                ),
+                make_value(
+                    PROFILE_VALUE,
+                    Type::Int,
+                    ValueOps::Load,
+                    vec![PROFILE_POINTER.into()],
+                ),
+                make_const_int(PROFILE_STEP, 1),
+                make_value(
+                    PROFILE_VALUE,
+                    Type::Int,
+                    ValueOps::Add,
+                    vec![PROFILE_VALUE.into(), PROFILE_STEP.into()],
+                ),
+                make_effect(
+                    EffectOps::Store,
+                    vec![PROFILE_POINTER.into(), PROFILE_VALUE.into()],
+                ),
+            ],
+        );
+    }
+    thread_pointer_argument(cfg, PROFILE);
+
+    if cfg.signature.name != "main" {
+        return labels;
+    }
+
+    let mut prologue = vec![
+        make_const_int(PROFILE_INDEX, total_counters as i64),
+        make_value(
+            PROFILE,
+            counter_type(),
+            ValueOps::Alloc,
+            vec![PROFILE_INDEX.into()],
+        ),
+        make_const_int(PROFILE_VALUE, 0),
+    ];
+    let mut epilogue = vec![];
+    for counter in 0..total_counters {
+        let address = [
+            make_const_int(PROFILE_INDEX, counter as i64),
+            make_value(
+                PROFILE_POINTER,
+                counter_type(),
+                ValueOps::PtrAdd,
+                vec![PROFILE.into(), PROFILE_INDEX.into()],
+            ),
+        ];
+        prologue.extend(address.clone());
+        prologue.push(make_effect(
+            EffectOps::Store,
+            vec![PROFILE_POINTER.into(), PROFILE_VALUE.into()],
+        ));
+        epilogue.extend(address);
+        epilogue.push(make_value(
+            PROFILE_VALUE,
+            Type::Int,
+            ValueOps::Load,
+            vec![PROFILE_POINTER.into()],
+        ));
+        epilogue.push(make_effect(
+            EffectOps::Print,
+            vec![PROFILE_INDEX.into(), PROFILE_VALUE.into()],
+        ));
+    }
+    epilogue.push(make_effect(EffectOps::Free, vec![PROFILE.into()]));
+
+    cfg.vertices[cfg.entry]
+        .instructions
+        .insert_many(0, prologue);
+    insert_before_returns(cfg, &epilogue);
+
+    labels
+}
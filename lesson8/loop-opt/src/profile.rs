@@ -0,0 +1,84 @@
+use std::cmp::Reverse;
+
+pub use bril_util::profile::{Profile, read_profile};
+use build_cfg::FunctionCfg;
+
+use crate::loops::{LoopForest, NaturalLoop, block_name};
+
+/// Decides which loops the loop passes work on and in what order.
+pub trait LoopPolicy {
+    /// Whether to optimize `natural_loop` at all.
+    fn should_optimize(
+        &self,
+        cfg: &FunctionCfg,
+        natural_loop: &NaturalLoop,
+    ) -> bool;
+
+    /// Loops with higher priority are optimized first.
+    fn priority(&self, _cfg: &FunctionCfg, _natural_loop: &NaturalLoop) -> u64 {
+        0
+    }
+}
+
+/// Optimizes every loop.
+pub struct OptimizeAll;
+
+impl LoopPolicy for OptimizeAll {
+    fn should_optimize(
+        &self,
+        _cfg: &FunctionCfg,
+        _natural_loop: &NaturalLoop,
+    ) -> bool {
+        true
+    }
+}
+
+/// Skips loops whose header executed fewer than `threshold` times in the
+/// profile, including those missing from it, and optimizes the hottest loops
+/// first.
+pub struct SkipColdLoops<'a> {
+    pub profile: &'a Profile,
+    pub threshold: u64,
+}
+
+impl SkipColdLoops<'_> {
+    fn header_count(
+        &self,
+        cfg: &FunctionCfg,
+        natural_loop: &NaturalLoop,
+    ) -> Option<u64> {
+        let label = block_name(cfg, natural_loop.header);
+        self.profile.count(&cfg.signature.name, &label)
+    }
+}
+
+impl LoopPolicy for SkipColdLoops<'_> {
+    fn should_optimize(
+        &self,
+        cfg: &FunctionCfg,
+        natural_loop: &NaturalLoop,
+    ) -> bool {
+        self.header_count(cfg, natural_loop)
+            .is_some_and(|count| count >= self.threshold)
+    }
+
+    fn priority(&self, cfg: &FunctionCfg, natural_loop: &NaturalLoop) -> u64 {
+        self.header_count(cfg, natural_loop).unwrap_or_default()
+    }
+}
+
+/// Removes the loops `policy` does not want optimized from `forest` and orders
+/// the rest by decreasing priority, which the loop passes follow except that
+/// LICM still handles nested loops before the loops enclosing them.
+pub fn apply_policy(
+    cfg: &FunctionCfg,
+    forest: &mut LoopForest,
+    policy: &dyn LoopPolicy,
+) {
+    forest
+        .loops
+        .retain(|natural_loop| policy.should_optimize(cfg, natural_loop));
+    forest.loops.sort_by_key(|natural_loop| {
+        Reverse(policy.priority(cfg, natural_loop))
+    });
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+
+use bril_rs::{EffectOps, Instruction, Type, ValueOps};
+use bril_util::{
+    InstructionExt,
+    builder::{make_effect, make_id, make_value, with_pos},
+};
+use build_cfg::{BasicBlockIdx, Exit, FunctionCfg, pass::AnalysisCache};
+use snafu::{OptionExt, Whatever, whatever};
+
+use crate::{
+    alias::{AliasAnalysis, MemoryLocation, PointsTo},
+    loops::{LoopForest, NaturalLoop, block_name, fresh_variable},
+};
+
+/// Promotes memory accessed through loop-invariant pointers that nothing else
+/// in the loop may alias to scalars: the value is loaded once in the
+/// preheader, the loads and stores in the loop become copies to and from a
+/// fresh variable, and the value is stored back once at each exit, which is
+/// first made dedicated. Returns the reasons any pointers accessed in loops
+/// could not be promoted.
+///
+/// Requires: `forest` has been normalized.
+pub fn promote_scalars(
+    cfg: &mut FunctionCfg,
+    forest: &mut LoopForest,
+    cache: &mut AnalysisCache,
+) -> Vec<String> {
+    let mut failures = vec![];
+    for i in 0..forest.loops.len() {
+        let aliases = cache.get_persistent::<PointsTo>(cfg);
+        let mut dedicated = false;
+        for pointer in accessed_pointers(cfg, &forest.loops[i]) {
+            match check_promotion(cfg, &aliases, &forest.loops[i], &pointer) {
+                Ok(element_type) => {
+                    if !dedicated {
+                        forest.dedicate_exits(cfg, i);
+                        dedicated = true;
+                    }
+                    promote(cfg, &forest.loops[i], &pointer, element_type);
+                }
+                Err(error) => failures.push(format!(
+                    "cannot promote `{pointer}` in loop at `{}`: {error}",
+                    block_name(cfg, forest.loops[i].header)
+                )),
+            }
+        }
+        if dedicated {
+            cache.invalidate();
+        }
+    }
+    failures
+}
+
+/// The first argument of a `load`, `store`, or `free`.
+fn memory_operand(instruction: &Instruction) -> Option<&String> {
+    match instruction {
+        Instruction::Value {
+            args,
+            op: ValueOps::Load,
+            ..
+        }
+        | Instruction::Effect {
+            args,
+            op: EffectOps::Store | EffectOps::Free,
+            ..
+        } => args.first(),
+        _ => None,
+    }
+}
+
+fn is_call(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Value {
+            op: ValueOps::Call,
+            ..
+        } | Instruction::Effect {
+            op: EffectOps::Call,
+            ..
+        }
+    )
+}
+
+fn accessed_pointers(
+    cfg: &FunctionCfg,
+    natural_loop: &NaturalLoop,
+) -> BTreeSet<String> {
+    natural_loop
+        .body
+        .iter()
+        .flat_map(|block| &cfg.vertices[*block].instructions)
+        .filter_map(memory_operand)
+        .cloned()
+        .collect()
+}
+
+/// Whether `pointer` is loaded from or stored to on every path to the end of
+/// `block` after its last definition, without anything in between that may
+/// free it, so that loading from it there cannot fail. Only follows unique
+/// predecessors backward.
+fn is_accessed_before(
+    cfg: &FunctionCfg,
+    aliases: &AliasAnalysis,
+    mut block: BasicBlockIdx,
+    pointer: &str,
+) -> bool {
+    let mut visited = vec![];
+    loop {
+        for instruction in cfg.vertices[block].instructions.iter().rev() {
+            if instruction.kill().map(String::as_str) == Some(pointer)
+                || is_call(instruction)
+            {
+                return false;
+            }
+            match instruction {
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Free,
+                    ..
+                } if aliases.may_alias(&args[0], pointer) => {
+                    return false;
+                }
+                _ if memory_operand(instruction).map(String::as_str)
+                    == Some(pointer) =>
+                {
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        visited.push(block);
+        match cfg.predecessors(block) {
+            [predecessor] if !visited.contains(predecessor) => {
+                block = *predecessor;
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// The type of the values `pointer` points to.
+fn element_type(cfg: &FunctionCfg, pointer: &str) -> Option<Type> {
+    let pointer_type = cfg
+        .signature
+        .arguments
+        .iter()
+        .find(|argument| argument.name == pointer)
+        .map(|argument| &argument.arg_type)
+        .or_else(|| {
+            cfg.vertices
+                .values()
+                .flat_map(|block| &block.instructions)
+                .find_map(|instruction| match instruction {
+                    Instruction::Value { dest, op_type, .. }
+                        if dest == pointer =>
+                    {
+                        Some(op_type)
+                    }
+                    _ => None,
+                })
+        })?;
+    match pointer_type {
+        Type::Pointer(element_type) => Some((**element_type).clone()),
+        _ => None,
+    }
+}
+
+/// Returns the type of the values `pointer` points to if it can be promoted.
+fn check_promotion(
+    cfg: &FunctionCfg,
+    aliases: &AliasAnalysis,
+    natural_loop: &NaturalLoop,
+    pointer: &str,
+) -> Result<Type, Whatever> {
+    if aliases
+        .locations(pointer)
+        .contains(&MemoryLocation::Unknown)
+    {
+        whatever!("it may point anywhere");
+    }
+
+    for block in &natural_loop.body {
+        if matches!(cfg.edges[*block], Exit::Return(_)) {
+            whatever!("the loop returns from the function");
+        }
+        for instruction in &cfg.vertices[*block].instructions {
+            if instruction.kill().map(String::as_str) == Some(pointer) {
+                whatever!("it is not loop invariant");
+            }
+            if is_call(instruction) {
+                whatever!("the loop contains a call");
+            }
+            if let Instruction::Effect {
+                op: EffectOps::Free,
+                ..
+            } = instruction
+            {
+                whatever!("the loop frees memory");
+            }
+            if let Some(other) = memory_operand(instruction) {
+                if other != pointer && aliases.may_alias(other, pointer) {
+                    whatever!("it may alias `{other}`");
+                }
+            }
+        }
+    }
+
+    if !is_accessed_before(cfg, aliases, natural_loop.preheader(), pointer) {
+        whatever!("it is not known to be initialized before the loop");
+    }
+
+    element_type(cfg, pointer)
+        .whatever_context("its element type could not be determined")
+}
+
+fn promote(
+    cfg: &mut FunctionCfg,
+    natural_loop: &NaturalLoop,
+    pointer: &str,
+    element_type: Type,
+) {
+    let scalar = fresh_variable(cfg, format!("{pointer}.promoted"));
+
+    for block in &natural_loop.body {
+        for instruction in &mut cfg.vertices[*block].instructions {
+            match instruction {
+                Instruction::Value {
+                    args,
+                    op: op @ ValueOps::Load,
+                    ..
+                } if args[0] == pointer => {
+                    *op = ValueOps::Id;
+                    *args = vec![scalar.clone()];
+                }
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Store,
+                    pos,
+                    ..
+                } if args[0] == pointer => {
+                    let copy = with_pos(
+                        make_id(&scalar, element_type.clone(), &args[1]),
+                        pos.clone(),
+                    );
+                    *instruction = copy;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let preheader = natural_loop.preheader();
+    let insertion_point = cfg.vertices[preheader].index_before_exit();
+    cfg.vertices[preheader].instructions.insert(
+        insertion_point,
+        make_value(
+            scalar.clone(),
+            element_type,
+            ValueOps::Load,
+            vec![pointer.to_string()],
+        ),
+    );
+
+    for exit in natural_loop.exit_blocks(cfg) {
+        cfg.vertices[exit].instructions.insert(
+            0,
+            make_effect(
+                EffectOps::Store,
+                vec![pointer.to_string(), scalar.clone()],
+            ),
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Local list scheduling: reorders the instructions of each block, as far as
+//! their dependences allow, so that values are defined close to where they are
+//! used and fewer variables are live at once.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap, HashSet},
+};
+
+use bril_rs::Instruction;
+use bril_util::{InstructionExt, capabilities::Capabilities, error::ToolError};
+use build_cfg::{
+    BasicBlockIdx, FunctionCfg, Instructions,
+    pass::{AnalysisCache, Changed, FunctionPass},
+    slotmap::SecondaryMap,
+    symbol::Symbol,
+};
+use dataflow::live_variables::LiveVariables;
+
+use crate::{
+    alias::{AliasAnalysis, PointsTo},
+    dependence::Accesses,
+};
+
+/// Which instructions of a block must stay after which others: an instruction
+/// depends on every earlier one whose [`Accesses`] conflict with its own.
+pub struct DependenceGraph {
+    predecessors: Vec<Vec<usize>>,
+    successors: Vec<Vec<usize>>,
+}
+
+impl DependenceGraph {
+    pub fn new(instructions: &[Instruction], aliases: &AliasAnalysis) -> Self {
+        let accesses = instructions
+            .iter()
+            .map(|instruction| Accesses::of([instruction]))
+            .collect::<Vec<_>>();
+        let mut predecessors = vec![vec![]; instructions.len()];
+        let mut successors = vec![vec![]; instructions.len()];
+        for later in 0..instructions.len() {
+            for earlier in 0..later {
+                if accesses[earlier]
+                    .conflict_with(&accesses[later], aliases)
+                    .is_some()
+                {
+                    predecessors[later].push(earlier);
+                    successors[earlier].push(later);
+                }
+            }
+        }
+        Self {
+            predecessors,
+            successors,
+        }
+    }
+
+    /// The indices of the instructions that must come before `instruction`.
+    pub fn predecessors(&self, instruction: usize) -> &[usize] {
+        &self.predecessors[instruction]
+    }
+
+    /// The indices of the instructions that must come after `instruction`.
+    pub fn successors(&self, instruction: usize) -> &[usize] {
+        &self.successors[instruction]
+    }
+}
+
+/// The total length of the live ranges in `instructions`, counted as the
+/// number of variables live after each instruction.
+fn live_range_length(
+    instructions: &[Instruction],
+    live_out: &HashSet<String>,
+) -> usize {
+    let mut live = live_out.clone();
+    let mut length = 0;
+    for instruction in instructions.iter().rev() {
+        length += live.len();
+        if let Some(kill) = instruction.kill() {
+            live.remove(kill);
+        }
+        live.extend(instruction.gen_set().iter().cloned());
+    }
+    length
+}
+
+/// How much scheduling `instruction` next shortens the live ranges: the number
+/// of variables it reads for the last time, less one if it defines a variable
+/// that is read later.
+fn priority(
+    instruction: &Instruction,
+    remaining_uses: &HashMap<&str, usize>,
+    live_out: &HashSet<String>,
+) -> isize {
+    let mut uses = HashMap::<&str, usize>::new();
+    for used in instruction.gen_set() {
+        *uses.entry(used).or_default() += 1;
+    }
+    let ended = uses
+        .iter()
+        .filter(|(variable, count)| {
+            remaining_uses[*variable] == **count
+                && !live_out.contains(**variable)
+        })
+        .count();
+    let started = instruction.kill().is_some_and(|dest| {
+        remaining_uses
+            .get(dest.as_str())
+            .is_some_and(|uses| *uses > 0)
+            || live_out.contains(dest)
+    });
+    ended as isize - started as isize
+}
+
+/// Orders `instructions` by repeatedly picking, among those whose dependences
+/// have all been picked, the one with the highest [`priority`], breaking ties
+/// by the original order. Returns the new order as indices.
+fn list_schedule(
+    instructions: &[Instruction],
+    graph: &DependenceGraph,
+    live_out: &HashSet<String>,
+) -> Vec<usize> {
+    let mut remaining_uses = HashMap::<&str, usize>::new();
+    for instruction in instructions {
+        for used in instruction.gen_set() {
+            *remaining_uses.entry(used).or_default() += 1;
+        }
+    }
+    let mut unscheduled_predecessors = (0..instructions.len())
+        .map(|i| graph.predecessors(i).len())
+        .collect::<Vec<_>>();
+    let mut ready = (0..instructions.len())
+        .filter(|i| unscheduled_predecessors[*i] == 0)
+        .collect::<BTreeSet<_>>();
+
+    let mut order = Vec::with_capacity(instructions.len());
+    while let Some(next) = ready.iter().copied().max_by_key(|i| {
+        (
+            priority(&instructions[*i], &remaining_uses, live_out),
+            Reverse(*i),
+        )
+    }) {
+        ready.remove(&next);
+        for used in instructions[next].gen_set() {
+            *remaining_uses
+                .get_mut(used.as_str())
+                .expect("every use was counted") -= 1;
+        }
+        for successor in graph.successors(next) {
+            unscheduled_predecessors[*successor] -= 1;
+            if unscheduled_predecessors[*successor] == 0 {
+                ready.insert(*successor);
+            }
+        }
+        order.push(next);
+    }
+    order
+}
+
+/// Schedules the instructions before the exit of each block, keeping a new
+/// order only if it shortens the live ranges in the block. Returns the number
+/// of blocks reordered.
+pub fn schedule_instructions(
+    cfg: &mut FunctionCfg,
+    live_in: &SecondaryMap<BasicBlockIdx, HashSet<Symbol>>,
+    aliases: &AliasAnalysis,
+) -> usize {
+    let mut reordered = 0;
+    let blocks = cfg.vertices.keys().collect::<Vec<_>>();
+    for block_idx in blocks {
+        let block = &cfg.vertices[block_idx];
+        let (body, exit) =
+            block.instructions.split_at(block.index_before_exit());
+        if body.len() < 2 {
+            continue;
+        }
+
+        let mut live_out = cfg
+            .successors(block_idx)
+            .into_iter()
+            .filter_map(|successor| live_in.get(successor))
+            .flatten()
+            .map(|variable| cfg.symbols.name(*variable).to_string())
+            .collect::<HashSet<_>>();
+        for instruction in exit {
+            live_out.extend(instruction.gen_set().iter().cloned());
+        }
+
+        let graph = DependenceGraph::new(body, aliases);
+        let order = list_schedule(body, &graph, &live_out);
+        let scheduled =
+            order.iter().map(|i| body[*i].clone()).collect::<Vec<_>>();
+        if live_range_length(&scheduled, &live_out)
+            >= live_range_length(body, &live_out)
+        {
+            continue;
+        }
+
+        let instructions = scheduled
+            .into_iter()
+            .chain(exit.iter().cloned())
+            .collect::<Instructions>();
+        cfg.vertices[block_idx].instructions = instructions;
+        reordered += 1;
+    }
+    reordered
+}
+
+/// Runs [`schedule_instructions`] as a pass.
+pub struct SchedulePass;
+
+impl FunctionPass for SchedulePass {
+    fn name(&self) -> &'static str {
+        "schedule"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // moving a definition out of a speculated region keeps it on abort
+        Capabilities::all() - Capabilities::SPECULATION
+    }
+
+    fn run(
+        &mut self,
+        cfg: &mut FunctionCfg,
+        cache: &mut AnalysisCache,
+    ) -> Result<Changed, ToolError> {
+        let live_in = cache.get::<LiveVariables>(cfg);
+        let aliases = cache.get_persistent::<PointsTo>(cfg);
+        let reordered = schedule_instructions(cfg, &live_in, &aliases);
+        Ok(Changed::from(reordered > 0))
+    }
+}
@@ -0,0 +1,179 @@
+use bril_rs::{Function, Instruction};
+use bril_util::InstructionExt;
+use build_cfg::{BasicBlockIdx, FunctionCfg, build_cfg};
+use loop_opt::{
+    licm::{Explanation, HoistFailure, loop_invariant_code_motion},
+    loops::LoopForest,
+};
+use purity::PuritySummaries;
+use serde_json::{Value, json};
+
+/// Runs LICM on `function`, which has a single loop, returning the CFG, the
+/// preheader of the loop, and why instructions were not hoisted.
+fn licm(function: Value) -> (FunctionCfg, BasicBlockIdx, Vec<Explanation>) {
+    let function: Function =
+        serde_json::from_value(function).expect("The function is valid Bril");
+    let mut cfg = build_cfg(&function, false).expect("The function has a CFG");
+    let dominators = dominators::compute_dominators(&cfg);
+    let mut forest = LoopForest::find(&cfg, &dominators);
+    forest.normalize(&mut cfg);
+    let hoisting = loop_invariant_code_motion(
+        &mut cfg,
+        &forest,
+        &PuritySummaries::default(),
+    );
+    (cfg, forest.loops[0].preheader(), hoisting.explanations)
+}
+
+fn defines(instructions: &[Instruction], variable: &str) -> bool {
+    instructions.iter().any(|instruction| {
+        instruction.kill().is_some_and(|dest| dest == variable)
+    })
+}
+
+fn failures(explanations: &[Explanation], variable: &str) -> Vec<HoistFailure> {
+    explanations
+        .iter()
+        .find(|explanation| {
+            explanation
+                .instruction
+                .kill()
+                .is_some_and(|dest| dest == variable)
+        })
+        .map(|explanation| explanation.failures.clone())
+        .unwrap_or_default()
+}
+
+/// A loop rotated so that its test is at the bottom, entered from `entry`,
+/// which ends with `guard`, and whose header runs `header` before jumping to
+/// the block with the `div`.
+fn rotated_loop_with_div(guard: &[Value], header: Value) -> Value {
+    let mut instructions = vec![
+        json!({ "dest": "i", "op": "const", "type": "int", "value": 0 }),
+        json!({ "dest": "one", "op": "const", "type": "int", "value": 1 }),
+    ];
+    instructions.extend_from_slice(guard);
+    instructions.extend([
+        json!({ "label": "loop" }),
+        header,
+        json!({ "op": "jmp", "labels": ["body"] }),
+        json!({ "label": "body" }),
+        json!({ "dest": "q", "op": "div", "type": "int", "args": ["a", "b"] }),
+        json!({ "op": "print", "args": ["q"] }),
+        json!({
+            "dest": "i", "op": "add", "type": "int", "args": ["i", "one"],
+        }),
+        json!({ "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] }),
+        json!({ "op": "br", "args": ["c"], "labels": ["loop", "done"] }),
+        json!({ "label": "done" }),
+        json!({ "op": "ret" }),
+    ]);
+    json!({
+        "name": "quotients",
+        "args": [
+            { "name": "n", "type": "int" },
+            { "name": "a", "type": "int" },
+            { "name": "b", "type": "int" },
+        ],
+        "instrs": instructions,
+    })
+}
+
+/// A copy of the loop's test that skips the loop when it fails.
+fn guard() -> [Value; 2] {
+    [
+        json!({ "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] }),
+        json!({ "op": "br", "args": ["c"], "labels": ["loop", "done"] }),
+    ]
+}
+
+fn nop() -> Value {
+    json!({ "op": "nop" })
+}
+
+/// When `n` is zero the loop runs zero times, but the guard skips the
+/// preheader too, so the `div` cannot trap there when `b` is zero.
+#[test]
+fn div_hoisted_from_guarded_loop() {
+    let (cfg, preheader, explanations) =
+        licm(rotated_loop_with_div(&guard(), nop()));
+    assert!(defines(&cfg.vertices[preheader].instructions, "q"));
+    assert_eq!(cfg.predecessors(preheader), [cfg.entry]);
+    assert!(failures(&explanations, "q").is_empty());
+}
+
+/// Even in a guarded loop, hoisting the `div` above the `print` in the header
+/// would trap before printing when `b` is zero.
+#[test]
+fn div_not_hoisted_above_print() {
+    let (cfg, preheader, explanations) = licm(rotated_loop_with_div(
+        &guard(),
+        json!({ "op": "print", "args": ["i"] }),
+    ));
+    assert!(!defines(&cfg.vertices[preheader].instructions, "q"));
+    assert_eq!(
+        failures(&explanations, "q"),
+        [HoistFailure::FollowsSideEffects]
+    );
+}
+
+/// A guard branching on a variable named like the loop's condition, but
+/// computed differently, is not a copy of the loop's test.
+#[test]
+fn div_not_hoisted_when_guard_tests_another_condition() {
+    let (cfg, preheader, explanations) = licm(rotated_loop_with_div(
+        &[
+            json!({
+                "dest": "c", "op": "le", "type": "bool", "args": ["i", "n"],
+            }),
+            json!({ "op": "br", "args": ["c"], "labels": ["loop", "done"] }),
+        ],
+        nop(),
+    ));
+    assert!(!defines(&cfg.vertices[preheader].instructions, "q"));
+    assert_eq!(failures(&explanations, "q"), [HoistFailure::MayTrap]);
+}
+
+/// Trapping instructions outside the header are only hoisted into the
+/// preheader of a loop guarded by its own test, so the `div` stays in this
+/// loop even though every iteration reaches it.
+#[test]
+fn div_not_hoisted_from_unguarded_loop() {
+    let (cfg, preheader, explanations) = licm(rotated_loop_with_div(
+        &[json!({ "op": "jmp", "labels": ["loop"] })],
+        nop(),
+    ));
+    assert!(!defines(&cfg.vertices[preheader].instructions, "q"));
+    assert_eq!(failures(&explanations, "q"), [HoistFailure::MayTrap]);
+}
+
+/// A `load` must see the `store` from the previous iteration.
+#[test]
+fn load_not_hoisted() {
+    let (cfg, preheader, explanations) = licm(json!({
+        "name": "counter",
+        "args": [{ "name": "n", "type": "int" }],
+        "instrs": [
+            { "dest": "one", "op": "const", "type": "int", "value": 1 },
+            {
+                "dest": "p", "op": "alloc", "type": { "ptr": "int" },
+                "args": ["one"],
+            },
+            { "dest": "i", "op": "const", "type": "int", "value": 0 },
+            { "op": "store", "args": ["p", "i"] },
+            { "op": "jmp", "labels": ["loop"] },
+            { "label": "loop" },
+            { "dest": "v", "op": "load", "type": "int", "args": ["p"] },
+            { "dest": "w", "op": "add", "type": "int", "args": ["v", "one"] },
+            { "op": "store", "args": ["p", "w"] },
+            { "dest": "i", "op": "add", "type": "int", "args": ["i", "one"] },
+            { "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] },
+            { "op": "br", "args": ["c"], "labels": ["loop", "done"] },
+            { "label": "done" },
+            { "op": "free", "args": ["p"] },
+            { "op": "ret" },
+        ],
+    }));
+    assert!(!defines(&cfg.vertices[preheader].instructions, "v"));
+    assert_eq!(failures(&explanations, "v"), [HoistFailure::HasSideEffects]);
+}
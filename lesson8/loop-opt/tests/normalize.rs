@@ -0,0 +1,101 @@
+use bril_rs::Function;
+use build_cfg::{FunctionCfg, build_cfg};
+use loop_opt::loops::LoopForest;
+use serde_json::{Value, json};
+
+fn cfg(function: Value) -> FunctionCfg {
+    let function: Function =
+        serde_json::from_value(function).expect("The function is valid Bril");
+    build_cfg(&function, false).expect("The function has a CFG")
+}
+
+/// `@count(n: int)`, a while loop rotated so the test is at the bottom and
+/// guarded by a copy of it, which leaves the loop through the same block the
+/// guard skips to.
+fn guarded_while_loop() -> FunctionCfg {
+    cfg(json!({
+        "name": "count",
+        "args": [{ "name": "n", "type": "int" }],
+        "instrs": [
+            { "dest": "i", "op": "const", "type": "int", "value": 0 },
+            { "dest": "one", "op": "const", "type": "int", "value": 1 },
+            { "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] },
+            { "op": "br", "args": ["c"], "labels": ["loop", "done"] },
+            { "label": "loop" },
+            { "op": "print", "args": ["i"] },
+            { "dest": "i", "op": "add", "type": "int", "args": ["i", "one"] },
+            { "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] },
+            { "op": "br", "args": ["c"], "labels": ["loop", "done"] },
+            { "label": "done" },
+            { "op": "ret" },
+        ],
+    }))
+}
+
+/// The preheader and the dedicated exit both reorient a branch, which must
+/// leave a CFG that verifies.
+#[test]
+fn normalized_guarded_while_loop_verifies() {
+    let mut cfg = guarded_while_loop();
+    let dominators = dominators::compute_dominators(&cfg);
+    let mut forest = LoopForest::find(&cfg, &dominators);
+    assert_eq!(forest.loops.len(), 1);
+
+    forest.normalize(&mut cfg);
+    cfg.verify().expect("Normalizing keeps the CFG well formed");
+    let preheader = forest.loops[0].preheader();
+    assert_eq!(cfg.successors(preheader), [forest.loops[0].header]);
+
+    let dedicated = forest.dedicate_exits(&mut cfg, 0);
+    assert_eq!(dedicated.len(), 1);
+    cfg.verify()
+        .expect("Dedicating exits keeps the CFG well formed");
+}
+
+/// `@search(n: int)`, a guarded loop that also breaks out of its body early
+/// to the block the guard skips to.
+fn loop_with_break() -> FunctionCfg {
+    cfg(json!({
+        "name": "search",
+        "args": [{ "name": "n", "type": "int" }],
+        "instrs": [
+            { "dest": "i", "op": "const", "type": "int", "value": 0 },
+            { "dest": "one", "op": "const", "type": "int", "value": 1 },
+            { "dest": "ten", "op": "const", "type": "int", "value": 10 },
+            { "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] },
+            { "op": "br", "args": ["c"], "labels": ["loop", "done"] },
+            { "label": "loop" },
+            { "dest": "big", "op": "gt", "type": "bool", "args": ["i", "ten"] },
+            { "op": "br", "args": ["big"], "labels": ["done", "body"] },
+            { "label": "body" },
+            { "dest": "i", "op": "add", "type": "int", "args": ["i", "one"] },
+            { "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] },
+            { "op": "br", "args": ["c"], "labels": ["loop", "done"] },
+            { "label": "done" },
+            { "op": "ret" },
+        ],
+    }))
+}
+
+/// Both edges leaving the loop for the shared exit go through the one new
+/// block, and only the guard still enters the exit directly.
+#[test]
+fn shared_exit_is_dedicated() {
+    let mut cfg = loop_with_break();
+    let dominators = dominators::compute_dominators(&cfg);
+    let mut forest = LoopForest::find(&cfg, &dominators);
+    forest.normalize(&mut cfg);
+
+    let [dedicated] = forest.dedicate_exits(&mut cfg, 0)[..] else {
+        panic!("The loop has one shared exit");
+    };
+    cfg.verify()
+        .expect("Dedicating exits keeps the CFG well formed");
+    let [exit] = cfg.successors(dedicated)[..] else {
+        panic!("The dedicated exit jumps to the shared exit");
+    };
+    assert_eq!(cfg.predecessors(dedicated).len(), 2);
+    assert_eq!(cfg.predecessors(exit).len(), 2);
+    assert!(cfg.predecessors(exit).contains(&dedicated));
+    assert!(!forest.loops[0].body.contains(&dedicated));
+}
@@ -0,0 +1,85 @@
+use bril_rs::Function;
+use build_cfg::{FunctionCfg, build_cfg};
+use loop_opt::{
+    licm::loop_invariant_code_motion,
+    loops::LoopForest,
+    profile::{Profile, SkipColdLoops, apply_policy},
+};
+use purity::PuritySummaries;
+use serde_json::json;
+
+/// `@twice(n: int)`, a loop at `.small` followed by a loop at `.large` whose
+/// body has one more block.
+fn two_loops() -> FunctionCfg {
+    let function: Function = serde_json::from_value(json!({
+        "name": "twice",
+        "args": [{ "name": "n", "type": "int" }],
+        "instrs": [
+            { "dest": "one", "op": "const", "type": "int", "value": 1 },
+            { "dest": "i", "op": "const", "type": "int", "value": 0 },
+            { "op": "jmp", "labels": ["small"] },
+            { "label": "small" },
+            { "dest": "i", "op": "add", "type": "int", "args": ["i", "one"] },
+            { "dest": "c", "op": "lt", "type": "bool", "args": ["i", "n"] },
+            { "op": "br", "args": ["c"], "labels": ["small", "between"] },
+            { "label": "between" },
+            { "dest": "j", "op": "const", "type": "int", "value": 0 },
+            { "op": "jmp", "labels": ["large"] },
+            { "label": "large" },
+            { "dest": "j", "op": "add", "type": "int", "args": ["j", "one"] },
+            { "op": "jmp", "labels": ["large.latch"] },
+            { "label": "large.latch" },
+            { "dest": "d", "op": "lt", "type": "bool", "args": ["j", "n"] },
+            { "op": "br", "args": ["d"], "labels": ["large", "done"] },
+            { "label": "done" },
+            { "op": "ret" },
+        ],
+    }))
+    .expect("The function is valid Bril");
+    build_cfg(&function, false).expect("The function has a CFG")
+}
+
+/// Runs LICM on [`two_loops`] with the loops prioritized by `profile`,
+/// returning the header of the loop it explained an instruction in first.
+fn first_loop_transformed(profile: &str) -> String {
+    let profile = Profile::parse(profile).expect("The profile is valid");
+    let mut cfg = two_loops();
+    let dominators = dominators::compute_dominators(&cfg);
+    let mut forest = LoopForest::find(&cfg, &dominators);
+    forest.normalize(&mut cfg);
+    apply_policy(
+        &cfg,
+        &mut forest,
+        &SkipColdLoops {
+            profile: &profile,
+            threshold: 1,
+        },
+    );
+    assert_eq!(forest.loops.len(), 2);
+
+    let hoisting = loop_invariant_code_motion(
+        &mut cfg,
+        &forest,
+        &PuritySummaries::default(),
+    );
+    hoisting
+        .explanations
+        .first()
+        .expect("The induction variable updates are not hoisted")
+        .loop_header
+        .clone()
+}
+
+/// Neither loop is nested in the other, so the hotter loop is transformed
+/// first, whichever loop is smaller.
+#[test]
+fn hotter_loop_transformed_first() {
+    assert_eq!(
+        first_loop_transformed("twice small 100\ntwice large 10\n"),
+        "small"
+    );
+    assert_eq!(
+        first_loop_transformed("twice small 10\ntwice large 100\n"),
+        "large"
+    );
+}
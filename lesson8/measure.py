@@ -0,0 +1,58 @@
+# Usage: python3 measure.py BENCHMARK.bril... > counts.csv
+#
+# Prints a CSV of the dynamic instruction counts of each benchmark before and
+# after loop optimization, as reported by `loop-opt --measure`.
+
+import csv, subprocess, sys
+from pathlib import Path
+
+LOOP_OPT = "../target/debug/loop-opt"
+
+RUNS = {
+    "baseline": ["--baseline"],
+    "licm": ["--stage", "1"],
+    "fusion": ["--stage", "2"],
+    "distribution": ["--stage", "3"],
+    "dead-loop-deletion": ["--stage", "4"],
+    "scalar-promotion": ["--stage", "5"],
+    "address-precomputation": ["--stage", "6"],
+}
+
+
+def benchmark_args(path):
+    for line in path.read_text().splitlines():
+        if line.startswith("# ARGS:"):
+            return line[len("# ARGS:") :].split()
+    return []
+
+
+def count(path, flags):
+    program = subprocess.run(
+        ["bril2json"], input=path.read_text(), capture_output=True, text=True
+    ).stdout
+    instrumented = subprocess.run(
+        [LOOP_OPT, "--measure", *flags],
+        input=program,
+        capture_output=True,
+        text=True,
+    ).stdout
+    program = subprocess.run(
+        ["bril2json"], input=instrumented, capture_output=True, text=True
+    ).stdout
+    result = subprocess.run(
+        ["brili", *benchmark_args(path)],
+        input=program,
+        capture_output=True,
+        text=True,
+    )
+    lines = result.stdout.strip().splitlines()
+    if result.returncode != 0 or not lines:
+        return "error"
+    return lines[-1]
+
+
+writer = csv.writer(sys.stdout)
+writer.writerow(["benchmark", "run", "dynamic_instructions"])
+for path in map(Path, sys.argv[1:]):
+    for run, flags in RUNS.items():
+        writer.writerow([path.stem, run, count(path, flags)])
@@ -0,0 +1,221 @@
+# Usage: python3 permute_check.py [--seed N] [--trials N] BENCHMARK.bril...
+#
+# Randomly reshapes the control flow of each benchmark without changing what
+# it does (shuffling block order, inserting redundant jumps, adding nested
+# zero-trip loops, and adding never-taken edges into the middle of loops) and
+# checks that every stage of loop-opt either preserves the output of the
+# program or cleanly refuses the input because its control flow is
+# irreducible.
+
+import argparse, copy, json, random, subprocess, sys
+from pathlib import Path
+
+LOOP_OPT = "../target/debug/loop-opt"
+STAGES = range(7)
+TERMINATORS = {"jmp", "br", "ret"}
+
+
+def run(command, input):
+    return subprocess.run(command, input=input, capture_output=True, text=True)
+
+
+def benchmark_args(path):
+    for line in path.read_text().splitlines():
+        if line.startswith("# ARGS:"):
+            return line[len("# ARGS:") :].split()
+    return []
+
+
+class Fresh:
+    def __init__(self):
+        self.count = 0
+
+    def __call__(self, name):
+        self.count += 1
+        return f"__permute.{name}.{self.count}"
+
+
+def to_blocks(function, fresh):
+    """Splits the instructions of `function` into labeled blocks that all end
+    in a terminator, so that the blocks after the entry can be reordered."""
+    blocks = []
+    for instruction in function.get("instrs", []):
+        if "label" in instruction:
+            blocks.append([instruction["label"], []])
+        else:
+            if not blocks or (
+                blocks[-1][1] and blocks[-1][1][-1].get("op") in TERMINATORS
+            ):
+                blocks.append([fresh("block"), []])
+            blocks[-1][1].append(instruction)
+    if not blocks:
+        blocks.append([fresh("block"), []])
+
+    # an entry block with a label could be jumped to, so give it a fresh
+    # predecessor that is never jumped to
+    blocks.insert(0, [fresh("entry"), [{"op": "jmp", "labels": [blocks[0][0]]}]])
+
+    for i, (_, instructions) in enumerate(blocks):
+        if not instructions or instructions[-1].get("op") not in TERMINATORS:
+            if i + 1 < len(blocks):
+                instructions.append({"op": "jmp", "labels": [blocks[i + 1][0]]})
+            else:
+                instructions.append({"op": "ret", "args": []})
+    return blocks
+
+
+def from_blocks(function, blocks):
+    function["instrs"] = []
+    for label, instructions in blocks:
+        function["instrs"].append({"label": label})
+        function["instrs"].extend(instructions)
+
+
+def shuffle_blocks(blocks, rng, fresh):
+    rest = blocks[1:]
+    rng.shuffle(rest)
+    return blocks[:1] + rest
+
+
+def insert_redundant_jumps(blocks, rng, fresh):
+    result = []
+    for label, instructions in blocks:
+        if len(instructions) > 1 and rng.random() < 0.5:
+            split = rng.randrange(len(instructions))
+            middle = fresh("split")
+            result.append(
+                [label, instructions[:split] + [{"op": "jmp", "labels": [middle]}]]
+            )
+            result.append([middle, instructions[split:]])
+        else:
+            result.append([label, instructions])
+    return result
+
+
+def retarget(blocks, old, new):
+    for _, instructions in blocks:
+        for instruction in instructions:
+            if "labels" in instruction:
+                instruction["labels"] = [
+                    new if label == old else label for label in instruction["labels"]
+                ]
+
+
+def add_zero_trip_loops(blocks, rng, fresh):
+    """Adds a loop that never runs in front of some blocks, which nests it in
+    any loop containing them."""
+    result = list(blocks)
+    for label, _ in blocks[1:]:
+        if rng.random() < 0.3:
+            condition = fresh("never")
+            preheader, header, body = fresh("preheader"), fresh("header"), fresh("body")
+            retarget(result, label, preheader)
+            result.append(
+                [
+                    preheader,
+                    [
+                        {
+                            "op": "const",
+                            "dest": condition,
+                            "type": "bool",
+                            "value": False,
+                        },
+                        {"op": "jmp", "labels": [header]},
+                    ],
+                ]
+            )
+            result.append([header, [{"op": "br", "args": [condition], "labels": [body, label]}]])
+            result.append([body, [{"op": "jmp", "labels": [header]}]])
+    return result
+
+
+def add_side_entry(blocks, rng, fresh):
+    """Adds a never-taken edge from the entry to some block, which makes the
+    control flow irreducible when that block is in the middle of a loop."""
+    if len(blocks) < 3:
+        return blocks
+    target = rng.choice(blocks[2:])[0]
+    condition = fresh("never")
+    entry_label, entry_instructions = blocks[0]
+    original_entry = entry_instructions[-1]["labels"][0]
+    blocks[0] = [
+        entry_label,
+        [
+            {"op": "const", "dest": condition, "type": "bool", "value": False},
+            {"op": "br", "args": [condition], "labels": [target, original_entry]},
+        ],
+    ]
+    return blocks
+
+
+TRANSFORMS = [
+    shuffle_blocks,
+    insert_redundant_jumps,
+    add_zero_trip_loops,
+    add_side_entry,
+]
+
+
+def permute(program, rng):
+    program = copy.deepcopy(program)
+    fresh = Fresh()
+    for function in program["functions"]:
+        blocks = to_blocks(function, fresh)
+        for transform in rng.sample(TRANSFORMS, rng.randint(1, len(TRANSFORMS))):
+            blocks = transform(blocks, rng, fresh)
+        from_blocks(function, blocks)
+    return program
+
+
+def check(path, program, expected, args, description):
+    """Returns whether every stage handled `program` correctly."""
+    ok = True
+    for stage in STAGES:
+        optimized = run([LOOP_OPT, "--stage", str(stage)], json.dumps(program))
+        if optimized.returncode != 0:
+            if "irreducible" in optimized.stderr:
+                continue
+            print(f"\x1b[31m{path.stem} ({description}, stage {stage}) CRASHED\x1b[m")
+            print(optimized.stderr)
+            ok = False
+            continue
+        optimized_json = run(["bril2json"], optimized.stdout)
+        actual = run(["brili", *args], optimized_json.stdout)
+        if (actual.returncode, actual.stdout) != expected:
+            print(f"\x1b[31m{path.stem} ({description}, stage {stage}) INCORRECT\x1b[m")
+            ok = False
+    return ok
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--seed", type=int, default=6120)
+    parser.add_argument("--trials", type=int, default=5)
+    parser.add_argument("benchmarks", nargs="+", type=Path)
+    opts = parser.parse_args()
+
+    ok = True
+    for path in opts.benchmarks:
+        rng = random.Random(f"{opts.seed}:{path.name}")
+        args = benchmark_args(path)
+        program = json.loads(run(["bril2json"], path.read_text()).stdout)
+        original = run(["brili", *args], json.dumps(program))
+        expected = (original.returncode, original.stdout)
+
+        for trial in range(opts.trials):
+            permuted = permute(program, rng)
+            # the permutations themselves must not change the program
+            reference = run(["brili", *args], json.dumps(permuted))
+            if (reference.returncode, reference.stdout) != expected:
+                print(f"\x1b[31m{path.stem} (trial {trial}) BAD PERMUTATION\x1b[m")
+                ok = False
+                continue
+            ok &= check(path, permuted, expected, args, f"trial {trial}")
+        if ok:
+            print(f"\x1b[32m{path.stem} OK\x1b[m")
+
+    sys.exit(0 if ok else 1)
+
+
+if __name__ == "__main__":
+    main()
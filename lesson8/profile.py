@@ -0,0 +1,45 @@
+# Usage: python3 profile.py BENCHMARK.bril > BENCHMARK.profile
+#
+# Runs the benchmark instrumented by `loop-opt --instrument-profile` and prints
+# how many times each block executed as lines `function label count`, which
+# `loop-opt --profile` reads to skip cold loops. Without instrumenting the
+# program, `bril-interp --profile-blocks BENCHMARK.profile` writes the same
+# profile.
+
+import subprocess, sys, tempfile
+from pathlib import Path
+
+LOOP_OPT = "../target/debug/loop-opt"
+
+
+def benchmark_args(path):
+    for line in path.read_text().splitlines():
+        if line.startswith("# ARGS:"):
+            return line[len("# ARGS:") :].split()
+    return []
+
+
+def run(command, input):
+    return subprocess.run(
+        command, input=input, capture_output=True, text=True, check=True
+    ).stdout
+
+
+path = Path(sys.argv[1])
+with tempfile.NamedTemporaryFile("r") as layout_file:
+    program = run(["bril2json"], path.read_text())
+    instrumented = run(
+        [LOOP_OPT, "--baseline", "--instrument-profile", layout_file.name],
+        program,
+    )
+    output = run(["brili", *benchmark_args(path)], run(["bril2json"], instrumented))
+    layout = {}
+    for line in layout_file.read().splitlines():
+        counter, function, label = line.split()
+        layout[counter] = (function, label)
+
+lines = output.strip().splitlines()[-len(layout) :] if layout else []
+for line in lines:
+    counter, count = line.split()
+    function, label = layout[counter]
+    print(function, label, count)
@@ -0,0 +1,233 @@
+//! Whole-program dead code elimination: removes functions that cannot be
+//! called from the roots of the program and parameters whose value cannot
+//! affect their function, either because it is never read or because every
+//! call passes the same constant.
+
+use std::collections::{BTreeSet, HashMap};
+
+use bril_rs::{
+    Code, ConstOps, EffectOps, Instruction, Literal, Program, ValueOps,
+};
+use bril_util::call_graph::CallGraph;
+use dataflow::live_variables::compute_live_variables;
+use snafu::{ResultExt, Whatever};
+
+/// The functions whose signatures are visible outside of the program: `main`
+/// and `exports` if the program has a `main`, otherwise every function, since
+/// a program without one is a library.
+pub fn roots(program: &Program, exports: &[String]) -> BTreeSet<String> {
+    if program
+        .functions
+        .iter()
+        .any(|function| function.name == "main")
+    {
+        exports
+            .iter()
+            .cloned()
+            .chain(["main".to_string()])
+            .collect()
+    } else {
+        program
+            .functions
+            .iter()
+            .map(|function| function.name.clone())
+            .collect()
+    }
+}
+
+/// Removes every function not reachable from `roots` in the call graph,
+/// returning their names.
+pub fn remove_dead_functions(
+    program: &mut Program,
+    roots: &BTreeSet<String>,
+) -> Vec<String> {
+    let call_graph = CallGraph::new(program);
+    let mut reachable = BTreeSet::new();
+    let mut worklist = roots
+        .iter()
+        .filter(|root| {
+            program
+                .functions
+                .iter()
+                .any(|function| function.name == **root)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    while let Some(function) = worklist.pop() {
+        if reachable.insert(function.clone()) {
+            worklist.extend(call_graph.callees(&function).iter().cloned());
+        }
+    }
+
+    let mut removed = vec![];
+    program.functions.retain(|function| {
+        let keep = reachable.contains(&function.name);
+        if !keep {
+            removed.push(function.name.clone());
+        }
+        keep
+    });
+    removed
+}
+
+/// The callee and arguments of `instruction`, if it is a call.
+fn call_arguments(instruction: &Instruction) -> Option<(&str, &[String])> {
+    match instruction {
+        Instruction::Value {
+            args,
+            funcs,
+            op: ValueOps::Call,
+            ..
+        }
+        | Instruction::Effect {
+            args,
+            funcs,
+            op: EffectOps::Call,
+            ..
+        } => Some((funcs[0].as_str(), args.as_slice())),
+        _ => None,
+    }
+}
+
+/// For each argument of each call to each function, the constant passed, if
+/// it is defined by a `const` earlier in the same block.
+fn call_site_constants(
+    program: &Program,
+) -> HashMap<String, Vec<Vec<Option<Literal>>>> {
+    let mut call_sites = HashMap::<_, Vec<_>>::new();
+    for function in &program.functions {
+        let mut constants = HashMap::new();
+        for code in &function.instrs {
+            let instruction = match code {
+                Code::Instruction(instruction) => instruction,
+                Code::Label { .. } => {
+                    constants.clear();
+                    continue;
+                }
+            };
+            if let Some((callee, args)) = call_arguments(instruction) {
+                call_sites.entry(callee.to_string()).or_default().push(
+                    args.iter()
+                        .map(|arg| constants.get(arg).cloned())
+                        .collect(),
+                );
+            }
+            match instruction {
+                Instruction::Constant { dest, value, .. } => {
+                    constants.insert(dest.clone(), value.clone());
+                }
+                Instruction::Value { dest, .. } => {
+                    constants.remove(dest);
+                }
+                Instruction::Effect { .. } => {}
+            }
+        }
+    }
+    call_sites
+}
+
+/// Why a parameter was removed.
+pub enum DeadArgument {
+    Unused,
+    Constant(Literal),
+}
+
+/// Removes the parameters of functions outside `roots` that are never read or
+/// always passed the same constant, which is then assigned on entry, and the
+/// corresponding arguments at every call. Returns the function and name of
+/// each removed parameter.
+pub fn remove_dead_arguments(
+    program: &mut Program,
+    roots: &BTreeSet<String>,
+) -> Result<Vec<(String, String, DeadArgument)>, Whatever> {
+    let call_sites = call_site_constants(program);
+
+    // the indices of the dead parameters of each function, in order
+    let mut dead = HashMap::new();
+    let mut removed = vec![];
+    for function in &mut program.functions {
+        if roots.contains(&function.name) {
+            continue;
+        }
+
+        let cfg = build_cfg::build_cfg(function, true)
+            .whatever_context("Failed to build cfg")?;
+        let live_on_entry = compute_live_variables(&cfg)
+            .remove(cfg.entry)
+            .unwrap_or_default();
+        let sites = call_sites
+            .get(&function.name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let mut dead_indices = vec![];
+        let mut prologue = vec![];
+        for (i, argument) in function.args.iter().enumerate() {
+            let constant = sites
+                .iter()
+                .map(|site| site.get(i).cloned().flatten())
+                .reduce(
+                    |first, other| if first == other { first } else { None },
+                )
+                .flatten();
+            let reason = if !live_on_entry
+                .contains(&cfg.symbols.intern(&argument.name))
+            {
+                DeadArgument::Unused
+            } else if let Some(constant) = constant {
+                prologue.push(Code::Instruction(Instruction::Constant {
+                    dest: argument.name.clone(),
+                    op: ConstOps::Const,
+                    pos: None,
+                    const_type: argument.arg_type.clone(),
+                    value: constant.clone(),
+                }));
+                DeadArgument::Constant(constant)
+            } else {
+                continue;
+            };
+            dead_indices.push(i);
+            removed.push((
+                function.name.clone(),
+                argument.name.clone(),
+                reason,
+            ));
+        }
+
+        if dead_indices.is_empty() {
+            continue;
+        }
+        for i in dead_indices.iter().rev() {
+            function.args.remove(*i);
+        }
+        function.instrs.splice(0..0, prologue);
+        dead.insert(function.name.clone(), dead_indices);
+    }
+
+    for function in &mut program.functions {
+        for code in &mut function.instrs {
+            let Code::Instruction(
+                Instruction::Value {
+                    args,
+                    funcs,
+                    op: ValueOps::Call,
+                    ..
+                }
+                | Instruction::Effect {
+                    args,
+                    funcs,
+                    op: EffectOps::Call,
+                    ..
+                },
+            ) = code
+            else {
+                continue;
+            };
+            for i in dead.get(&funcs[0]).into_iter().flatten().rev() {
+                args.remove(*i);
+            }
+        }
+    }
+
+    Ok(removed)
+}
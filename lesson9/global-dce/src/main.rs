@@ -0,0 +1,77 @@
+use std::{fs, io, path::PathBuf};
+
+use argh::FromArgs;
+use bril_rs::Program;
+use global_dce::{
+    DeadArgument, remove_dead_arguments, remove_dead_functions, roots,
+};
+use snafu::{ResultExt, Whatever};
+
+/// Removes functions unreachable from `main` and parameters that cannot
+/// affect their function
+#[derive(FromArgs)]
+struct Opts {
+    /// input Bril file: omit for stdin
+    #[argh(positional)]
+    input: Option<PathBuf>,
+
+    /// a function besides `main` that may be called from outside the program,
+    /// so it is kept with its signature unchanged
+    #[argh(option)]
+    export: Vec<String>,
+
+    /// show the events of a pass at a level, e.g., `global-dce=info`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+
+    let mut program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        serde_json::from_str(&contents).whatever_context(
+            "Failed to parse input file as a valid Bril program",
+        )?
+    } else {
+        serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?
+    };
+
+    let roots = roots(&program, &opts.export);
+
+    // removing either can make more of the other dead
+    loop {
+        let functions = remove_dead_functions(&mut program, &roots);
+        for function in &functions {
+            tracing::info!(target: "global-dce", "removed @{function}");
+        }
+        let arguments = remove_dead_arguments(&mut program, &roots)?;
+        for (function, argument, reason) in &arguments {
+            match reason {
+                DeadArgument::Unused => tracing::info!(
+                    target: "global-dce",
+                    "removed unused argument {argument} of @{function}"
+                ),
+                DeadArgument::Constant(value) => tracing::info!(
+                    target: "global-dce",
+                    "removed argument {argument} of @{function}, which is always {value}"
+                ),
+            }
+        }
+        if functions.is_empty() && arguments.is_empty() {
+            break;
+        }
+    }
+
+    print!("{}", program);
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! Classifies the functions of a Bril program by their side effects, so that
+//! intraprocedural optimizations can treat calls like other instructions.
+//! Only [`Purity::PureTotal`] functions are known to terminate: others may
+//! still trap or run forever.
+
+use std::collections::HashMap;
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+use bril_util::call_graph::{CallGraph, called_function};
+use build_cfg::{disk_cache::DiskCache, fingerprint::Fingerprint};
+use serde_json::Value;
+
+/// The side effects of a function, from fewest to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Purity {
+    /// The function is [`Purity::Pure`] and also always returns without
+    /// trapping, since it has no loops, recursion, or trapping instructions,
+    /// so unused calls can be removed and calls can be speculated.
+    PureTotal,
+    /// The function only computes its result from its arguments, so calls
+    /// with the same arguments can be merged, but it may trap or run forever.
+    Pure,
+    /// The function may also read memory, so calls cannot be moved past
+    /// writes.
+    ReadOnly,
+    /// The function may write memory, allocate, print, or call a function
+    /// that does.
+    Effectful,
+}
+
+/// The [`Purity`] of each function in a program. Functions without a summary,
+/// including every function when there are no summaries at all, are assumed
+/// to be [`Purity::Effectful`].
+#[derive(Default)]
+pub struct PuritySummaries {
+    summaries: HashMap<String, Purity>,
+}
+
+impl PuritySummaries {
+    /// Summarizes each function in `program` bottom-up over its call graph,
+    /// so mutually recursive functions share a summary.
+    pub fn new(program: &Program) -> Self {
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function))
+            .collect::<HashMap<_, _>>();
+
+        let mut summaries = Self::default();
+        for component in CallGraph::new(program).strongly_connected_components()
+        {
+            // calls within the component are ignored since every member ends
+            // up with the same summary
+            let within =
+                |callee: &str| component.iter().any(|member| member == callee);
+            let purity = component
+                .iter()
+                .flat_map(|name| &functions[name.as_str()].instrs)
+                .filter_map(|code| match (called_function(code), code) {
+                    (Some(callee), _) if within(callee) => None,
+                    (_, Code::Instruction(instruction)) => {
+                        Some(summaries.instruction_purity(instruction))
+                    }
+                    (_, Code::Label { .. }) => None,
+                })
+                .max()
+                .unwrap_or(Purity::PureTotal);
+            // a call within the component is recursion, which may not end
+            let may_not_return = component.iter().any(|name| {
+                let function = functions[name.as_str()];
+                has_backward_jump(function)
+                    || function
+                        .instrs
+                        .iter()
+                        .any(|code| called_function(code).is_some_and(within))
+            });
+            let purity = if may_not_return {
+                purity.max(Purity::Pure)
+            } else {
+                purity
+            };
+            for name in component {
+                summaries.summaries.insert(name, purity);
+            }
+        }
+        summaries
+    }
+
+    /// Like [`PuritySummaries::new`], but loaded from or saved to `cache`,
+    /// keyed by the text of `program`, since the summaries of a function
+    /// depend on every function it may call.
+    pub fn cached(program: &Program, cache: &DiskCache) -> Self {
+        cache.get_or_compute(
+            "purity-v1",
+            Fingerprint::new(&program.to_string()),
+            |summaries: &Self| {
+                summaries
+                    .summaries
+                    .iter()
+                    .map(|(name, purity)| {
+                        let purity = match purity {
+                            Purity::PureTotal => "pure-total",
+                            Purity::Pure => "pure",
+                            Purity::ReadOnly => "read-only",
+                            Purity::Effectful => "effectful",
+                        };
+                        (name.clone(), Value::from(purity))
+                    })
+                    .collect::<serde_json::Map<_, _>>()
+                    .into()
+            },
+            |value| {
+                let summaries = value
+                    .as_object()?
+                    .iter()
+                    .map(|(name, purity)| {
+                        let purity = match purity.as_str()? {
+                            "pure-total" => Purity::PureTotal,
+                            "pure" => Purity::Pure,
+                            "read-only" => Purity::ReadOnly,
+                            "effectful" => Purity::Effectful,
+                            _ => return None,
+                        };
+                        Some((name.clone(), purity))
+                    })
+                    .collect::<Option<_>>()?;
+                Some(Self { summaries })
+            },
+            || Self::new(program),
+        )
+    }
+
+    /// The purity of the function `name`.
+    pub fn purity(&self, name: &str) -> Purity {
+        self.summaries
+            .get(name)
+            .copied()
+            .unwrap_or(Purity::Effectful)
+    }
+
+    /// The side effects of executing `instruction`, where calls have the
+    /// purity of their callee.
+    pub fn instruction_purity(&self, instruction: &Instruction) -> Purity {
+        match instruction {
+            Instruction::Value {
+                funcs,
+                op: ValueOps::Call,
+                ..
+            }
+            | Instruction::Effect {
+                funcs,
+                op: EffectOps::Call,
+                ..
+            } => self.purity(&funcs[0]),
+            Instruction::Constant { .. } => Purity::PureTotal,
+            Instruction::Value {
+                op: ValueOps::Load, ..
+            } => Purity::ReadOnly,
+            Instruction::Value {
+                op: ValueOps::Alloc,
+                ..
+            } => Purity::Effectful,
+            // dividing by zero and converting an invalid code point trap
+            Instruction::Value {
+                op: ValueOps::Div | ValueOps::Int2char,
+                ..
+            } => Purity::Pure,
+            Instruction::Value { .. } => Purity::PureTotal,
+            Instruction::Effect {
+                op:
+                    EffectOps::Jump
+                    | EffectOps::Branch
+                    | EffectOps::Return
+                    | EffectOps::Nop
+                    | EffectOps::Set,
+                ..
+            } => Purity::PureTotal,
+            Instruction::Effect { .. } => Purity::Effectful,
+        }
+    }
+}
+
+/// Whether some jump or branch in `function` goes to a label at or before it,
+/// without which control only moves forward, so the function cannot loop.
+fn has_backward_jump(function: &Function) -> bool {
+    let positions = function
+        .instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, code)| match code {
+            Code::Label { label, .. } => Some((label.as_str(), index)),
+            Code::Instruction(_) => None,
+        })
+        .collect::<HashMap<_, _>>();
+    function
+        .instrs
+        .iter()
+        .enumerate()
+        .any(|(index, code)| match code {
+            Code::Instruction(Instruction::Effect { labels, .. }) => {
+                labels.iter().any(|label| {
+                    positions
+                        .get(label.as_str())
+                        .is_some_and(|&position| position <= index)
+                })
+            }
+            _ => false,
+        })
+}
@@ -0,0 +1,121 @@
+//! Turns self-recursive calls in tail position into jumps back to the start
+//! of the function, so that the recursion runs as a loop.
+
+use bril_rs::{EffectOps, Function, Instruction, ValueOps};
+use bril_util::names::NameGenerator;
+use build_cfg::{BasicBlockIdx, FunctionCfg, Label, LabeledExit};
+use snafu::{ResultExt, Whatever};
+
+/// The arguments of the self-recursive call in tail position ending `block`,
+/// that is, the call just before a `ret` returning what it returned.
+fn tail_call_arguments(
+    cfg: &FunctionCfg,
+    block: BasicBlockIdx,
+) -> Option<Vec<String>> {
+    let name = &cfg.signature.name;
+    match (
+        &cfg.vertices[block].instructions[..],
+        &cfg.vertices[block].exit,
+    ) {
+        (
+            [
+                ..,
+                Instruction::Value {
+                    args,
+                    dest,
+                    funcs,
+                    op: ValueOps::Call,
+                    ..
+                },
+                _,
+            ],
+            LabeledExit::Return(Some(returned)),
+        ) if funcs[0] == *name && returned == dest => Some(args.clone()),
+        (
+            [
+                ..,
+                Instruction::Effect {
+                    args,
+                    funcs,
+                    op: EffectOps::Call,
+                    ..
+                },
+                _,
+            ],
+            LabeledExit::Return(None),
+        ) if funcs[0] == *name => Some(args.clone()),
+        _ => None,
+    }
+}
+
+/// Replaces each self-recursive tail call in `function` with assignments of
+/// the arguments to the parameters and a jump back to the entry, returning
+/// the number of calls replaced.
+pub fn eliminate_tail_calls(
+    function: &mut Function,
+) -> Result<usize, Whatever> {
+    let mut names = NameGenerator::new(function);
+    let mut cfg = build_cfg::build_cfg(function, true)
+        .whatever_context("Failed to build cfg")?;
+    cfg.make_fallthroughs_explicit();
+
+    let tail_calls = cfg
+        .vertices
+        .keys()
+        .filter_map(|block| Some((block, tail_call_arguments(&cfg, block)?)))
+        .collect::<Vec<_>>();
+    if tail_calls.is_empty() {
+        return Ok(0);
+    }
+
+    let entry = cfg.entry;
+    if cfg.vertices[entry].label.is_none() {
+        cfg.vertices[entry].label = Some(Label {
+            name: names.fresh(&format!("{}.start", cfg.signature.name)),
+        });
+    }
+
+    for (block, arguments) in &tail_calls {
+        // drop the call and the return
+        let instructions = &mut cfg.vertices[*block].instructions;
+        instructions.truncate(instructions.len() - 2);
+
+        // the arguments may refer to parameters, so they are all copied
+        // before any parameter is assigned
+        let mut assignments = vec![];
+        for (parameter, argument) in
+            cfg.signature.arguments.iter().zip(arguments)
+        {
+            if parameter.name == *argument {
+                continue;
+            }
+            let temporary = names.fresh(&format!("{}.tail", parameter.name));
+            instructions.push(Instruction::Value {
+                args: vec![argument.clone()],
+                dest: temporary.clone(),
+                funcs: vec![],
+                labels: vec![],
+                op: ValueOps::Id,
+                pos: None,
+                op_type: parameter.arg_type.clone(),
+            });
+            assignments.push(Instruction::Value {
+                args: vec![temporary],
+                dest: parameter.name.clone(),
+                funcs: vec![],
+                labels: vec![],
+                op: ValueOps::Id,
+                pos: None,
+                op_type: parameter.arg_type.clone(),
+            });
+        }
+        instructions.extend(assignments);
+
+        cfg.vertices[*block].exit = LabeledExit::Fallthrough;
+        cfg.edges.remove(*block);
+        cfg.set_unconditional_edge(*block, entry);
+    }
+
+    *function = cfg.into_function();
+    Ok(tail_calls.len())
+}
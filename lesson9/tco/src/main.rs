@@ -0,0 +1,65 @@
+use std::{fs, io, path::PathBuf};
+
+use argh::FromArgs;
+use bril_rs::Program;
+use bril_util::filter::{FunctionFilter, FunctionPattern};
+use snafu::{ResultExt, Whatever};
+use tco::eliminate_tail_calls;
+
+/// Turns self-recursive tail calls into loops
+#[derive(FromArgs)]
+struct Opts {
+    /// only optimize functions matching this name, like `@main` or `@test_*`,
+    /// printing the rest unchanged: may be repeated, and omit for every
+    /// function
+    #[argh(option)]
+    function: Vec<FunctionPattern>,
+
+    /// input Bril file: omit for stdin
+    #[argh(positional)]
+    input: Option<PathBuf>,
+
+    /// show the events of a pass at a level, e.g., `tco=info`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+
+    let mut program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        serde_json::from_str(&contents).whatever_context(
+            "Failed to parse input file as a valid Bril program",
+        )?
+    } else {
+        serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?
+    };
+
+    let filter = FunctionFilter::new(opts.function);
+    for function in &mut program.functions {
+        if !filter.selects(&function.name) {
+            continue;
+        }
+        let eliminated = eliminate_tail_calls(function)?;
+        if eliminated > 0 {
+            tracing::info!(
+                target: "tco",
+                "@{}: eliminated {eliminated} tail calls",
+                function.name
+            );
+        }
+    }
+
+    print!("{}", program);
+
+    Ok(())
+}
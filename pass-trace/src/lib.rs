@@ -0,0 +1,41 @@
+//! The logging shared by every pass. Passes emit [`tracing`] events whose
+//! target is their name, like `tracing::debug!(target: "licm", ...)`, and
+//! binaries take `--trace pass=level` options, which [`init`] turns into the
+//! filter deciding which events are written to standard error.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+
+use std::io::{self, IsTerminal};
+
+use snafu::{ResultExt, Whatever, whatever};
+use tracing_subscriber::{
+    EnvFilter,
+    filter::{Directive, LevelFilter},
+};
+
+/// Writes the events allowed by `directives` to standard error, along with
+/// warnings and errors from every pass. Each directive is `pass=level`, like
+/// `licm=debug`, or just a level for every pass.
+pub fn init(directives: &[String]) -> Result<(), Whatever> {
+    let mut filter =
+        EnvFilter::default().add_directive(LevelFilter::WARN.into());
+    for directive in directives {
+        filter = filter.add_directive(
+            directive.parse::<Directive>().whatever_context(format!(
+                "Invalid trace directive `{directive}`: expected `pass=level`"
+            ))?,
+        );
+    }
+
+    if tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .without_time()
+        .try_init()
+        .is_err()
+    {
+        whatever!("Tracing was already initialized");
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use bril_util::InstructionExt;
+use build_cfg::{
+    FunctionCfg,
+    pass::{Analysis, AnalysisCache},
+};
+use dataflow::live_variables::LiveVariables;
+
+/// Which variables of a function are live at the same time and so cannot
+/// share a register.
+#[derive(Default)]
+pub struct InterferenceGraph {
+    edges: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl InterferenceGraph {
+    pub fn variables(&self) -> impl Iterator<Item = &String> {
+        self.edges.keys()
+    }
+
+    pub fn neighbors(&self, variable: &str) -> impl Iterator<Item = &String> {
+        self.edges.get(variable).into_iter().flatten()
+    }
+
+    pub fn interferes(&self, first: &str, second: &str) -> bool {
+        self.edges
+            .get(first)
+            .is_some_and(|neighbors| neighbors.contains(second))
+    }
+
+    fn add_variable(&mut self, variable: &str) {
+        self.edges.entry(variable.to_string()).or_default();
+    }
+
+    fn add_edge(&mut self, first: &str, second: &str) {
+        self.add_variable(first);
+        self.add_variable(second);
+        if first != second {
+            self.edges
+                .get_mut(first)
+                .unwrap()
+                .insert(second.to_string());
+            self.edges
+                .get_mut(second)
+                .unwrap()
+                .insert(first.to_string());
+        }
+    }
+
+    /// Builds the graph from the variables live on entry to each block by
+    /// walking every block backward from its live-out set, so a definition
+    /// interferes with everything live after it.
+    pub fn build(cfg: &FunctionCfg, cache: &mut AnalysisCache) -> Self {
+        let live_in = cache.get::<LiveVariables>(cfg);
+        let mut graph = Self::default();
+
+        // the arguments are all defined at once on entry, even the unused
+        // ones, which still need distinct names in the signature
+        let arguments = cfg.argument_definitions();
+        for argument in &arguments {
+            let name = cfg.symbols.name(argument.variable);
+            graph.add_variable(&name);
+            for other in &arguments[..argument.position] {
+                graph.add_edge(&name, &cfg.symbols.name(other.variable));
+            }
+            for live in live_in.get(cfg.entry).into_iter().flatten() {
+                graph.add_edge(&name, &cfg.symbols.name(*live));
+            }
+        }
+
+        for (block_idx, block) in &cfg.vertices {
+            let mut live = cfg
+                .successors(block_idx)
+                .into_iter()
+                .filter_map(|successor| live_in.get(successor))
+                .flatten()
+                .map(|variable| cfg.symbols.name(*variable).to_string())
+                .collect::<HashSet<_>>();
+            for instruction in block.instructions.iter().rev() {
+                if let Some(kill) = instruction.kill() {
+                    graph.add_variable(kill);
+                    for other in &live {
+                        graph.add_edge(kill, other);
+                    }
+                    live.remove(kill);
+                }
+                for used in instruction.gen_set() {
+                    graph.add_variable(used);
+                    live.insert(used.clone());
+                }
+            }
+        }
+
+        graph
+    }
+}
+
+/// The [`InterferenceGraph`] of a function, built from [`LiveVariables`].
+pub struct Interference;
+
+impl Analysis for Interference {
+    type Output = InterferenceGraph;
+
+    fn compute(cfg: &FunctionCfg, cache: &mut AnalysisCache) -> Self::Output {
+        InterferenceGraph::build(cfg, cache)
+    }
+}
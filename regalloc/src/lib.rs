@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Type, ValueOps};
+use bril_util::{
+    builder::{make_const_int, make_effect, make_value},
+    names::NameGenerator,
+};
+use build_cfg::{
+    BasicBlockIdx, Exit, FunctionCfg, LabeledExit, pass::AnalysisCache,
+};
+use snafu::{ResultExt, Whatever, whatever};
+use ssa::{compute_definition_sites, insert_new_empty_entry_block};
+
+use crate::interference::InterferenceGraph;
+
+pub mod interference;
+
+/// A function whose variables were renamed to registers.
+pub struct Allocation {
+    pub function: Function,
+    /// The variables that live in memory instead of a register.
+    pub spilled: Vec<String>,
+}
+
+/// Registers are split by type, since a Bril variable holds values of a
+/// single type, so e.g. `ptr<int>` registers are `ptr.int.r0`, `ptr.int.r1`,
+/// and so on.
+fn class_name(ty: &Type) -> String {
+    match ty {
+        Type::Pointer(pointee) => format!("ptr.{}", class_name(pointee)),
+        other => other.to_string(),
+    }
+}
+
+fn register_name(ty: &Type, color: usize) -> String {
+    format!("{}.r{}", class_name(ty), color)
+}
+
+fn variable_types(cfg: &FunctionCfg) -> BTreeMap<String, Type> {
+    let mut types = compute_definition_sites(cfg)
+        .0
+        .into_iter()
+        .map(|(variable, (ty, _))| (variable, ty))
+        .collect::<BTreeMap<_, _>>();
+    types.extend(
+        cfg.signature
+            .arguments
+            .iter()
+            .map(|argument| (argument.name.clone(), argument.arg_type.clone())),
+    );
+    types
+}
+
+struct Coloring {
+    colors: HashMap<String, usize>,
+    spilled: Vec<String>,
+}
+
+/// Colors `graph` with `registers` colors per type by simplifying and then
+/// selecting optimistically, as in Briggs et al., so a variable is only
+/// spilled once no color is left for it. Spill slots are treated as stack
+/// addresses rather than variables, so they are never colored.
+fn color(
+    graph: &InterferenceGraph,
+    types: &BTreeMap<String, Type>,
+    registers: usize,
+    unspillable: &HashSet<String>,
+    slots: &HashSet<String>,
+) -> Result<Coloring, Whatever> {
+    let mut remaining = BTreeSet::new();
+    for variable in graph.variables() {
+        if slots.contains(variable) {
+            continue;
+        }
+        if !types.contains_key(variable) {
+            whatever!("Variable {} is used but never defined", variable);
+        }
+        remaining.insert(variable);
+    }
+    let same_class = |first: &str, second: &str| types[first] == types[second];
+
+    let mut stack = vec![];
+    while !remaining.is_empty() {
+        let degree = |variable: &str| {
+            graph
+                .neighbors(variable)
+                .filter(|neighbor| {
+                    remaining.contains(neighbor)
+                        && same_class(variable, neighbor)
+                })
+                .count()
+        };
+        let next = remaining
+            .iter()
+            .copied()
+            .find(|variable| degree(variable) < registers)
+            .or_else(|| {
+                remaining
+                    .iter()
+                    .copied()
+                    .filter(|variable| !unspillable.contains(*variable))
+                    .max_by_key(|variable| degree(variable))
+            })
+            .or_else(|| {
+                remaining
+                    .iter()
+                    .copied()
+                    .max_by_key(|variable| degree(variable))
+            })
+            .expect("remaining is not empty");
+        remaining.remove(next);
+        stack.push(next);
+    }
+
+    let mut colors = HashMap::new();
+    let mut spilled = vec![];
+    while let Some(variable) = stack.pop() {
+        let taken = graph
+            .neighbors(variable)
+            .filter(|neighbor| {
+                !slots.contains(*neighbor) && same_class(variable, neighbor)
+            })
+            .filter_map(|neighbor| colors.get(neighbor).copied())
+            .collect::<HashSet<usize>>();
+        match (0..registers).find(|color| !taken.contains(color)) {
+            Some(color) => {
+                colors.insert(variable.clone(), color);
+            }
+            None if unspillable.contains(variable) => {
+                whatever!(
+                    "Cannot fit the values live alongside {} into {} registers",
+                    variable,
+                    registers
+                );
+            }
+            None => spilled.push(variable.clone()),
+        }
+    }
+
+    Ok(Coloring { colors, spilled })
+}
+
+/// Updates the exit of `block_idx` to match its final instruction after the
+/// variables in it were renamed.
+fn sync_exit(cfg: &mut FunctionCfg, block_idx: BasicBlockIdx) {
+    let block = &mut cfg.vertices[block_idx];
+    match (block.instructions.last(), &mut block.exit) {
+        (
+            Some(Instruction::Effect {
+                op: EffectOps::Branch,
+                args,
+                ..
+            }),
+            LabeledExit::Conditional { condition, .. },
+        ) => {
+            *condition = args[0].clone();
+            if let Some(Exit::Conditional { condition, .. }) =
+                cfg.edges.get_mut(block_idx)
+            {
+                *condition = args[0].clone();
+            }
+        }
+        (
+            Some(Instruction::Effect {
+                op: EffectOps::Return,
+                args,
+                ..
+            }),
+            exit,
+        ) => {
+            *exit = LabeledExit::Return(args.first().cloned());
+            cfg.edges
+                .insert(block_idx, Exit::Return(args.first().cloned()));
+        }
+        _ => {}
+    }
+}
+
+/// Moves each variable in `spilled` into its own `alloc`ed slot, loading it
+/// into a fresh temporary before every use and storing a fresh temporary
+/// into it after every definition. Returns the temporaries and the slots.
+///
+/// Requires: there are no fallthrough edges and the entry block has no
+/// predecessors.
+fn insert_spill_code(
+    cfg: &mut FunctionCfg,
+    spilled: &[String],
+    types: &BTreeMap<String, Type>,
+    names: &mut NameGenerator,
+) -> (HashSet<String>, HashSet<String>) {
+    let mut temporaries = HashSet::new();
+    let slots = spilled
+        .iter()
+        .map(|variable| {
+            (variable.clone(), names.fresh(&format!("{variable}.slot")))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let one = names.fresh("spill.one");
+    temporaries.insert(one.clone());
+    let mut prologue = vec![make_const_int(&one, 1)];
+    for (variable, slot) in &slots {
+        prologue.push(make_value(
+            slot,
+            Type::Pointer(Box::new(types[variable].clone())),
+            ValueOps::Alloc,
+            vec![one.clone()],
+        ));
+    }
+    for argument in &mut cfg.signature.arguments {
+        if let Some(slot) = slots.get(&argument.name) {
+            let temporary = names.fresh(&format!("{}.spill", argument.name));
+            prologue.push(make_effect(
+                EffectOps::Store,
+                vec![slot.clone(), temporary.clone()],
+            ));
+            temporaries.insert(temporary.clone());
+            argument.name = temporary;
+        }
+    }
+
+    for block_idx in cfg.vertices.keys().collect::<Vec<_>>() {
+        let block = &mut cfg.vertices[block_idx];
+        let mut instructions = vec![];
+        for mut instruction in block.instructions.drain(..) {
+            let mut loaded = HashMap::new();
+            if let Instruction::Value { args, .. }
+            | Instruction::Effect { args, .. } = &mut instruction
+            {
+                for argument in args.iter_mut() {
+                    let Some(slot) = slots.get(argument.as_str()) else {
+                        continue;
+                    };
+                    if !loaded.contains_key(argument.as_str()) {
+                        let temporary =
+                            names.fresh(&format!("{argument}.spill"));
+                        instructions.push(make_value(
+                            &temporary,
+                            types[argument.as_str()].clone(),
+                            ValueOps::Load,
+                            vec![slot.clone()],
+                        ));
+                        temporaries.insert(temporary.clone());
+                        loaded.insert(argument.clone(), temporary);
+                    }
+                    *argument = loaded[argument.as_str()].clone();
+                }
+            }
+
+            if matches!(
+                instruction,
+                Instruction::Effect {
+                    op: EffectOps::Return,
+                    ..
+                }
+            ) {
+                instructions.extend(slots.values().map(|slot| {
+                    make_effect(EffectOps::Free, vec![slot.clone()])
+                }));
+            }
+
+            let store = match &mut instruction {
+                Instruction::Constant { dest, .. }
+                | Instruction::Value { dest, .. } => {
+                    slots.get(dest.as_str()).map(|slot| {
+                        let temporary = names.fresh(&format!("{dest}.spill"));
+                        temporaries.insert(temporary.clone());
+                        *dest = temporary.clone();
+                        make_effect(
+                            EffectOps::Store,
+                            vec![slot.clone(), temporary],
+                        )
+                    })
+                }
+                Instruction::Effect { .. } => None,
+            };
+            instructions.push(instruction);
+            instructions.extend(store);
+        }
+        block.instructions = instructions.into();
+        sync_exit(cfg, block_idx);
+    }
+
+    cfg.vertices[cfg.entry]
+        .instructions
+        .insert_many(0, prologue);
+
+    (temporaries, slots.into_values().collect())
+}
+
+/// Renames every colored variable to its register.
+fn assign_registers(
+    cfg: &mut FunctionCfg,
+    colors: &HashMap<String, usize>,
+    types: &BTreeMap<String, Type>,
+) {
+    let rename = |variable: &mut String| {
+        if let Some(color) = colors.get(variable.as_str()) {
+            *variable = register_name(&types[variable.as_str()], *color);
+        }
+    };
+
+    for argument in &mut cfg.signature.arguments {
+        rename(&mut argument.name);
+    }
+    for block_idx in cfg.vertices.keys().collect::<Vec<_>>() {
+        for instruction in &mut cfg.vertices[block_idx].instructions {
+            match instruction {
+                Instruction::Constant { dest, .. } => rename(dest),
+                Instruction::Value { args, dest, .. } => {
+                    args.iter_mut().for_each(rename);
+                    rename(dest);
+                }
+                Instruction::Effect { args, .. } => {
+                    args.iter_mut().for_each(rename);
+                }
+            }
+        }
+        sync_exit(cfg, block_idx);
+    }
+}
+
+/// Allocates the variables of `function` to `registers` registers of each
+/// type by coloring its [`InterferenceGraph`], spilling variables to memory
+/// and recoloring until every remaining variable fits.
+pub fn allocate_registers(
+    function: &Function,
+    registers: usize,
+) -> Result<Allocation, Whatever> {
+    if registers == 0 {
+        whatever!("There must be at least one register");
+    }
+    let in_ssa = function.instrs.iter().any(|code| {
+        matches!(
+            code,
+            Code::Instruction(
+                Instruction::Value {
+                    op: ValueOps::Get,
+                    ..
+                } | Instruction::Effect {
+                    op: EffectOps::Set,
+                    ..
+                }
+            )
+        )
+    });
+    if in_ssa {
+        whatever!("@{} must be converted out of SSA first", function.name);
+    }
+
+    let mut names = NameGenerator::new(function);
+    let mut cfg = build_cfg::build_cfg(function, true)
+        .whatever_context("Failed to build cfg")?;
+    // the spill slots are allocated once in the entry block
+    if cfg
+        .rev_edges
+        .get(cfg.entry)
+        .is_some_and(|predecessors| !predecessors.is_empty())
+    {
+        insert_new_empty_entry_block(&mut cfg);
+    }
+    cfg.make_fallthroughs_explicit();
+
+    let mut spilled = vec![];
+    let mut unspillable = HashSet::new();
+    let mut slots = HashSet::new();
+    loop {
+        let types = variable_types(&cfg);
+        let graph =
+            InterferenceGraph::build(&cfg, &mut AnalysisCache::default());
+        let coloring = color(&graph, &types, registers, &unspillable, &slots)
+            .whatever_context(format!(
+            "Failed to allocate registers in @{}",
+            function.name
+        ))?;
+        if coloring.spilled.is_empty() {
+            assign_registers(&mut cfg, &coloring.colors, &types);
+            return Ok(Allocation {
+                function: cfg.into_function(),
+                spilled,
+            });
+        }
+
+        let (temporaries, new_slots) =
+            insert_spill_code(&mut cfg, &coloring.spilled, &types, &mut names);
+        unspillable.extend(temporaries);
+        slots.extend(new_slots);
+        spilled.extend(coloring.spilled);
+    }
+}
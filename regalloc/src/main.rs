@@ -0,0 +1,61 @@
+use std::{fs, io, path::PathBuf};
+
+use argh::FromArgs;
+use bril_rs::Program;
+use regalloc::allocate_registers;
+use snafu::{ResultExt, Whatever};
+
+/// Allocates the variables of each function to registers by graph coloring,
+/// spilling to memory when they do not fit
+#[derive(FromArgs)]
+struct Opts {
+    /// input Bril file: omit for stdin
+    #[argh(positional)]
+    input: Option<PathBuf>,
+
+    /// number of registers available for each type
+    #[argh(option, short = 'k', default = "8")]
+    registers: usize,
+
+    /// show the events of a pass at a level, e.g., `regalloc=info`: may be
+    /// repeated
+    #[argh(option)]
+    trace: Vec<String>,
+}
+
+#[snafu::report]
+fn main() -> Result<(), Whatever> {
+    let opts = argh::from_env::<Opts>();
+    pass_trace::init(&opts.trace)?;
+
+    let mut program: Program = if let Some(path) = &opts.input {
+        let contents = fs::read_to_string(path).whatever_context(format!(
+            "Failed to read the contents of {}",
+            path.to_string_lossy()
+        ))?;
+        serde_json::from_str(&contents).whatever_context(
+            "Failed to parse input file as a valid Bril program",
+        )?
+    } else {
+        serde_json::from_reader(io::stdin()).whatever_context(
+            "Failed to parse standard input as a valid Bril program",
+        )?
+    };
+
+    for function in &mut program.functions {
+        let allocation = allocate_registers(function, opts.registers)?;
+        if !allocation.spilled.is_empty() {
+            tracing::info!(
+                target: "regalloc",
+                "@{}: spilled {}",
+                function.name,
+                allocation.spilled.join(", ")
+            );
+        }
+        *function = allocation.function;
+    }
+
+    print!("{}", program);
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Specializes functions on the constants their callers pass. When every call
+//! to a function passes the same constant for a parameter, the calls go to a
+//! copy of the function without that parameter, which defines it as that
+//! constant instead. Constant folding and dead code elimination can then
+//! simplify the copy, and global DCE can remove the original once nothing
+//! calls it.
+
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, Instruction, Literal, Program};
+use bril_util::{
+    InstructionExt, builder::make_const, call_graph::called_function,
+};
+
+fn call_arguments(instruction: &Instruction) -> &[String] {
+    match instruction {
+        Instruction::Value { args, .. } | Instruction::Effect { args, .. } => {
+            args
+        }
+        Instruction::Constant { .. } => &[],
+    }
+}
+
+/// For each call to `callee` in `program`, the constant each argument is
+/// known to be from a `const` earlier in the same block, if any.
+fn constant_arguments(
+    program: &Program,
+    callee: &str,
+) -> Vec<Vec<Option<Literal>>> {
+    let mut calls = vec![];
+    for function in &program.functions {
+        let mut constants = HashMap::<&str, &Literal>::new();
+        for code in &function.instrs {
+            let instruction = match code {
+                Code::Label { .. } => {
+                    constants.clear();
+                    continue;
+                }
+                Code::Instruction(instruction) => instruction,
+            };
+            if called_function(code) == Some(callee) {
+                calls.push(
+                    call_arguments(instruction)
+                        .iter()
+                        .map(|argument| {
+                            constants.get(argument.as_str()).copied().cloned()
+                        })
+                        .collect(),
+                );
+            }
+            match instruction {
+                Instruction::Constant { dest, value, .. } => {
+                    constants.insert(dest, value);
+                }
+                _ => {
+                    if let Some(dest) = instruction.kill() {
+                        constants.remove(dest.as_str());
+                    }
+                }
+            }
+        }
+    }
+    calls
+}
+
+/// The constant every one of `calls` passes for each of the first `arity`
+/// parameters, if there are any calls and they agree.
+fn common_constants(
+    calls: &[Vec<Option<Literal>>],
+    arity: usize,
+) -> Vec<Option<Literal>> {
+    (0..arity)
+        .map(|i| {
+            let (first, rest) = calls.split_first()?;
+            let constant = first.get(i)?.as_ref()?;
+            rest.iter()
+                .all(|call| {
+                    call.get(i).and_then(Option::as_ref) == Some(constant)
+                })
+                .then(|| constant.clone())
+        })
+        .collect()
+}
+
+/// Redirects every call to `callee` in `program` to `specialized`, dropping
+/// the arguments for the parameters that are [`Some`] in `constants`.
+fn redirect_calls(
+    program: &mut Program,
+    callee: &str,
+    specialized: &str,
+    constants: &[Option<Literal>],
+) {
+    for function in &mut program.functions {
+        for code in &mut function.instrs {
+            if called_function(code) != Some(callee) {
+                continue;
+            }
+            let Code::Instruction(
+                Instruction::Value { args, funcs, .. }
+                | Instruction::Effect { args, funcs, .. },
+            ) = code
+            else {
+                unreachable!("only instructions are calls");
+            };
+            funcs[0] = specialized.to_string();
+            let mut kept = constants.iter().map(Option::is_none);
+            args.retain(|_| kept.next().unwrap_or(true));
+        }
+    }
+}
+
+/// Specializes each function in `program` other than `@main` whose calls all
+/// pass the same constant for some parameter, as described in the [crate]
+/// documentation. Calls the original makes to itself are redirected too, so
+/// a recursive function is specialized only if its recursive calls also pass
+/// the constants. Returns the number of functions specialized.
+pub fn specialize_calls(program: &mut Program) -> usize {
+    let mut function_names = program
+        .functions
+        .iter()
+        .map(|function| function.name.clone())
+        .collect::<HashSet<_>>();
+
+    let mut specialized_count = 0;
+    for i in 0..program.functions.len() {
+        let callee = &program.functions[i];
+        if callee.name == "main" {
+            continue;
+        }
+        let calls = constant_arguments(program, &callee.name);
+        let constants = common_constants(&calls, callee.args.len());
+        if constants.iter().all(Option::is_none) {
+            continue;
+        }
+
+        let base = format!("{}.specialized", callee.name);
+        let name = if function_names.contains(&base) {
+            (1..)
+                .map(|i| format!("{base}.{i}"))
+                .find(|candidate| !function_names.contains(candidate))
+                .expect("There are infinitely many candidate names")
+        } else {
+            base
+        };
+        function_names.insert(name.clone());
+
+        let mut args = vec![];
+        let mut instrs = vec![];
+        for (parameter, constant) in callee.args.iter().zip(&constants) {
+            match constant {
+                Some(constant) => {
+                    instrs.push(Code::Instruction(make_const(
+                        parameter.name.clone(),
+                        parameter.arg_type.clone(),
+                        constant.clone(),
+                    )));
+                }
+                None => args.push(parameter.clone()),
+            }
+        }
+        instrs.extend(callee.instrs.iter().cloned());
+
+        let mut specialized = callee.clone();
+        specialized.name = name.clone();
+        specialized.args = args;
+        specialized.instrs = instrs;
+        let callee = callee.name.clone();
+        program.functions.push(specialized);
+
+        redirect_calls(program, &callee, &name, &constants);
+        specialized_count += 1;
+    }
+    specialized_count
+}
@@ -0,0 +1,62 @@
+//! The `--watch` flag shared by the lesson binaries: rerunning a tool whenever
+//! its input file changes, so its output can be watched while editing the
+//! program. Changes are found by polling the modification time, which works
+//! the same on every platform and for editors that replace the file on save.
+
+use std::{
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use snafu::{Whatever, whatever};
+
+/// How often the input is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// When the file at `path` was last modified, or `None` while it does not
+/// exist, like in the middle of being replaced.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Runs `tool` once, or, if `watch`, again every time the file at `input` is
+/// modified, until interrupted. While watching, errors are printed instead of
+/// ending the loop, since the next edit may fix them.
+///
+/// Requires: `input` is not `None` if `watch` is set, since standard input
+/// cannot be reread.
+pub fn run(
+    input: Option<&Path>,
+    watch: bool,
+    mut tool: impl FnMut() -> Result<(), Whatever>,
+) -> Result<(), Whatever> {
+    if !watch {
+        return tool();
+    }
+    let Some(input) = input else {
+        whatever!("--watch requires an input file instead of standard input");
+    };
+
+    let mut last_modified = modified(input);
+    loop {
+        if let Err(error) = tool() {
+            eprintln!("{}", snafu::Report::from_error(error));
+        }
+        eprintln!(
+            "--- watching {} for changes: press Ctrl-C to stop ---",
+            input.to_string_lossy()
+        );
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = modified(input);
+            if current.is_some() && current != last_modified {
+                last_modified = current;
+                break;
+            }
+        }
+    }
+}